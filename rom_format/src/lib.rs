@@ -0,0 +1,208 @@
+/*
+Rom image header
+================
+A rom image may start with a 64 byte header, which is stripped before the image is mapped at the rom
+base address. Images that don't start with the magic are treated as headerless flat binaries.
+All fields are little endian.
+
+0x00 = magic, "RVFMROM\0"
+0x08 = format version (u16, currently 1)
+0x0A = header size in bytes (u16, 64 for version 1)
+0x0C = entry point address of hart 0, must be a word aligned address in the rom image
+0x10 = minimum ram size in bytes
+0x14 = required hart count
+0x18 = image length in bytes, not including the header
+0x1C = CRC-32 (IEEE) of the image, not including the header
+0x20 = title, UTF-8, padded with zeros (32 bytes)
+ */
+
+pub const ROM_HEADER_MAGIC: [u8; 8] = *b"RVFMROM\0";
+pub const ROM_HEADER_VERSION: u16 = 1;
+pub const ROM_HEADER_SIZE: usize = 0x40;
+pub const ROM_TITLE_LENGTH: usize = 0x20;
+pub const ROM_BASE_ADDRESS: u32 = 0xF800_0000;
+pub const ROM_MAX_SIZE: usize = 0x0800_0000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomHeader {
+    pub version: u16,
+    pub title: String,
+    pub entry_point: u32,
+    pub min_ram_size: u32,
+    pub hart_count: u32,
+    pub image_length: u32,
+    pub checksum: u32,
+}
+
+pub struct RomImage {
+    pub header: Option<RomHeader>,
+    pub data: Vec<u8>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+impl RomHeader {
+    pub fn new(title: &str, entry_point: u32, min_ram_size: u32, hart_count: u32, image: &[u8]) -> Result<Self, String> {
+        if title.len() > ROM_TITLE_LENGTH {
+            return Err(format!("title must be at most {} bytes long, got {}", ROM_TITLE_LENGTH, title.len()));
+        }
+        if title.contains('\0') {
+            return Err("title must not contain zero bytes".to_string());
+        }
+        if image.len() > ROM_MAX_SIZE - ROM_HEADER_SIZE {
+            return Err(format!("image is too large for the rom ({} bytes)", image.len()));
+        }
+        let header = Self {
+            version: ROM_HEADER_VERSION,
+            title: title.to_string(),
+            entry_point,
+            min_ram_size,
+            hart_count,
+            image_length: image.len() as u32,
+            checksum: crc32(image),
+        };
+        header.validate_entry_point()?;
+        Ok(header)
+    }
+
+    fn validate_entry_point(&self) -> Result<(), String> {
+        let rom_end = ROM_BASE_ADDRESS as u64 + self.image_length as u64;
+        if (self.entry_point & 3) != 0 || self.entry_point < ROM_BASE_ADDRESS || self.entry_point as u64 >= rom_end {
+            return Err(format!("entry point {:08X} is not a word aligned address inside the rom image", self.entry_point));
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> [u8; ROM_HEADER_SIZE] {
+        let mut bytes = [0u8; ROM_HEADER_SIZE];
+        bytes[0x00..0x08].copy_from_slice(&ROM_HEADER_MAGIC);
+        bytes[0x08..0x0A].copy_from_slice(&self.version.to_le_bytes());
+        bytes[0x0A..0x0C].copy_from_slice(&(ROM_HEADER_SIZE as u16).to_le_bytes());
+        bytes[0x0C..0x10].copy_from_slice(&self.entry_point.to_le_bytes());
+        bytes[0x10..0x14].copy_from_slice(&self.min_ram_size.to_le_bytes());
+        bytes[0x14..0x18].copy_from_slice(&self.hart_count.to_le_bytes());
+        bytes[0x18..0x1C].copy_from_slice(&self.image_length.to_le_bytes());
+        bytes[0x1C..0x20].copy_from_slice(&self.checksum.to_le_bytes());
+        bytes[0x20..0x20 + self.title.len()].copy_from_slice(self.title.as_bytes());
+        bytes
+    }
+}
+
+impl RomImage {
+    pub fn parse(mut file: Vec<u8>) -> Result<Self, String> {
+        if file.len() < ROM_HEADER_SIZE || file[0x00..0x08] != ROM_HEADER_MAGIC {
+            if file.len() > ROM_MAX_SIZE {
+                return Err(format!("rom image is too large ({} bytes)", file.len()));
+            }
+            return Ok(Self {
+                header: None,
+                data: file,
+            });
+        }
+        let version = read_u16(&file, 0x08);
+        if version != ROM_HEADER_VERSION {
+            return Err(format!("unsupported rom header version {}", version));
+        }
+        let header_size = read_u16(&file, 0x0A) as usize;
+        if header_size != ROM_HEADER_SIZE {
+            return Err(format!("invalid rom header size {}", header_size));
+        }
+        let title_bytes = &file[0x20..0x20 + ROM_TITLE_LENGTH];
+        let title_length = title_bytes.iter().position(|x| *x == 0).unwrap_or(ROM_TITLE_LENGTH);
+        let title = std::str::from_utf8(&title_bytes[..title_length]).map_err(|_| "rom title is not valid UTF-8".to_string())?.to_string();
+        let header = RomHeader {
+            version,
+            title,
+            entry_point: read_u32(&file, 0x0C),
+            min_ram_size: read_u32(&file, 0x10),
+            hart_count: read_u32(&file, 0x14),
+            image_length: read_u32(&file, 0x18),
+            checksum: read_u32(&file, 0x1C),
+        };
+        let data = file.split_off(ROM_HEADER_SIZE);
+        if data.len() != header.image_length as usize {
+            return Err(format!("rom image length is {} bytes, but the header specifies {}", data.len(), header.image_length));
+        }
+        if data.len() > ROM_MAX_SIZE {
+            return Err(format!("rom image is too large ({} bytes)", data.len()));
+        }
+        let checksum = crc32(&data);
+        if checksum != header.checksum {
+            return Err(format!("rom checksum mismatch, expected {:08X} but the image has {:08X}", header.checksum, checksum));
+        }
+        header.validate_entry_point()?;
+        Ok(Self {
+            header: Some(header),
+            data,
+        })
+    }
+
+    pub fn entry_point(&self) -> u32 {
+        self.header.as_ref().map(|header| header.entry_point).unwrap_or(ROM_BASE_ADDRESS)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn headered_rom(header: &RomHeader, image: &[u8]) -> Vec<u8> {
+        let mut rom = header.to_bytes().to_vec();
+        rom.extend_from_slice(image);
+        rom
+    }
+
+    #[test]
+    fn rom_header_round_trip() {
+        let image: Vec<u8> = (0..256u32).map(|x| x as u8).collect();
+        let header = RomHeader::new("Test Rom", ROM_BASE_ADDRESS + 0x10, 0x0020_0000, 2, &image).unwrap();
+        let rom = headered_rom(&header, &image);
+
+        let parsed = RomImage::parse(rom.clone()).unwrap();
+        assert_eq!(parsed.header.as_ref(), Some(&header));
+        assert_eq!(parsed.entry_point(), ROM_BASE_ADDRESS + 0x10);
+        assert_eq!(parsed.data, image);
+
+        let mut corrupted = rom.clone();
+        corrupted[0x40] ^= 1;
+        assert!(RomImage::parse(corrupted).is_err());
+        assert!(RomImage::parse(rom[..rom.len() - 1].to_vec()).is_err());
+        assert!(RomHeader::new("Test Rom", ROM_BASE_ADDRESS + 0x100, 0, 1, &image).is_err());
+
+        let headerless = RomImage::parse(image.clone()).unwrap();
+        assert!(headerless.header.is_none());
+        assert_eq!(headerless.entry_point(), ROM_BASE_ADDRESS);
+        assert_eq!(headerless.data, image);
+    }
+
+    #[test]
+    fn large_rom_images() {
+        let image: Vec<u8> = (0..0x0090_0000u32).map(|x| (x >> 8) as u8).collect();
+        let header = RomHeader::new("Large Rom", ROM_BASE_ADDRESS + 0x0080_0000, 0, 1, &image).unwrap();
+        let parsed = RomImage::parse(headered_rom(&header, &image)).unwrap();
+        assert_eq!(parsed.entry_point(), ROM_BASE_ADDRESS + 0x0080_0000);
+        assert_eq!(parsed.data, image);
+
+        let largest = vec![0u8; ROM_MAX_SIZE];
+        assert_eq!(RomImage::parse(largest).unwrap().data.len(), ROM_MAX_SIZE);
+        assert!(RomImage::parse(vec![0u8; ROM_MAX_SIZE + 1]).is_err());
+        assert!(RomHeader::new("Too Large", ROM_BASE_ADDRESS, 0, 1, &vec![0u8; ROM_MAX_SIZE]).is_err());
+    }
+}
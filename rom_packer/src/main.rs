@@ -0,0 +1,94 @@
+use rom_format::{RomHeader, RomImage, ROM_BASE_ADDRESS, ROM_TITLE_LENGTH};
+
+const PROGRAM_USAGE: &str = "usage:
+    rom_packer [flags] <input binary> <output rom>
+        Stamps a rom header onto a flat binary. If the input already has a header, it is replaced.
+        flags:
+        * -title <title>: Title of the rom, at most 32 bytes. Defaults to the output file name.
+        * -entry <address>: Entry point address in hex. Defaults to the rom base address.
+        * -ram <MiB>: Minimum ram size in MiB. Defaults to 1.
+        * -harts <count>: Number of harts the rom requires. Defaults to 1.
+    rom_packer -info <rom>
+        Prints the header of a rom.
+";
+
+fn parse_flag_value<T>(flag: &str, value: Option<String>, parse_fn: impl FnOnce(&str) -> Option<T>) -> Result<T, String> {
+    let value = value.ok_or_else(|| format!("missing value for {}", flag))?;
+    parse_fn(&value).ok_or_else(|| format!("invalid value for {}: {}", flag, value))
+}
+
+fn print_info(rom_path: &str) -> Result<(), String> {
+    let rom_file = std::fs::read(rom_path).map_err(|error| format!("failed to read {}: {}", rom_path, error))?;
+    let rom_image = RomImage::parse(rom_file)?;
+    match rom_image.header {
+        Some(header) => {
+            println!("title:        {}", header.title);
+            println!("version:      {}", header.version);
+            println!("entry point:  {:08X}", header.entry_point);
+            println!("minimum ram:  {} bytes", header.min_ram_size);
+            println!("harts:        {}", header.hart_count);
+            println!("image length: {} bytes", header.image_length);
+            println!("checksum:     {:08X}", header.checksum);
+        },
+        None => println!("{} is a headerless rom, entry point {:08X}", rom_path, rom_image.entry_point()),
+    }
+    Ok(())
+}
+
+fn pack(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut title = None;
+    let mut entry_point = ROM_BASE_ADDRESS;
+    let mut min_ram_size = 0x10_0000;
+    let mut hart_count = 1;
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-title" => title = Some(parse_flag_value(&arg, args.next(), |value| Some(value.to_string()))?),
+            "-entry" => entry_point = parse_flag_value(&arg, args.next(), |value| u32::from_str_radix(value.trim_start_matches("0x"), 16).ok())?,
+            "-ram" => min_ram_size = parse_flag_value(&arg, args.next(), |value| value.parse::<u32>().ok().and_then(|mib| mib.checked_mul(0x10_0000)))?,
+            "-harts" => hart_count = parse_flag_value(&arg, args.next(), |value| value.parse::<u32>().ok())?,
+            _ => paths.push(arg),
+        }
+    }
+    let [input_path, output_path] = &paths[..] else {
+        return Err(PROGRAM_USAGE.to_string());
+    };
+    let title = title.unwrap_or_else(|| {
+        let file_stem = std::path::Path::new(output_path).file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        file_stem.chars().scan(0, |length, c| {
+            *length += c.len_utf8();
+            (*length <= ROM_TITLE_LENGTH).then_some(c)
+        }).collect()
+    });
+
+    let input_file = std::fs::read(input_path).map_err(|error| format!("failed to read {}: {}", input_path, error))?;
+    let image = RomImage::parse(input_file)?.data;
+    let header = RomHeader::new(&title, entry_point, min_ram_size, hart_count, &image)?;
+    let mut rom = header.to_bytes().to_vec();
+    rom.extend_from_slice(&image);
+
+    // make sure the emulator will accept what we wrote
+    let packed_header = RomImage::parse(rom.clone())?.header;
+    assert_eq!(packed_header.as_ref(), Some(&header));
+
+    std::fs::write(output_path, &rom).map_err(|error| format!("failed to write {}: {}", output_path, error))?;
+    println!("packed \"{}\": {} byte image, entry point {:08X}, checksum {:08X}", header.title, header.image_length, header.entry_point, header.checksum);
+    Ok(())
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+    let result = if args.peek().map(|arg| arg == "-info").unwrap_or(false) {
+        args.next();
+        match args.next() {
+            Some(rom_path) => print_info(&rom_path),
+            None => Err(PROGRAM_USAGE.to_string()),
+        }
+    } else {
+        pack(args)
+    };
+    if let Err(error) = result {
+        println!("{}", error);
+        std::process::exit(1);
+    }
+}
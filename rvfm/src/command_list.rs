@@ -60,20 +60,21 @@ pub enum CommandListHeaderError {
 }
 
 pub fn parse_commandlist_header(commandlist_addr: u32, machine: &Arc<Machine>) -> Result<CommandList, CommandListHeaderError> {
-    if !Machine::ADDRESS_RANGE_RAM.contains(&commandlist_addr) || !Machine::ADDRESS_RANGE_RAM.contains(&(commandlist_addr + 8)) {
+    // checked against the configured ram, lists can point anywhere through call_list and jump_list
+    let ram_size = machine.config.ram_size as u64;
+    if commandlist_addr as u64 + 8 > ram_size {
         return Err(CommandListHeaderError::HeaderNotInRam);
     }
     std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel);
-    let list_len = machine.read_u32_unaligned(commandlist_addr).unwrap();
-    let transfer_completion_flag = machine.read_u32_unaligned(commandlist_addr + 4).unwrap();
+    let list_len = machine.read_u32_unaligned(commandlist_addr).to_opt().ok_or(CommandListHeaderError::HeaderNotInRam)?;
+    let transfer_completion_flag = machine.read_u32_unaligned(commandlist_addr + 4).to_opt().ok_or(CommandListHeaderError::HeaderNotInRam)?;
 
     if list_len > MAX_COMMANDLIST_LEN {
         return Err(CommandListHeaderError::ListTooLong);
     }
     let list_start = commandlist_addr + 8;
-    let list_end = commandlist_addr + 8 + list_len;
 
-    if !Machine::ADDRESS_RANGE_RAM.contains(&list_start) || !Machine::ADDRESS_RANGE_RAM.contains(&list_end) {
+    if list_start as u64 + list_len as u64 > ram_size {
         return Err(CommandListHeaderError::ListNotInRam);
     }
 
@@ -84,12 +85,14 @@ pub fn parse_commandlist_header(commandlist_addr: u32, machine: &Arc<Machine>) -
         offset: 0,
     });
 
-    if header.data.len() < list_len as usize {
-        header.data.resize(list_len as usize, 0);
-    }
+    // pooled lists may be longer than this one
+    header.data.resize(list_len as usize, 0);
 
     header.offset = 0;
-    machine.read_block(list_start, &mut header.data[0..list_len as usize]).unwrap();
+    if machine.read_block(list_start, &mut header.data[0..list_len as usize]).to_opt().is_none() {
+        retire_commandlist(header);
+        return Err(CommandListHeaderError::ListNotInRam);
+    }
     if transfer_completion_flag != 0 {
         machine.write_u32(transfer_completion_flag, 1);
         std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel);
@@ -101,3 +104,23 @@ pub fn parse_commandlist_header(commandlist_addr: u32, machine: &Arc<Machine>) -
 pub fn retire_commandlist(cl: CommandList) {
     LIST_POOL.with(|pool| pool.borrow_mut().push(cl));
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::MachineConfig;
+
+    #[test]
+    fn lists_outside_configured_ram() {
+        let machine = Arc::new(Machine::new_headless(&[], &MachineConfig { hart_count: 1, ram_size: 0x0001_0000, ..MachineConfig::default() }).unwrap());
+        assert_eq!(parse_commandlist_header(0x0001_0000, &machine).err(), Some(CommandListHeaderError::HeaderNotInRam));
+        assert_eq!(parse_commandlist_header(0xFFFC, &machine).err(), Some(CommandListHeaderError::HeaderNotInRam));
+        assert_eq!(parse_commandlist_header(0xFFFF_FFFC, &machine).err(), Some(CommandListHeaderError::HeaderNotInRam));
+
+        assert!(machine.write_u32(0x1000, 0x0002_0000).is_ok());
+        assert_eq!(parse_commandlist_header(0x1000, &machine).err(), Some(CommandListHeaderError::ListNotInRam));
+
+        assert!(machine.write_u32(0x1000, 8).is_ok());
+        assert_eq!(parse_commandlist_header(0x1000, &machine).map(|list| list.len()), Ok(8));
+    }
+}
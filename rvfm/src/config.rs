@@ -1,13 +1,80 @@
+use std::path::PathBuf;
+
+use crate::rom::ROM_BASE_ADDRESS;
+
 pub struct Config {
     pub ui_scale: f32,
+    pub window_title: String,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            ui_scale: 2.0
+            ui_scale: 2.0,
+            window_title: "RVFM".to_string(),
         }
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct MachineConfig {
+    pub hart_count: usize,
+    pub ram_size: u32,
+    pub vram_size: u32,
+    // host threads the rasterizer shades tiles on, each one costs a shading unit context
+    pub raster_threads: usize,
+    pub deterministic_seed: Option<u64>,
+    pub semihosting_root: Option<PathBuf>,
+    pub entry_point: u32,
+}
+
+impl MachineConfig {
+    pub const MAX_HART_COUNT: usize = 8;
+    pub const MAX_RAM_SIZE: u32 = 0x0800_0000;
+    pub const RAM_SIZE_GRANULARITY: u32 = 0x0001_0000;
+    pub const MAX_VRAM_SIZE: u32 = 0x2000_0000;
+    pub const DEFAULT_VRAM_SIZE: u32 = 0x0800_0000;
+    pub const MAX_RASTER_THREADS: usize = 8;
+    pub const DEFAULT_MAX_RASTER_THREADS: usize = 4;
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=Self::MAX_HART_COUNT).contains(&self.hart_count) {
+            return Err(format!("hart count must be between 1 and {}, got {}", Self::MAX_HART_COUNT, self.hart_count));
+        }
+        if self.ram_size == 0 || self.ram_size > Self::MAX_RAM_SIZE || (self.ram_size & (Self::RAM_SIZE_GRANULARITY - 1)) != 0 {
+            return Err(format!("ram size must be a non-zero multiple of {} KiB no larger than {} MiB, got {} bytes", Self::RAM_SIZE_GRANULARITY >> 10, Self::MAX_RAM_SIZE >> 20, self.ram_size));
+        }
+        if self.vram_size == 0 || self.vram_size > Self::MAX_VRAM_SIZE || (self.vram_size & (Self::RAM_SIZE_GRANULARITY - 1)) != 0 {
+            return Err(format!("vram size must be a non-zero multiple of {} KiB no larger than {} MiB, got {} bytes", Self::RAM_SIZE_GRANULARITY >> 10, Self::MAX_VRAM_SIZE >> 20, self.vram_size));
+        }
+        if !(1..=Self::MAX_RASTER_THREADS).contains(&self.raster_threads) {
+            return Err(format!("raster thread count must be between 1 and {}, got {}", Self::MAX_RASTER_THREADS, self.raster_threads));
+        }
+        if (self.entry_point & 3) != 0 || self.entry_point < ROM_BASE_ADDRESS {
+            return Err(format!("entry point must be a word aligned address in rom, got {:08X}", self.entry_point));
+        }
+        if let Some(semihosting_root) = self.semihosting_root.as_ref() {
+            if cfg!(not(debug_assertions)) {
+                return Err("semihosting is only available in debug builds".to_string());
+            }
+            if !semihosting_root.is_dir() {
+                return Err(format!("semihosting root {:?} is not a directory", semihosting_root));
+            }
+        }
+        Ok(())
+    }
+}
 
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self {
+            hart_count: 4,
+            ram_size: Self::MAX_RAM_SIZE,
+            vram_size: Self::DEFAULT_VRAM_SIZE,
+            raster_threads: std::thread::available_parallelism().map_or(1, |n| n.get()).min(Self::DEFAULT_MAX_RASTER_THREADS),
+            deterministic_seed: None,
+            semihosting_root: None,
+            entry_point: ROM_BASE_ADDRESS,
+        }
+    }
+}
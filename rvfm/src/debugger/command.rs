@@ -1,3 +1,5 @@
+use crate::config::MachineConfig;
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ListType {
     Breakpoints,
@@ -27,8 +29,8 @@ pub enum Command {
     List(Vec<ListType>),
     SetBreakpoint(BreakpointName),
     ClearBreakpoint(BreakpointName),
-    Stop([bool; 4]),
-    Continue([bool; 4]),
+    Stop([bool; MachineConfig::MAX_HART_COUNT]),
+    Continue([bool; MachineConfig::MAX_HART_COUNT]),
     Hart(usize),
     Regs,
     SingleStep,
@@ -60,6 +62,13 @@ fn decode_hex(hex: &str) -> u32 {
     value_out as u32
 }
 
+fn parse_hart(hart: &str) -> Option<usize> {
+    match hart.parse::<usize>() {
+        Ok(hart) if hart < MachineConfig::MAX_HART_COUNT => Some(hart),
+        _ => None
+    }
+}
+
 impl Command {
     pub fn parse(command_string: &str) -> Option<Command> {
         if command_string.len() == 0 {
@@ -93,43 +102,28 @@ impl Command {
             },
             Some(&"step") | Some(&"s") => Some(Command::SingleStep),
             Some(&"continue") | Some(&"c") => {
-                let mut harts = [false; 4];
+                let mut harts = [false; MachineConfig::MAX_HART_COUNT];
                 for hart_target in words[1..].iter() {
                     match *hart_target {
-                        "0" => harts[0] = true,
-                        "1" => harts[1] = true,
-                        "2" => harts[2] = true,
-                        "3" => harts[3] = true,
-                        "all" => harts = [true, true, true, true],
-                        _ => return None
+                        "all" => harts = [true; MachineConfig::MAX_HART_COUNT],
+                        hart => harts[parse_hart(hart)?] = true,
                     }
                 }
                 Some(Command::Continue(harts))
             },
             Some(&"stop") | Some(&"S") => {
-                let mut harts = [false; 4];
+                let mut harts = [false; MachineConfig::MAX_HART_COUNT];
                 for hart_target in words[1..].iter() {
                     match *hart_target {
-                        "0" => harts[0] = true,
-                        "1" => harts[1] = true,
-                        "2" => harts[2] = true,
-                        "3" => harts[3] = true,
-                        "all" => harts = [true, true, true, true],
-                        _ => return None
+                        "all" => harts = [true; MachineConfig::MAX_HART_COUNT],
+                        hart => harts[parse_hart(hart)?] = true,
                     }
                 }
                 Some(Command::Stop(harts))
             },
             Some(&"hart") | Some(&"H") => {
                 if let Some(hart) = words.get(1) {
-                    let hart = match *hart {
-                        "0" => 0,
-                        "1" => 1,
-                        "2" => 2,
-                        "3" => 3,
-                        _ => return None
-                    };
-                    Some(Command::Hart(hart))
+                    Some(Command::Hart(parse_hart(hart)?))
                 } else {
                     None
                 }
@@ -32,8 +32,8 @@ struct Breakpoint {
 }
 
 pub struct Debugger {
-    harts                 : [Hart; 4],
-    exec_modes            : [HartExecutionMode; 4],
+    harts                 : Vec<Hart>,
+    exec_modes            : Vec<HartExecutionMode>,
     alive                 : bool,
     machine               : Arc<Machine>,
     breakpoints           : Vec<Breakpoint>,
@@ -128,16 +128,12 @@ impl Drop for StdinLineStream {
 impl Debugger {
     pub fn new(machine: Arc<Machine>) -> Self {
         let shared_csrs = SharedCSRs::new();
+        let hart_count = machine.config.hart_count;
         Self {
-            harts: [
-                Hart::new(0xF800_0000, 0, &shared_csrs, &machine),
-                Hart::new(0xF800_0000, 1, &shared_csrs, &machine),
-                Hart::new(0xF800_0000, 2, &shared_csrs, &machine),
-                Hart::new(0xF800_0000, 3, &shared_csrs, &machine),
-            ],
+            harts: (0..hart_count).map(|hart_id| Hart::new(machine.config.entry_point, hart_id as u32, &shared_csrs, &machine)).collect(),
             machine,
             alive: true,
-            exec_modes: [HartExecutionMode::Stopped; 4],
+            exec_modes: vec![HartExecutionMode::Stopped; hart_count],
             breakpoints: Vec::new(),
             breakpoint_id_counter: 0,
         }
@@ -207,9 +203,10 @@ impl Debugger {
         let mut symbol_tree: BTreeMap<u32, (String, symbol::Type)> = BTreeMap::new();
         let mut stdout = std::io::stdout();
         let mut line_stream = StdinLineStream::new();
-        let mut last_execution_modes = [HartExecutionMode::Stopped; 4];
-        let mut last_breakpoint_addrs = [3; 4];
-        let mut hart_breakpoints = [0usize; 4];
+        let hart_count = self.harts.len();
+        let mut last_execution_modes = vec![HartExecutionMode::Stopped; hart_count];
+        let mut last_breakpoint_addrs = vec![3; hart_count];
+        let mut hart_breakpoints = vec![0usize; hart_count];
         let mut current_hart = 0;
         let mut print_prompt = true;
 
@@ -249,7 +246,7 @@ impl Debugger {
 
         let mut last_command = None;
         loop {
-            for hart in 0..4 {
+            for hart in 0..hart_count {
                 if self.exec_modes[hart] == HartExecutionMode::Running || self.exec_modes[hart] == HartExecutionMode::SingleStepping {
                     let pc = self.harts[hart].pc;
                     let mut hit_breakpoint = false;
@@ -279,7 +276,7 @@ impl Debugger {
             }
 
             let mut stop = false;
-            for hart in 0..4 {
+            for hart in 0..hart_count {
                 if self.exec_modes[hart] != last_execution_modes[hart] {
                     stop |= match self.exec_modes[hart] {
                         HartExecutionMode::Stopped => {
@@ -319,7 +316,7 @@ impl Debugger {
                 }
             }
             if stop {
-                for hart in 0..4 {
+                for hart in 0..hart_count {
                     match self.exec_modes[hart] {
                         HartExecutionMode::Running | HartExecutionMode::SingleStepping => {
                             self.exec_modes[hart] = HartExecutionMode::Stopped;
@@ -331,7 +328,7 @@ impl Debugger {
                     }
                 }
             } else {
-                last_execution_modes = self.exec_modes;
+                last_execution_modes = self.exec_modes.clone();
             }
             
 
@@ -432,7 +429,7 @@ impl Debugger {
                         }
                     },
                     Some(Command::Stop(harts_to_stop)) => {
-                        for hart in 0..4 {
+                        for hart in 0..hart_count {
                             match (harts_to_stop[hart], self.exec_modes[hart]) {
                                 (true, HartExecutionMode::Running | HartExecutionMode::WaitingForInterrupt | HartExecutionMode::HitBreakpoint) => {
                                     self.exec_modes[hart] = HartExecutionMode::Stopped;
@@ -447,7 +444,7 @@ impl Debugger {
                         }
                     },
                     Some(Command::Continue(harts_to_continue)) => {
-                        for hart in 0..4 {
+                        for hart in 0..hart_count {
                             match (harts_to_continue[hart], self.exec_modes[hart]) {
                                 (true, HartExecutionMode::Stopped | HartExecutionMode::WaitingForInterrupt) => {
                                     self.exec_modes[hart] = HartExecutionMode::Running;
@@ -462,7 +459,9 @@ impl Debugger {
                         }
                     },
                     Some(Command::Hart(hart)) => {
-                        if hart != current_hart {
+                        if hart >= hart_count {
+                            println!("no hart {} (hart count: {})", hart, hart_count);
+                        } else if hart != current_hart {
                             println!("switched to hart {}", hart);
                             current_hart = hart;
                         } else {
@@ -560,28 +559,19 @@ impl Debugger {
             println!("    - continue <hart> ([, <hart>]) : continues the targeted hart(s)");
             println!("          aliases                  : continue c");
             println!("          harts                    :");
-            println!("              * 0");
-            println!("              * 1");
-            println!("              * 2");
-            println!("              * 3");
+            println!("              * 0 .. {}", self.harts.len() - 1);
             println!("              * all");
             println!("");
             println!("    - stop <hart> [(, <hart>)]     : stops the targeted hart(s)");
             println!("          aliases                  : stop S");
             println!("          harts                    :");
-            println!("              * 0");
-            println!("              * 1");
-            println!("              * 2");
-            println!("              * 3");
+            println!("              * 0 .. {}", self.harts.len() - 1);
             println!("              * all");
             println!("");
             println!("    - hart <hart>                  : stops the targeted hart(s)");
             println!("          aliases                  : hart H");
             println!("          harts                    :");
-            println!("              * 0");
-            println!("              * 1");
-            println!("              * 2");
-            println!("              * 3");
+            println!("              * 0 .. {}", self.harts.len() - 1);
             println!("");
             println!("    - set_bp <breakpoint>          : creates a breakpoint at the address/symbol specified");
             println!("          aliases                  : set_bp b");
@@ -0,0 +1,117 @@
+use std::{collections::hash_map::RandomState, hash::{BuildHasher, Hasher}, time::{SystemTime, UNIX_EPOCH}};
+
+use parking_lot::Mutex;
+use static_init::dynamic;
+
+use crate::{config::MachineConfig, hart::current_hart, machine::{WriteResult, ReadResult}};
+
+/*
+Entropy registers (read-only)
+=============================
+0x00 = random value, a new one is generated on every read
+0x04 = deterministic mode (1 if the values are derived from the machine seed)
+
+Each hart reads its own sequence, so seeded runs don't depend on how the host schedules harts.
+ */
+
+struct EntropyState {
+    state: u64,
+    deterministic: bool,
+}
+
+impl EntropyState {
+    // splitmix64
+    fn next(&mut self) -> u32 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        (z ^ (z >> 31)) as u32
+    }
+}
+
+#[dynamic]
+static ENTROPY_STATE: [Mutex<EntropyState>; MachineConfig::MAX_HART_COUNT] = [(); MachineConfig::MAX_HART_COUNT].map(|_| Mutex::new(EntropyState { state: host_seed(), deterministic: false }));
+
+fn host_seed() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map(|t| t.as_nanos()).unwrap_or(0));
+    hasher.finish()
+}
+
+pub fn entropy_init(deterministic_seed: Option<u64>) {
+    for (hart, state) in ENTROPY_STATE.iter().enumerate() {
+        *state.lock() = match deterministic_seed {
+            // mix the hart id into the seed so every hart gets its own stream
+            Some(seed) => EntropyState { state: seed ^ (hart as u64).wrapping_mul(0xD6E8_FEB8_6659_FD93), deterministic: true },
+            None => EntropyState { state: host_seed(), deterministic: false },
+        };
+    }
+}
+
+pub fn entropy_read_u32(offset: u32) -> ReadResult<u32> {
+    let state = &ENTROPY_STATE[current_hart() as usize % MachineConfig::MAX_HART_COUNT];
+    match offset {
+        0x00 => ReadResult::Ok(state.lock().next()),
+        0x04 => ReadResult::Ok(if state.lock().deterministic { 1 } else { 0 }),
+        _ => ReadResult::InvalidAddress
+    }
+}
+
+pub fn entropy_read_u16(offset: u32) -> ReadResult<u16> {
+    entropy_read_u32(offset).map(|x| x as u16)
+}
+
+pub fn entropy_read_u8(offset: u32) -> ReadResult<u8> {
+    entropy_read_u32(offset).map(|x| x as u8)
+}
+
+pub fn entropy_write_u32(offset: u32, _value: u32) -> WriteResult {
+    match offset {
+        0x00 | 0x04 => WriteResult::ReadOnly,
+        _ => WriteResult::InvalidAddress
+    }
+}
+
+pub fn entropy_write_u16(offset: u32, value: u16) -> WriteResult {
+    entropy_write_u32(offset, value as u32)
+}
+
+pub fn entropy_write_u8(offset: u32, value: u8) -> WriteResult {
+    entropy_write_u32(offset, value as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::hart::set_current_hart;
+    use crate::machine::DEVICE_TEST_LOCK;
+
+    fn read_as(hart: u32) -> u32 {
+        set_current_hart(hart);
+        entropy_read_u32(0x00).to_opt().unwrap()
+    }
+
+    #[test]
+    fn seeded_sequences_are_per_hart() {
+        let _lock = DEVICE_TEST_LOCK.lock();
+        entropy_init(Some(42));
+        let first: Vec<Vec<u32>> = (0..2).map(|hart| (0..8).map(|_| read_as(hart)).collect()).collect();
+        // interleaving the reads differently doesn't change what each hart sees
+        entropy_init(Some(42));
+        let mut second = vec![Vec::new(), Vec::new()];
+        for _ in 0..8 {
+            second[1].push(read_as(1));
+            second[0].push(read_as(0));
+        }
+        assert_eq!(first, second);
+        assert_ne!(first[0], first[1]);
+        assert_eq!(entropy_read_u32(0x04).to_opt(), Some(1));
+
+        entropy_init(Some(43));
+        assert_ne!((0..8).map(|_| read_as(0)).collect::<Vec<_>>(), first[0]);
+        entropy_init(None);
+        assert_eq!(entropy_read_u32(0x04).to_opt(), Some(0));
+        set_current_hart(0);
+    }
+}
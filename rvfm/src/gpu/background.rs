@@ -0,0 +1,181 @@
+use super::buffer::BufferModule;
+use super::texture::{rgba_unorm8_fetch_fn, TextureModule};
+use super::types::Background;
+
+pub const BACKGROUND_COUNT: usize = 4;
+pub const LAYER_PRIORITY_COUNT: u8 = 4;
+
+pub const BACKGROUND_TILE_INDEX_MASK : u16 = 0x3FFF;
+pub const BACKGROUND_TILE_FLIP_X     : u16 = 0x4000;
+pub const BACKGROUND_TILE_FLIP_Y     : u16 = 0x8000;
+
+pub struct BackgroundTable {
+    pub backgrounds: [Background; BACKGROUND_COUNT],
+}
+
+impl BackgroundTable {
+    pub fn new() -> Self {
+        Self {
+            backgrounds: [Background::new(); BACKGROUND_COUNT],
+        }
+    }
+
+    /*
+    draws every enabled background of the given priority into target. lower
+    background indices end up on top of higher ones within the same priority.
+     */
+    pub fn composite(&self, priority: u8, textures: &[TextureModule; 64], buffers: &[BufferModule; 256], target: &mut [[u8; 4]], width: u32, height: u32) {
+        for background in self.backgrounds.iter().rev() {
+            if background.enabled && background.priority == priority {
+                draw_background(background, textures, buffers, target, width, height);
+            }
+        }
+    }
+}
+
+/*
+map entries are u16: tile index in the low 14 bits, then flip x and flip y.
+tiles are numbered left to right, top to bottom across the atlas texture. the
+scanline buffer holds one (i16 x, i16 y) scroll offset per screen line.
+ */
+fn draw_background(background: &Background, textures: &[TextureModule; 64], buffers: &[BufferModule; 256], target: &mut [[u8; 4]], width: u32, height: u32) {
+    let atlas = &textures[background.atlas_texture as usize];
+    // configure_background checks the layout, this only skips atlases reconfigured since
+    let Some(fetch_fn) = rgba_unorm8_fetch_fn(atlas.config.pixel_layout) else {
+        return;
+    };
+    let tile_size = background.tile_size as i32;
+    let palette = buffers[atlas.palette as usize].bytes();
+    let atlas_tiles_x = atlas.config.width as i32 / tile_size.max(1);
+    if tile_size == 0 || atlas_tiles_x == 0 {
+        return;
+    }
+    let map = buffers[background.map_buffer as usize].bytes();
+    let scanlines = background.scanline_buffer.map(|buffer| buffers[buffer as usize].bytes());
+    let map_width  = background.map_size.x * tile_size;
+    let map_height = background.map_size.y * tile_size;
+    if map_width <= 0 || map_height <= 0 {
+        return;
+    }
+    for y in 0..height as i32 {
+        let (line_x, line_y) = match scanlines {
+            Some(bytes) if bytes.len() >= (y as usize + 1) * 4 => {
+                let offset = y as usize * 4;
+                (
+                    i16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as i32,
+                    i16::from_le_bytes([bytes[offset + 2], bytes[offset + 3]]) as i32,
+                )
+            },
+            _ => (0, 0),
+        };
+        let mut map_y = y + background.scroll.y + line_y;
+        if background.wrap {
+            map_y = map_y.rem_euclid(map_height);
+        } else if map_y < 0 || map_y >= map_height {
+            continue;
+        }
+        for x in 0..width as i32 {
+            let mut map_x = x + background.scroll.x + line_x;
+            if background.wrap {
+                map_x = map_x.rem_euclid(map_width);
+            } else if map_x < 0 || map_x >= map_width {
+                continue;
+            }
+            let entry_offset = ((map_x / tile_size) + (map_y / tile_size) * background.map_size.x) as usize * 2;
+            let Some(entry) = map.get(entry_offset..entry_offset + 2) else {
+                continue;
+            };
+            let entry = u16::from_le_bytes([entry[0], entry[1]]);
+            let tile = (entry & BACKGROUND_TILE_INDEX_MASK) as i32;
+            let mut tile_x = map_x % tile_size;
+            let mut tile_y = map_y % tile_size;
+            if entry & BACKGROUND_TILE_FLIP_X != 0 {
+                tile_x = tile_size - 1 - tile_x;
+            }
+            if entry & BACKGROUND_TILE_FLIP_Y != 0 {
+                tile_y = tile_size - 1 - tile_y;
+            }
+            let atlas_x = (tile % atlas_tiles_x) * tile_size + tile_x;
+            let atlas_y = (tile / atlas_tiles_x) * tile_size + tile_y;
+            if atlas_y >= atlas.config.height as i32 {
+                continue;
+            }
+            let pixel = fetch_fn(atlas, palette, atlas_x as u32, atlas_y as u32);
+            if pixel[3] == 0 {
+                continue;
+            }
+            target[(x + y * width as i32) as usize] = pixel;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::types::{I32x2, ImageDataLayout, PixelDataLayout, Texture};
+
+    const BACKDROP: [u8; 4] = [9, 9, 9, 9];
+
+    /*
+    a 4x2 D8x4 atlas of two 2x2 tiles, red is 1 + x + 4 * y. buffer 0 is a 2x1
+    map of tile 1 then tile 0 flipped both ways, buffer 1 scrolls line 1 by
+    (2, -1).
+     */
+    fn resources() -> (Box<[TextureModule; 64]>, Box<[BufferModule; 256]>) {
+        let mut textures = Box::new([(); 64].map(|_| TextureModule::new()));
+        textures[0].config = Texture { width: 4, height: 2, pixel_layout: PixelDataLayout::D8x4, image_layout: ImageDataLayout::Contiguous };
+        textures[0].memory = (0..8).map(|i| u32::from_le_bytes([i as u8 + 1, 0, 0, 255])).collect();
+        let mut buffers = Box::new([(); 256].map(|_| BufferModule::new()));
+        let map = [1u16, BACKGROUND_TILE_FLIP_X | BACKGROUND_TILE_FLIP_Y].map(u16::to_le_bytes).concat();
+        let scanlines = [0i16, 0, 2, -1].map(i16::to_le_bytes).concat();
+        for (buffer, bytes) in buffers.iter_mut().zip([map, scanlines]) {
+            buffer.memory[0..bytes.len()].copy_from_slice(&bytes);
+            buffer.length = bytes.len() as u32;
+        }
+        (textures, buffers)
+    }
+
+    fn background(wrap: bool, scanline_buffer: Option<u8>) -> Background {
+        Background {
+            enabled: true,
+            tile_size: 2,
+            map_size: I32x2 { x: 2, y: 1 },
+            scroll: I32x2 { x: 1, y: 0 },
+            wrap,
+            scanline_buffer,
+            ..Background::new()
+        }
+    }
+
+    fn red(target: &[[u8; 4]]) -> Vec<u8> {
+        target.iter().map(|pixel| pixel[0]).collect()
+    }
+
+    #[test]
+    fn wrap_scanline_scroll_and_tile_flip() {
+        let (textures, buffers) = resources();
+        let mut table = BackgroundTable::new();
+        table.backgrounds[0] = background(true, Some(1));
+        let mut target = [BACKDROP; 8];
+        table.composite(0, &textures, &buffers, &mut target, 4, 2);
+        assert_eq!(red(&target), [
+            4, 6, 5, 3,
+            5, 3, 4, 6,
+        ]);
+    }
+
+    #[test]
+    fn unwrapped_maps_leave_the_rest_of_the_layer_empty() {
+        let (textures, buffers) = resources();
+        let mut table = BackgroundTable::new();
+        table.backgrounds[1] = background(false, None);
+        let mut target = [BACKDROP; 8];
+        table.composite(1, &textures, &buffers, &mut target, 4, 2);
+        assert_eq!(red(&target), [9; 8]);
+        table.composite(0, &textures, &buffers, &mut target, 4, 2);
+        assert_eq!(red(&target), [
+            4, 6, 5, 9,
+            8, 2, 1, 9,
+        ]);
+    }
+}
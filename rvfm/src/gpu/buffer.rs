@@ -9,6 +9,38 @@ pub struct BufferModule {
 
 pub const BUFFER_MAX_SIZE: u32 = 1024 * 128;
 
+/*
+buffers as a shader sees them. raster workers share buffers read only, a shader
+that writes buffers gets them to itself.
+ */
+pub enum BufferAccess<'a> {
+    Shared(&'a [BufferModule; 256]),
+    Exclusive(&'a mut [BufferModule; 256]),
+}
+
+impl BufferAccess<'_> {
+    pub fn get(&self, index: usize) -> &BufferModule {
+        match self {
+            Self::Shared(buffers) => &buffers[index],
+            Self::Exclusive(buffers) => &buffers[index],
+        }
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut BufferModule> {
+        match self {
+            Self::Shared(_) => None,
+            Self::Exclusive(buffers) => Some(&mut buffers[index]),
+        }
+    }
+
+    pub fn reborrow(&mut self) -> BufferAccess<'_> {
+        match self {
+            Self::Shared(buffers) => BufferAccess::Shared(buffers),
+            Self::Exclusive(buffers) => BufferAccess::Exclusive(buffers),
+        }
+    }
+}
+
 impl BufferModule {
     pub fn new() -> Self {
         Self {
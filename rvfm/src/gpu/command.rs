@@ -1,6 +1,6 @@
 use bytemuck::cast_slice_mut;
 
-use super::{shader::ShaderType, types::*};
+use super::{fragment_shader::DepthCompareFn, queue::FENCE_COUNT, rasterizer::IndexType, shader::ShaderType, statistics::TimestampSource, types::*};
 use crate::command_list::CommandList;
 
 #[derive(Debug)]
@@ -42,8 +42,9 @@ pub enum Command {
         irq: bool
     },
     /*
-    configure_texture < width > <height > <texture> <pixel_layout> <image_layout>    ..
-    [         05 00 ] [ XX XX ] [ YY YY ] [    ZZ ] [         UU ] [         VV ] [ 00 00 00 ]
+    configure_texture < width > <height > <texture> <pixel_layout> <image_layout> <palette>   ..
+    [         05 00 ] [ XX XX ] [ YY YY ] [    ZZ ] [         UU ] [         VV ] [     PP ] [ 00 00 ]
+    palette is the buffer indexed pixel layouts are looked up through.
      */
     ConfigureTexture {
         width: u32,
@@ -51,6 +52,7 @@ pub enum Command {
         texture: u8,
         pixel_layout: PixelDataLayout,
         image_layout: ImageDataLayout,
+        palette: u8,
     },
     /*
     upload_texture <texture> < src_image_layout> < src_address >
@@ -180,6 +182,249 @@ pub enum Command {
         length: u32,
         offset: u32,
     },
+    /*
+    upload_compute_pipeline_state <index>   ..   < address     >
+    [                     11 00 ] [  II ] [ 00 ] [ AA AA AA AA ]
+    */
+    UploadComputePipelineState {
+        index: u8,
+        address: u32,
+    },
+    /*
+    dispatch_compute <state_index> <shader> <workgroup size>    ..    <workgroups x> <workgroups y>
+    [        12 00 ] [        II ] [    SS ] [        WW WW ] [ 00 00 ] [     XX XX ] [     YY YY ]
+     */
+    DispatchCompute {
+        state_index: u8,
+        shader: u8,
+        workgroup_size: u16,
+        workgroups_x: u16,
+        workgroups_y: u16,
+    },
+    /*
+    configure_sampler <sampler> <filter> <overflow u> <overflow v>    ..
+    [         13 00 ] [    SS ] [    FF ] [       UU ] [       VV ] [ 00 00 ]
+     */
+    ConfigureSampler {
+        sampler: u8,
+        config: TextureSampler,
+    },
+    /*
+    generate_mipmaps <texture> <component type>
+    [        14 00 ] [    TT ] [            CC ]
+     */
+    GenerateMipmaps {
+        texture: u8,
+        component_type: TexelComponentType,
+    },
+    /*
+    upload_texture_level <texture> <level> < src_address >
+    [            15 00 ] [    TT ] [  LL ] [ AA AA AA AA ]
+     */
+    UploadTextureLevel {
+        texture: u8,
+        level: u8,
+        src_addr: u32,
+    },
+    /*
+    set_sprite <sprite> <texture> < x     > < y     > < src x > < src y > < width > < height> <flags> <priority>   ..
+    [  16 00 ] [   II ] [     TT ] [ XX XX ] [ YY YY ] [ UU UU ] [ VV VV ] [ WW WW ] [ HH HH ] [  FF ] [      PP ] [ 00 00 ]
+    flags: 1 = enabled, 2 = flip x, 4 = flip y, 8 = cutout
+    priority is 0 - 3, 0 drawn on top. an enabled sprite's texture has to be configured
+    with a D8x4, D32x4, I4 or I8 layout before set_sprite.
+     */
+    SetSprite {
+        sprite: u8,
+        config: Sprite,
+    },
+    /*
+    move_sprite <sprite>   ..   < x     > < y     >
+    [   17 00 ] [   II ] [ 00 ] [ XX XX ] [ YY YY ]
+     */
+    MoveSprite {
+        sprite: u8,
+        x: i16,
+        y: i16,
+    },
+    /*
+    configure_background <background> <flags> <atlas> <map buffer> <scanline buffer> <tile size> < map width > < map height> <priority>     ..
+    [          18 00 ] [       BB ] [  FF ] [  TT ] [        MM ] [             SS ] [      ZZ ] [     WW WW ] [      HH HH ] [      PP ] [ 00 00 00 ]
+    flags: 1 = enabled, 2 = wrap, 4 = scanline scroll
+    priority is 0 - 3, 0 drawn on top. an enabled background's atlas has to be configured
+    with a D8x4, D32x4, I4 or I8 layout before configure_background.
+     */
+    ConfigureBackground {
+        background: u8,
+        config: Background,
+    },
+    /*
+    scroll_background <background>   ..   < x     > < y     >
+    [       19 00 ] [       BB ] [ 00 ] [ XX XX ] [ YY YY ]
+     */
+    ScrollBackground {
+        background: u8,
+        x: i16,
+        y: i16,
+    },
+    /*
+    draw_graphics_pipeline_instanced <state_index> <fragment shader> <vertex shader>      ..      < vertex count> < x low > < x high> < y low > < y high> < instance count>
+    [                        1A 00 ] [        II ] [            FF ] [          VV ] [ 00 00 00 ] [ CC CC CC CC ] [ xx xx ] [ XX XX ] [ yy yy ] [ YY YY ] [  NN NN NN NN  ]
+     */
+    DrawGraphicsPipelineInstanced {
+        state_index: u8,
+        fragment_shader: u8,
+        vertex_shader: u8,
+        vertex_count: u32,
+        x_low: u16,
+        x_high: u16,
+        y_low: u16,
+        y_high: u16,
+        instance_count: u32,
+    },
+    /*
+    draw_indexed_graphics_pipeline <state_index> <fragment shader> <vertex shader> <index buffer> <index type>  ..  < index count > < index offset> < x low > < x high> < y low > < y high> < instance count>
+    [                      1B 00 ] [        II ] [            FF ] [          VV ] [         BB ] [       TT ] [ 00 ] [ CC CC CC CC ] [ OO OO OO OO ] [ xx xx ] [ XX XX ] [ yy yy ] [ YY YY ] [  NN NN NN NN  ]
+    index type is 0 for u16 indices and 1 for u32 indices. the index offset is in bytes.
+     */
+    DrawIndexedGraphicsPipeline {
+        state_index: u8,
+        fragment_shader: u8,
+        vertex_shader: u8,
+        index_buffer: u8,
+        index_type: IndexType,
+        index_count: u32,
+        index_offset: u32,
+        x_low: u16,
+        x_high: u16,
+        y_low: u16,
+        y_high: u16,
+        instance_count: u32,
+    },
+    /*
+    query_vram     ..      <   address   >
+    [  1C 00 ] [ 00 00 ] [ AA AA AA AA ]
+    writes three words to the address: the vram size in bytes, the free vram in bytes,
+    and the number of texture configurations that have failed for lack of vram.
+     */
+    QueryVram {
+        address: u32,
+    },
+    /*
+    set_texture_palette <texture> <palette>
+    [           1D 00 ] [    TT ] [     PP ]
+    palette is a buffer of 256 rgba unorm8 entries. only indexed textures use it.
+     */
+    SetTexturePalette {
+        texture: u8,
+        palette: u8,
+    },
+    /*
+    download_texture <texture> <dst_image_layout> <   x   > <   y   > < width > <height > < dst address > <completion addr> <interrupt>    ..
+    [        1E 00 ] [    TT ] [              LL ] [ xx xx ] [ yy yy ] [ WW WW ] [ HH HH ] [ AA AA AA AA ] [   CC CC CC CC ] [       II ] [ 00 00 00 ]
+    copies the region of the texture to ram in dst_image_layout, then writes 1 to the completion address.
+     */
+    DownloadTexture {
+        texture: u8,
+        dst_image_layout: ImageDataLayout,
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+        dst_addr: u32,
+        completion_addr: u32,
+        interrupt: bool,
+    },
+    /*
+    write_timestamp <source>  ..   <   address   >
+    [       1F 00 ] [    SS ] [ 00 ] [ AA AA AA AA ]
+    writes a u64, low word first. source 0 is host nanoseconds since the gpu started,
+    source 1 is virtual time in cycles run by hart 0, which never falls behind the
+    start of the current frame.
+     */
+    WriteTimestamp {
+        source: TimestampSource,
+        address: u32,
+    },
+    /*
+    begin_statistics_query   ..
+    [              20 00 ] [ 00 00 ]
+    resets the pipeline statistics counters of the queue it runs on. each queue
+    counts only the work run from its own command lists.
+     */
+    BeginStatisticsQuery,
+    /*
+    end_statistics_query     ..      <   address   >
+    [            21 00 ] [ 00 00 ] [ AA AA AA AA ]
+    writes five words counted on this queue since its last begin_statistics_query: vertices shaded,
+    primitives rasterized, fragments shaded, fragments passing depth and stencil, and
+    pixels covered by blits.
+     */
+    EndStatisticsQuery {
+        address: u32,
+    },
+    /*
+    call_list    ..      <   address   >
+    [  22 00 ] [ 00 00 ] [ AA AA AA AA ]
+    address points at a command list header, the same as a submission. the list
+    runs to its end and then returns to the command after the call. calls nest up
+    to 8 deep. the header's completion flag is written every time the list is
+    called, so lists reused every frame should leave it 0.
+     */
+    CallList {
+        address: u32,
+    },
+    /*
+    jump_list    ..      <   address   >
+    [  23 00 ] [ 00 00 ] [ AA AA AA AA ]
+    abandons the rest of the current list and continues with the list at address.
+    a jump inside a called list still returns to the caller once the target ends.
+     */
+    JumpList {
+        address: u32,
+    },
+    /*
+    skip_if <compare> ..   <   address   > <  reference  > <    mask     > < skip bytes  >
+    [ 24 00 ] [    CC ] [ 00 ] [ AA AA AA AA ] [ VV VV VV VV ] [ MM MM MM MM ] [ SS SS SS SS ]
+    skips the next skip bytes of the list when (ram[address] & mask) compared
+    against reference passes. compare uses the depth compare function encoding.
+     */
+    SkipIf {
+        compare_fn: DepthCompareFn,
+        address: u32,
+        reference: u32,
+        mask: u32,
+        skip_bytes: u32,
+    },
+    /*
+    wait_until <compare> ..   <   address   > <  reference  > <    mask     >
+    [    25 00 ] [    CC ] [ 00 ] [ AA AA AA AA ] [ VV VV VV VV ] [ MM MM MM MM ]
+    stalls the queue until (ram[address] & mask) compared against reference passes.
+    other queues keep running in the meantime.
+     */
+    WaitUntil {
+        compare_fn: DepthCompareFn,
+        address: u32,
+        reference: u32,
+        mask: u32,
+    },
+    /*
+    signal_fence <fence>  ..   <    value    >
+    [    26 00 ] [  FF ] [ 00 ] [ VV VV VV VV ]
+    sets one of the 16 fences shared by all queues to value.
+     */
+    SignalFence {
+        fence: u8,
+        value: u32,
+    },
+    /*
+    wait_fence <fence>  ..   <    value    >
+    [  27 00 ] [  FF ] [ 00 ] [ VV VV VV VV ]
+    stalls the queue until the fence is at least value, letting other queues run.
+     */
+    WaitFence {
+        fence: u8,
+        value: u32,
+    },
 }
 
 impl Command {
@@ -246,7 +491,8 @@ impl Command {
                 let texture = command_list.read_u8(offset + 6)?;
                 let pixel_layout = PixelDataLayout::from_u8(command_list.read_u8(offset + 7)?)?;
                 let image_layout = ImageDataLayout::from_u8(command_list.read_u8(offset + 8)?)?;
-                Some((offset + 12, Command::ConfigureTexture { texture, pixel_layout, image_layout, width, height }))
+                let palette = command_list.read_u8(offset + 9)?;
+                Some((offset + 12, Command::ConfigureTexture { texture, pixel_layout, image_layout, width, height, palette }))
             },
             Some(0x00_06) => {
                 let texture = command_list.read_u8(offset + 2)?;
@@ -345,6 +591,201 @@ impl Command {
                 let buffer_offset = command_list.read_u32(offset + 12)?;
                 Some((offset + 16, Command::WriteBuffer { buffer, src_addr, length, offset: buffer_offset }))
             },
+            Some(0x00_11) => {
+                let index = command_list.read_u8(offset + 2)?;
+                let address = command_list.read_u32(offset + 4)?;
+                Some((offset + 8, Command::UploadComputePipelineState { index, address }))
+            },
+            Some(0x00_12) => {
+                let state_index = command_list.read_u8(offset + 2)?;
+                let shader = command_list.read_u8(offset + 3)?;
+                let workgroup_size = command_list.read_u16(offset + 4)?;
+                let workgroups_x = command_list.read_u16(offset + 8)?;
+                let workgroups_y = command_list.read_u16(offset + 10)?;
+                Some((offset + 12, Command::DispatchCompute { state_index, shader, workgroup_size, workgroups_x, workgroups_y }))
+            },
+            Some(0x00_13) => {
+                let sampler = command_list.read_u8(offset + 2)?;
+                let filter = SampleFilter::from_u8(command_list.read_u8(offset + 3)?)?;
+                let overflow_u = OverflowMode::from_u8(command_list.read_u8(offset + 4)?)?;
+                let overflow_v = OverflowMode::from_u8(command_list.read_u8(offset + 5)?)?;
+                Some((offset + 8, Command::ConfigureSampler { sampler, config: TextureSampler { filter, overflow_u, overflow_v } }))
+            },
+            Some(0x00_14) => {
+                let texture = command_list.read_u8(offset + 2)?;
+                let component_type = TexelComponentType::from_u8(command_list.read_u8(offset + 3)?)?;
+                Some((offset + 4, Command::GenerateMipmaps { texture, component_type }))
+            },
+            Some(0x00_15) => {
+                let texture = command_list.read_u8(offset + 2)?;
+                let level = command_list.read_u8(offset + 3)?;
+                let src_addr = command_list.read_u32(offset + 4)?;
+                Some((offset + 8, Command::UploadTextureLevel { texture, level, src_addr }))
+            },
+            Some(0x0016) => {
+                let sprite = command_list.read_u8(offset + 2)?;
+                let texture = command_list.read_u8(offset + 3)?;
+                let x = command_list.read_u16(offset + 4)? as i16 as i32;
+                let y = command_list.read_u16(offset + 6)? as i16 as i32;
+                let src_x = command_list.read_u16(offset + 8)? as i32;
+                let src_y = command_list.read_u16(offset + 10)? as i32;
+                let width = command_list.read_u16(offset + 12)? as i32;
+                let height = command_list.read_u16(offset + 14)? as i32;
+                let flags = command_list.read_u8(offset + 16)?;
+                let priority = command_list.read_u8(offset + 17)?;
+                Some((offset + 20, Command::SetSprite { sprite, config: Sprite {
+                    enabled: flags & 1 != 0,
+                    texture,
+                    position: I32x2 { x, y },
+                    source: Rect {
+                        position: I32x2 { x: src_x, y: src_y },
+                        size: I32x2 { x: width, y: height },
+                    },
+                    flip_x: flags & 2 != 0,
+                    flip_y: flags & 4 != 0,
+                    cutout: flags & 8 != 0,
+                    priority,
+                } }))
+            },
+            Some(0x00_17) => {
+                let sprite = command_list.read_u8(offset + 2)?;
+                let x = command_list.read_u16(offset + 4)? as i16;
+                let y = command_list.read_u16(offset + 6)? as i16;
+                Some((offset + 8, Command::MoveSprite { sprite, x, y }))
+            },
+            Some(0x00_18) => {
+                let background = command_list.read_u8(offset + 2)?;
+                let flags = command_list.read_u8(offset + 3)?;
+                let atlas_texture = command_list.read_u8(offset + 4)?;
+                let map_buffer = command_list.read_u8(offset + 5)?;
+                let scanline_buffer = command_list.read_u8(offset + 6)?;
+                let tile_size = command_list.read_u8(offset + 7)?;
+                let map_width = command_list.read_u16(offset + 8)? as i32;
+                let map_height = command_list.read_u16(offset + 10)? as i32;
+                let priority = command_list.read_u8(offset + 12)?;
+                Some((offset + 16, Command::ConfigureBackground { background, config: Background {
+                    enabled: flags & 1 != 0,
+                    atlas_texture,
+                    map_buffer,
+                    tile_size,
+                    map_size: I32x2 { x: map_width, y: map_height },
+                    scroll: I32x2 { x: 0, y: 0 },
+                    wrap: flags & 2 != 0,
+                    scanline_buffer: if flags & 4 != 0 { Some(scanline_buffer) } else { None },
+                    priority,
+                } }))
+            },
+            Some(0x00_19) => {
+                let background = command_list.read_u8(offset + 2)?;
+                let x = command_list.read_u16(offset + 4)? as i16;
+                let y = command_list.read_u16(offset + 6)? as i16;
+                Some((offset + 8, Command::ScrollBackground { background, x, y }))
+            },
+            Some(0x00_1A) => {
+                /*
+                draw_graphics_pipeline_instanced <state_index> <fragment shader> <vertex shader>      ..      < vertex count> < x low > < x high> < y low > < y high> < instance count>
+                [                        1A 00 ] [        II ] [            FF ] [          VV ] [ 00 00 00 ] [ CC CC CC CC ] [ xx xx ] [ XX XX ] [ yy yy ] [ YY YY ] [  NN NN NN NN  ]
+                */
+                let state_index = command_list.read_u8(offset + 2)?;
+                let fragment_shader = command_list.read_u8(offset + 3)?;
+                let vertex_shader = command_list.read_u8(offset + 4)?;
+                let vertex_count = command_list.read_u32(offset + 8)?;
+                let x_low = command_list.read_u16(offset + 12)?;
+                let x_high = command_list.read_u16(offset + 14)?;
+                let y_low = command_list.read_u16(offset + 16)?;
+                let y_high = command_list.read_u16(offset + 18)?;
+                let instance_count = command_list.read_u32(offset + 20)?;
+                Some((offset + 24, Command::DrawGraphicsPipelineInstanced { state_index, fragment_shader, vertex_shader, vertex_count, x_low, x_high, y_low, y_high, instance_count }))
+            },
+            Some(0x00_1B) => {
+                /*
+                draw_indexed_graphics_pipeline <state_index> <fragment shader> <vertex shader> <index buffer> <index type>  ..  < index count > < index offset> < x low > < x high> < y low > < y high> < instance count>
+                [                      1B 00 ] [        II ] [            FF ] [          VV ] [         BB ] [       TT ] [ 00 ] [ CC CC CC CC ] [ OO OO OO OO ] [ xx xx ] [ XX XX ] [ yy yy ] [ YY YY ] [  NN NN NN NN  ]
+                */
+                let state_index = command_list.read_u8(offset + 2)?;
+                let fragment_shader = command_list.read_u8(offset + 3)?;
+                let vertex_shader = command_list.read_u8(offset + 4)?;
+                let index_buffer = command_list.read_u8(offset + 5)?;
+                let index_type = IndexType::from_u8(command_list.read_u8(offset + 6)?)?;
+                let index_count = command_list.read_u32(offset + 8)?;
+                let index_offset = command_list.read_u32(offset + 12)?;
+                let x_low = command_list.read_u16(offset + 16)?;
+                let x_high = command_list.read_u16(offset + 18)?;
+                let y_low = command_list.read_u16(offset + 20)?;
+                let y_high = command_list.read_u16(offset + 22)?;
+                let instance_count = command_list.read_u32(offset + 24)?;
+                Some((offset + 28, Command::DrawIndexedGraphicsPipeline { state_index, fragment_shader, vertex_shader, index_buffer, index_type, index_count, index_offset, x_low, x_high, y_low, y_high, instance_count }))
+            },
+            Some(0x00_1C) => {
+                let address = command_list.read_u32(offset + 4)?;
+                Some((offset + 8, Command::QueryVram { address }))
+            },
+            Some(0x00_1D) => {
+                let texture = command_list.read_u8(offset + 2)?;
+                let palette = command_list.read_u8(offset + 3)?;
+                Some((offset + 4, Command::SetTexturePalette { texture, palette }))
+            },
+            Some(0x00_1E) => {
+                let texture = command_list.read_u8(offset + 2)?;
+                let dst_image_layout = ImageDataLayout::from_u8(command_list.read_u8(offset + 3)?)?;
+                let x = command_list.read_u16(offset + 4)?;
+                let y = command_list.read_u16(offset + 6)?;
+                let width = command_list.read_u16(offset + 8)?;
+                let height = command_list.read_u16(offset + 10)?;
+                let dst_addr = command_list.read_u32(offset + 12)?;
+                let completion_addr = command_list.read_u32(offset + 16)?;
+                let interrupt = command_list.read_u8(offset + 20)? != 0;
+                Some((offset + 24, Command::DownloadTexture { texture, dst_image_layout, x, y, width, height, dst_addr, completion_addr, interrupt }))
+            },
+            Some(0x00_1F) => {
+                let source = TimestampSource::from_u8(command_list.read_u8(offset + 2)?)?;
+                let address = command_list.read_u32(offset + 4)?;
+                Some((offset + 8, Command::WriteTimestamp { source, address }))
+            },
+            Some(0x00_20) => Some((offset + 4, Command::BeginStatisticsQuery)),
+            Some(0x00_21) => {
+                let address = command_list.read_u32(offset + 4)?;
+                Some((offset + 8, Command::EndStatisticsQuery { address }))
+            },
+            Some(0x00_22) => {
+                let address = command_list.read_u32(offset + 4)?;
+                Some((offset + 8, Command::CallList { address }))
+            },
+            Some(0x00_23) => {
+                let address = command_list.read_u32(offset + 4)?;
+                Some((offset + 8, Command::JumpList { address }))
+            },
+            Some(0x00_24) => {
+                let compare_fn = DepthCompareFn::from_u8(command_list.read_u8(offset + 2)?)?;
+                let address = command_list.read_u32(offset + 4)?;
+                let reference = command_list.read_u32(offset + 8)?;
+                let mask = command_list.read_u32(offset + 12)?;
+                let skip_bytes = command_list.read_u32(offset + 16)?;
+                Some((offset + 20, Command::SkipIf { compare_fn, address, reference, mask, skip_bytes }))
+            },
+            Some(0x00_25) => {
+                let compare_fn = DepthCompareFn::from_u8(command_list.read_u8(offset + 2)?)?;
+                let address = command_list.read_u32(offset + 4)?;
+                let reference = command_list.read_u32(offset + 8)?;
+                let mask = command_list.read_u32(offset + 12)?;
+                Some((offset + 16, Command::WaitUntil { compare_fn, address, reference, mask }))
+            },
+            Some(0x00_26) => {
+                let fence = command_list.read_u8(offset + 2)?;
+                if fence as usize >= FENCE_COUNT {
+                    return None;
+                }
+                let value = command_list.read_u32(offset + 4)?;
+                Some((offset + 8, Command::SignalFence { fence, value }))
+            },
+            Some(0x00_27) => {
+                let fence = command_list.read_u8(offset + 2)?;
+                if fence as usize >= FENCE_COUNT {
+                    return None;
+                }
+                let value = command_list.read_u32(offset + 4)?;
+                Some((offset + 8, Command::WaitFence { fence, value }))
+            },
             _  => None,
         }
     }
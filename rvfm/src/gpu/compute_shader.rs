@@ -0,0 +1,155 @@
+use super::{buffer::{BufferAccess, BufferModule}, shader::*, texture::TextureModule};
+
+#[derive(Debug, Default)]
+pub(crate) struct ComputePipelineState {
+    pub constants: Vec<ShaderConstantAssignment>,
+    pub resource_map: ResourceMap,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ComputeShaderResult {
+    pub remaining_count: usize,
+    pub remaining_offset: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ComputeShaderError {
+    BadShader
+}
+
+pub const COMPUTE_SCALAR_INPUT_BUILTIN_INVOCATION_ID       : usize = 0x00;
+pub const COMPUTE_SCALAR_INPUT_BUILTIN_LOCAL_INVOCATION_ID : usize = 0x01;
+
+pub const COMPUTE_VECTOR_INPUT_BUILTIN_WORKGROUP_ID        : usize = 0x00;
+
+pub struct ComputeShaderCall<'a> {
+    pub shader            : u8,
+
+    pub invocation_count  : usize,
+    pub invocation_offset : usize,
+    pub workgroup_size    : usize,
+    pub workgroups_x      : usize,
+
+    pub shading_unit_context     : &'a mut ShadingUnitContext,
+    pub shading_unit_run_context : ShadingUnitRunContext<'a>,
+
+    pub buffer_modules  : &'a mut [BufferModule; 256],
+    pub texture_modules : &'a mut [TextureModule; 64],
+    pub shader_modules: &'a [ShaderModule],
+
+    pub resource_map: &'a ResourceMap,
+}
+
+pub fn run_compute_shader(mut call: ComputeShaderCall<'_>) -> Result<ComputeShaderResult, ComputeShaderError> {
+    let shader = &call.shader_modules[call.shader as usize];
+    if shader.shader_type != ShaderType::Compute {
+        return Err(ComputeShaderError::BadShader);
+    }
+
+    let invocation_count = call.invocation_count.min(CORE_COUNT);
+
+    for i in 0..invocation_count {
+        let builtin_invocation_id = i + call.invocation_offset;
+        let workgroup = builtin_invocation_id / call.workgroup_size;
+        call.shading_unit_run_context.scalar_input_array[COMPUTE_SCALAR_INPUT_BUILTIN_INVOCATION_ID      ][i] = builtin_invocation_id as u32;
+        call.shading_unit_run_context.scalar_input_array[COMPUTE_SCALAR_INPUT_BUILTIN_LOCAL_INVOCATION_ID][i] = (builtin_invocation_id % call.workgroup_size) as u32;
+        call.shading_unit_run_context.vector_input_array[COMPUTE_VECTOR_INPUT_BUILTIN_WORKGROUP_ID       ][i] = [
+            (workgroup % call.workgroups_x) as u32,
+            (workgroup / call.workgroups_x) as u32,
+            0,
+            0
+        ];
+    }
+
+    let instructions = &shader.instruction_buffer[0..shader.instruction_count];
+    call.shading_unit_context.run_shader(invocation_count, instructions, &mut call.shading_unit_run_context, &mut BufferAccess::Exclusive(call.buffer_modules), call.texture_modules, call.resource_map);
+
+    Ok(ComputeShaderResult {
+        remaining_count  : call.invocation_count  - invocation_count,
+        remaining_offset : call.invocation_offset + invocation_count,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::marker::PhantomData;
+
+    use super::*;
+
+    const INVOCATIONS: usize = CORE_COUNT + 904;
+    const RECORDS: usize = 0x5000;
+
+    fn scalar(address: fn(u8, PhantomData<Scalar>) -> RegisterAddress<Scalar>, index: u8) -> RegisterAddress<Scalar> {
+        address(index, PhantomData)
+    }
+
+    /*
+    every invocation stores its id at id * 4 in buffer slot 1, then copies the
+    u16s at the start of buffer slot 2 into a u32 record at RECORDS + id * 16.
+    the slots are mapped to other buffer modules to go through the resource map.
+     */
+    fn invocation_id_shader() -> ShaderModule {
+        use RegisterAddress::{Constant, Input, Local};
+        let instructions = [
+            ShaderInstruction::ScalarBinaryOp { src_a: scalar(Input, 0), src_b: scalar(Constant, 0), dst: scalar(Local, 0), op: ScalarBinaryOp::Multiply(OpDataType::I32) },
+            ShaderInstruction::ScalarBinaryOp { src_a: scalar(Input, 0), src_b: scalar(Constant, 1), dst: scalar(Local, 1), op: ScalarBinaryOp::Multiply(OpDataType::I32) },
+            ShaderInstruction::WriteScalarToBuffer { data_type: BufferDataType::U32, scalar: scalar(Input, 0), offset: 0, addr_dst_u32: Some(scalar(Local, 0)), buffer: 1 },
+            ShaderInstruction::ReadBufferToVector { data_type: VectorBufferDataType::V3(BufferDataType::U16), vector: Local(0, PhantomData), offset: 0, addr_src_u32: None, buffer: 2 },
+            ShaderInstruction::WriteVectorToBuffer { data_type: VectorBufferDataType::V3(BufferDataType::U32), src: Local(0, PhantomData), offset: RECORDS as u32, addr_src_u32: Some(scalar(Local, 1)), buffer: 1 },
+            ShaderInstruction::ReadBufferToScalar { data_type: BufferDataType::U16, scalar: scalar(Local, 2), offset: 6, addr_src_u32: None, buffer: 2 },
+            ShaderInstruction::WriteScalarToBuffer { data_type: BufferDataType::U32, scalar: scalar(Local, 2), offset: RECORDS as u32 + 12, addr_dst_u32: Some(scalar(Local, 1)), buffer: 1 },
+        ];
+        let mut module = ShaderModule { shader_type: ShaderType::Compute, ..ShaderModule::default() };
+        module.instruction_buffer[..instructions.len()].copy_from_slice(&instructions);
+        module.instruction_count = instructions.len();
+        module
+    }
+
+    #[test]
+    fn dispatch_writes_invocation_ids() {
+        let mut buffer_modules = Box::new([(); 256].map(|_| BufferModule::new()));
+        let mut texture_modules = Box::new([(); 64].map(|_| TextureModule::new()));
+        let shader_modules = [invocation_id_shader()];
+        buffer_modules[5].length = super::super::buffer::BUFFER_MAX_SIZE;
+        buffer_modules[7].memory[..8].copy_from_slice(&[0x34, 0x12, 0x78, 0x56, 0xBC, 0x9A, 0xF0, 0xDE]);
+        buffer_modules[7].length = 8;
+        let mut resource_map = ResourceMap::default();
+        resource_map.buffer[1] = 5;
+        resource_map.buffer[2] = 7;
+
+        let mut constant_array = Box::new(ShadingUnitConstantArray::new());
+        constant_array.scalar_constant_array[0] = 4;
+        constant_array.scalar_constant_array[1] = 16;
+        let mut io_arrays = ShadingUnitIOArrays::new();
+        let mut shading_unit_context = ShadingUnitContext::new();
+        let (mut invocation_count, mut invocation_offset, mut batches) = (INVOCATIONS, 0, 0);
+        while invocation_count > 0 {
+            let [input_array, output_array, _] = &mut io_arrays.0;
+            let result = run_compute_shader(ComputeShaderCall {
+                shader: 0,
+                invocation_count,
+                invocation_offset,
+                workgroup_size: 64,
+                workgroups_x: INVOCATIONS.div_ceil(64),
+                shading_unit_context: &mut shading_unit_context,
+                shading_unit_run_context: ShadingUnitRunContext::new(&mut constant_array, input_array, output_array),
+                buffer_modules: &mut buffer_modules,
+                texture_modules: &mut texture_modules,
+                shader_modules: &shader_modules,
+                resource_map: &resource_map,
+            }).unwrap();
+            invocation_count = result.remaining_count;
+            invocation_offset = result.remaining_offset;
+            batches += 1;
+        }
+        assert_eq!((invocation_offset, batches), (INVOCATIONS, 2));
+
+        let words: &[u32] = bytemuck::cast_slice(&buffer_modules[5].memory[..]);
+        for id in 0..INVOCATIONS {
+            assert_eq!(words[id], id as u32);
+            let record = RECORDS / 4 + id * 4;
+            assert_eq!(words[record..record + 4], [0x1234, 0x5678, 0x9ABC, 0xDEF0], "record of invocation {}", id);
+        }
+        assert!(buffer_modules[1].memory.iter().chain(&buffer_modules[2].memory[..]).all(|&x| x == 0));
+    }
+}
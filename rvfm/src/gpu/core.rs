@@ -1,6 +1,6 @@
 
-use std::collections::VecDeque;
 use std::sync::Arc;
+use std::time::Instant;
 
 use bytemuck::{Pod, cast_slice, cast_slice_mut};
 
@@ -8,70 +8,195 @@ use crate::gpu::shader_parser::parse_shader_bytecode;
 use crate::interrupt_controller::{INTERRUPT_CONTROLLER, InterruptType};
 use crate::machine::{Machine, ReadResult};
 use crate::ui::main_window::MainWindow;
-use crate::command_list::{CommandList, retire_commandlist};
+use crate::command_list::{CommandList, parse_commandlist_header, retire_commandlist};
 
 use super::command::Command;
 use super::pipeline_state::GraphicsPipelineState;
-use super::shader::{ShaderModule, ShaderType, ShadingUnitConstantArray, ShadingUnitContext, ShadingUnitIOArrays};
-use super::rasterizer::{run_rasterizer, RasterRect, RasterizerCall};
+use super::compute_shader::{run_compute_shader, ComputePipelineState, ComputeShaderCall};
+use super::shader::{setup_shader_constants, ShaderModule, ShaderType, ShadingUnitConstantArray, ShadingUnitContext, ShadingUnitIOArrays, ShadingUnitRunContext, SAMPLER_COUNT};
+use super::fragment_shader::DepthCompareFn;
+use super::rasterizer::{run_rasterizer, IndexBuffer, RasterRect, RasterWorker, RasterizerCall};
 use super::texture::*;
 use super::buffer::*;
-use super::types::{ConstantSampler, VideoMode, PixelDataLayout, ImageDataLayout, PixelDataType, ColorBlendOp, AlphaBlendOp};
+use super::sprite::{SpriteTable, SPRITE_COUNT};
+use super::background::{BackgroundTable, BACKGROUND_COUNT, LAYER_PRIORITY_COUNT};
+use super::vram::{VramError, VramPool};
+use super::statistics::{GpuStatistics, TimestampSource};
+use super::queue::{CommandQueue, FENCE_COUNT, QUEUE_COUNT, QUEUE_GRAPHICS, QUEUE_PRIORITY};
+use super::types::{Background, Sprite, I32x2, ConstantSampler, VideoMode, VideoResolution, PixelDataLayout, ImageDataLayout, PixelDataType, ColorBlendOp, AlphaBlendOp, TextureSampler, TexelComponentType};
+
+const MAX_CALL_DEPTH: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum TexcopyDirection {
+    Upload,
+    Download,
+}
 
 pub struct Core {
-    command_lists:      VecDeque<CommandList>,
+    command_queues:     [CommandQueue; QUEUE_COUNT],
+    fences:             [u32; FENCE_COUNT],
     video_mode:         VideoMode,
     constant_samplers:  [ConstantSampler;       64],
     textures:           [TextureModule;         64],
+    vram:               VramPool,
     buffers:            [BufferModule;         256],
     shaders:            [ShaderModule;         128],
     graphics_states:    [GraphicsPipelineState; 64],
+    compute_states:     [ComputePipelineState;  64],
     shader_context:     Box<ShadingUnitContext>,
     shader_constants:   ShadingUnitConstantArray,
     shader_io_arrays:   Box<ShadingUnitIOArrays>,
-
+    raster_workers:     Vec<RasterWorker>,
+    sprites:            SpriteTable,
+    backgrounds:        BackgroundTable,
+    present_buffer:     Box<[[u8; 4]]>,
+    // the queue run_queue is executing, statistics are counted against it
+    current_queue:      usize,
+    epoch:              Instant,
 }
 
 impl Core {
-    pub fn new() -> Self {
+    pub fn new(vram_size: usize, raster_threads: usize) -> Self {
         Self {
-            command_lists: VecDeque::new(),
-            video_mode: VideoMode { resolution: super::types::VideoResolution::V256x192, backgrounds: false, sprites: false, triangles: false },
+            command_queues: [(); QUEUE_COUNT].map(|_| CommandQueue::default()),
+            fences: [0; FENCE_COUNT],
+            video_mode: VideoMode { resolution: VideoResolution::V256x192, backgrounds: false, sprites: false, triangles: false },
             constant_samplers: [(); 64].map(|_| ConstantSampler::new()),
             textures: [(); 64].map(|_| TextureModule::new()),
+            vram: VramPool::new(vram_size),
             buffers: [(); 256].map(|_| BufferModule::new()),
             shaders: [(); 128].map(|_| ShaderModule::default()),
             graphics_states: [(); 64].map(|_| GraphicsPipelineState::default()),
+            compute_states: [(); 64].map(|_| ComputePipelineState::default()),
             shader_context: ShadingUnitContext::new(),
             shader_constants: ShadingUnitConstantArray::new(),
             shader_io_arrays: ShadingUnitIOArrays::new(),
+            raster_workers: RasterWorker::pool(raster_threads),
+            sprites: SpriteTable::new(),
+            backgrounds: BackgroundTable::new(),
+            present_buffer: vec![[0u8; 4]; VideoResolution::V512x384.pixel_count()].into_boxed_slice(),
+            current_queue: QUEUE_GRAPHICS,
+            epoch: Instant::now(),
         }
     }
 
-    pub fn add_command_list(&mut self, list: CommandList) {
-        self.command_lists.push_back(list);
+    pub fn add_command_list(&mut self, queue: usize, list: CommandList) {
+        self.command_queues[queue].pending.push_back(list);
     }
 
-    pub fn process(&mut self, machine: &Arc<Machine>, main_window: &MainWindow) {
-        while let Some(command_list) = self.command_lists.pop_front() {
-            self.execute_command_list(command_list, machine, main_window);
-        }
+    pub fn is_idle(&self) -> bool {
+        self.command_queues.iter().all(CommandQueue::is_empty)
     }
 
-    fn execute_command_list(&mut self, command_list: CommandList, machine: &Arc<Machine>, main_window: &MainWindow) {
-        let mut offset = 0;
-        while (offset as usize) < command_list.len() {
-            if let Some((new_offset, command)) = Command::read(&command_list, offset) {
-                self.execute_command(command, machine, main_window);
-                offset = new_offset;
+    // runs one command list from the highest priority queue that can make progress.
+    // returns false when every queue is empty or blocked.
+    pub fn process(&mut self, machine: &Arc<Machine>, main_window: &MainWindow) -> bool {
+        QUEUE_PRIORITY.into_iter().any(|queue| self.run_queue(queue, machine, main_window))
+    }
+
+    // runs until the current list ends, jumps or blocks, returning whether anything ran
+    fn run_queue(&mut self, queue: usize, machine: &Arc<Machine>, main_window: &MainWindow) -> bool {
+        let Some(mut cursor) = self.command_queues[queue].next_list() else {
+            return false;
+        };
+        self.current_queue = queue;
+        let mut progress = false;
+        loop {
+            if (cursor.offset as usize) >= cursor.list.len() {
+                match cursor.call_stack.pop() {
+                    Some((caller, return_offset)) => {
+                        retire_commandlist(std::mem::replace(&mut cursor.list, caller));
+                        cursor.offset = return_offset;
+                        continue;
+                    },
+                    None => {
+                        retire_commandlist(cursor.list);
+                        return true;
+                    },
+                }
+            }
+            if let Some((new_offset, command)) = Command::read(&cursor.list, cursor.offset) {
+                // blocked waits are read again the next time the queue is scheduled
+                let blocked = match command {
+                    Command::WaitFence { fence, value } => self.fences[fence as usize] < value,
+                    Command::WaitUntil { compare_fn, address, reference, mask } =>
+                        machine.read_u32(address).to_opt().is_some() && !self.test_memory(compare_fn, address, reference, mask, machine),
+                    _ => false,
+                };
+                if blocked {
+                    self.command_queues[queue].active = Some(cursor);
+                    return progress;
+                }
+                progress = true;
+                cursor.offset = new_offset;
+                match command {
+                    Command::CallList { address } => {
+                        println!("GPU: call_list(address: {:08X})", address);
+                        if cursor.call_stack.len() >= MAX_CALL_DEPTH {
+                            println!("GPU: call_list ERROR: calls nested too deep!");
+                            continue;
+                        }
+                        match parse_commandlist_header(address, machine) {
+                            Ok(callee) => {
+                                let caller = std::mem::replace(&mut cursor.list, callee);
+                                cursor.call_stack.push((caller, cursor.offset));
+                                cursor.offset = 0;
+                            },
+                            Err(error) => println!("GPU: call_list ERROR: {:?}", error),
+                        }
+                    },
+                    Command::JumpList { address } => {
+                        println!("GPU: jump_list(address: {:08X})", address);
+                        match parse_commandlist_header(address, machine) {
+                            Ok(target) => {
+                                retire_commandlist(std::mem::replace(&mut cursor.list, target));
+                                cursor.offset = 0;
+                                // hand the queue back so lists jumping in a cycle can't hold up other queues
+                                self.command_queues[queue].active = Some(cursor);
+                                return true;
+                            },
+                            Err(error) => println!("GPU: jump_list ERROR: {:?}", error),
+                        }
+                    },
+                    Command::SkipIf { compare_fn, address, reference, mask, skip_bytes } => {
+                        if self.test_memory(compare_fn, address, reference, mask, machine) {
+                            cursor.offset = cursor.offset.saturating_add(skip_bytes);
+                        }
+                    },
+                    command => self.execute_command(command, machine, main_window),
+                }
             } else {
                 println!("command parse failed!");
                 // todo: Set an error when we find an invalid command
                 // for now, just skip the rest of the offending command list
-                break;
+                cursor.offset = cursor.list.len() as u32;
             }
         }
-        retire_commandlist(command_list);
+    }
+
+    fn test_memory(&self, compare_fn: DepthCompareFn, address: u32, reference: u32, mask: u32, machine: &Arc<Machine>) -> bool {
+        std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel);
+        match machine.read_u32(address).to_opt() {
+            Some(value) => compare_fn.compare(value & mask, reference),
+            None => {
+                println!("GPU: test_memory ERROR: bad address!");
+                false
+            },
+        }
+    }
+
+    // run_queue holds the queue back until the predicate passes
+    fn wait_until(&mut self, compare_fn: DepthCompareFn, address: u32, reference: u32, mask: u32, machine: &Arc<Machine>) {
+        println!("GPU: wait_until(compare_fn: {:?}, address: {:08X}, reference: {:08X}, mask: {:08X})", compare_fn, address, reference, mask);
+        if machine.read_u32(address).to_opt().is_none() {
+            println!("GPU: wait_until ERROR: bad address!");
+        }
+    }
+
+    fn signal_fence(&mut self, fence: u8, value: u32) {
+        println!("GPU: signal_fence(fence: {}, value: {})", fence, value);
+        self.fences[fence as usize] = value;
     }
 
     fn execute_command(&mut self, command: Command, machine: &Arc<Machine>, main_window: &MainWindow) {
@@ -86,8 +211,8 @@ impl Core {
                 self.set_video_mode(mode, main_window),
             Command::WriteFlag { address, value, irq } => 
                 self.write_flag(machine, address, value, irq),
-            Command::ConfigureTexture { texture, pixel_layout, image_layout, width, height } => 
-                self.configure_texture(texture, pixel_layout, image_layout, width, height),
+            Command::ConfigureTexture { texture, pixel_layout, image_layout, width, height, palette } => 
+                self.configure_texture(texture, pixel_layout, image_layout, width, height, palette),
             Command::UploadTexture { texture, src_image_layout, src_addr } => 
                 self.upload_texture(texture, src_image_layout, src_addr, machine),
             Command::ConfigureBuffer { buffer, length } => 
@@ -111,10 +236,67 @@ impl Core {
                     upper_left:  (x_low  as u32, y_low  as u32),
                     lower_right: (x_high as u32, y_high as u32),
                 };
-                self.draw_graphics_pipeline(state_index, vertex_shader, fragment_shader, vertex_count, target_rect);
+                self.draw_graphics_pipeline(state_index, vertex_shader, fragment_shader, vertex_count, 1, None, target_rect);
+            },
+            Command::DrawGraphicsPipelineInstanced { state_index, vertex_shader, fragment_shader, vertex_count, x_low, x_high, y_low, y_high, instance_count } => {
+                let target_rect = RasterRect {
+                    upper_left:  (x_low  as u32, y_low  as u32),
+                    lower_right: (x_high as u32, y_high as u32),
+                };
+                self.draw_graphics_pipeline(state_index, vertex_shader, fragment_shader, vertex_count, instance_count, None, target_rect);
+            },
+            Command::DrawIndexedGraphicsPipeline { state_index, vertex_shader, fragment_shader, index_buffer, index_type, index_count, index_offset, x_low, x_high, y_low, y_high, instance_count } => {
+                let target_rect = RasterRect {
+                    upper_left:  (x_low  as u32, y_low  as u32),
+                    lower_right: (x_high as u32, y_high as u32),
+                };
+                let index_buffer = IndexBuffer {
+                    buffer: index_buffer,
+                    offset: index_offset,
+                    t: index_type,
+                };
+                self.draw_graphics_pipeline(state_index, vertex_shader, fragment_shader, index_count, instance_count, Some(index_buffer), target_rect);
             },
             Command::WriteBuffer { buffer, src_addr, length, offset } => 
                 self.write_buffer(buffer, src_addr, length, offset, machine),
+            Command::UploadComputePipelineState { index, address } =>
+                self.upload_compute_pipeline_state(index, address, machine),
+            Command::DispatchCompute { state_index, shader, workgroup_size, workgroups_x, workgroups_y } =>
+                self.dispatch_compute(state_index, shader, workgroup_size, workgroups_x, workgroups_y),
+            Command::ConfigureSampler { sampler, config } =>
+                self.configure_sampler(sampler, config),
+            Command::GenerateMipmaps { texture, component_type } =>
+                self.generate_mipmaps(texture, component_type),
+            Command::UploadTextureLevel { texture, level, src_addr } =>
+                self.upload_texture_level(texture, level, src_addr, machine),
+            Command::SetSprite { sprite, config } =>
+                self.set_sprite(sprite, config),
+            Command::MoveSprite { sprite, x, y } =>
+                self.move_sprite(sprite, x, y),
+            Command::ConfigureBackground { background, config } =>
+                self.configure_background(background, config),
+            Command::ScrollBackground { background, x, y } =>
+                self.scroll_background(background, x, y),
+            Command::QueryVram { address } =>
+                self.query_vram(address, machine),
+            Command::SetTexturePalette { texture, palette } =>
+                self.set_texture_palette(texture, palette),
+            Command::DownloadTexture { texture, dst_image_layout, x, y, width, height, dst_addr, completion_addr, interrupt } =>
+                self.download_texture(texture, dst_image_layout, x, y, width, height, dst_addr, completion_addr, interrupt, machine),
+            Command::WriteTimestamp { source, address } =>
+                self.write_timestamp(source, address, machine),
+            Command::BeginStatisticsQuery =>
+                self.begin_statistics_query(),
+            Command::EndStatisticsQuery { address } =>
+                self.end_statistics_query(address, machine),
+            Command::WaitUntil { compare_fn, address, reference, mask } =>
+                self.wait_until(compare_fn, address, reference, mask, machine),
+            Command::SignalFence { fence, value } =>
+                self.signal_fence(fence, value),
+            Command::WaitFence { fence, value } =>
+                println!("GPU: wait_fence(fence: {}, value: {})", fence, value),
+            // list control flow is handled by run_queue
+            Command::CallList { .. } | Command::JumpList { .. } | Command::SkipIf { .. } => {},
         }
     }
 
@@ -133,13 +315,42 @@ impl Core {
         self.constant_samplers[sampler as usize] = value;
     }
 
-    fn configure_texture(&mut self, texture: u8, pixel_layout: PixelDataLayout, image_layout: ImageDataLayout, width: u32, height: u32) {
-        println!("GPU: configure_texture({}, pixel_layout: {:?}, image_layout: {:?}, width: {}, height: {})", texture, pixel_layout, image_layout, width, height);
+    fn configure_texture(&mut self, texture: u8, pixel_layout: PixelDataLayout, image_layout: ImageDataLayout, width: u32, height: u32, palette: u8) {
+        println!("GPU: configure_texture({}, pixel_layout: {:?}, image_layout: {:?}, width: {}, height: {}, palette: {})", texture, pixel_layout, image_layout, width, height, palette);
+        if texture >= 64 {
+            println!("GPU: configure_texture ERROR: texture out of range!");
+            return;
+        }
+        if width > TEXTURE_MAX_SIZE || height > TEXTURE_MAX_SIZE {
+            println!("GPU: configure_texture ERROR: {}x{} is larger than the maximum texture size of {}x{}!", width, height, TEXTURE_MAX_SIZE, TEXTURE_MAX_SIZE);
+            return;
+        }
         let texture_regs = &mut self.textures[texture as usize];
+        self.vram.release(&std::mem::take(&mut texture_regs.memory));
         texture_regs.config.width = width as u16;
         texture_regs.config.height = height as u16;
         texture_regs.config.image_layout = image_layout;
         texture_regs.config.pixel_layout = pixel_layout;
+        texture_regs.mip_levels = 1;
+        texture_regs.palette = palette;
+        match self.vram.allocate(texture_regs.allocation_bytes()) {
+            Ok(memory) => texture_regs.memory = memory,
+            Err(VramError::OutOfMemory { requested, free }) => {
+                // the old storage is already gone, so leave the texture empty rather than half configured
+                println!("GPU: configure_texture ERROR: out of vram for texture {}, needs {} bytes but only {} are free!", texture, requested, free);
+                texture_regs.config.width = 0;
+                texture_regs.config.height = 0;
+            },
+        }
+    }
+
+    fn set_texture_palette(&mut self, texture: u8, palette: u8) {
+        println!("GPU: set_texture_palette({}, palette: {})", texture, palette);
+        if texture >= 64 {
+            println!("GPU: set_texture_palette ERROR: texture out of range!");
+            return;
+        }
+        self.textures[texture as usize].palette = palette;
     }
 
     fn present_texture(&mut self, texture: u8, completion_addr: u32, interrupt: bool, machine: &Arc<Machine>, main_window: &MainWindow) {
@@ -149,7 +360,94 @@ impl Core {
             return;
         }
         let texture = &self.textures[texture as usize];
-        main_window.present_texture(texture.memory.as_ptr() as *const u8, completion_addr, interrupt, machine.clone())
+        let (width, height) = self.video_mode.resolution.as_w_h();
+        let pixel_count = self.video_mode.resolution.pixel_count();
+        let indexed = texture.config.pixel_layout.is_indexed();
+        // always present from present_buffer, texture storage can be freed by configure_texture before the ui reads it
+        if indexed {
+            let palette = self.buffers[texture.palette as usize].bytes();
+            for (i, pixel) in self.present_buffer[..pixel_count].iter_mut().enumerate() {
+                *pixel = palette_color(palette, texture.fetch_index(i as u32 % width, i as u32 / width));
+            }
+        } else {
+            let texels = cast_slice::<u32, [u8; 4]>(&texture.memory[..]);
+            if texels.len() < pixel_count {
+                println!("GPU: present_texture ERROR: texture memory is smaller than the video resolution!");
+            }
+            let copy_count = texels.len().min(pixel_count);
+            self.present_buffer[..copy_count].copy_from_slice(&texels[..copy_count]);
+            self.present_buffer[copy_count..pixel_count].fill([0; 4]);
+        }
+        for priority in (0..LAYER_PRIORITY_COUNT).rev() {
+            if self.video_mode.backgrounds {
+                self.backgrounds.composite(priority, &self.textures, &self.buffers, &mut self.present_buffer[..pixel_count], width, height);
+            }
+            if self.video_mode.sprites {
+                self.sprites.composite(priority, &self.textures, &self.buffers, &mut self.present_buffer[..pixel_count], width, height);
+            }
+        }
+        main_window.present_texture(self.present_buffer.as_ptr() as *const u8, completion_addr, interrupt, machine.clone())
+    }
+
+    fn set_sprite(&mut self, sprite: u8, config: Sprite) {
+        println!("GPU: set_sprite({}, {:?})", sprite, config);
+        if sprite as usize >= SPRITE_COUNT {
+            println!("GPU: set_sprite ERROR: sprite out of range!");
+            return;
+        }
+        if config.texture >= 64 {
+            println!("GPU: set_sprite ERROR: texture out of range!");
+            return;
+        }
+        if config.priority >= LAYER_PRIORITY_COUNT {
+            println!("GPU: set_sprite ERROR: priority out of range!");
+            return;
+        }
+        if config.enabled && rgba_unorm8_fetch_fn(self.textures[config.texture as usize].config.pixel_layout).is_none() {
+            println!("GPU: set_sprite ERROR: unsupported texture pixel layout {:?}", self.textures[config.texture as usize].config.pixel_layout);
+            return;
+        }
+        self.sprites.sprites[sprite as usize] = config;
+    }
+
+    fn move_sprite(&mut self, sprite: u8, x: i16, y: i16) {
+        println!("GPU: move_sprite({}, x: {}, y: {})", sprite, x, y);
+        if sprite as usize >= SPRITE_COUNT {
+            println!("GPU: move_sprite ERROR: sprite out of range!");
+            return;
+        }
+        self.sprites.sprites[sprite as usize].position = I32x2 { x: x as i32, y: y as i32 };
+    }
+
+    fn configure_background(&mut self, background: u8, config: Background) {
+        println!("GPU: configure_background({}, {:?})", background, config);
+        if background as usize >= BACKGROUND_COUNT {
+            println!("GPU: configure_background ERROR: background out of range!");
+            return;
+        }
+        if config.atlas_texture >= 64 {
+            println!("GPU: configure_background ERROR: atlas texture out of range!");
+            return;
+        }
+        if config.priority >= LAYER_PRIORITY_COUNT {
+            println!("GPU: configure_background ERROR: priority out of range!");
+            return;
+        }
+        if config.enabled && rgba_unorm8_fetch_fn(self.textures[config.atlas_texture as usize].config.pixel_layout).is_none() {
+            println!("GPU: configure_background ERROR: unsupported atlas pixel layout {:?}", self.textures[config.atlas_texture as usize].config.pixel_layout);
+            return;
+        }
+        let scroll = self.backgrounds.backgrounds[background as usize].scroll;
+        self.backgrounds.backgrounds[background as usize] = Background { scroll, ..config };
+    }
+
+    fn scroll_background(&mut self, background: u8, x: i16, y: i16) {
+        println!("GPU: scroll_background({}, x: {}, y: {})", background, x, y);
+        if background as usize >= BACKGROUND_COUNT {
+            println!("GPU: scroll_background ERROR: background out of range!");
+            return;
+        }
+        self.backgrounds.backgrounds[background as usize].scroll = I32x2 { x: x as i32, y: y as i32 };
     }
 
     fn clear_texture(&mut self, texture: u8, constant_sampler: u8) {
@@ -178,6 +476,44 @@ impl Core {
         }
     }
 
+    fn query_vram(&mut self, address: u32, machine: &Arc<Machine>) {
+        println!("GPU: query_vram(address: {:08X})", address);
+        let info = [self.vram.size() as u32, self.vram.free() as u32, self.vram.allocation_failures];
+        for (i, value) in info.into_iter().enumerate() {
+            if !machine.write_u32(address + i as u32 * 4, value).is_ok() {
+                println!("GPU: query_vram ERROR: bad address!");
+                return;
+            }
+        }
+        std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel);
+    }
+
+    fn write_timestamp(&mut self, source: TimestampSource, address: u32, machine: &Arc<Machine>) {
+        println!("GPU: write_timestamp(source: {:?}, address: {:08X})", source, address);
+        let timestamp = source.read(self.epoch);
+        if !machine.write_u32(address, timestamp as u32).is_ok() || !machine.write_u32(address + 4, (timestamp >> 32) as u32).is_ok() {
+            println!("GPU: write_timestamp ERROR: bad address!");
+        }
+        std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel);
+    }
+
+    fn begin_statistics_query(&mut self) {
+        println!("GPU: begin_statistics_query()");
+        self.command_queues[self.current_queue].statistics = GpuStatistics::default();
+    }
+
+    fn end_statistics_query(&mut self, address: u32, machine: &Arc<Machine>) {
+        let statistics = self.command_queues[self.current_queue].statistics;
+        println!("GPU: end_statistics_query(address: {:08X}) - {:?}", address, statistics);
+        for (i, value) in statistics.words().into_iter().enumerate() {
+            if !machine.write_u32(address + i as u32 * 4, value).is_ok() {
+                println!("GPU: end_statistics_query ERROR: bad address!");
+                return;
+            }
+        }
+        std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel);
+    }
+
     fn upload_texture(&mut self, texture: u8, src_image_layout: ImageDataLayout, src_addr: u32, machine: &Arc<Machine>) {
         println!("GPU: upload_texture(texture: {texture}, src_image_layout: {:?}, src_addr: {:08X})", src_image_layout, src_addr);
         if texture >= 64 {
@@ -188,26 +524,91 @@ impl Core {
         if src_image_layout == texture.config.image_layout {
             let _ = machine.read_block(src_addr, texture.data_slice_mut());
         } else {
-            match texture.config.pixel_layout.pixel_bytes() {
-                1 => Self::texcopy_cross_layout_internal::<1>(texture, src_image_layout, src_addr, machine),
-                2 => Self::texcopy_cross_layout_internal::<2>(texture, src_image_layout, src_addr, machine),
-                4 => Self::texcopy_cross_layout_internal::<4>(texture, src_image_layout, src_addr, machine),
-                8 => Self::texcopy_cross_layout_internal::<8>(texture, src_image_layout, src_addr, machine),
-                16 => Self::texcopy_cross_layout_internal::<16>(texture, src_image_layout, src_addr, machine),
-                _ => unreachable!()
-            }
+            let (width, height) = (texture.config.width as u32, texture.config.height as u32);
+            let mut image = vec![0u8; texture.config.pixel_layout.image_bytes(width, height)];
+            let _ = machine.read_block(src_addr, &mut image);
+            Self::texcopy_cross_layout(texture, src_image_layout, &mut image, 0, 0, width, height, TexcopyDirection::Upload);
+        }
+    }
+
+    fn download_texture(&mut self, texture: u8, dst_image_layout: ImageDataLayout, x: u16, y: u16, width: u16, height: u16, dst_addr: u32, completion_addr: u32, interrupt: bool, machine: &Arc<Machine>) {
+        println!("GPU: download_texture(texture: {texture}, dst_image_layout: {:?}, x: {x}, y: {y}, width: {width}, height: {height}, dst_addr: {:08X}, completion: {:08X}, interrupt: {:?})", dst_image_layout, dst_addr, completion_addr, interrupt);
+        if texture >= 64 {
+            println!("GPU: download_texture ERROR: texture out of range!");
+            return;
+        }
+        let texture = &mut self.textures[texture as usize];
+        let (x, y) = (x as u32, y as u32);
+        let width = (width as u32).min((texture.config.width as u32).saturating_sub(x));
+        let height = (height as u32).min((texture.config.height as u32).saturating_sub(y));
+        let whole_texture = x == 0 && y == 0 && width == texture.config.width as u32 && height == texture.config.height as u32;
+        let result = if whole_texture && dst_image_layout == texture.config.image_layout {
+            machine.write_block(dst_addr, texture.data_slice())
+        } else {
+            let mut image = vec![0u8; texture.config.pixel_layout.image_bytes(width, height)];
+            Self::texcopy_cross_layout(texture, dst_image_layout, &mut image, x, y, width, height, TexcopyDirection::Download);
+            machine.write_block(dst_addr, &image)
+        };
+        if !result.is_ok() {
+            println!("GPU: download_texture ERROR: bad destination address!");
+        }
+        std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel);
+        if !machine.write_u32(completion_addr, 1).is_ok() {
+            println!("GPU: download_texture ERROR: bad completion address!");
+        }
+        std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel);
+        if interrupt {
+            INTERRUPT_CONTROLLER.trigger_interrupt(InterruptType::Gpu);
+        }
+    }
+
+    /*
+    copies a width x height region at (x, y) of the texture to or from image, which holds the region
+    alone in image_layout. the texture keeps its own image layout.
+     */
+    fn texcopy_cross_layout(texture: &mut TextureModule, image_layout: ImageDataLayout, image: &mut [u8], x: u32, y: u32, width: u32, height: u32, direction: TexcopyDirection) {
+        if texture.config.pixel_layout == PixelDataLayout::I4 {
+            Self::texcopy_cross_layout_i4(texture, image_layout, image, x, y, width, height, direction);
+            return;
+        }
+        match texture.config.pixel_layout.pixel_bytes() {
+            1 => Self::texcopy_cross_layout_internal::<1>(texture, image_layout, image, x, y, width, height, direction),
+            2 => Self::texcopy_cross_layout_internal::<2>(texture, image_layout, image, x, y, width, height, direction),
+            4 => Self::texcopy_cross_layout_internal::<4>(texture, image_layout, image, x, y, width, height, direction),
+            8 => Self::texcopy_cross_layout_internal::<8>(texture, image_layout, image, x, y, width, height, direction),
+            16 => Self::texcopy_cross_layout_internal::<16>(texture, image_layout, image, x, y, width, height, direction),
+            _ => unreachable!()
         }
     }
 
-    fn texcopy_cross_layout_internal<const N: usize>(texture: &mut TextureModule, src_image_layout: ImageDataLayout, src_addr: u32, machine: &Arc<Machine>)
+    fn texcopy_cross_layout_internal<const N: usize>(texture: &mut TextureModule, image_layout: ImageDataLayout, image: &mut [u8], x: u32, y: u32, width: u32, height: u32, direction: TexcopyDirection)
         where [u8; N]: Pod {
-        let mut pixel_val = [0u8; N];
-        for y in 0..texture.config.height as u32 {
-            for x in 0..texture.config.width as u32 {
-                let src_index = src_image_layout.index(x, y, texture.config.width as u32);
-                let src_offset = src_index * N as u32;
-                machine.read_block(src_addr + src_offset, &mut pixel_val[0..N]);
-                texture.store(x, y, pixel_val);
+        for image_y in 0..height {
+            for image_x in 0..width {
+                let offset = image_layout.index(image_x, image_y, width) as usize * N;
+                let Some(pixel) = image.get_mut(offset..offset + N) else {
+                    continue;
+                };
+                match direction {
+                    TexcopyDirection::Upload => texture.store::<[u8; N]>(x + image_x, y + image_y, cast_slice::<u8, [u8; N]>(pixel)[0]),
+                    TexcopyDirection::Download => pixel.copy_from_slice(&texture.fetch::<[u8; N]>(x + image_x, y + image_y)),
+                }
+            }
+        }
+    }
+
+    fn texcopy_cross_layout_i4(texture: &mut TextureModule, image_layout: ImageDataLayout, image: &mut [u8], x: u32, y: u32, width: u32, height: u32, direction: TexcopyDirection) {
+        for image_y in 0..height {
+            for image_x in 0..width {
+                let index = image_layout.index(image_x, image_y, width) as usize;
+                let shift = (index & 1) * 4;
+                let Some(byte) = image.get_mut(index / 2) else {
+                    continue;
+                };
+                match direction {
+                    TexcopyDirection::Upload => texture.store_index(x + image_x, y + image_y, *byte >> shift),
+                    TexcopyDirection::Download => *byte = (*byte & !(0xF << shift)) | (texture.fetch_index(x + image_x, y + image_y) << shift),
+                }
             }
         }
     }
@@ -227,15 +628,31 @@ impl Core {
         }
     }
 
+    fn count_blit_pixels(&mut self, width: u16, height: u16) {
+        let statistics = &mut self.command_queues[self.current_queue].statistics;
+        statistics.blit_pixels = statistics.blit_pixels.wrapping_add(width as u32 * height as u32);
+    }
+
     fn direct_blit(&mut self, src_tex: u8, dst_tex: u8, src_x: u16, src_y: u16, dst_x: u16, dst_y: u16, width: u16, height: u16) {
         println!("GPU: direct_blit(src_texture: {src_tex}, dest_texture: {dst_tex}, src_x: {src_x}, src_y: {src_y}, dst_x: {dst_x}, dst_y: {dst_y}, width: {width}, height: {height})");
         if src_tex == dst_tex {
             return;
         }
+        self.count_blit_pixels(width, height);
         unsafe {
             let src_tex = &*(&self.textures[src_tex as usize] as *const TextureModule);
             let dst_tex = &mut *(&mut self.textures[dst_tex as usize] as *mut TextureModule);
-            if src_tex.config.pixel_layout == dst_tex.config.pixel_layout {
+            let src_layout = src_tex.config.pixel_layout;
+            let dst_layout = dst_tex.config.pixel_layout;
+            if src_layout.is_indexed() {
+                let palette = self.buffers[src_tex.palette as usize].bytes();
+                match dst_layout {
+                    PixelDataLayout::I4 | PixelDataLayout::I8 => Self::direct_blit_index(src_tex, dst_tex, src_x, src_y, dst_x, dst_y, width, height),
+                    PixelDataLayout::D8x4  => Self::direct_blit_palette(src_tex, palette, dst_tex, src_x, src_y, dst_x, dst_y, width, height, |color| color),
+                    PixelDataLayout::D32x4 => Self::direct_blit_palette(src_tex, palette, dst_tex, src_x, src_y, dst_x, dst_y, width, height, |color| color.map(unorm8_to_f32)),
+                    _ => println!("GPU: direct_blit ERROR: can't expand a palette into {:?}!", dst_layout),
+                }
+            } else if src_layout == dst_layout {
                 match dst_tex.config.pixel_layout.pixel_bytes() {
                     1  => Self::direct_blit_internal::< 1>(src_tex, dst_tex, src_x, src_y, dst_x, dst_y, width, height),
                     2  => Self::direct_blit_internal::< 2>(src_tex, dst_tex, src_x, src_y, dst_x, dst_y, width, height),
@@ -259,15 +676,46 @@ impl Core {
         }
     }
 
+    fn direct_blit_index(src_tex: &TextureModule, dst_tex: &mut TextureModule, src_x: u16, src_y: u16, dst_x: u16, dst_y: u16, width: u16, height: u16) {
+        for y in 0..height as u32 {
+            for x in 0..width as u32 {
+                let index = src_tex.fetch_index(x + src_x as u32, y + src_y as u32);
+                dst_tex.store_index(x + dst_x as u32, y + dst_y as u32, index);
+            }
+        }
+    }
+
+    fn direct_blit_palette<T: Pod, F: Fn([u8; 4]) -> T>(src_tex: &TextureModule, palette: &[u8], dst_tex: &mut TextureModule, src_x: u16, src_y: u16, dst_x: u16, dst_y: u16, width: u16, height: u16, convert_fn: F) {
+        for y in 0..height as u32 {
+            for x in 0..width as u32 {
+                let color = palette_color(palette, src_tex.fetch_index(x + src_x as u32, y + src_y as u32));
+                dst_tex.store::<T>(x + dst_x as u32, y + dst_y as u32, convert_fn(color));
+            }
+        }
+    }
+
     fn cutout_blit(&mut self, src_tex: u8, dst_tex: u8, src_x: u16, src_y: u16, dst_x: u16, dst_y: u16, width: u16, height: u16, pixel_type: PixelDataType) {
         println!("GPU: direct_blit(src_texture: {src_tex}, dest_texture: {dst_tex}, src_x: {src_x}, src_y: {src_y}, dst_x: {dst_x}, dst_y: {dst_y}, width: {width}, height: {height})");
         if src_tex == dst_tex {
             return;
         }
+        self.count_blit_pixels(width, height);
         unsafe {
             let src_tex = &*(&self.textures[src_tex as usize] as *const TextureModule);
             let dst_tex = &mut *(&mut self.textures[dst_tex as usize] as *mut TextureModule);
-            if src_tex.config.pixel_layout == dst_tex.config.pixel_layout {
+            if matches!(pixel_type, PixelDataType::Indexed4 | PixelDataType::Indexed8) {
+                if !src_tex.config.pixel_layout.is_indexed() {
+                    println!("GPU: cutout_blit ERROR: source texture isn't indexed!");
+                    return;
+                }
+                // entries with zero palette alpha are transparent
+                let palette = self.buffers[src_tex.palette as usize].bytes();
+                match dst_tex.config.pixel_layout {
+                    PixelDataLayout::I4 | PixelDataLayout::I8 => Self::cutout_blit_index(src_tex, palette, dst_tex, src_x, src_y, dst_x, dst_y, width, height),
+                    PixelDataLayout::D8x4 => Self::cutout_blit_palette(src_tex, palette, dst_tex, src_x, src_y, dst_x, dst_y, width, height),
+                    _ => println!("GPU: cutout_blit ERROR: can't expand a palette into {:?}!", dst_tex.config.pixel_layout),
+                }
+            } else if src_tex.config.pixel_layout == dst_tex.config.pixel_layout {
                 match (pixel_type, dst_tex.config.pixel_layout.pixel_bytes()) {
                     (PixelDataType::RgbaUNorm8,  4) => Self::cutout_blit_internal::<4, _>(src_tex, dst_tex, src_x, src_y, dst_x, dst_y, width, height, |bytes| bytes[3] != 0),
                     (PixelDataType::RgbaF32,    16) => Self::cutout_blit_internal::<16, _>(src_tex, dst_tex, src_x, src_y, dst_x, dst_y, width, height, |bytes| cast_slice::<_, f32>(&bytes[..])[3] > 0.0),
@@ -292,10 +740,33 @@ impl Core {
         }
     }
 
+    fn cutout_blit_index(src_tex: &TextureModule, palette: &[u8], dst_tex: &mut TextureModule, src_x: u16, src_y: u16, dst_x: u16, dst_y: u16, width: u16, height: u16) {
+        for y in 0..height as u32 {
+            for x in 0..width as u32 {
+                let index = src_tex.fetch_index(x + src_x as u32, y + src_y as u32);
+                if palette_color(palette, index)[3] != 0 {
+                    dst_tex.store_index(x + dst_x as u32, y + dst_y as u32, index);
+                }
+            }
+        }
+    }
+
+    fn cutout_blit_palette(src_tex: &TextureModule, palette: &[u8], dst_tex: &mut TextureModule, src_x: u16, src_y: u16, dst_x: u16, dst_y: u16, width: u16, height: u16) {
+        for y in 0..height as u32 {
+            for x in 0..width as u32 {
+                let color = palette_color(palette, src_tex.fetch_index(x + src_x as u32, y + src_y as u32));
+                if color[3] != 0 {
+                    dst_tex.store::<[u8; 4]>(x + dst_x as u32, y + dst_y as u32, color);
+                }
+            }
+        }
+    }
+
     fn draw_blended_rect(&mut self, src_tex: u8, dst_tex: u8, src_x: u16, src_y: u16, dst_x: u16, dst_y: u16, width: u16, height: u16, src_pixel_type: PixelDataType, dst_pixel_type: PixelDataType, color_blend_op: ColorBlendOp, alpha_blend_op: AlphaBlendOp) {
         if src_pixel_type.component_count() != dst_pixel_type.component_count() {
             return;
         }
+        self.count_blit_pixels(width, height);
         match (src_pixel_type, dst_pixel_type) {
             (PixelDataType::RgbaUNorm8, PixelDataType::RgbaUNorm8) => self.draw_blended_rect_rgba(
                 src_tex, dst_tex, src_x, src_y, dst_x, dst_y, width, height, color_blend_op, alpha_blend_op,
@@ -321,70 +792,25 @@ impl Core {
                 &read_rgba_f32,
                 &write_rgba_f32,
             ),
+            (PixelDataType::Indexed4, PixelDataType::RgbaUNorm8) |
+            (PixelDataType::Indexed8, PixelDataType::RgbaUNorm8) => self.draw_blended_rect_paletted(
+                src_tex, dst_tex, src_x, src_y, dst_x, dst_y, width, height, color_blend_op, alpha_blend_op,
+                &read_rgba_unorm8,
+                &write_rgba_unorm8
+            ),
+            (PixelDataType::Indexed4, PixelDataType::RgbaF32) |
+            (PixelDataType::Indexed8, PixelDataType::RgbaF32) => self.draw_blended_rect_paletted(
+                src_tex, dst_tex, src_x, src_y, dst_x, dst_y, width, height, color_blend_op, alpha_blend_op,
+                &read_rgba_f32,
+                &write_rgba_f32
+            ),
             _ => println!("GPU: unimplemented pixel data type set in draw_blended_rect(): src: {:?}, dst: {:?}", src_pixel_type, dst_pixel_type)
         }
     }
     
     fn draw_blended_rect_rgba<const N_SRC: usize, const N_DST: usize, FSrcRead: Fn(&[u8; N_SRC]) -> [f32; 4], FDstRead: Fn(&[u8; N_DST])-> [f32; 4], FDstWrite: Fn(&mut [u8; N_DST], [f32; 4])>(&mut self, src_tex: u8, dst_tex: u8, src_x: u16, src_y: u16, dst_x: u16, dst_y: u16, width: u16, height: u16, color_blend_op: ColorBlendOp, alpha_blend_op: AlphaBlendOp, f_src_read: &FSrcRead, f_dst_read: &FDstRead, f_dst_write: &FDstWrite)
         where [u8; N_SRC]: Pod, [u8; N_DST]: Pod {
-        let color_fn: fn([f32; 4], [f32; 4]) -> [f32; 3] = match color_blend_op {
-            ColorBlendOp::Zero  => |  _,   _| [
-                0.0,
-                0.0,
-                0.0
-            ],
-            ColorBlendOp::Src   => |src,   _| [
-                src[0],
-                src[1],
-                src[2],
-            ],
-            ColorBlendOp::Dst   => |  _, dst| [
-                dst[0],
-                dst[1],
-                dst[2],
-            ],
-            ColorBlendOp::Add   => |src, dst| [
-                src[0] + dst[0],
-                src[1] + dst[1],
-                src[2] + dst[2]
-            ],
-            ColorBlendOp::Sub   => |src, dst| [
-                dst[0] - src[0],
-                dst[1] - src[1],
-                dst[2] - src[2]
-            ],
-            ColorBlendOp::RSub  => |src, dst| [
-                src[0] - dst[0],
-                src[1] - dst[1],
-                src[2] - dst[2]
-            ],
-            ColorBlendOp::Avg   => |src, dst| [
-                (src[0] + dst[0]) * 0.5,
-                (src[1] + dst[1]) * 0.5,
-                (src[2] + dst[2]) * 0.5
-            ],
-            ColorBlendOp::Blend => |src, dst| [
-                src[0] * src[3] + dst[0] * (1.0 - src[3]),
-                src[1] * src[3] + dst[1] * (1.0 - src[3]),
-                src[2] * src[3] + dst[2] * (1.0 - src[3]),
-            ],
-            ColorBlendOp::RBlend => |src, dst| [
-                src[0] * (1.0 - src[3]) + dst[0] * src[3],
-                src[1] * (1.0 - src[3]) + dst[1] * src[3],
-                src[2] * (1.0 - src[3]) + dst[2] * src[3],
-            ],
-        };
-        let alpha_fn = match alpha_blend_op {
-            AlphaBlendOp::Zero => |_, _| 0.0,
-            AlphaBlendOp::One => |_, _| 1.0,
-            AlphaBlendOp::Src => |src, _| src,
-            AlphaBlendOp::Dst => |_, dst| dst,
-            AlphaBlendOp::Avg => |src, dst| (src + dst) * 0.5,
-            AlphaBlendOp::Add => |src, dst| src + dst,
-            AlphaBlendOp::Sub => |src, dst| dst - src,
-            AlphaBlendOp::RSub => |src, dst| src - dst,
-            AlphaBlendOp::Blend => |src, dst| dst + (1.0 - dst) * src,
-        };
+        let (color_fn, alpha_fn) = blend_fns(color_blend_op, alpha_blend_op);
         let src_texture = unsafe { & (*(&self.textures[src_tex as usize] as *const _)) };
         let dst_texture = unsafe { &mut (*(&mut self.textures[dst_tex as usize] as *mut _)) };
         Self::draw_blended_rect_internal(
@@ -399,6 +825,30 @@ impl Core {
         )
     }
     
+    fn draw_blended_rect_paletted<const N_DST: usize, FDstRead: Fn(&[u8; N_DST])-> [f32; 4], FDstWrite: Fn(&mut [u8; N_DST], [f32; 4])>(&mut self, src_tex: u8, dst_tex: u8, src_x: u16, src_y: u16, dst_x: u16, dst_y: u16, width: u16, height: u16, color_blend_op: ColorBlendOp, alpha_blend_op: AlphaBlendOp, f_dst_read: &FDstRead, f_dst_write: &FDstWrite)
+        where [u8; N_DST]: Pod {
+        let (color_fn, alpha_fn) = blend_fns(color_blend_op, alpha_blend_op);
+        let src_texture = unsafe { & (*(&self.textures[src_tex as usize] as *const TextureModule)) };
+        let dst_texture = unsafe { &mut (*(&mut self.textures[dst_tex as usize] as *mut TextureModule)) };
+        let palette = self.buffers[src_texture.palette as usize].bytes();
+        if src_x >= src_texture.config.width || src_y >= src_texture.config.height || dst_x >= dst_texture.config.width || dst_y >= dst_texture.config.height {
+            return;
+        }
+        let width = width.min(src_texture.config.width - src_x).min(dst_texture.config.width - dst_x);
+        let height = height.min(src_texture.config.height - src_y).min(dst_texture.config.height - dst_y);
+        for y in 0..height as u32 {
+            for x in 0..width as u32 {
+                let src = read_rgba_unorm8(&palette_color(palette, src_texture.fetch_index(x + src_x as u32, y + src_y as u32)));
+                let mut dst_bytes = dst_texture.fetch::<[u8; N_DST]>(x + dst_x as u32, y + dst_y as u32);
+                let dst = f_dst_read(&dst_bytes);
+                let color_result = color_fn(src, dst);
+                let alpha_result = alpha_fn(src[3], dst[3]);
+                f_dst_write(&mut dst_bytes, [color_result[0], color_result[1], color_result[2], alpha_result]);
+                dst_texture.store::<[u8; N_DST]>(x + dst_x as u32, y + dst_y as u32, dst_bytes);
+            }
+        }
+    }
+
     #[allow(unused)]
     fn draw_blended_rect_color<const N_SRC: usize, const N_DST: usize, F: Fn(&[u8; N_SRC], &mut [u8; N_DST])>(&mut self, src_tex: u8, dst_tex: u8, src_x: u16, src_y: u16, dst_x: u16, dst_y: u16, width: u16, height: u16, color_blend_op: ColorBlendOp) {
         todo!()
@@ -450,7 +900,7 @@ impl Core {
         println!("GPU: configure_graphics_resource_mappings(state_index: {}, buffer_count: {}, texture_count: {}, buffer_mapping_list_addr: {}, texture_mapping_list_addr: {})", state_index, buffer_count, texture_count, buffer_mapping_list_addr, texture_mapping_list_addr);
     }
 
-    fn draw_graphics_pipeline(&mut self, state: u8, vertex_shader: u8, fragment_shader: u8, vertex_count: u32, target_rect: RasterRect) {
+    fn draw_graphics_pipeline(&mut self, state: u8, vertex_shader: u8, fragment_shader: u8, vertex_count: u32, instance_count: u32, index_buffer: Option<IndexBuffer>, target_rect: RasterRect) {
         let state = &self.graphics_states[state as usize];
         let rasterizer_call = RasterizerCall {
             constant_array: &mut self.shader_constants,
@@ -459,7 +909,10 @@ impl Core {
             texture_modules: &mut self.textures,
             shader_modules: &mut self.shaders,
             vertex_count: vertex_count as usize,
+            instance_count: instance_count as usize,
+            index_buffer,
             shading_unit_context: &mut self.shader_context,
+            workers: &mut self.raster_workers,
             state: &state.raster_state,
             vertex_shader,
             fragment_shader,
@@ -467,10 +920,101 @@ impl Core {
             fragment_state: &state.fragment_state,
             target_rect,
             resource_map: &state.raster_state.resource_map,
+            statistics: &mut self.command_queues[self.current_queue].statistics,
         };
         run_rasterizer(rasterizer_call);
     }
 
+    fn upload_compute_pipeline_state(&mut self, index: u8, address: u32, machine: &Arc<Machine>) {
+        println!("GPU: upload_compute_pipeline_state(index: {}, address: {:08X})", index, address);
+        if let Some(state) = ComputePipelineState::read_from_address(address, machine) {
+            self.compute_states[(index & 0x3F) as usize] = state;
+        } else {
+            println!("Compute pipeline state upload failed");
+        }
+    }
+
+    fn dispatch_compute(&mut self, state: u8, shader: u8, workgroup_size: u16, workgroups_x: u16, workgroups_y: u16) {
+        let state = &self.compute_states[(state & 0x3F) as usize];
+        if workgroup_size == 0 || workgroups_x == 0 {
+            return;
+        }
+        setup_shader_constants(&mut self.shader_constants, &state.constants[..], &state.resource_map, &mut self.buffers);
+        let mut invocation_count = workgroup_size as usize * workgroups_x as usize * workgroups_y as usize;
+        let mut invocation_offset = 0;
+        while invocation_count > 0 {
+            let [input_array, output_array, _] = &mut self.shader_io_arrays.0;
+            let compute_call = ComputeShaderCall {
+                shader: shader & 0x7F,
+                invocation_count,
+                invocation_offset,
+                workgroup_size: workgroup_size as usize,
+                workgroups_x: workgroups_x as usize,
+                shading_unit_context: &mut self.shader_context,
+                shading_unit_run_context: ShadingUnitRunContext::new(&mut self.shader_constants, input_array, output_array),
+                buffer_modules: &mut self.buffers,
+                texture_modules: &mut self.textures,
+                shader_modules: &self.shaders,
+                resource_map: &state.resource_map,
+            };
+            match run_compute_shader(compute_call) {
+                Ok(result) => {
+                    invocation_count = result.remaining_count;
+                    invocation_offset = result.remaining_offset;
+                },
+                Err(e) => {
+                    println!("GPU: COMPUTE SHADER ERROR: {:?}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn configure_sampler(&mut self, sampler: u8, config: TextureSampler) {
+        println!("GPU: configure_sampler({}, {:?})", sampler, config);
+        if sampler as usize >= SAMPLER_COUNT {
+            println!("GPU: configure_sampler ERROR: sampler out of range!");
+            return;
+        }
+        self.shader_constants.sampler_array[sampler as usize] = config;
+    }
+
+    fn generate_mipmaps(&mut self, texture: u8, component_type: TexelComponentType) {
+        println!("GPU: generate_mipmaps(texture: {}, component_type: {:?})", texture, component_type);
+        if texture >= 64 {
+            println!("GPU: generate_mipmaps ERROR: texture out of range!");
+            return;
+        }
+        if self.textures[texture as usize].config.pixel_layout.is_indexed() {
+            println!("GPU: generate_mipmaps ERROR: palette indices can't be filtered!");
+            return;
+        }
+        self.textures[texture as usize].generate_mipmaps(component_type);
+    }
+
+    fn upload_texture_level(&mut self, texture: u8, level: u8, src_addr: u32, machine: &Arc<Machine>) {
+        println!("GPU: upload_texture_level(texture: {}, level: {}, src_addr: {:08X})", texture, level, src_addr);
+        if texture >= 64 {
+            println!("GPU: upload_texture_level ERROR: texture out of range!");
+            return;
+        }
+        let texture = &mut self.textures[texture as usize];
+        let level = level as usize;
+        if level >= texture.max_mip_levels() {
+            println!("GPU: upload_texture_level ERROR: level out of range!");
+            return;
+        }
+        std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel);
+        match machine.read_block(src_addr, texture.level_data_slice_mut(level)) {
+            ReadResult::Ok(_) => {},
+            ReadResult::InvalidAddress => {
+                println!("GPU: upload_texture_level ERROR: bad address!");
+                return;
+            },
+        }
+        texture.mip_levels = texture.mip_levels.max(level + 1);
+    }
+
     fn write_buffer(&mut self, buffer: u8, src_addr: u32, length: u32, offset: u32, machine: &Arc<Machine>) {
         let buffer_slice = self.buffers[buffer as usize].bytes_mut();
         println!("GPU: write_buffer(buffer: {}, src_addr: {:08X}, length: {:X}, offset: {:08X})", buffer, src_addr, length, offset);
@@ -490,7 +1034,7 @@ fn unorm8_to_f32(x: u8) -> f32 {
     x as f32 * 0.00392156862745098
 }
 
-fn f32_to_unorm8(x: f32) -> u8 {
+pub(crate) fn f32_to_unorm8(x: f32) -> u8 {
     (x * 255.999) as u8
 }
 
@@ -514,6 +1058,71 @@ fn read_rg_unorm8(bytes: &[u8; 2]) -> [f32; 4] {
     ]
 }
 
+type ColorBlendFn = fn([f32; 4], [f32; 4]) -> [f32; 3];
+type AlphaBlendFn = fn(f32, f32) -> f32;
+
+fn blend_fns(color_blend_op: ColorBlendOp, alpha_blend_op: AlphaBlendOp) -> (ColorBlendFn, AlphaBlendFn) {
+    let color_fn: ColorBlendFn = match color_blend_op {
+        ColorBlendOp::Zero  => |  _,   _| [
+            0.0,
+            0.0,
+            0.0
+        ],
+        ColorBlendOp::Src   => |src,   _| [
+            src[0],
+            src[1],
+            src[2],
+        ],
+        ColorBlendOp::Dst   => |  _, dst| [
+            dst[0],
+            dst[1],
+            dst[2],
+        ],
+        ColorBlendOp::Add   => |src, dst| [
+            src[0] + dst[0],
+            src[1] + dst[1],
+            src[2] + dst[2]
+        ],
+        ColorBlendOp::Sub   => |src, dst| [
+            dst[0] - src[0],
+            dst[1] - src[1],
+            dst[2] - src[2]
+        ],
+        ColorBlendOp::RSub  => |src, dst| [
+            src[0] - dst[0],
+            src[1] - dst[1],
+            src[2] - dst[2]
+        ],
+        ColorBlendOp::Avg   => |src, dst| [
+            (src[0] + dst[0]) * 0.5,
+            (src[1] + dst[1]) * 0.5,
+            (src[2] + dst[2]) * 0.5
+        ],
+        ColorBlendOp::Blend => |src, dst| [
+            src[0] * src[3] + dst[0] * (1.0 - src[3]),
+            src[1] * src[3] + dst[1] * (1.0 - src[3]),
+            src[2] * src[3] + dst[2] * (1.0 - src[3]),
+        ],
+        ColorBlendOp::RBlend => |src, dst| [
+            src[0] * (1.0 - src[3]) + dst[0] * src[3],
+            src[1] * (1.0 - src[3]) + dst[1] * src[3],
+            src[2] * (1.0 - src[3]) + dst[2] * src[3],
+        ],
+    };
+    let alpha_fn: AlphaBlendFn = match alpha_blend_op {
+        AlphaBlendOp::Zero => |_, _| 0.0,
+        AlphaBlendOp::One => |_, _| 1.0,
+        AlphaBlendOp::Src => |src, _| src,
+        AlphaBlendOp::Dst => |_, dst| dst,
+        AlphaBlendOp::Avg => |src, dst| (src + dst) * 0.5,
+        AlphaBlendOp::Add => |src, dst| src + dst,
+        AlphaBlendOp::Sub => |src, dst| dst - src,
+        AlphaBlendOp::RSub => |src, dst| src - dst,
+        AlphaBlendOp::Blend => |src, dst| dst + (1.0 - dst) * src,
+    };
+    (color_fn, alpha_fn)
+}
+
 fn read_rgba_unorm8(bytes: &[u8; 4]) -> [f32; 4] {
     [
         unorm8_to_f32(bytes[0]),
@@ -537,3 +1146,275 @@ fn read_rgba_f32(bytes: &[u8; 16]) -> [f32; 4] {
 fn write_rgba_f32(bytes: &mut [u8; 16], value: [f32; 4]) {
     cast_slice_mut::<u8, [f32; 4]>(bytes)[0] = value;
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::MachineConfig;
+    use crate::gpu::queue::{QUEUE_HIGH_PRIORITY, QUEUE_TRANSFER};
+
+    const FLAGS: u32 = 0x8000;
+    const CMP_EQUAL: u32 = 6;
+
+    fn machine() -> Arc<Machine> {
+        Arc::new(Machine::new_headless(&[], &MachineConfig { hart_count: 1, ram_size: 0x0001_0000, ..MachineConfig::default() }).unwrap())
+    }
+
+    fn write_flag(address: u32, value: u32) -> Vec<u32> {
+        vec![0x04, address, value]
+    }
+
+    fn call_list(address: u32) -> Vec<u32> {
+        vec![0x22, address]
+    }
+
+    fn jump_list(address: u32) -> Vec<u32> {
+        vec![0x23, address]
+    }
+
+    fn skip_if(address: u32, reference: u32, skip_bytes: u32) -> Vec<u32> {
+        vec![0x24 | (CMP_EQUAL << 16), address, reference, !0, skip_bytes]
+    }
+
+    fn wait_until(address: u32, reference: u32) -> Vec<u32> {
+        vec![0x25 | (CMP_EQUAL << 16), address, reference, !0]
+    }
+
+    fn signal_fence(fence: u32, value: u32) -> Vec<u32> {
+        vec![0x26 | (fence << 16), value]
+    }
+
+    fn wait_fence(fence: u32, value: u32) -> Vec<u32> {
+        vec![0x27 | (fence << 16), value]
+    }
+
+    fn direct_blit(src_tex: u32, dst_tex: u32, width: u32, height: u32) -> Vec<u32> {
+        vec![0x09 | (src_tex << 16) | (dst_tex << 24), 0, 0, width | (height << 16)]
+    }
+
+    fn begin_statistics_query() -> Vec<u32> {
+        vec![0x20]
+    }
+
+    fn end_statistics_query(address: u32) -> Vec<u32> {
+        vec![0x21, address]
+    }
+
+    fn upload_texture(texture: u32, src_image_layout: u32, src_addr: u32) -> Vec<u32> {
+        vec![0x06 | (texture << 16) | (src_image_layout << 24), src_addr]
+    }
+
+    // region is x, y, width and height
+    fn download_texture(texture: u32, dst_image_layout: u32, [x, y, width, height]: [u32; 4], dst_addr: u32, completion_addr: u32) -> Vec<u32> {
+        vec![0x1E | (texture << 16) | (dst_image_layout << 24), x | (y << 16), width | (height << 16), dst_addr, completion_addr, 0]
+    }
+
+    // writes a command list to ram and reads it back the way a submission does
+    fn list(machine: &Arc<Machine>, address: u32, commands: &[Vec<u32>]) -> CommandList {
+        let words = commands.concat();
+        assert!(machine.write_u32(address, words.len() as u32 * 4).is_ok());
+        assert!(machine.write_u32(address + 4, 0).is_ok());
+        for (i, word) in words.iter().enumerate() {
+            assert!(machine.write_u32(address + 8 + i as u32 * 4, *word).is_ok());
+        }
+        parse_commandlist_header(address, machine).unwrap()
+    }
+
+    fn flag(machine: &Arc<Machine>, index: u32) -> u32 {
+        machine.read_u32(FLAGS + index * 4).to_opt().unwrap()
+    }
+
+    fn run_until_idle(core: &mut Core, machine: &Arc<Machine>, main_window: &MainWindow) {
+        for _ in 0..1000 {
+            if !core.process(machine, main_window) {
+                break;
+            }
+        }
+        assert!(core.is_idle());
+    }
+
+    #[test]
+    fn call_jump_and_skip() {
+        let machine = machine();
+        let main_window = MainWindow::headless();
+        let mut core = Core::new(0x10_0000, 1);
+        list(&machine, 0x2000, &[write_flag(FLAGS + 4, 2)]);
+        list(&machine, 0x3000, &[write_flag(FLAGS + 8, 3)]);
+        let main = list(&machine, 0x1000, &[
+            write_flag(FLAGS, 1),
+            call_list(0x2000),
+            skip_if(FLAGS, 1, 12),
+            write_flag(FLAGS + 12, 0xBAD),
+            skip_if(FLAGS, 2, 12),
+            write_flag(FLAGS + 16, 5),
+            jump_list(0x3000),
+            write_flag(FLAGS + 20, 0xBAD),
+        ]);
+        core.add_command_list(QUEUE_GRAPHICS, main);
+        run_until_idle(&mut core, &machine, &main_window);
+        assert_eq!((0..6).map(|i| flag(&machine, i)).collect::<Vec<_>>(), [1, 2, 3, 0, 5, 0]);
+    }
+
+    #[test]
+    fn recursive_calls_stop_at_max_depth() {
+        let machine = machine();
+        let main_window = MainWindow::headless();
+        let mut core = Core::new(0x10_0000, 1);
+        core.add_command_list(QUEUE_GRAPHICS, list(&machine, 0x1000, &[call_list(0x1000), write_flag(FLAGS, 1)]));
+        run_until_idle(&mut core, &machine, &main_window);
+        assert_eq!(flag(&machine, 0), 1);
+    }
+
+    #[test]
+    fn jump_cycles_yield_to_other_queues() {
+        let machine = machine();
+        let main_window = MainWindow::headless();
+        let mut core = Core::new(0x10_0000, 1);
+        list(&machine, 0x2000, &[write_flag(FLAGS + 4, 2), jump_list(0x1000)]);
+        core.add_command_list(QUEUE_GRAPHICS, list(&machine, 0x1000, &[write_flag(FLAGS, 1), jump_list(0x2000)]));
+        for _ in 0..10 {
+            assert!(core.process(&machine, &main_window));
+        }
+        assert_eq!((flag(&machine, 0), flag(&machine, 1)), (1, 2));
+
+        core.add_command_list(QUEUE_HIGH_PRIORITY, list(&machine, 0x3000, &[write_flag(FLAGS + 8, 3)]));
+        assert!(core.process(&machine, &main_window));
+        assert_eq!(flag(&machine, 2), 3);
+        assert!(!core.is_idle());
+    }
+
+    #[test]
+    fn queues_run_in_priority_order() {
+        let machine = machine();
+        let main_window = MainWindow::headless();
+        let mut core = Core::new(0x10_0000, 1);
+        core.add_command_list(QUEUE_TRANSFER, list(&machine, 0x1000, &[write_flag(FLAGS + 8, 3)]));
+        core.add_command_list(QUEUE_GRAPHICS, list(&machine, 0x2000, &[write_flag(FLAGS + 4, 2)]));
+        core.add_command_list(QUEUE_HIGH_PRIORITY, list(&machine, 0x3000, &[write_flag(FLAGS, 1)]));
+        let mut ran = Vec::new();
+        while core.process(&machine, &main_window) {
+            ran.push((0..3).map(|i| flag(&machine, i)).collect::<Vec<_>>());
+        }
+        assert_eq!(ran, [vec![1, 0, 0], vec![1, 2, 0], vec![1, 2, 3]]);
+        assert!(core.is_idle());
+    }
+
+    #[test]
+    fn wait_fence_blocks_until_signalled() {
+        let machine = machine();
+        let main_window = MainWindow::headless();
+        let mut core = Core::new(0x10_0000, 1);
+        core.add_command_list(QUEUE_GRAPHICS, list(&machine, 0x1000, &[wait_fence(3, 1), write_flag(FLAGS, 1)]));
+        assert!(!core.process(&machine, &main_window));
+        assert_eq!(flag(&machine, 0), 0);
+
+        // the blocked graphics queue lets the transfer queue run and signal it
+        core.add_command_list(QUEUE_TRANSFER, list(&machine, 0x2000, &[write_flag(FLAGS + 4, 2), signal_fence(3, 1)]));
+        assert!(core.process(&machine, &main_window));
+        assert_eq!((flag(&machine, 0), flag(&machine, 1)), (0, 2));
+        assert!(core.process(&machine, &main_window));
+        assert_eq!(flag(&machine, 0), 1);
+        assert!(core.is_idle());
+    }
+
+    #[test]
+    fn statistics_are_counted_per_queue() {
+        let machine = machine();
+        let main_window = MainWindow::headless();
+        let mut core = Core::new(0x10_0000, 1);
+        core.add_command_list(QUEUE_GRAPHICS, list(&machine, 0x1000, &[
+            begin_statistics_query(),
+            direct_blit(0, 1, 4, 2),
+            wait_fence(0, 1),
+            end_statistics_query(FLAGS),
+        ]));
+        // runs while graphics waits on the fence, between its begin and end
+        core.add_command_list(QUEUE_TRANSFER, list(&machine, 0x2000, &[
+            begin_statistics_query(),
+            direct_blit(0, 1, 3, 3),
+            direct_blit(2, 2, 5, 5),
+            end_statistics_query(FLAGS + 0x20),
+            signal_fence(0, 1),
+        ]));
+        run_until_idle(&mut core, &machine, &main_window);
+        assert_eq!((0..5).map(|i| flag(&machine, i)).collect::<Vec<_>>(), [0, 0, 0, 0, 8]);
+        assert_eq!((8..13).map(|i| flag(&machine, i)).collect::<Vec<_>>(), [0, 0, 0, 0, 9]);
+    }
+
+    #[test]
+    fn wait_until_blocks_until_memory_matches() {
+        let machine = machine();
+        let main_window = MainWindow::headless();
+        let mut core = Core::new(0x10_0000, 1);
+        core.add_command_list(QUEUE_GRAPHICS, list(&machine, 0x1000, &[wait_until(FLAGS + 4, 5), write_flag(FLAGS, 1)]));
+        for _ in 0..3 {
+            assert!(!core.process(&machine, &main_window));
+        }
+        assert_eq!(flag(&machine, 0), 0);
+        assert!(machine.write_u32(FLAGS + 4, 5).is_ok());
+        assert!(core.process(&machine, &main_window));
+        assert_eq!(flag(&machine, 0), 1);
+        assert!(core.is_idle());
+    }
+
+    #[test]
+    fn set_sprite_rejects_bad_priority_and_layout() {
+        let mut core = Core::new(0x10_0000, 1);
+        let sprite = Sprite { enabled: true, texture: 1, priority: 2, ..Sprite::new() };
+        core.set_sprite(0, Sprite { priority: LAYER_PRIORITY_COUNT, ..sprite });
+        core.set_sprite(1, sprite);
+        core.set_sprite(2, Sprite { enabled: false, ..sprite });
+        assert_eq!(core.sprites.sprites[0], Sprite::new());
+        assert_eq!(core.sprites.sprites[1], Sprite::new());
+        assert_eq!(core.sprites.sprites[2], Sprite { enabled: false, ..sprite });
+
+        core.configure_texture(1, PixelDataLayout::D8x4, ImageDataLayout::Contiguous, 8, 8, 0);
+        core.set_sprite(1, sprite);
+        assert_eq!(core.sprites.sprites[1], sprite);
+    }
+
+    #[test]
+    fn configure_background_rejects_bad_priority_and_layout() {
+        let mut core = Core::new(0x10_0000, 1);
+        let background = Background { enabled: true, atlas_texture: 1, priority: 3, ..Background::new() };
+        core.configure_background(0, Background { priority: LAYER_PRIORITY_COUNT, ..background });
+        core.configure_background(1, background);
+        core.configure_background(2, Background { enabled: false, ..background });
+        assert_eq!(core.backgrounds.backgrounds[0], Background::new());
+        assert_eq!(core.backgrounds.backgrounds[1], Background::new());
+        assert_eq!(core.backgrounds.backgrounds[2], Background { enabled: false, ..background });
+
+        core.configure_texture(1, PixelDataLayout::I8, ImageDataLayout::Contiguous, 8, 8, 0);
+        core.configure_background(1, background);
+        assert_eq!(core.backgrounds.backgrounds[1], background);
+    }
+
+    #[test]
+    fn download_texture_round_trips_uploads() {
+        let machine = machine();
+        let main_window = MainWindow::headless();
+        let mut core = Core::new(0x10_0000, 1);
+        let image: Vec<u8> = (0..64).collect();
+        assert!(machine.write_block(0x4000, &image).is_ok());
+        core.configure_texture(1, PixelDataLayout::D8x1, ImageDataLayout::Block4x4, 8, 8, 0);
+        core.add_command_list(QUEUE_TRANSFER, list(&machine, 0x1000, &[
+            upload_texture(1, 0, 0x4000),
+            download_texture(1, 0, [0, 0, 8, 8], 0x5000, FLAGS),
+            download_texture(1, 0, [2, 1, 5, 3], 0x5100, FLAGS + 4),
+            // a region running off the texture is clamped to it
+            download_texture(1, 0, [6, 6, 4, 4], 0x5200, FLAGS + 8),
+        ]));
+        run_until_idle(&mut core, &machine, &main_window);
+        assert_eq!((0..3).map(|i| flag(&machine, i)).collect::<Vec<_>>(), [1, 1, 1]);
+        // the texture is stored in 4x4 blocks but comes back in the layout asked for
+        assert_ne!(core.textures[1].data_slice(), &image[..]);
+        let download = |address, length| {
+            let mut bytes = vec![0u8; length];
+            assert!(machine.read_block(address, &mut bytes).to_opt().is_some());
+            bytes
+        };
+        assert_eq!(download(0x5000, 64), image);
+        assert_eq!(download(0x5100, 15), [10, 11, 12, 13, 14, 18, 19, 20, 21, 22, 26, 27, 28, 29, 30]);
+        assert_eq!(download(0x5200, 5), [54, 55, 62, 63, 0]);
+    }
+}
@@ -1,6 +1,6 @@
 use super::shader::*;
-use super::texture::TextureModule;
-use super::buffer::BufferModule;
+use super::texture::{TextureModule, TextureView};
+use super::buffer::BufferAccess;
 use super::types::PixelDataLayout;
 
 #[derive(Debug, Default)]
@@ -37,6 +37,10 @@ impl FragmentOutputType {
             _ => None?
         })
     }
+
+    pub fn is_f32_source(&self) -> bool {
+        matches!(self, Self::F32ToF32 | Self::F32ToInt | Self::F32ToUInt | Self::F32ToINorm | Self::F32ToUNorm)
+    }
 }
 
 pub const FRAGMENT_VECTOR_INPUT_BUILTIN_POSITION    : usize = 0x00;
@@ -45,6 +49,8 @@ pub const FRAGMENT_VECTOR_INPUT_BUILTIN_LINEAR      : usize = 0x02;
 pub const FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS  : usize = 0x03;
 pub const FRAGMENT_VECTOR_INPUT_USER_OFFSET         : usize = 0x10;
 
+pub const FRAGMENT_SCALAR_INPUT_BUILTIN_FRONT_FACING : usize = 0x00;
+
 pub const FRAGMENT_SCALAR_OUTPUT_BUILTIN_DISCARD : usize = 0x00;
 pub const FRAGMENT_SCALAR_OUTPUT_BUILTIN_DEPTH   : usize = 0x01;
 pub const FRAGMENT_SCALAR_OUTPUT_USER_OFFSET     : usize = 0x10;
@@ -55,7 +61,111 @@ pub struct FragmentOutputAssignment {
     pub texture: u8,
     pub t: FragmentOutputType,
     pub c: ShaderCardinality,
-    pub offset: [u32; 2]
+    pub offset: [u32; 2],
+    pub blend: Option<FragmentBlendState>,
+    pub write_mask: u8,
+}
+
+pub const FRAGMENT_WRITE_MASK_ALL: u8 = 0xF;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+}
+
+impl BlendFactor {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        Some(match x {
+            0 => Self::Zero,
+            1 => Self::One,
+            2 => Self::SrcColor,
+            3 => Self::OneMinusSrcColor,
+            4 => Self::DstColor,
+            5 => Self::OneMinusDstColor,
+            6 => Self::SrcAlpha,
+            7 => Self::OneMinusSrcAlpha,
+            8 => Self::DstAlpha,
+            9 => Self::OneMinusDstAlpha,
+            _ => None?
+        })
+    }
+
+    fn factor(&self, src: [f32; 4], dst: [f32; 4], c: usize) -> f32 {
+        match self {
+            Self::Zero             => 0.0,
+            Self::One              => 1.0,
+            Self::SrcColor         => src[c],
+            Self::OneMinusSrcColor => 1.0 - src[c],
+            Self::DstColor         => dst[c],
+            Self::OneMinusDstColor => 1.0 - dst[c],
+            Self::SrcAlpha         => src[3],
+            Self::OneMinusSrcAlpha => 1.0 - src[3],
+            Self::DstAlpha         => dst[3],
+            Self::OneMinusDstAlpha => 1.0 - dst[3],
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlendOp {
+    Add,
+    Subtract,
+    ReverseSubtract,
+    Min,
+    Max,
+}
+
+impl BlendOp {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        Some(match x {
+            0 => Self::Add,
+            1 => Self::Subtract,
+            2 => Self::ReverseSubtract,
+            3 => Self::Min,
+            4 => Self::Max,
+            _ => None?
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FragmentBlendState {
+    pub color_src: BlendFactor,
+    pub color_dst: BlendFactor,
+    pub color_op: BlendOp,
+    pub alpha_src: BlendFactor,
+    pub alpha_dst: BlendFactor,
+    pub alpha_op: BlendOp,
+}
+
+impl FragmentBlendState {
+    pub fn blend(&self, src: [f32; 4], dst: [f32; 4]) -> [f32; 4] {
+        [0, 1, 2, 3].map(|c| {
+            let (src_factor, dst_factor, op) = if c < 3 {
+                (self.color_src, self.color_dst, self.color_op)
+            } else {
+                (self.alpha_src, self.alpha_dst, self.alpha_op)
+            };
+            let s = src[c] * src_factor.factor(src, dst, c);
+            let d = dst[c] * dst_factor.factor(src, dst, c);
+            match op {
+                BlendOp::Add             => s + d,
+                BlendOp::Subtract        => s - d,
+                BlendOp::ReverseSubtract => d - s,
+                BlendOp::Min             => src[c].min(dst[c]),
+                BlendOp::Max             => src[c].max(dst[c]),
+            }
+        })
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -66,6 +176,8 @@ pub enum DepthCompareFn {
     LessOrEqual,
     Greater,
     GreaterOrEqual,
+    Equal,
+    NotEqual,
 }
 
 impl DepthCompareFn {
@@ -77,9 +189,88 @@ impl DepthCompareFn {
             3 => Self::LessOrEqual,
             4 => Self::Greater,
             5 => Self::GreaterOrEqual,
+            6 => Self::Equal,
+            7 => Self::NotEqual,
             _ => None?
         })
     }
+
+    // whether value passes against the stored one
+    pub fn compare<T: PartialOrd>(&self, value: T, stored: T) -> bool {
+        match self {
+            Self::Never          => false,
+            Self::Always         => true,
+            Self::Less           => value < stored,
+            Self::LessOrEqual    => value <= stored,
+            Self::Greater        => value > stored,
+            Self::GreaterOrEqual => value >= stored,
+            Self::Equal          => value == stored,
+            Self::NotEqual       => value != stored,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    IncrementClamp,
+    DecrementClamp,
+    Invert,
+    IncrementWrap,
+    DecrementWrap,
+}
+
+impl StencilOp {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        Some(match x {
+            0 => Self::Keep,
+            1 => Self::Zero,
+            2 => Self::Replace,
+            3 => Self::IncrementClamp,
+            4 => Self::DecrementClamp,
+            5 => Self::Invert,
+            6 => Self::IncrementWrap,
+            7 => Self::DecrementWrap,
+            _ => None?
+        })
+    }
+
+    pub fn apply(&self, value: u8, reference: u8) -> u8 {
+        match self {
+            Self::Keep           => value,
+            Self::Zero           => 0,
+            Self::Replace        => reference,
+            Self::IncrementClamp => value.saturating_add(1),
+            Self::DecrementClamp => value.saturating_sub(1),
+            Self::Invert         => !value,
+            Self::IncrementWrap  => value.wrapping_add(1),
+            Self::DecrementWrap  => value.wrapping_sub(1),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct StencilFaceState {
+    pub compare_fn: DepthCompareFn,
+    pub fail_op: StencilOp,
+    pub depth_fail_op: StencilOp,
+    pub pass_op: StencilOp,
+}
+
+/*
+the stencil texture is D8x1. the test compares reference & read_mask against
+the stored value & read_mask, and ops only change the bits in write_mask.
+ */
+#[derive(Clone, Debug)]
+pub struct FragmentStencilState {
+    pub stencil_texture: u8,
+    pub reference: u8,
+    pub read_mask: u8,
+    pub write_mask: u8,
+    pub front: StencilFaceState,
+    pub back: StencilFaceState,
 }
 
 #[derive(Clone, Debug)]
@@ -87,6 +278,7 @@ pub struct FragmentDepthState {
     pub depth_texture: u8,
     pub compare_fn: DepthCompareFn,
     pub depth_write: bool,
+    pub stencil: Option<FragmentStencilState>,
 }
 
 pub struct FragmentShaderCall<'a> {
@@ -98,65 +290,132 @@ pub struct FragmentShaderCall<'a> {
     pub shading_unit_context     : &'a mut ShadingUnitContext,
     pub shading_unit_run_context : &'a mut ShadingUnitRunContext<'a>,
     
-    pub buffer_modules  : &'a mut [BufferModule; 256],
-    pub texture_modules : &'a mut [TextureModule; 64],
+    pub buffer_modules  : BufferAccess<'a>,
+    pub texture_modules : &'a [TextureModule; 64],
     pub shader_modules  : &'a [ShaderModule; 128],
+    pub targets         : &'a mut TileTargets,
 
     pub resource_map: &'a ResourceMap,
 }
 
-pub fn run_fragment_shader(mut call: FragmentShaderCall<'_>) {
+/*
+everything a tile's fragments can write: the depth and stencil textures and each
+output, as views over the tile's rect. shaders keep sampling texture_modules, so
+they see the targets as they were before the batch.
+ */
+#[derive(Default)]
+pub struct TileTargets {
+    upper_left: (u32, u32),
+    views: Vec<TextureView>,
+}
+
+impl TileTargets {
+    pub fn load(state: &FragmentState, resource_map: &ResourceMap, texture_modules: &[TextureModule; 64], upper_left: (u32, u32), lower_right: (u32, u32)) -> Self {
+        let mut targets = Self { upper_left, views: Vec::new() };
+        let (width, height) = (lower_right.0 - upper_left.0 + 1, lower_right.1 - upper_left.1 + 1);
+        let mut add_view = |texture: u8, offset: [u32; 2]| {
+            let texture = resource_map.texture[texture as usize] as usize;
+            if targets.find(texture, offset).is_none() {
+                let (x, y) = (upper_left.0.saturating_add(offset[0]), upper_left.1.saturating_add(offset[1]));
+                targets.views.push(TextureView::load(texture_modules, texture, x, y, width, height));
+            }
+        };
+        if let Some(depth_state) = &state.depth_state {
+            add_view(depth_state.depth_texture, [0, 0]);
+            if let Some(stencil) = &depth_state.stencil {
+                add_view(stencil.stencil_texture, [0, 0]);
+            }
+        }
+        for output in state.output_assignments.iter() {
+            add_view(output.texture, output.offset);
+        }
+        targets
+    }
+
+    pub fn write_back(&self, texture_modules: &mut [TextureModule; 64]) {
+        for view in self.views.iter() {
+            view.write_back(texture_modules);
+        }
+    }
+
+    fn find(&self, texture: usize, offset: [u32; 2]) -> Option<usize> {
+        let (x, y) = (self.upper_left.0.saturating_add(offset[0]), self.upper_left.1.saturating_add(offset[1]));
+        self.views.iter().position(|view| view.texture == texture && view.x == x && view.y == y)
+    }
+}
+
+// returns how many fragments passed the depth and stencil tests
+pub fn run_fragment_shader(mut call: FragmentShaderCall<'_>) -> usize {
     let invocation_count = call.fragmen_count;
-    let mut continue_instructions = true;
 
     let shader = &call.shader_modules[call.shader as usize];
     if shader.shader_type != ShaderType::Fragment {
         println!("GPU: ERROR: fragment shader supplied is not a fragment shader!");
-        return;
-    }
-    let mut instructions = shader.instruction_buffer[0..shader.instruction_count].iter();
-    while let (Some(instruction), true) = (instructions.next(), continue_instructions) {
-        if call.shading_unit_context.run_instruction(invocation_count, instruction, &mut call.shading_unit_run_context, &mut call.buffer_modules, &mut call.texture_modules, call.resource_map).is_none() {
-            continue_instructions = false;
-        }
+        return 0;
     }
+    let instructions = &shader.instruction_buffer[0..shader.instruction_count];
+    call.shading_unit_context.run_shader(invocation_count, instructions, call.shading_unit_run_context, &mut call.buffer_modules, call.texture_modules, call.resource_map);
     
-    let mut depth_pass_buffer = [0u32; (CORE_COUNT + 31) >> 5];
+    let mut pass_buffer = [0u32; (CORE_COUNT + 31) >> 5];
 
     if let Some(depth_state) = &call.state.depth_state {
         let depth_texture_index = call.resource_map.texture[depth_state.depth_texture as usize] as usize;
-        let depth_texture = &mut call.texture_modules[depth_texture_index];
-        if depth_texture.config.pixel_layout != PixelDataLayout::D32x1 {
-            return;
+        // a depth test that can't fail and doesn't write never has to touch the depth texture
+        let depth_test = depth_state.compare_fn != DepthCompareFn::Always || depth_state.depth_write;
+        if depth_test && call.texture_modules[depth_texture_index].config.pixel_layout != PixelDataLayout::D32x1 {
+            return 0;
+        }
+        let stencil_texture_index = depth_state.stencil.as_ref().map(|stencil| call.resource_map.texture[stencil.stencil_texture as usize] as usize);
+        if stencil_texture_index.is_some_and(|index| call.texture_modules[index].config.pixel_layout != PixelDataLayout::D8x1) {
+            return 0;
         }
+        let Some(depth_view) = call.targets.find(depth_texture_index, [0, 0]) else {
+            return 0;
+        };
+        let stencil_view = stencil_texture_index.and_then(|index| call.targets.find(index, [0, 0]));
         for f in 0..call.fragmen_count {
             let position = call.shading_unit_run_context.vector_input_array[FRAGMENT_VECTOR_INPUT_BUILTIN_POSITION][f].map(|x| f32::from_bits(x));
+            let (x, y) = (position[0] as u32, position[1] as u32);
+            let front_facing = call.shading_unit_run_context.scalar_input_array[FRAGMENT_SCALAR_INPUT_BUILTIN_FRONT_FACING][f] != 0;
+            let stencil = depth_state.stencil.as_ref().zip(stencil_view).map(|(stencil, view)| {
+                let face = if front_facing { stencil.front } else { stencil.back };
+                (stencil, face, view, call.targets.views[view].fetch::<u8>(x, y))
+            });
+            let stencil_pass = stencil.is_none_or(|(stencil, face, _, stencil_val)| face.compare_fn.compare(stencil.reference & stencil.read_mask, stencil_val & stencil.read_mask));
             let depth_val = position[2].clamp(0.0, 1.0);
-            let depth_texture_val = f32::from_bits(depth_texture.fetch::<u32>(position[0] as u32, position[1] as u32));
-            let pass = match depth_state.compare_fn {
-                DepthCompareFn::Always         => true,
-                DepthCompareFn::Never          => false,
-                DepthCompareFn::Greater        => depth_val > depth_texture_val,
-                DepthCompareFn::GreaterOrEqual => depth_val >= depth_texture_val,
-                DepthCompareFn::Less           => depth_val < depth_texture_val,
-                DepthCompareFn::LessOrEqual    => depth_val <= depth_texture_val,
-            };
-            if pass {
+            let depth_pass = !depth_test || depth_state.compare_fn.compare(depth_val, f32::from_bits(call.targets.views[depth_view].fetch::<u32>(x, y)));
+            if let Some((stencil, face, view, stencil_val)) = stencil {
+                let op = if !stencil_pass {
+                    face.fail_op
+                } else if !depth_pass {
+                    face.depth_fail_op
+                } else {
+                    face.pass_op
+                };
+                let result = op.apply(stencil_val, stencil.reference);
+                call.targets.views[view].store::<u8>(x, y, (stencil_val & !stencil.write_mask) | (result & stencil.write_mask));
+            }
+            if stencil_pass && depth_pass {
                 if depth_state.depth_write {
-                    depth_texture.store::<u32>(position[0] as u32, position[1] as u32, depth_val.to_bits());
+                    call.targets.views[depth_view].store::<u32>(x, y, depth_val.to_bits());
                 }
-                depth_pass_buffer[f >> 5] |= (1 << (f & 31));
+                pass_buffer[f >> 5] |= 1 << (f & 31);
             }
         }
     } else {
-        depth_pass_buffer.fill(0xFFFFFFFF);
+        pass_buffer.fill(0xFFFFFFFF);
     }
+    let passed = |f: usize| pass_buffer[f >> 5] & (1 << (f & 31)) != 0;
+    let passed_count = (0..call.fragmen_count).filter(|&f| passed(f)).count();
     'output: for output in call.state.output_assignments.iter() {
         let texture_index = call.resource_map.texture[output.texture as usize] as usize;
-        let texture = &mut call.texture_modules[texture_index];
+        let Some(view) = call.targets.find(texture_index, output.offset) else {
+            continue;
+        };
+        let view = &mut call.targets.views[view];
         match output.c {
             ShaderCardinality::Scalar => {
-                let write_fn: fn(&mut TextureModule, u32, u32, u32) -> () = match (output.t, texture.config.pixel_layout) {
+                let write_fn: fn(&mut TextureModule, u32, u32, u32) -> () = match (output.t, view.module.config.pixel_layout) {
 
                     (FragmentOutputType::F32ToF32,   PixelDataLayout::D32x1) =>
                         |texture: &mut TextureModule, x: u32, y: u32, scalar_value: u32| {
@@ -263,6 +522,16 @@ pub fn run_fragment_shader(mut call: FragmentShaderCall<'_>) {
                             texture.store::<u32>(x, y, scalar_value);
                         },
 
+                    (FragmentOutputType::UIntToUInt, PixelDataLayout::I8   ) =>
+                        |texture: &mut TextureModule, x: u32, y: u32, scalar_value: u32| {
+                            texture.store::<u8>(x, y, scalar_value as u8);
+                        },
+
+                    (FragmentOutputType::UIntToUInt, PixelDataLayout::I4   ) =>
+                        |texture: &mut TextureModule, x: u32, y: u32, scalar_value: u32| {
+                            texture.store_index(x, y, scalar_value as u8);
+                        },
+
                     (FragmentOutputType::UIntToF32,  PixelDataLayout::D8x1 ) |
                     (FragmentOutputType::UIntToF32,  PixelDataLayout::D16x1) => continue 'output,
 
@@ -277,17 +546,22 @@ pub fn run_fragment_shader(mut call: FragmentShaderCall<'_>) {
                     (_                            ,  PixelDataLayout::D16x4) => continue 'output,
                     (_                            ,  PixelDataLayout::D32x2) => continue 'output,
                     (_                            ,  PixelDataLayout::D32x4) => continue 'output,
+                    (_                            ,  PixelDataLayout::I4   ) => continue 'output,
+                    (_                            ,  PixelDataLayout::I8   ) => continue 'output,
                 };
                 let scalar_output = &call.shading_unit_run_context.scalar_output_array[output.output as usize];
-                for f in 0..call.fragmen_count {
+                let resolve = output.blend.is_some() || output.write_mask & 1 == 0;
+                for f in (0..call.fragmen_count).filter(|&f| passed(f)) {
                     let position = call.shading_unit_run_context.vector_input_array[FRAGMENT_VECTOR_INPUT_BUILTIN_POSITION][f].map(|x| f32::from_bits(x) as u32);
-                    write_fn(texture, output.offset[0] + position[0], output.offset[1] + position[1], scalar_output[f]);
+                    let Some((x, y)) = view.local(output.offset[0] + position[0], output.offset[1] + position[1]) else {
+                        continue;
+                    };
+                    let value = if resolve { resolve_output(output, &view.module, x, y, [scalar_output[f], 0, 0, 0])[0] } else { scalar_output[f] };
+                    write_fn(&mut view.module, x, y, value);
                 }
             },
             ShaderCardinality::V2 => {
-                let texture_index = call.resource_map.texture[output.texture as usize] as usize;
-                let texture = &mut call.texture_modules[texture_index];
-                let write_fn: fn(&mut TextureModule, u32, u32, [u32; 4]) -> () = match (output.t, texture.config.pixel_layout) {
+                let write_fn: fn(&mut TextureModule, u32, u32, [u32; 4]) -> () = match (output.t, view.module.config.pixel_layout) {
                     (FragmentOutputType::F32ToF32, PixelDataLayout::D32x2) => 
                         |texture: &mut TextureModule, x: u32, y: u32, vector_value: [u32; 4]| {
                             texture.store::<[u32; 2]>(x, y, [vector_value[0], vector_value[1]]);
@@ -295,15 +569,18 @@ pub fn run_fragment_shader(mut call: FragmentShaderCall<'_>) {
                     _ => panic!("Unimplemented fragment output: {:?}", output),
                 };
                 let vector_output = &call.shading_unit_run_context.vector_output_array[output.output as usize];
-                for f in 0..call.fragmen_count {
+                let resolve = output.blend.is_some() || output.write_mask & FRAGMENT_WRITE_MASK_ALL != FRAGMENT_WRITE_MASK_ALL;
+                for f in (0..call.fragmen_count).filter(|&f| passed(f)) {
                     let position = call.shading_unit_run_context.vector_input_array[FRAGMENT_VECTOR_INPUT_BUILTIN_POSITION][f].map(|x| f32::from_bits(x) as u32);
-                    write_fn(texture, output.offset[0] + position[0], output.offset[1] + position[1], vector_output[f]);
+                    let Some((x, y)) = view.local(output.offset[0] + position[0], output.offset[1] + position[1]) else {
+                        continue;
+                    };
+                    let value = if resolve { resolve_output(output, &view.module, x, y, vector_output[f]) } else { vector_output[f] };
+                    write_fn(&mut view.module, x, y, value);
                 }
             }
             ShaderCardinality::V3 => {
-                let texture_index = call.resource_map.texture[output.texture as usize] as usize;
-                let texture = &mut call.texture_modules[texture_index];
-                let write_fn: fn(&mut TextureModule, u32, u32, [u32; 4]) -> () = match (output.t, texture.config.pixel_layout) {
+                let write_fn: fn(&mut TextureModule, u32, u32, [u32; 4]) -> () = match (output.t, view.module.config.pixel_layout) {
                     (FragmentOutputType::F32ToF32, PixelDataLayout::D32x4) => 
                         |texture: &mut TextureModule, x: u32, y: u32, vector_value: [u32; 4]| {
                             texture.store::<[u32; 3]>(x, y, [vector_value[0], vector_value[1], vector_value[2]]);
@@ -311,15 +588,18 @@ pub fn run_fragment_shader(mut call: FragmentShaderCall<'_>) {
                     _ => panic!("Unimplemented fragment output: {:?}", output),
                 };
                 let vector_output = &call.shading_unit_run_context.vector_output_array[output.output as usize];
-                for f in 0..call.fragmen_count {
+                let resolve = output.blend.is_some() || output.write_mask & FRAGMENT_WRITE_MASK_ALL != FRAGMENT_WRITE_MASK_ALL;
+                for f in (0..call.fragmen_count).filter(|&f| passed(f)) {
                     let position = call.shading_unit_run_context.vector_input_array[FRAGMENT_VECTOR_INPUT_BUILTIN_POSITION][f].map(|x| f32::from_bits(x) as u32);
-                    write_fn(texture, output.offset[0] + position[0], output.offset[1] + position[1], vector_output[f]);
+                    let Some((x, y)) = view.local(output.offset[0] + position[0], output.offset[1] + position[1]) else {
+                        continue;
+                    };
+                    let value = if resolve { resolve_output(output, &view.module, x, y, vector_output[f]) } else { vector_output[f] };
+                    write_fn(&mut view.module, x, y, value);
                 }
             }
             ShaderCardinality::V4 => {
-                let texture_index = call.resource_map.texture[output.texture as usize] as usize;
-                let texture = &mut call.texture_modules[texture_index];
-                let write_fn: fn(&mut TextureModule, u32, u32, [u32; 4]) -> () = match (output.t, texture.config.pixel_layout) {
+                let write_fn: fn(&mut TextureModule, u32, u32, [u32; 4]) -> () = match (output.t, view.module.config.pixel_layout) {
                     (FragmentOutputType::F32ToF32, PixelDataLayout::D32x4) => 
                         |texture: &mut TextureModule, x: u32, y: u32, vector_value: [u32; 4]| {
                             texture.store::<[u32; 4]>(x, y, vector_value);
@@ -331,12 +611,159 @@ pub fn run_fragment_shader(mut call: FragmentShaderCall<'_>) {
                     _ => panic!("Unimplemented fragment output: {:?}", output),
                 };
                 let vector_output = &call.shading_unit_run_context.vector_output_array[output.output as usize];
-                for f in 0..call.fragmen_count {
+                let resolve = output.blend.is_some() || output.write_mask & FRAGMENT_WRITE_MASK_ALL != FRAGMENT_WRITE_MASK_ALL;
+                for f in (0..call.fragmen_count).filter(|&f| passed(f)) {
                     let position = call.shading_unit_run_context.vector_input_array[FRAGMENT_VECTOR_INPUT_BUILTIN_POSITION][f].map(|x| f32::from_bits(x) as u32);
-                    write_fn(texture, output.offset[0] + position[0], output.offset[1] + position[1], vector_output[f]);
+                    let Some((x, y)) = view.local(output.offset[0] + position[0], output.offset[1] + position[1]) else {
+                        continue;
+                    };
+                    let value = if resolve { resolve_output(output, &view.module, x, y, vector_output[f]) } else { vector_output[f] };
+                    write_fn(&mut view.module, x, y, value);
                 }
             }
         }
     }
+    passed_count
+}
+
+
+/*
+reads the destination texel back in the output's source domain so it can be
+blended with (f32 sources only) or kept for masked out components.
+ */
+fn resolve_output(output: &FragmentOutputAssignment, texture: &TextureModule, x: u32, y: u32, src: [u32; 4]) -> [u32; 4] {
+    let dst = read_output_texel(output.t, texture, x, y);
+    let mut value = match output.blend {
+        Some(blend) if output.t.is_f32_source() => {
+            let mut src_f32 = src.map(f32::from_bits);
+            let mut dst_f32 = dst.map(f32::from_bits);
+            if output.c != ShaderCardinality::V4 {
+                src_f32[3] = 1.0;
+            }
+            if texture.config.pixel_layout.component_count() < 4 {
+                dst_f32[3] = 1.0;
+            }
+            blend.blend(src_f32, dst_f32).map(f32::to_bits)
+        },
+        _ => src,
+    };
+    for c in 0..4 {
+        if output.write_mask & (1 << c) == 0 {
+            value[c] = dst[c];
+        }
+    }
+    value
 }
 
+fn read_output_texel(t: FragmentOutputType, texture: &TextureModule, x: u32, y: u32) -> [u32; 4] {
+    let pixel_layout = texture.config.pixel_layout;
+    let raw = match pixel_layout {
+        PixelDataLayout::D8x1  => [texture.fetch::<u8>(x, y) as u32, 0, 0, 0],
+        PixelDataLayout::D8x2  => { let [a, b] = texture.fetch::<[u8; 2]>(x, y); [a as u32, b as u32, 0, 0] },
+        PixelDataLayout::D8x4  => texture.fetch::<[u8; 4]>(x, y).map(|c| c as u32),
+        PixelDataLayout::D16x1 => [texture.fetch::<u16>(x, y) as u32, 0, 0, 0],
+        PixelDataLayout::D16x2 => { let [a, b] = texture.fetch::<[u16; 2]>(x, y); [a as u32, b as u32, 0, 0] },
+        PixelDataLayout::D16x4 => texture.fetch::<[u16; 4]>(x, y).map(|c| c as u32),
+        PixelDataLayout::D32x1 => [texture.fetch::<u32>(x, y), 0, 0, 0],
+        PixelDataLayout::D32x2 => { let [a, b] = texture.fetch::<[u32; 2]>(x, y); [a, b, 0, 0] },
+        PixelDataLayout::D32x4 => texture.fetch::<[u32; 4]>(x, y),
+        PixelDataLayout::I4    |
+        PixelDataLayout::I8    => [texture.fetch_index(x, y) as u32, 0, 0, 0],
+    };
+    let shift = 32 - (pixel_layout.pixel_bits() / pixel_layout.component_count()) as u32;
+    let unsigned_max = (u32::MAX >> shift) as f32;
+    let signed_max = (u32::MAX >> (shift + 1)) as f32;
+    raw.map(|c| {
+        let signed = ((c << shift) as i32) >> shift;
+        match t {
+            FragmentOutputType::F32ToF32   => c,
+            FragmentOutputType::F32ToUInt  |
+            FragmentOutputType::F32ToUNorm => (c as f32 / unsigned_max).to_bits(),
+            FragmentOutputType::F32ToInt   |
+            FragmentOutputType::F32ToINorm => (signed as f32 / signed_max).to_bits(),
+            FragmentOutputType::IntToInt   => signed as u32,
+            FragmentOutputType::UIntToUInt => c,
+            FragmentOutputType::IntToF32   => f32::from_bits(c) as i32 as u32,
+            FragmentOutputType::UIntToF32  => f32::from_bits(c) as u32,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::types::{ImageDataLayout, Texture};
+
+    const ALPHA_BLEND: FragmentBlendState = FragmentBlendState {
+        color_src: BlendFactor::SrcAlpha,
+        color_dst: BlendFactor::OneMinusSrcAlpha,
+        color_op: BlendOp::Add,
+        alpha_src: BlendFactor::One,
+        alpha_dst: BlendFactor::Zero,
+        alpha_op: BlendOp::Add,
+    };
+
+    fn texture(pixel_layout: PixelDataLayout, texel: [u32; 4]) -> TextureModule {
+        let mut texture = TextureModule::new();
+        texture.config = Texture { width: 1, height: 1, pixel_layout, image_layout: ImageDataLayout::Contiguous };
+        texture.memory = texel.into();
+        texture
+    }
+
+    fn output(t: FragmentOutputType, blend: Option<FragmentBlendState>, write_mask: u8) -> FragmentOutputAssignment {
+        FragmentOutputAssignment { output: 0, texture: 0, t, c: ShaderCardinality::V4, offset: [0, 0], blend, write_mask }
+    }
+
+    #[test]
+    fn blend_factors_and_ops() {
+        let src = [1.0, 0.5, 0.0, 0.25];
+        let dst = [0.0, 1.0, 0.5, 0.5];
+        assert_eq!(ALPHA_BLEND.blend(src, dst), [0.25, 0.875, 0.375, 0.25]);
+
+        let blend = |color_src, color_dst, color_op, alpha_op| FragmentBlendState { color_src, color_dst, color_op, alpha_src: BlendFactor::One, alpha_dst: BlendFactor::One, alpha_op };
+        assert_eq!(blend(BlendFactor::One, BlendFactor::One, BlendOp::Subtract, BlendOp::ReverseSubtract).blend(src, dst), [1.0, -0.5, -0.5, 0.25]);
+        assert_eq!(blend(BlendFactor::DstColor, BlendFactor::Zero, BlendOp::ReverseSubtract, BlendOp::Add).blend(src, dst), [0.0, -0.5, 0.0, 0.75]);
+        assert_eq!(blend(BlendFactor::OneMinusDstAlpha, BlendFactor::SrcColor, BlendOp::Add, BlendOp::Add).blend(src, dst), [0.5, 0.75, 0.0, 0.75]);
+        // min and max ignore the factors
+        assert_eq!(blend(BlendFactor::Zero, BlendFactor::Zero, BlendOp::Min, BlendOp::Max).blend(src, dst), [0.0, 0.5, 0.0, 0.5]);
+        assert_eq!(blend(BlendFactor::Zero, BlendFactor::Zero, BlendOp::Max, BlendOp::Min).blend(src, dst), [1.0, 1.0, 0.5, 0.25]);
+    }
+
+    #[test]
+    fn stencil_ops() {
+        for (op, expected) in [
+            (StencilOp::Keep,           [0x00, 0x80, 0xff]),
+            (StencilOp::Zero,           [0x00, 0x00, 0x00]),
+            (StencilOp::Replace,        [0x5a, 0x5a, 0x5a]),
+            (StencilOp::IncrementClamp, [0x01, 0x81, 0xff]),
+            (StencilOp::DecrementClamp, [0x00, 0x7f, 0xfe]),
+            (StencilOp::Invert,         [0xff, 0x7f, 0x00]),
+            (StencilOp::IncrementWrap,  [0x01, 0x81, 0x00]),
+            (StencilOp::DecrementWrap,  [0xff, 0x7f, 0xfe]),
+        ] {
+            assert_eq!([0x00, 0x80, 0xff].map(|value| op.apply(value, 0x5a)), expected, "{:?}", op);
+        }
+    }
+
+    #[test]
+    fn write_mask_keeps_masked_out_components() {
+        let dst = [1.0f32, 2.0, 3.0, 4.0];
+        let texture = texture(PixelDataLayout::D32x4, dst.map(f32::to_bits));
+        let src = [5.0f32, 6.0, 7.0, 8.0].map(f32::to_bits);
+        let resolve = |blend, write_mask| resolve_output(&output(FragmentOutputType::F32ToF32, blend, write_mask), &texture, 0, 0, src).map(f32::from_bits);
+        assert_eq!(resolve(None, 0b0101), [5.0, 2.0, 7.0, 4.0]);
+        assert_eq!(resolve(None, 0), dst);
+        let add = FragmentBlendState { color_src: BlendFactor::One, color_dst: BlendFactor::One, color_op: BlendOp::Add, alpha_src: BlendFactor::One, alpha_dst: BlendFactor::One, alpha_op: BlendOp::Add };
+        assert_eq!(resolve(Some(add), 0b1010), [1.0, 8.0, 3.0, 12.0]);
+    }
+
+    #[test]
+    fn unorm_targets_blend_in_normalized_values() {
+        let texture = texture(PixelDataLayout::D8x4, [u32::from_le_bytes([0, 255, 51, 255]), 0, 0, 0]);
+        let src = [1.0f32, 0.0, 1.0, 0.2].map(f32::to_bits);
+        let value = resolve_output(&output(FragmentOutputType::F32ToUNorm, Some(ALPHA_BLEND), FRAGMENT_WRITE_MASK_ALL), &texture, 0, 0, src).map(f32::from_bits);
+        for (c, expected) in [0.2, 0.8, 0.36, 0.2].into_iter().enumerate() {
+            assert!((value[c] - expected).abs() < 1e-6, "component {} is {}, expected {}", c, value[c], expected);
+        }
+    }
+}
@@ -6,11 +6,18 @@ mod buffer;
 mod shader;
 mod vertex_shader;
 mod fragment_shader;
+mod compute_shader;
 mod rasterizer;
 mod shader_parser;
 mod pipeline_state;
+mod sprite;
+mod background;
+mod vram;
+mod statistics;
+mod queue;
 
-use std::sync::{mpsc::{self, Receiver, TryRecvError}, Arc};
+use std::sync::{mpsc::{self, Receiver, RecvTimeoutError, TryRecvError}, Arc};
+use std::time::Duration;
 use parking_lot::Mutex;
 use static_init::dynamic;
 use crate::{machine::{ReadResult, WriteResult}, pointer_queue::PointerQueue, ui::main_window::MainWindow};
@@ -35,49 +42,53 @@ pub fn gpu_init(machine: &Arc<Machine>, main_window: MainWindow) {
     });
 }
 
+// how often queues blocked on wait_until check ram again
+const WAIT_POLL_INTERVAL: Duration = Duration::from_micros(50);
+
 fn gpu_thread(queue: Receiver<(u32, u32)>, machine: Arc<Machine>, main_window: MainWindow) {
-    let mut core = Core::new();
+    let mut core = Core::new(machine.config.vram_size as usize, machine.config.raster_threads);
     loop {
-        match queue.recv() {
-            Ok((queue_index, commandlist_addr)) => {
-                match parse_commandlist_header(commandlist_addr, &machine) {
-                    Ok(command_list) => {
-                        core.add_command_list(command_list);
-                    },
-                    Err(error) => {
-                        println!("GPU: ERROR: Failed command list submission ({:#010X}): {:?}", commandlist_addr, error);
-                    },
-                }
-            },
-            Err(_) => {
-                return;
+        // only sleep on the submission channel once nothing can run
+        let submission = if core.process(&machine, &main_window) {
+            match queue.try_recv() {
+                Ok(submission) => Some(submission),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        } else if core.is_idle() {
+            match queue.recv() {
+                Ok(submission) => Some(submission),
+                Err(_) => return,
+            }
+        } else {
+            match queue.recv_timeout(WAIT_POLL_INTERVAL) {
+                Ok(submission) => Some(submission),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return,
             }
         };
-        'receive: loop {
-            match queue.try_recv() {
-                Ok((_queue_index, commandlist_addr)) => {
-                    match parse_commandlist_header(commandlist_addr, &machine) {
-                        Ok(command_list) => {
-                            core.add_command_list(command_list);
-                        },
-                        Err(error) => {
-                            println!("GPU: ERROR: Failed command list submission ({:#010X}): {:?}", commandlist_addr, error);
-                        },
-                    }
+        let Some(submission) = submission else {
+            continue;
+        };
+        // take everything already submitted so priorities apply across all of it
+        for (queue_index, commandlist_addr) in std::iter::once(submission).chain(queue.try_iter()) {
+            match parse_commandlist_header(commandlist_addr, &machine) {
+                Ok(command_list) => {
+                    core.add_command_list(queue_index as usize, command_list);
+                },
+                Err(error) => {
+                    println!("GPU: ERROR: Failed command list submission ({:#010X}): {:?}", commandlist_addr, error);
                 },
-                Err(TryRecvError::Empty) => break 'receive,
-                Err(TryRecvError::Disconnected) => return,
             }
         }
-        core.process(&machine, &main_window);
     }
 }
 
 pub fn gpu_write_u32(offset: u32, value: u32) -> WriteResult {
     match offset {
-        0 => {
+        0 | 4 | 8 => {
             GPU_QUEUE_LOCAL.with(|queue| {
-                queue.send((0, value)).unwrap();
+                queue.send((offset >> 2, value)).unwrap();
             });
             WriteResult::Ok
         },
@@ -95,7 +106,7 @@ pub fn gpu_write_u8(offset: u32, value: u8) -> WriteResult {
 
 pub fn gpu_read_u32(offset: u32) -> ReadResult<u32> {
     match offset {
-        0 => ReadResult::Ok(0),
+        0 | 4 | 8 => ReadResult::Ok(0),
         _ => ReadResult::InvalidAddress
     }
 }
@@ -1,9 +1,10 @@
 use std::sync::Arc;
 use crate::machine::{Machine, ReadResult};
-use super::rasterizer::{Interpolation, RasterizerState, RasterizerVaryingAssignment, ShaderVaryingType};
+use super::rasterizer::{CullMode, FrontFace, Interpolation, RasterRect, RasterizerState, RasterizerVaryingAssignment, ShaderVaryingType, Topology, Viewport};
 use super::shader::{ResourceMap, ShaderCardinality, ShaderInputType, ShaderConstantAssignment};
 use super::vertex_shader::{VertexInputAssignment, VertexState};
-use super::fragment_shader::{DepthCompareFn, FragmentDepthState, FragmentOutputAssignment, FragmentOutputType, FragmentState};
+use super::compute_shader::ComputePipelineState;
+use super::fragment_shader::{BlendFactor, BlendOp, DepthCompareFn, FragmentBlendState, FragmentDepthState, FragmentOutputAssignment, FragmentOutputType, FragmentState, FragmentStencilState, StencilFaceState, StencilOp, FRAGMENT_WRITE_MASK_ALL};
 
 #[derive(Debug, Default)]
 pub struct GraphicsPipelineState {
@@ -36,13 +37,14 @@ impl VertexState {
         let input_count = machine.read_u8(address + 4).to_opt()?;
         let mut inputs = Vec::new();
         for i in 0..input_count {
-            let input_address = input_array_address + i as u32 * 12;
+            let input_address = input_array_address + i as u32 * 16;
             let input = machine.read_u8(input_address + 0).to_opt()?;
             let src_buffer = machine.read_u8(input_address + 1).to_opt()?;
             let input_type = machine.read_u8(input_address + 2).to_opt()?;
             let input_cardinality = machine.read_u8(input_address + 3).to_opt()?;
             let offset = machine.read_u32(input_address + 4).to_opt()?;
             let stride = machine.read_u32(input_address + 8).to_opt()?;
+            let step_rate = machine.read_u32(input_address + 12).to_opt()?;
             let input_type = ShaderInputType::from_u8(input_type)?;
             let input_cardinality = ShaderCardinality::from_u8(input_cardinality)?;
             inputs.push(VertexInputAssignment {
@@ -50,6 +52,7 @@ impl VertexState {
                 src_buffer,
                 offset,
                 stride,
+                step_rate,
                 t: input_type,
                 c: input_cardinality
             });
@@ -65,15 +68,38 @@ impl FragmentState {
         let depth_state_address = machine.read_u32(address + 0).to_opt()?;
         let output_array_address = machine.read_u32(address + 4).to_opt()?;
         let output_count = machine.read_u8(address + 8).to_opt()?;
+        let blend_array_address = machine.read_u32(address + 12).to_opt()?;
         let depth_state = if depth_state_address != 0 {
             let depth_texture = machine.read_u8(depth_state_address + 0).to_opt()?;
             let compare_fn = machine.read_u8(depth_state_address + 1).to_opt()?;
             let compare_fn = DepthCompareFn::from_u8(compare_fn)?;
             let depth_write = machine.read_u8(depth_state_address + 2).to_opt()?;
+            let stencil_enable = machine.read_u8(depth_state_address + 3).to_opt()?;
+            let stencil = if stencil_enable != 0 {
+                let read_face = |face_address: u32| -> Option<StencilFaceState> {
+                    Some(StencilFaceState {
+                        compare_fn: DepthCompareFn::from_u8(machine.read_u8(face_address + 0).to_opt()?)?,
+                        fail_op: StencilOp::from_u8(machine.read_u8(face_address + 1).to_opt()?)?,
+                        depth_fail_op: StencilOp::from_u8(machine.read_u8(face_address + 2).to_opt()?)?,
+                        pass_op: StencilOp::from_u8(machine.read_u8(face_address + 3).to_opt()?)?,
+                    })
+                };
+                Some(FragmentStencilState {
+                    stencil_texture: machine.read_u8(depth_state_address + 4).to_opt()?,
+                    reference: machine.read_u8(depth_state_address + 5).to_opt()?,
+                    read_mask: machine.read_u8(depth_state_address + 6).to_opt()?,
+                    write_mask: machine.read_u8(depth_state_address + 7).to_opt()?,
+                    front: read_face(depth_state_address + 8)?,
+                    back: read_face(depth_state_address + 12)?,
+                })
+            } else {
+                None
+            };
             Some(FragmentDepthState {
                 depth_texture,
                 compare_fn,
-                depth_write: depth_write != 0
+                depth_write: depth_write != 0,
+                stencil
             })
         } else {
             None
@@ -89,12 +115,40 @@ impl FragmentState {
             let output_cardinality = ShaderCardinality::from_u8(output_cardinality)?;
             let offset_x = machine.read_u32(output_assignment_address + 4).to_opt()?;
             let offset_y = machine.read_u32(output_assignment_address + 8).to_opt()?;
+            let (blend, write_mask) = if blend_array_address != 0 {
+                let blend_address = blend_array_address + 8 * o as u32;
+                let blend_enable = machine.read_u8(blend_address + 0).to_opt()?;
+                let color_src = BlendFactor::from_u8(machine.read_u8(blend_address + 1).to_opt()?)?;
+                let color_dst = BlendFactor::from_u8(machine.read_u8(blend_address + 2).to_opt()?)?;
+                let color_op = BlendOp::from_u8(machine.read_u8(blend_address + 3).to_opt()?)?;
+                let alpha_src = BlendFactor::from_u8(machine.read_u8(blend_address + 4).to_opt()?)?;
+                let alpha_dst = BlendFactor::from_u8(machine.read_u8(blend_address + 5).to_opt()?)?;
+                let alpha_op = BlendOp::from_u8(machine.read_u8(blend_address + 6).to_opt()?)?;
+                let write_mask = machine.read_u8(blend_address + 7).to_opt()?;
+                let blend = if blend_enable != 0 {
+                    Some(FragmentBlendState {
+                        color_src,
+                        color_dst,
+                        color_op,
+                        alpha_src,
+                        alpha_dst,
+                        alpha_op
+                    })
+                } else {
+                    None
+                };
+                (blend, write_mask)
+            } else {
+                (None, FRAGMENT_WRITE_MASK_ALL)
+            };
             output_assignments.push(FragmentOutputAssignment {
                 output,
                 texture,
                 t: output_type,
                 c: output_cardinality,
-                offset: [offset_x, offset_y]
+                offset: [offset_x, offset_y],
+                blend,
+                write_mask
             });
         }
         Some(FragmentState {
@@ -115,6 +169,33 @@ impl RasterizerState {
         println!("RasterizerState::read_from_address(): constant_count = {constant_count}, constant_array_address: {:08X}", constant_array_address);
         let buffer_mapping_count = machine.read_u8(address + 18).to_opt()?;
         let texture_mapping_count = machine.read_u8(address + 19).to_opt()?;
+        let front_face = FrontFace::from_u8(machine.read_u8(address + 20).to_opt()?)?;
+        let cull_mode = CullMode::from_u8(machine.read_u8(address + 21).to_opt()?)?;
+        let flags = machine.read_u8(address + 22).to_opt()?;
+        let topology = Topology::from_u8(machine.read_u8(address + 23).to_opt()?)?;
+        let scissor = if flags & 1 != 0 {
+            let x_low = machine.read_u16(address + 24).to_opt()?;
+            let x_high = machine.read_u16(address + 26).to_opt()?;
+            let y_low = machine.read_u16(address + 28).to_opt()?;
+            let y_high = machine.read_u16(address + 30).to_opt()?;
+            Some(RasterRect {
+                upper_left:  (x_low  as u32, y_low  as u32),
+                lower_right: (x_high as u32, y_high as u32),
+            })
+        } else {
+            None
+        };
+        let viewport = if flags & 2 != 0 {
+            Some(Viewport {
+                x: f32::from_bits(machine.read_u32(address + 32).to_opt()?),
+                y: f32::from_bits(machine.read_u32(address + 36).to_opt()?),
+                width: f32::from_bits(machine.read_u32(address + 40).to_opt()?),
+                height: f32::from_bits(machine.read_u32(address + 44).to_opt()?),
+            })
+        } else {
+            None
+        };
+        let line_width = f32::from_bits(machine.read_u32(address + 48).to_opt()?);
         let mut varyings = Vec::new();
         let mut constants = Vec::new();
         for v in 0..varying_count {
@@ -157,6 +238,52 @@ impl RasterizerState {
 
         Some(RasterizerState {
             varyings,
+            constants,
+            resource_map,
+            front_face,
+            cull_mode,
+            scissor,
+            viewport,
+            topology,
+            line_width
+        })
+    }
+}
+
+impl ComputePipelineState {
+    pub fn read_from_address(address: u32, machine: &Arc<Machine>) -> Option<Self> {
+        let constant_array_address = machine.read_u32(address + 0).to_opt()?;
+        let buffer_mapping_array_address = machine.read_u32(address + 4).to_opt()?;
+        let texture_mapping_array_address = machine.read_u32(address + 8).to_opt()?;
+        let constant_count = machine.read_u8(address + 12).to_opt()?;
+        let buffer_mapping_count = machine.read_u8(address + 13).to_opt()?;
+        let texture_mapping_count = machine.read_u8(address + 14).to_opt()?;
+        let mut constants = Vec::new();
+        for c in 0..constant_count {
+            let constant_address = constant_array_address + c as u32 * 8;
+            let offset = machine.read_u32(constant_address + 0).to_opt()?;
+            let constant = machine.read_u8(constant_address + 4).to_opt()?;
+            let source_buffer = machine.read_u8(constant_address + 5).to_opt()?;
+            let c = machine.read_u8(constant_address + 6).to_opt()?;
+            let c = ShaderCardinality::from_u8(c)?;
+            let t = machine.read_u8(constant_address + 7).to_opt()?;
+            let t = ShaderInputType::from_u8(t)?;
+            constants.push(ShaderConstantAssignment {
+                constant,
+                source_buffer,
+                offset,
+                t,
+                c
+            });
+        }
+        let mut resource_map = ResourceMap::default();
+        if buffer_mapping_count != 0 {
+            machine.read_block(buffer_mapping_array_address, &mut resource_map.buffer[0..buffer_mapping_count as usize]).to_opt()?;
+        }
+        if texture_mapping_count != 0 {
+            machine.read_block(texture_mapping_array_address, &mut resource_map.texture[0..texture_mapping_count as usize]).to_opt()?;
+        }
+        Some(ComputePipelineState {
             constants,
             resource_map
         })
@@ -0,0 +1,56 @@
+use std::collections::VecDeque;
+
+use crate::command_list::CommandList;
+
+use super::statistics::GpuStatistics;
+
+/*
+submission queues, each with its own register. graphics sits at offset 0 where
+the single queue used to be. the gpu runs whichever queue that can make progress
+comes first in QUEUE_PRIORITY, switching between queues at the end of a command
+list, after a jump_list or when a queue blocks on wait_fence or wait_until.
+ */
+pub const QUEUE_GRAPHICS: usize = 0;
+pub const QUEUE_TRANSFER: usize = 1;
+pub const QUEUE_HIGH_PRIORITY: usize = 2;
+pub const QUEUE_COUNT: usize = 3;
+
+pub const QUEUE_PRIORITY: [usize; QUEUE_COUNT] = [QUEUE_HIGH_PRIORITY, QUEUE_GRAPHICS, QUEUE_TRANSFER];
+
+pub const FENCE_COUNT: usize = 16;
+
+// a command list part way through execution, along with the lists that called it
+pub struct ListCursor {
+    pub list: CommandList,
+    pub offset: u32,
+    pub call_stack: Vec<(CommandList, u32)>,
+}
+
+impl ListCursor {
+    pub fn new(list: CommandList) -> Self {
+        Self {
+            list,
+            offset: 0,
+            call_stack: Vec::new(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct CommandQueue {
+    pub pending: VecDeque<CommandList>,
+    // the list that was running when the queue last blocked
+    pub active: Option<ListCursor>,
+    // counters for statistics queries, only counting work run from this queue
+    pub statistics: GpuStatistics,
+}
+
+impl CommandQueue {
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty() && self.active.is_none()
+    }
+
+    pub fn next_list(&mut self) -> Option<ListCursor> {
+        self.active.take().or_else(|| self.pending.pop_front().map(ListCursor::new))
+    }
+}
@@ -1,4 +1,9 @@
-use super::{buffer::BufferModule, fragment_shader::*, shader::*, texture::TextureModule, vertex_shader::*};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::JoinHandle;
+use super::{buffer::{BufferAccess, BufferModule}, fragment_shader::*, shader::*, texture::TextureModule, vertex_shader::*};
+use super::statistics::GpuStatistics;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Interpolation {
@@ -56,18 +61,158 @@ pub struct RasterizerVaryingAssignment {
     pub t: ShaderVaryingType,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum FrontFace {
+    #[default]
+    CounterClockwise,
+    Clockwise,
+}
+
+impl FrontFace {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        Some(match x {
+            0 => Self::CounterClockwise,
+            1 => Self::Clockwise,
+            _ => None?
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum CullMode {
+    #[default]
+    None,
+    Front,
+    Back,
+}
+
+impl CullMode {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        Some(match x {
+            0 => Self::None,
+            1 => Self::Front,
+            2 => Self::Back,
+            _ => None?
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Topology {
+    #[default]
+    TriangleList,
+    TriangleStrip,
+    TriangleFan,
+    PointList,
+    LineList,
+    LineStrip,
+}
+
+impl Topology {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        Some(match x {
+            0 => Self::TriangleList,
+            1 => Self::TriangleStrip,
+            2 => Self::TriangleFan,
+            3 => Self::PointList,
+            4 => Self::LineList,
+            5 => Self::LineStrip,
+            _ => None?
+        })
+    }
+
+    pub fn primitive_count(&self, vertex_count: usize) -> usize {
+        match self {
+            Self::TriangleList => vertex_count / 3,
+            Self::TriangleStrip | Self::TriangleFan => vertex_count.saturating_sub(2),
+            Self::PointList => vertex_count,
+            Self::LineList => vertex_count / 2,
+            Self::LineStrip => vertex_count.saturating_sub(1),
+        }
+    }
+
+    /*
+    the vertices of a primitive, provoking vertex first. odd strip triangles
+    swap their last two vertices and fan triangles rotate the center vertex to
+    the back, so winding is kept without moving the provoking vertex. points
+    and lines repeat their last vertex.
+     */
+    pub fn primitive_vertices(&self, primitive: usize) -> [usize; 3] {
+        let p = primitive;
+        match self {
+            Self::TriangleList => [p * 3, p * 3 + 1, p * 3 + 2],
+            Self::TriangleStrip if p.is_multiple_of(2) => [p, p + 1, p + 2],
+            Self::TriangleStrip => [p, p + 2, p + 1],
+            Self::TriangleFan => [p + 1, p + 2, 0],
+            Self::PointList => [p; 3],
+            Self::LineList => [p * 2, p * 2 + 1, p * 2 + 1],
+            Self::LineStrip => [p, p + 1, p + 1],
+        }
+    }
+}
+
+/*
+maps normalized device coordinates (after the divide by w) onto a pixel rect.
+-1 lands on x/y and +1 lands on x + width / y + height.
+ */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct RasterizerState {
     pub varyings: Vec<RasterizerVaryingAssignment>,
     pub constants: Vec<ShaderConstantAssignment>,
     pub resource_map: ResourceMap,
+    pub front_face: FrontFace,
+    pub cull_mode: CullMode,
+    pub scissor: Option<RasterRect>,
+    pub viewport: Option<Viewport>,
+    pub topology: Topology,
+    // in pixels, points take their size from the vertex shader instead
+    pub line_width: f32,
 }
 
+#[derive(Copy, Clone, Debug)]
 pub struct RasterRect {
     pub upper_left  : (u32, u32),
     pub lower_right : (u32, u32),
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IndexType {
+    U16,
+    U32,
+}
+
+impl IndexType {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        Some(match x {
+            0 => Self::U16,
+            1 => Self::U32,
+            _ => None?
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            Self::U16 => 2,
+            Self::U32 => 4,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct IndexBuffer {
+    pub buffer: u8,
+    pub offset: u32,
+    pub t: IndexType,
+}
+
 pub struct RasterizerCall<'a> {
     pub constant_array           :  &'a mut ShadingUnitConstantArray,
     pub io_arrays                : &'a mut [ShadingUnitIOArray; 3],
@@ -77,8 +222,11 @@ pub struct RasterizerCall<'a> {
     pub shader_modules           :  &'a [ShaderModule; 128],
 
     pub vertex_count             : usize,
+    pub instance_count           : usize,
+    pub index_buffer             : Option<IndexBuffer>,
 
     pub shading_unit_context     : &'a mut ShadingUnitContext,
+    pub workers                  : &'a mut [RasterWorker],
 
     pub state                    : &'a RasterizerState,
 
@@ -91,19 +239,187 @@ pub struct RasterizerCall<'a> {
     pub target_rect              : RasterRect,
 
     pub resource_map             : &'a ResourceMap,
+
+    pub statistics               : &'a mut GpuStatistics,
 }
 
 const TILE_SIZE: u32 = 32;
 
+/*
+fragment shading state for a raster thread beyond the first, which shades with
+the core's own context. io_arrays are [0] fragment outputs and [1] fragment
+inputs, vertex outputs are shared from the core. the thread lives as long as
+the worker, so batches don't pay for spawning threads.
+ */
+pub struct RasterWorker {
+    shading_unit_context: Box<ShadingUnitContext>,
+    io_arrays: Box<[ShadingUnitIOArray; 2]>,
+    constant_array: ShadingUnitConstantArray,
+    thread: RasterThread,
+}
+
+impl RasterWorker {
+    pub fn new() -> Self {
+        Self {
+            shading_unit_context: ShadingUnitContext::new(),
+            io_arrays: unsafe { Box::new_zeroed().assume_init() },
+            constant_array: ShadingUnitConstantArray::new(),
+            thread: RasterThread::spawn(),
+        }
+    }
+
+    // the workers a rasterizer running on thread_count threads needs
+    pub fn pool(thread_count: usize) -> Vec<Self> {
+        (1..thread_count).map(|_| Self::new()).collect()
+    }
+}
+
+type RasterJob = Box<dyn FnOnce() + Send + 'static>;
+
+struct RasterThread {
+    jobs: Option<Sender<RasterJob>>,
+    done: Receiver<()>,
+    handle: Option<JoinHandle<()>>,
+}
+
+// waits for a job handed to a RasterThread when dropped
+struct RasterJobGuard<'a> {
+    done: &'a Receiver<()>,
+}
+
+impl RasterThread {
+    fn spawn() -> Self {
+        let (job_sender, job_receiver) = mpsc::channel::<RasterJob>();
+        let (done_sender, done_receiver) = mpsc::channel();
+        let handle = std::thread::Builder::new().name("raster worker".into()).spawn(move || {
+            for job in job_receiver {
+                job();
+                if done_sender.send(()).is_err() {
+                    break;
+                }
+            }
+        }).expect("failed to spawn raster worker thread");
+        Self {
+            jobs: Some(job_sender),
+            done: done_receiver,
+            handle: Some(handle),
+        }
+    }
+
+    /*
+    runs job on the thread. the job may borrow from the caller, so the guard
+    it returns has to be dropped (which waits for the job) before those borrows
+    end, also when the caller unwinds. if the job panics, the guard still
+    returns once the job has been dropped.
+     */
+    fn run<'a>(&'a self, job: Box<dyn FnOnce() + Send + 'a>) -> RasterJobGuard<'a> {
+        let job: RasterJob = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'a>, RasterJob>(job) };
+        self.jobs.as_ref().unwrap().send(job).expect("raster worker thread exited");
+        RasterJobGuard { done: &self.done }
+    }
+}
+
+impl Drop for RasterThread {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for RasterJobGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.done.recv();
+    }
+}
+
+/*
+a primitive after clipping, viewport transform and culling: a convex polygon of
+3 or 4 corners in target coordinates. weights map the first three corners onto
+the primitive's vertices.
+ */
+#[derive(Copy, Clone, Debug)]
+struct ScreenPolygon {
+    vertices: [usize; 3],
+    corners: [[f32; 4]; 4],
+    corner_count: usize,
+    weights: [[f32; 3]; 3],
+    front_facing: bool,
+}
+
+struct Tile {
+    rect: RasterRect,
+    polygons: Vec<u32>,
+}
+
 pub fn run_rasterizer(mut call: RasterizerCall<'_>) {
     setup_shader_constants(call.constant_array, &call.state.constants[..], &call.state.resource_map, call.buffer_modules);
+    for worker in call.workers.iter_mut() {
+        worker.constant_array.clone_from(call.constant_array);
+    }
 
-    let mut vertex_count = call.vertex_count;
-    let mut vertex_offset = 0;
+    let clip_rect = match call.state.scissor {
+        Some(scissor) => RasterRect {
+            upper_left:  (call.target_rect.upper_left.0.max(scissor.upper_left.0),   call.target_rect.upper_left.1.max(scissor.upper_left.1)),
+            lower_right: (call.target_rect.lower_right.0.min(scissor.lower_right.0), call.target_rect.lower_right.1.min(scissor.lower_right.1)),
+        },
+        None => call.target_rect,
+    };
+    if clip_rect.upper_left.0 > clip_rect.lower_right.0 || clip_rect.upper_left.1 > clip_rect.lower_right.1 {
+        return;
+    }
+
+    // tiles are aligned to TILE_SIZE in target coordinates, then cut down to the clip rect
+    let tile_origin = (clip_rect.upper_left.0 / TILE_SIZE, clip_rect.upper_left.1 / TILE_SIZE);
+    let tiles_x = clip_rect.lower_right.0 / TILE_SIZE - tile_origin.0 + 1;
+    let tiles_y = clip_rect.lower_right.1 / TILE_SIZE - tile_origin.1 + 1;
+    let mut tiles: Vec<Tile> = (0..tiles_x * tiles_y).map(|t| {
+        let x = (tile_origin.0 + t % tiles_x) * TILE_SIZE;
+        let y = (tile_origin.1 + t / tiles_x) * TILE_SIZE;
+        Tile {
+            rect: RasterRect {
+                upper_left:  (x.max(clip_rect.upper_left.0), y.max(clip_rect.upper_left.1)),
+                lower_right: ((x + TILE_SIZE - 1).min(clip_rect.lower_right.0), (y + TILE_SIZE - 1).min(clip_rect.lower_right.1)),
+            },
+            polygons: Vec::new(),
+        }
+    }).collect();
+
+    let topology = call.state.topology;
+    let primitive_count = topology.primitive_count(call.vertex_count);
+    let mut invocations = Vec::with_capacity(CORE_COUNT);
+    let mut primitives = Vec::with_capacity(CORE_COUNT);
+    let mut polygons = Vec::new();
+    // post-transform vertex cache: maps a vertex id to the lane it was already shaded in, within the current batch
+    let mut vertex_cache = HashMap::new();
+
+    for instance in 0..call.instance_count as u32 {
+        let mut next_primitive = 0;
+        while next_primitive < primitive_count {
+            invocations.clear();
+            primitives.clear();
+            vertex_cache.clear();
+            while next_primitive < primitive_count && invocations.len() + 3 <= CORE_COUNT {
+                let mut primitive = [0usize; 3];
+                for (k, (lane, vertex)) in primitive.iter_mut().zip(topology.primitive_vertices(next_primitive)).enumerate() {
+                    let Some(vertex_id) = fetch_vertex_id(call.index_buffer, call.buffer_modules, vertex) else {
+                        println!("GPU: draw ERROR: index {} out of range of the index buffer!", vertex);
+                        return;
+                    };
+                    *lane = *vertex_cache.entry(vertex_id).or_insert_with(|| {
+                        invocations.push(VertexInvocation {
+                            vertex_id,
+                            provoking: false,
+                        });
+                        invocations.len() - 1
+                    });
+                    invocations[*lane].provoking |= k == 0;
+                }
+                primitives.push(primitive);
+                next_primitive += 1;
+            }
 
-    while vertex_count > 0 {
-        let vertex_invocation_count = vertex_count - (vertex_count % 3);
-        {
             let vertex_run_context = unsafe { ShadingUnitRunContext {
                 scalar_input_array:  &mut *(&mut call.io_arrays[0].scalar_array as *mut _),
                 vector_input_array:  &mut *(&mut call.io_arrays[0].vector_array as *mut _),
@@ -111,12 +427,14 @@ pub fn run_rasterizer(mut call: RasterizerCall<'_>) {
                 vector_output_array: &mut *(&mut call.io_arrays[1].vector_array as *mut _),
                 scalar_constant_array: &mut *(&mut call.constant_array.scalar_constant_array as *mut _),
                 vector_constant_array: &mut *(&mut call.constant_array.vector_constant_array as *mut _),
+                sampler_array: & *(&call.constant_array.sampler_array as *const _),
+                derivatives: false,
             } };
             let vertex_call = unsafe { VertexShaderCall {
                 shader: call.vertex_shader,
                 state: & *(call.vertex_state as *const _),
-                vertex_count: vertex_invocation_count,
-                vertex_offset,
+                invocations: &invocations[..],
+                instance,
                 shading_unit_context: &mut *(call.shading_unit_context as *mut _),
                 shading_unit_run_context: vertex_run_context,
                 buffer_modules: &mut *(call.buffer_modules as *mut _),
@@ -124,388 +442,803 @@ pub fn run_rasterizer(mut call: RasterizerCall<'_>) {
                 resource_map: call.resource_map,
                 shader_modules: & *(call.shader_modules as *const _),
             } };
-            match run_vertex_shader(vertex_call) {
-                Ok(result) => {
-                    vertex_count = result.remaining_count;
-                    vertex_offset = result.remaining_offset;
-                },
-                Err(e) => {
-                    println!("GPU: VERTEX SHADER ERROR: {:?}", e);
-                    return
-                },
-            };
+            if let Err(e) = run_vertex_shader(vertex_call) {
+                println!("GPU: VERTEX SHADER ERROR: {:?}", e);
+                return
+            }
+            call.statistics.vertices_shaded = call.statistics.vertices_shaded.wrapping_add(invocations.len() as u32);
+
+            polygons.clear();
+            let vertex_outputs = &call.io_arrays[1];
+            for &primitive in primitives.iter() {
+                if primitive.iter().any(|&v| vertex_outputs.scalar_array[VERTEX_SCALAR_OUTPUT_BUILTIN_VERTEX_DISCARD][v] != 0) {
+                    continue;
+                }
+
+                let [p0, p1, p2] = primitive.map(|v| vertex_outputs.vector_array[VERTEX_VECTOR_OUTPUT_BUILTIN_VERTEX_POSITION][v].map(|x| f32::from_bits(x)));
+                let polygon_count = polygons.len();
+
+                match topology {
+                    Topology::PointList => {
+                        let point_size = f32::from_bits(vertex_outputs.scalar_array[VERTEX_SCALAR_OUTPUT_BUILTIN_POINT_SIZE][primitive[0]]);
+                        setup_point(call.state, &call.target_rect, primitive, p0, point_size, &mut polygons);
+                    },
+                    Topology::LineList | Topology::LineStrip => setup_line(call.state, &call.target_rect, primitive, [p0, p1], &mut polygons),
+                    Topology::TriangleList | Topology::TriangleStrip | Topology::TriangleFan => setup_triangle(call.state, &call.target_rect, primitive, [p0, p1, p2], &mut polygons),
+                }
+                if polygons.len() > polygon_count {
+                    call.statistics.primitives_rasterized = call.statistics.primitives_rasterized.wrapping_add(1);
+                }
+            }
+
+            for (p, polygon) in polygons.iter().enumerate() {
+                let Some(bounds) = polygon_bounds(polygon, &clip_rect) else {
+                    continue;
+                };
+                for tile_y in bounds.upper_left.1 / TILE_SIZE..=bounds.lower_right.1 / TILE_SIZE {
+                    for tile_x in bounds.upper_left.0 / TILE_SIZE..=bounds.lower_right.0 / TILE_SIZE {
+                        tiles[((tile_x - tile_origin.0) + (tile_y - tile_origin.1) * tiles_x) as usize].polygons.push(p as u32);
+                    }
+                }
+            }
+
+            let tile_statistics = shade_tiles(&mut call, &polygons, &tiles);
+            call.statistics.add(&tile_statistics);
+
+            for tile in tiles.iter_mut() {
+                tile.polygons.clear();
+            }
         }
+    }
+}
 
-        let target_rect_width = call.target_rect.lower_right.0 - call.target_rect.upper_left.0;
-        let target_rect_height = call.target_rect.lower_right.1 - call.target_rect.upper_left.1;
+fn setup_triangle(state: &RasterizerState, target_rect: &RasterRect, vertices: [usize; 3], positions: [[f32; 4]; 3], polygons: &mut Vec<ScreenPolygon>) {
+    // positions are only clip space when there's a viewport to divide them into
+    let polygon = clip_triangle(positions, state.viewport.is_some());
 
-        let mut fragment_invocation_count = 0;
+    for s in 1..polygon.count.saturating_sub(1) {
+        let triangle = [polygon.vertices[0], polygon.vertices[s], polygon.vertices[s + 1]];
 
-        for t in 0..vertex_invocation_count / 3 {
-            let v0 = t * 3 + 0;
-            let v1 = t * 3 + 1;
-            let v2 = t * 3 + 2;
+        let p0_sc = viewport_transform(triangle[0].position, state.viewport, target_rect);
+        let p1_sc = viewport_transform(triangle[1].position, state.viewport, target_rect);
+        let p2_sc = viewport_transform(triangle[2].position, state.viewport, target_rect);
 
-            let discard_a = call.io_arrays[1].scalar_array[VERTEX_SCALAR_OUTPUT_BUILTIN_VERTEX_DISCARD][v0];
-            let discard_b = call.io_arrays[1].scalar_array[VERTEX_SCALAR_OUTPUT_BUILTIN_VERTEX_DISCARD][v1];
-            let discard_c = call.io_arrays[1].scalar_array[VERTEX_SCALAR_OUTPUT_BUILTIN_VERTEX_DISCARD][v2];
+        // screen space y points down, so a positive area winds clockwise on screen
+        let area = (p1_sc[0] - p0_sc[0]) * (p2_sc[1] - p0_sc[1]) - (p2_sc[0] - p0_sc[0]) * (p1_sc[1] - p0_sc[1]);
+        let front_facing = (area > 0.0) == (state.front_face == FrontFace::Clockwise);
+        match state.cull_mode {
+            CullMode::Front if front_facing => continue,
+            CullMode::Back if !front_facing => continue,
+            _ => {}
+        }
 
-            if (discard_a | discard_b | discard_c) != 0 {
+        polygons.push(ScreenPolygon {
+            vertices,
+            corners: [p0_sc, p1_sc, p2_sc, p2_sc],
+            corner_count: 3,
+            weights: triangle.map(|vertex| vertex.weights),
+            front_facing,
+        });
+    }
+}
+
+/*
+points are squares of the vertex shader's point size, centered on the vertex.
+they're clipped as a whole: one past the near or far plane isn't drawn at all.
+ */
+fn setup_point(state: &RasterizerState, target_rect: &RasterRect, vertices: [usize; 3], position: [f32; 4], point_size: f32, polygons: &mut Vec<ScreenPolygon>) {
+    if state.viewport.is_some() && CLIP_PLANES.iter().any(|plane| plane(position) < 0.0) {
+        return;
+    }
+    let half_size = point_size * 0.5;
+    if half_size.is_nan() || half_size <= 0.0 {
+        return;
+    }
+    let center = viewport_transform(position, state.viewport, target_rect);
+    polygons.push(ScreenPolygon {
+        vertices,
+        corners: [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].map(|(dx, dy)| [center[0] + dx * half_size, center[1] + dy * half_size, center[2], center[3]]),
+        corner_count: 4,
+        weights: [[1.0, 0.0, 0.0]; 3],
+        front_facing: true,
+    });
+}
+
+/*
+lines are rectangles line_width pixels wide, centered on the segment between
+the two vertices.
+ */
+fn setup_line(state: &RasterizerState, target_rect: &RasterRect, vertices: [usize; 3], positions: [[f32; 4]; 2], polygons: &mut Vec<ScreenPolygon>) {
+    let Some([a, b]) = clip_line(positions, state.viewport.is_some()) else {
+        return;
+    };
+    let a_sc = viewport_transform(a.position, state.viewport, target_rect);
+    let b_sc = viewport_transform(b.position, state.viewport, target_rect);
+    let dx = b_sc[0] - a_sc[0];
+    let dy = b_sc[1] - a_sc[1];
+    let length = (dx * dx + dy * dy).sqrt();
+    let half_width = state.line_width * 0.5;
+    if length <= 0.0 || half_width.is_nan() || half_width <= 0.0 {
+        return;
+    }
+    let nx = -dy / length * half_width;
+    let ny =  dx / length * half_width;
+    polygons.push(ScreenPolygon {
+        vertices,
+        corners: [
+            [a_sc[0] + nx, a_sc[1] + ny, a_sc[2], a_sc[3]],
+            [b_sc[0] + nx, b_sc[1] + ny, b_sc[2], b_sc[3]],
+            [b_sc[0] - nx, b_sc[1] - ny, b_sc[2], b_sc[3]],
+            [a_sc[0] - nx, a_sc[1] - ny, a_sc[2], a_sc[3]],
+        ],
+        corner_count: 4,
+        weights: [a.weights, b.weights, b.weights],
+        front_facing: true,
+    });
+}
+
+// the pixels of clip_rect a polygon's bounding box touches, if any
+fn polygon_bounds(polygon: &ScreenPolygon, clip_rect: &RasterRect) -> Option<RasterRect> {
+    let corners = &polygon.corners[0..polygon.corner_count];
+    let x_min_target = corners.iter().fold(f32::MAX, |m, p| m.min(p[0]));
+    let y_min_target = corners.iter().fold(f32::MAX, |m, p| m.min(p[1]));
+    let x_max_target = corners.iter().fold(f32::MIN, |m, p| m.max(p[0]));
+    let y_max_target = corners.iter().fold(f32::MIN, |m, p| m.max(p[1]));
+
+    if x_max_target < clip_rect.upper_left.0 as f32 || x_min_target > clip_rect.lower_right.0 as f32 || y_max_target < clip_rect.upper_left.1 as f32 || y_min_target > clip_rect.lower_right.1 as f32 {
+        return None;
+    }
+
+    Some(RasterRect {
+        upper_left: (
+            ((x_min_target as i32).max(0) as u32).clamp(clip_rect.upper_left.0, clip_rect.lower_right.0),
+            ((y_min_target as i32).max(0) as u32).clamp(clip_rect.upper_left.1, clip_rect.lower_right.1),
+        ),
+        lower_right: (
+            ((x_max_target as i32).max(0) as u32).clamp(clip_rect.upper_left.0, clip_rect.lower_right.0),
+            ((y_max_target as i32).max(0) as u32).clamp(clip_rect.upper_left.1, clip_rect.lower_right.1),
+        ),
+    })
+}
+
+/*
+spreads the batch's tiles over the core and the raster workers. every tile is
+scanned in primitive order into its own target views, which are written back in
+tile order once shading is done, so results don't depend on how many threads
+there are or which one picks up which tile.
+ */
+fn shade_tiles(call: &mut RasterizerCall<'_>, polygons: &[ScreenPolygon], tiles: &[Tile]) -> GpuStatistics {
+    let busy_tiles = tiles.iter().filter(|tile| !tile.polygons.is_empty()).count();
+    let thread_count = if call.shader_modules[call.fragment_shader as usize].writes_buffers() {
+        1
+    } else {
+        (call.workers.len() + 1).min(busy_tiles)
+    };
+    if thread_count == 0 {
+        return GpuStatistics::default();
+    }
+
+    let next_tile = AtomicUsize::new(0);
+    let [core_outputs, vertex_outputs, core_inputs] = &mut *call.io_arrays;
+    let vertex_outputs = &*vertex_outputs;
+    let texture_modules = &*call.texture_modules;
+    let (shader_modules, state, fragment_shader, fragment_state, resource_map) = (call.shader_modules, call.state, call.fragment_shader, call.fragment_state, call.resource_map);
+
+    let run_worker = |shading_unit_context: &mut ShadingUnitContext, fragment_outputs: &mut ShadingUnitIOArray, fragment_inputs: &mut ShadingUnitIOArray, constant_array: &mut ShadingUnitConstantArray, buffer_modules: BufferAccess<'_>| {
+        let mut tile_call = TileShadingCall {
+            vertex_outputs,
+            fragment_inputs,
+            fragment_outputs,
+            constant_array,
+            shading_unit_context,
+            buffer_modules,
+            texture_modules,
+            shader_modules,
+            state,
+            fragment_shader,
+            fragment_state,
+            resource_map,
+            targets: TileTargets::default(),
+            fragment_invocation_count: 0,
+            statistics: GpuStatistics::default(),
+        };
+        let mut shaded = Vec::new();
+        loop {
+            let index = next_tile.fetch_add(1, Ordering::Relaxed);
+            let Some(tile) = tiles.get(index) else {
+                break;
+            };
+            if tile.polygons.is_empty() {
                 continue;
             }
+            tile_call.targets = TileTargets::load(fragment_state, resource_map, texture_modules, tile.rect.upper_left, tile.rect.lower_right);
+            for &p in tile.polygons.iter() {
+                scan_polygon(&mut tile_call, &tile.rect, &polygons[p as usize]);
+            }
+            flush_fragments(&mut tile_call);
+            shaded.push((index, std::mem::take(&mut tile_call.targets)));
+        }
+        (shaded, tile_call.statistics)
+    };
 
-            let p0 = call.io_arrays[1].vector_array[VERTEX_VECTOR_OUTPUT_BUILTIN_VERTEX_POSITION][v0].map(|x| f32::from_bits(x));
-            let p1 = call.io_arrays[1].vector_array[VERTEX_VECTOR_OUTPUT_BUILTIN_VERTEX_POSITION][v1].map(|x| f32::from_bits(x));
-            let p2 = call.io_arrays[1].vector_array[VERTEX_VECTOR_OUTPUT_BUILTIN_VERTEX_POSITION][v2].map(|x| f32::from_bits(x));
+    let results = if thread_count == 1 {
+        vec![run_worker(call.shading_unit_context, core_outputs, core_inputs, call.constant_array, BufferAccess::Exclusive(call.buffer_modules))]
+    } else {
+        let buffer_modules = &*call.buffer_modules;
+        let run_worker = &run_worker;
+        let mut worker_results: Vec<_> = (1..thread_count).map(|_| None).collect();
+        let guards: Vec<_> = call.workers[0..thread_count - 1].iter_mut().zip(worker_results.iter_mut()).map(|(worker, result)| {
+            let RasterWorker { shading_unit_context, io_arrays, constant_array, thread } = worker;
+            let [fragment_outputs, fragment_inputs] = &mut **io_arrays;
+            thread.run(Box::new(move || {
+                *result = Some(run_worker(shading_unit_context, fragment_outputs, fragment_inputs, constant_array, BufferAccess::Shared(buffer_modules)));
+            }))
+        }).collect();
+        let mut results = vec![run_worker(call.shading_unit_context, core_outputs, core_inputs, call.constant_array, BufferAccess::Shared(buffer_modules))];
+        drop(guards);
+        results.extend(worker_results.into_iter().map(|result| result.expect("raster worker panicked")));
+        results
+    };
 
-            let p0_sc = [
-                ((p0[0] as f32) * 0.5 + 0.5) * target_rect_width  as f32 + call.target_rect.upper_left.0 as f32,
-                ((p0[1] as f32) * 0.5 + 0.5) * target_rect_height  as f32 + call.target_rect.upper_left.1 as f32,
-                p0[2],
-                p0[3]
-            ];
-            let p1_sc = [
-                ((p1[0] as f32) * 0.5 + 0.5) * target_rect_width  as f32 + call.target_rect.upper_left.0 as f32,
-                ((p1[1] as f32) * 0.5 + 0.5) * target_rect_height  as f32 + call.target_rect.upper_left.1 as f32,
-                p0[2],
-                p0[3]
-            ];
-            let p2_sc = [
-                ((p2[0] as f32) * 0.5 + 0.5) * target_rect_width  as f32 + call.target_rect.upper_left.0 as f32,
-                ((p2[1] as f32) * 0.5 + 0.5) * target_rect_height  as f32 + call.target_rect.upper_left.1 as f32,
-                p0[2],
-                p0[3]
-            ];
+    let mut statistics = GpuStatistics::default();
+    let mut shaded = Vec::new();
+    for (tiles, worker_statistics) in results {
+        statistics.add(&worker_statistics);
+        shaded.extend(tiles);
+    }
+    shaded.sort_by_key(|&(index, _)| index);
+    for (_, targets) in shaded {
+        targets.write_back(call.texture_modules);
+    }
+    statistics
+}
+
+struct TileShadingCall<'a> {
+    vertex_outputs           : &'a ShadingUnitIOArray,
+    fragment_inputs          : &'a mut ShadingUnitIOArray,
+    fragment_outputs         : &'a mut ShadingUnitIOArray,
+    constant_array           : &'a mut ShadingUnitConstantArray,
+    shading_unit_context     : &'a mut ShadingUnitContext,
+
+    buffer_modules           : BufferAccess<'a>,
+    texture_modules          : &'a [TextureModule; 64],
+    shader_modules           : &'a [ShaderModule; 128],
+
+    state                    : &'a RasterizerState,
+    fragment_shader          : u8,
+    fragment_state           : &'a FragmentState,
+    resource_map             : &'a ResourceMap,
+    targets                  : TileTargets,
 
-            let x_min = p0[0].min(p1[0]).min(p2[0]);
-            let y_min = p0[1].min(p1[1]).min(p2[1]);
-            let x_max = p0[0].max(p1[0]).max(p2[0]);
-            let y_max = p0[1].max(p1[1]).max(p2[1]);
+    fragment_invocation_count: usize,
+    statistics               : GpuStatistics,
+}
 
-            if x_max < -1.0 || x_min > 1.0 || y_max < -1.0 || y_min > 1.0 {
+fn flush_fragments(call: &mut TileShadingCall<'_>) {
+    let invocation_count = call.fragment_invocation_count;
+    if invocation_count > 0 {
+        compute_varying_values(invocation_count, call);
+        invoke_fragment_shader(invocation_count, call);
+        call.fragment_invocation_count = 0;
+    }
+}
+
+/*
+scans a convex polygon within clip_rect. barycentrics are taken over the first
+three corners, so a 4th corner has to lie on the same plane of z and weights,
+which holds for the rectangles points and lines turn into. points and lines
+are always front facing. with a viewport the barycentric builtin, and so smooth
+varyings, are perspective correct, depth is always interpolated in screen space.
+ */
+fn scan_polygon(call: &mut TileShadingCall<'_>, clip_rect: &RasterRect, polygon: &ScreenPolygon) {
+    let Some(bounds) = polygon_bounds(polygon, clip_rect) else {
+        return;
+    };
+    let [v0, v1, v2] = polygon.vertices;
+    let corners = &polygon.corners[0..polygon.corner_count];
+    let points = [corners[0], corners[1], corners[2]];
+    let [p0_sc, p1_sc, p2_sc] = points;
+
+    // (first lane, first x) of the current row and (first lane, first x, last x) of the previous row, within the current batch
+    let mut row: Option<(usize, u32)> = None;
+    let mut prev_row: Option<(usize, u32, u32)> = None;
+
+    for y in bounds.upper_left.1..=bounds.lower_right.1 {
+        let mut x_min = f32::MAX;
+        let mut x_max = f32::MIN;
+        for e in 0..corners.len() {
+            let mut pa = corners[e];
+            let mut pb = corners[(e + 1) % corners.len()];
+            if pa[1] > pb[1] {
+                std::mem::swap(&mut pa, &mut pb);
+            }
+            if !((pa[1] <= y as f32) && (pb[1] >= y as f32)) {
                 continue;
             }
+            let edge_dx = pb[0] - pa[0];
+            let edge_dy = pb[1] - pa[1];
+            let dy = y as f32 - pa[1];
+            let dx = (dy * edge_dx) / edge_dy;
+            let x = pa[0] + dx;
+            x_min = x_min.min(x);
+            x_max = x_max.max(x);
+        }
+        let x_min = (x_min.ceil() as u32).max(bounds.upper_left.0);
+        let x_max = (x_max.floor() as u32).min(bounds.lower_right.0);
 
-            let x_min_target = ((x_min as f32) * 0.5 + 0.5) * target_rect_width  as f32 + call.target_rect.upper_left.0 as f32;
-            let x_max_target = ((x_max as f32) * 0.5 + 0.5) * target_rect_width  as f32 + call.target_rect.upper_left.0 as f32;
-            let y_min_target = ((y_min as f32) * 0.5 + 0.5) * target_rect_height as f32 + call.target_rect.upper_left.1 as f32;
-            let y_max_target = ((y_max as f32) * 0.5 + 0.5) * target_rect_height as f32 + call.target_rect.upper_left.1 as f32;
-
-            let x_min_clip = ((x_min_target as i32).max(0) as u32).clamp(call.target_rect.upper_left.0, call.target_rect.lower_right.0);
-            let x_max_clip = ((x_max_target as i32).max(0) as u32).clamp(call.target_rect.upper_left.0, call.target_rect.lower_right.0);
-            let y_min_clip = ((y_min_target as i32).max(0) as u32).clamp(call.target_rect.upper_left.1, call.target_rect.lower_right.1);
-            let y_max_clip = ((y_max_target as i32).max(0) as u32).clamp(call.target_rect.upper_left.1, call.target_rect.lower_right.1);
-            // TODO: Tiling access pattern
-
-            for y in y_min_clip..=y_max_clip {
-                let mut x_min = f32::MAX;
-                let mut x_max = f32::MIN;
-                let points = [p0_sc, p1_sc, p2_sc, p0_sc, p1_sc];
-                for e in 0..3 {
-                    let mut pa = points[e];
-                    let mut pb = points[e + 1];
-                    if pa[1] > pb[1] {
-                        std::mem::swap(&mut pa, &mut pb);
-                    }
-                    if !((pa[1] <= y as f32) && (pb[1] >= y as f32)) {
-                        continue;
-                    }
-                    let edge_dx = pb[0] - pa[0];
-                    let edge_dy = pb[1] - pa[1];
-                    let dy = y as f32 - pa[1];
-                    let dx = (dy * edge_dx) / edge_dy;
-                    let x = pa[0] + dx;
-                    x_min = x_min.min(x);
-                    x_max = x_max.max(x);
-                }
-                let x_min = (x_min.ceil() as u32).max(x_min_clip);
-                let x_max = (x_max.floor() as u32).min(x_max_clip);
-                
-                for x in x_min..=x_max {
-                    let mut dx_pn = [0.0f32; 3];
-                    let mut dy_pn = [0.0f32; 3];
-                    for e in 0..3 {
-                        dx_pn[e] = x as f32 - points[e][0];
-                        dy_pn[e] = y as f32 - points[e][1];
+        for x in x_min..=x_max {
+            let mut dx_pn = [0.0f32; 3];
+            let mut dy_pn = [0.0f32; 3];
+            for e in 0..3 {
+                dx_pn[e] = x as f32 - points[e][0];
+                dy_pn[e] = y as f32 - points[e][1];
+            }
+            let areas = [
+                dx_pn[2] * dy_pn[1] - dx_pn[1] * dy_pn[2],
+                dx_pn[0] * dy_pn[2] - dx_pn[2] * dy_pn[0],
+                dx_pn[1] * dy_pn[0] - dx_pn[0] * dy_pn[1],
+            ];
+            let recip_lengths = [
+                1.0f32 / (dx_pn[0].powf(2.0) + dy_pn[0].powf(2.0)).sqrt(),
+                1.0f32 / (dx_pn[1].powf(2.0) + dy_pn[1].powf(2.0)).sqrt(),
+                1.0f32 / (dx_pn[2].powf(2.0) + dy_pn[2].powf(2.0)).sqrt(),
+            ];
+            let recip_recip_length_sum = 1.0 / (recip_lengths[0] + recip_lengths[1] + recip_lengths[2]);
+            let recip_area_sum = 1.0 / (areas[0] + areas[1] + areas[2]);
+            let b0 = areas[0] * recip_area_sum;
+            let b1 = areas[1] * recip_area_sum;
+            let b2 = areas[2] * recip_area_sum;
+            let l0 = recip_lengths[0] * recip_recip_length_sum;
+            let l1 = recip_lengths[1] * recip_recip_length_sum;
+            let l2 = recip_lengths[2] * recip_recip_length_sum;
+            let z = p0_sc[2] * b0 + p1_sc[2] * b1 + p2_sc[2] * b2;
+            let [b0, b1, b2] = if call.state.viewport.is_some() {
+                // corners keep their clip space w, interpolate b / w and divide by the interpolated 1 / w
+                let [q0, q1, q2] = [b0 / p0_sc[3], b1 / p1_sc[3], b2 / p2_sc[3]];
+                let recip_q_sum = 1.0 / (q0 + q1 + q2);
+                [q0 * recip_q_sum, q1 * recip_q_sum, q2 * recip_q_sum]
+            } else {
+                [b0, b1, b2]
+            };
+            let lane = call.fragment_invocation_count;
+            call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_POSITION   ][lane] = [(x as f32).to_bits(), (y as f32).to_bits(), (z as f32).to_bits(), 0];
+            let [b0, b1, b2] = clip_weights_to_triangle(&polygon.weights, [b0, b1, b2]);
+            let [l0, l1, l2] = clip_weights_to_triangle(&polygon.weights, [l0, l1, l2]);
+            call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_BARYCENTRIC][lane] = [b0.to_bits(), b1.to_bits(), b2.to_bits(), 0];
+            call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_LINEAR     ][lane] = [l0.to_bits(), l1.to_bits(), l2.to_bits(), 0];
+            call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS ][lane] = [v0 as u32, v1 as u32, v2 as u32, 0];
+            call.fragment_inputs.scalar_array[FRAGMENT_SCALAR_INPUT_BUILTIN_FRONT_FACING][lane] = polygon.front_facing as u32;
+
+            let neighbors = call.shading_unit_context.derivative_neighbors_mut();
+            neighbors[lane] = [DERIVATIVE_NEIGHBOR_NONE; 2];
+            match row {
+                Some((row_lane, _)) if row_lane < lane => {
+                    neighbors[lane][0] = (lane - 1) as u32;
+                    if neighbors[lane - 1][0] == DERIVATIVE_NEIGHBOR_NONE {
+                        neighbors[lane - 1][0] = lane as u32;
                     }
-                    let areas = [
-                        dx_pn[2] * dy_pn[1] - dx_pn[1] * dy_pn[2],
-                        dx_pn[0] * dy_pn[2] - dx_pn[2] * dy_pn[0],
-                        dx_pn[1] * dy_pn[0] - dx_pn[0] * dy_pn[1],
-                    ];
-                    let recip_lengths = [
-                        1.0f32 / (dx_pn[0].powf(2.0) + dy_pn[0].powf(2.0)).sqrt(),
-                        1.0f32 / (dx_pn[1].powf(2.0) + dy_pn[1].powf(2.0)).sqrt(),
-                        1.0f32 / (dx_pn[2].powf(2.0) + dy_pn[2].powf(2.0)).sqrt(),
-                    ];
-                    let recip_recip_length_sum = 1.0 / (recip_lengths[0] + recip_lengths[1] + recip_lengths[2]);
-                    let recip_area_sum = 1.0 / (areas[0] + areas[1] + areas[2]);
-                    let b0 = areas[0] * recip_area_sum;
-                    let b1 = areas[1] * recip_area_sum;
-                    let b2 = areas[2] * recip_area_sum;
-                    let l0 = recip_lengths[0] * recip_recip_length_sum;
-                    let l1 = recip_lengths[1] * recip_recip_length_sum;
-                    let l2 = recip_lengths[2] * recip_recip_length_sum;
-                    let z = p0[2] * b0 + p1[2] * b1 + p2[2] * b2;
-                    call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_POSITION   ][fragment_invocation_count] = [(x as f32).to_bits(), (y as f32).to_bits(), (z as f32).to_bits(), 0];
-                    call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_BARYCENTRIC][fragment_invocation_count] = [b0.to_bits(), b1.to_bits(), b2.to_bits(), 0];
-                    call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_LINEAR     ][fragment_invocation_count] = [l0.to_bits(), l1.to_bits(), l2.to_bits(), 0];
-                    call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS ][fragment_invocation_count] = [v0 as u32, v1 as u32, v2 as u32, 0];
-                    
-                    fragment_invocation_count += 1;
-
-                    if fragment_invocation_count == CORE_COUNT {
-                        fragment_invocation_count = 0;
-                        compute_varying_values(CORE_COUNT, &mut call);
-                        invoke_fragment_shader(CORE_COUNT, &mut call);
+                },
+                Some(_) => {},
+                None => row = Some((lane, x)),
+            }
+            if let Some((prev_lane, prev_x_min, prev_x_max)) = prev_row {
+                if x >= prev_x_min && x <= prev_x_max {
+                    let above = prev_lane + (x - prev_x_min) as usize;
+                    neighbors[lane][1] = above as u32;
+                    if neighbors[above][1] == DERIVATIVE_NEIGHBOR_NONE {
+                        neighbors[above][1] = lane as u32;
                     }
                 }
             }
+
+            call.fragment_invocation_count += 1;
+
+            if call.fragment_invocation_count == CORE_COUNT {
+                row = None;
+                prev_row = None;
+                flush_fragments(call);
+            }
+        }
+        prev_row = row.map(|(row_lane, row_x_min)| (row_lane, row_x_min, row_x_min + (call.fragment_invocation_count - row_lane) as u32 - 1));
+        row = None;
+    }
+}
+
+fn fetch_vertex_id(index_buffer: Option<IndexBuffer>, buffer_modules: &[BufferModule; 256], vertex: usize) -> Option<u32> {
+    let Some(index_buffer) = index_buffer else {
+        return Some(vertex as u32);
+    };
+    let bytes = buffer_modules[index_buffer.buffer as usize].bytes();
+    let offset = index_buffer.offset as usize + vertex * index_buffer.t.size();
+    if offset + index_buffer.t.size() > bytes.len() {
+        return None;
+    }
+    Some(match index_buffer.t {
+        IndexType::U16 => read_bytes_u16(bytes, offset) as u32,
+        IndexType::U32 => read_bytes_u32(bytes, offset),
+    })
+}
+
+/*
+a vertex of a clipped triangle. weights blend the three vertices of the triangle
+it was clipped from, which is how varyings get carried onto new vertices.
+ */
+#[derive(Copy, Clone, Debug)]
+struct ClipVertex {
+    position: [f32; 4],
+    weights: [f32; 3],
+}
+
+impl ClipVertex {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        Self {
+            position: [0, 1, 2, 3].map(|c| self.position[c] + (other.position[c] - self.position[c]) * t),
+            weights:  [0, 1, 2].map(|c| self.weights[c] + (other.weights[c] - self.weights[c]) * t),
+        }
+    }
+}
+
+// a triangle gains at most one vertex per clip plane
+const CLIP_POLYGON_MAX: usize = 3 + CLIP_PLANES.len();
+
+// smallest w a vertex may have after clipping, so the divide by w stays finite
+const CLIP_W_EPSILON: f32 = 1.0e-6;
+
+/*
+visible clip space is 0 <= z <= w. every plane is given as a signed distance
+which is negative outside.
+ */
+const CLIP_PLANES: [fn([f32; 4]) -> f32; 3] = [
+    |p| p[2],
+    |p| p[3] - p[2],
+    |p| p[3] - CLIP_W_EPSILON,
+];
+
+struct ClipPolygon {
+    vertices: [ClipVertex; CLIP_POLYGON_MAX],
+    count: usize,
+}
+
+fn clip_triangle(positions: [[f32; 4]; 3], clip: bool) -> ClipPolygon {
+    let mut polygon = ClipPolygon {
+        vertices: [ClipVertex { position: [0.0; 4], weights: [0.0; 3] }; CLIP_POLYGON_MAX],
+        count: 3,
+    };
+    for (i, position) in positions.into_iter().enumerate() {
+        let mut weights = [0.0; 3];
+        weights[i] = 1.0;
+        polygon.vertices[i] = ClipVertex { position, weights };
+    }
+    if !clip {
+        return polygon;
+    }
+    for plane in CLIP_PLANES {
+        if polygon.vertices[..polygon.count].iter().all(|vertex| plane(vertex.position) >= 0.0) {
+            continue;
+        }
+        let input = polygon.vertices;
+        let input_count = polygon.count;
+        polygon.count = 0;
+        for i in 0..input_count {
+            let a = input[i];
+            let b = input[(i + 1) % input_count];
+            let distance_a = plane(a.position);
+            let distance_b = plane(b.position);
+            if distance_a >= 0.0 {
+                polygon.vertices[polygon.count] = a;
+                polygon.count += 1;
+            }
+            if (distance_a >= 0.0) != (distance_b >= 0.0) {
+                let t = distance_a / (distance_a - distance_b);
+                polygon.vertices[polygon.count] = a.lerp(&b, t);
+                polygon.count += 1;
+            }
         }
-        if fragment_invocation_count > 0 {
-            compute_varying_values(fragment_invocation_count, &mut call);
-            invoke_fragment_shader(fragment_invocation_count, &mut call);
+        if polygon.count < 3 {
+            polygon.count = 0;
+            break;
         }
     }
+    polygon
 }
 
-fn compute_varying_values(invocation_count: usize, call: &mut RasterizerCall<'_>) {
+// a line's vertices are weighted as the first two vertices of its primitive
+fn clip_line(positions: [[f32; 4]; 2], clip: bool) -> Option<[ClipVertex; 2]> {
+    let mut line = [
+        ClipVertex { position: positions[0], weights: [1.0, 0.0, 0.0] },
+        ClipVertex { position: positions[1], weights: [0.0, 1.0, 0.0] },
+    ];
+    if !clip {
+        return Some(line);
+    }
+    for plane in CLIP_PLANES {
+        let distance_a = plane(line[0].position);
+        let distance_b = plane(line[1].position);
+        if distance_a < 0.0 && distance_b < 0.0 {
+            return None;
+        }
+        if (distance_a >= 0.0) != (distance_b >= 0.0) {
+            let clipped = line[0].lerp(&line[1], distance_a / (distance_a - distance_b));
+            if distance_a < 0.0 {
+                line[0] = clipped;
+            } else {
+                line[1] = clipped;
+            }
+        }
+    }
+    Some(line)
+}
+
+// turns weights over a clipped triangle's vertices into weights over the original triangle's vertices
+fn clip_weights_to_triangle(triangle_weights: &[[f32; 3]; 3], weights: [f32; 3]) -> [f32; 3] {
+    [0, 1, 2].map(|c| triangle_weights[0][c] * weights[0] + triangle_weights[1][c] * weights[1] + triangle_weights[2][c] * weights[2])
+}
+
+/*
+without a viewport, positions are taken as already being in normalized device
+coordinates and are stretched over the target rect.
+ */
+fn viewport_transform(p: [f32; 4], viewport: Option<Viewport>, target_rect: &RasterRect) -> [f32; 4] {
+    match viewport {
+        Some(viewport) => {
+            let recip_w = 1.0 / p[3];
+            [
+                (p[0] * recip_w * 0.5 + 0.5) * viewport.width  + viewport.x,
+                (p[1] * recip_w * 0.5 + 0.5) * viewport.height + viewport.y,
+                p[2] * recip_w,
+                p[3]
+            ]
+        },
+        None => {
+            let target_rect_width  = target_rect.lower_right.0 - target_rect.upper_left.0;
+            let target_rect_height = target_rect.lower_right.1 - target_rect.upper_left.1;
+            [
+                (p[0] * 0.5 + 0.5) * target_rect_width  as f32 + target_rect.upper_left.0 as f32,
+                (p[1] * 0.5 + 0.5) * target_rect_height as f32 + target_rect.upper_left.1 as f32,
+                p[2],
+                p[3]
+            ]
+        },
+    }
+}
+
+fn compute_varying_values(invocation_count: usize, call: &mut TileShadingCall<'_>) {
     for varying in call.state.varyings.iter() {
         match varying.t {
             ShaderVaryingType::F32x4(Interpolation::ProvokingVertexFlat) |
             ShaderVaryingType::I32x4(Interpolation::ProvokingVertexFlat) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, _, _, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    call.io_arrays[2].vector_array[varying.slot as usize][i] = call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize];
+                    let [v0, _, _, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    call.fragment_inputs.vector_array[varying.slot as usize][i] = call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize];
                 });
             },
             ShaderVaryingType::F32x3(Interpolation::ProvokingVertexFlat) |
             ShaderVaryingType::I32x3(Interpolation::ProvokingVertexFlat) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, _, _, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    let value: [u32; 3] = call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize][0..=2].try_into().unwrap();
-                    call.io_arrays[2].vector_array[varying.slot as usize][i][0..=2].copy_from_slice(&value);
+                    let [v0, _, _, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    let value: [u32; 3] = call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize][0..=2].try_into().unwrap();
+                    call.fragment_inputs.vector_array[varying.slot as usize][i][0..=2].copy_from_slice(&value);
                 });
             },
             ShaderVaryingType::F32x2(Interpolation::ProvokingVertexFlat) |
             ShaderVaryingType::I32x2(Interpolation::ProvokingVertexFlat) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, _, _, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    let value: [u32; 2] = call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize][0..=1].try_into().unwrap();
-                    call.io_arrays[2].vector_array[varying.slot as usize][i][0..=1].copy_from_slice(&value);
+                    let [v0, _, _, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    let value: [u32; 2] = call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize][0..=1].try_into().unwrap();
+                    call.fragment_inputs.vector_array[varying.slot as usize][i][0..=1].copy_from_slice(&value);
                 });
             },
             ShaderVaryingType::F32(Interpolation::ProvokingVertexFlat) |
             ShaderVaryingType::I32(Interpolation::ProvokingVertexFlat) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, _, _, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    call.io_arrays[2].scalar_array[varying.slot as usize][i] = call.io_arrays[1].scalar_array[varying.slot as usize][v0 as usize];
+                    let [v0, _, _, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    call.fragment_inputs.scalar_array[varying.slot as usize][i] = call.vertex_outputs.scalar_array[varying.slot as usize][v0 as usize];
                 });
             },
             ShaderVaryingType::F32x4(Interpolation::Smooth) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    let [b0, b1, b2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_BARYCENTRIC][i].map(|x| f32::from_bits(x));
-                    call.io_arrays[2].vector_array[varying.slot as usize][i] = [0, 1, 2, 3].map(|c| {
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    let [b0, b1, b2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_BARYCENTRIC][i].map(|x| f32::from_bits(x));
+                    call.fragment_inputs.vector_array[varying.slot as usize][i] = [0, 1, 2, 3].map(|c| {
                         (
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize][c]) * b0 +
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v1 as usize][c]) * b1 +
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v2 as usize][c]) * b2
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize][c]) * b0 +
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v1 as usize][c]) * b1 +
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v2 as usize][c]) * b2
                         ).to_bits()
                     });
                 });
             },
             ShaderVaryingType::F32x3(Interpolation::Smooth) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    let [b0, b1, b2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_BARYCENTRIC][i].map(|x| f32::from_bits(x));
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    let [b0, b1, b2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_BARYCENTRIC][i].map(|x| f32::from_bits(x));
                     let value = [0, 1, 2].map(|c| {
                         (
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize][c]) * b0 +
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v1 as usize][c]) * b1 +
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v2 as usize][c]) * b2
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize][c]) * b0 +
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v1 as usize][c]) * b1 +
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v2 as usize][c]) * b2
                         ).to_bits()
                     });
-                    call.io_arrays[2].vector_array[varying.slot as usize][i][0..=2].copy_from_slice(&value);
+                    call.fragment_inputs.vector_array[varying.slot as usize][i][0..=2].copy_from_slice(&value);
                 });
             },
             ShaderVaryingType::F32x2(Interpolation::Smooth) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    let [b0, b1, b2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_BARYCENTRIC][i].map(|x| f32::from_bits(x));
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    let [b0, b1, b2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_BARYCENTRIC][i].map(|x| f32::from_bits(x));
                     let value = [0, 1].map(|c| {
                         (
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize][c]) * b0 +
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v1 as usize][c]) * b1 +
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v2 as usize][c]) * b2
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize][c]) * b0 +
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v1 as usize][c]) * b1 +
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v2 as usize][c]) * b2
                         ).to_bits()
                     });
-                    call.io_arrays[2].vector_array[varying.slot as usize][i][0..=1].copy_from_slice(&value);
+                    call.fragment_inputs.vector_array[varying.slot as usize][i][0..=1].copy_from_slice(&value);
                 });
             },
             ShaderVaryingType::F32(Interpolation::Smooth) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    let [b0, b1, b2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_BARYCENTRIC][i].map(|x| f32::from_bits(x));
-                    call.io_arrays[2].scalar_array[varying.slot as usize][i] =
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    let [b0, b1, b2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_BARYCENTRIC][i].map(|x| f32::from_bits(x));
+                    call.fragment_inputs.scalar_array[varying.slot as usize][i] =
                         (
-                            f32::from_bits(call.io_arrays[1].scalar_array[varying.slot as usize][v0 as usize]) * b0 +
-                            f32::from_bits(call.io_arrays[1].scalar_array[varying.slot as usize][v1 as usize]) * b1 +
-                            f32::from_bits(call.io_arrays[1].scalar_array[varying.slot as usize][v2 as usize]) * b2
+                            f32::from_bits(call.vertex_outputs.scalar_array[varying.slot as usize][v0 as usize]) * b0 +
+                            f32::from_bits(call.vertex_outputs.scalar_array[varying.slot as usize][v1 as usize]) * b1 +
+                            f32::from_bits(call.vertex_outputs.scalar_array[varying.slot as usize][v2 as usize]) * b2
                         ).to_bits()
                 });
             },
 
             ShaderVaryingType::F32x4(Interpolation::Linear) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    let [b0, b1, b2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_LINEAR    ][i].map(|x| f32::from_bits(x));
-                    call.io_arrays[2].vector_array[varying.slot as usize][i] = [0, 1, 2, 3].map(|c| {
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    let [b0, b1, b2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_LINEAR    ][i].map(|x| f32::from_bits(x));
+                    call.fragment_inputs.vector_array[varying.slot as usize][i] = [0, 1, 2, 3].map(|c| {
                         (
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize][c]) * b0 +
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v1 as usize][c]) * b1 +
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v2 as usize][c]) * b2
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize][c]) * b0 +
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v1 as usize][c]) * b1 +
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v2 as usize][c]) * b2
                         ).to_bits()
                     });
                 });
             },
             ShaderVaryingType::F32x3(Interpolation::Linear) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    let [b0, b1, b2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_LINEAR    ][i].map(|x| f32::from_bits(x));
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    let [b0, b1, b2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_LINEAR    ][i].map(|x| f32::from_bits(x));
                     let value = [0, 1, 2].map(|c| {
                         (
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize][c]) * b0 +
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v1 as usize][c]) * b1 +
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v2 as usize][c]) * b2
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize][c]) * b0 +
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v1 as usize][c]) * b1 +
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v2 as usize][c]) * b2
                         ).to_bits()
                     });
-                    call.io_arrays[2].vector_array[varying.slot as usize][i][0..=2].copy_from_slice(&value);
+                    call.fragment_inputs.vector_array[varying.slot as usize][i][0..=2].copy_from_slice(&value);
                 });
             },
             ShaderVaryingType::F32x2(Interpolation::Linear) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    let [b0, b1, b2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_LINEAR    ][i].map(|x| f32::from_bits(x));
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    let [b0, b1, b2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_LINEAR    ][i].map(|x| f32::from_bits(x));
                     let value = [0, 1].map(|c| {
                         (
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize][c]) * b0 +
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v1 as usize][c]) * b1 +
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v2 as usize][c]) * b2
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize][c]) * b0 +
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v1 as usize][c]) * b1 +
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v2 as usize][c]) * b2
                         ).to_bits()
                     });
-                    call.io_arrays[2].vector_array[varying.slot as usize][i][0..=1].copy_from_slice(&value);
+                    call.fragment_inputs.vector_array[varying.slot as usize][i][0..=1].copy_from_slice(&value);
                 });
             },
             ShaderVaryingType::F32(Interpolation::Linear) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    let [b0, b1, b2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_LINEAR    ][i].map(|x| f32::from_bits(x));
-                    call.io_arrays[2].scalar_array[varying.slot as usize][i] =
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    let [b0, b1, b2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_LINEAR    ][i].map(|x| f32::from_bits(x));
+                    call.fragment_inputs.scalar_array[varying.slot as usize][i] =
                         (
-                            f32::from_bits(call.io_arrays[1].scalar_array[varying.slot as usize][v0 as usize]) * b0 +
-                            f32::from_bits(call.io_arrays[1].scalar_array[varying.slot as usize][v1 as usize]) * b1 +
-                            f32::from_bits(call.io_arrays[1].scalar_array[varying.slot as usize][v2 as usize]) * b2
+                            f32::from_bits(call.vertex_outputs.scalar_array[varying.slot as usize][v0 as usize]) * b0 +
+                            f32::from_bits(call.vertex_outputs.scalar_array[varying.slot as usize][v1 as usize]) * b1 +
+                            f32::from_bits(call.vertex_outputs.scalar_array[varying.slot as usize][v2 as usize]) * b2
                         ).to_bits()
                 });
             },
 
             ShaderVaryingType::F32x4(Interpolation::Min) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    call.io_arrays[2].vector_array[varying.slot as usize][i] = [0, 1, 2, 3].map(|c| {
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    call.fragment_inputs.vector_array[varying.slot as usize][i] = [0, 1, 2, 3].map(|c| {
                         (
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize][c]).min(
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v1 as usize][c])).min(
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v2 as usize][c]))
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize][c]).min(
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v1 as usize][c])).min(
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v2 as usize][c]))
                         ).to_bits()
                     });
                 });
             },
             ShaderVaryingType::F32x3(Interpolation::Min) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
                     let value = [0, 1, 2].map(|c| {
                         (
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize][c]).min(
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v1 as usize][c])).min(
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v2 as usize][c]))
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize][c]).min(
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v1 as usize][c])).min(
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v2 as usize][c]))
                         ).to_bits()
                     });
-                    call.io_arrays[2].vector_array[varying.slot as usize][i][0..=2].copy_from_slice(&value);
+                    call.fragment_inputs.vector_array[varying.slot as usize][i][0..=2].copy_from_slice(&value);
                 });
             },
             ShaderVaryingType::F32x2(Interpolation::Min) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
                     let value = [0, 1].map(|c| {
                         (
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize][c]).min(
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v1 as usize][c])).min(
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v2 as usize][c]))
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize][c]).min(
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v1 as usize][c])).min(
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v2 as usize][c]))
                         ).to_bits()
                     });
-                    call.io_arrays[2].vector_array[varying.slot as usize][i][0..=1].copy_from_slice(&value);
+                    call.fragment_inputs.vector_array[varying.slot as usize][i][0..=1].copy_from_slice(&value);
                 });
             },
             ShaderVaryingType::F32(Interpolation::Min) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    call.io_arrays[2].scalar_array[varying.slot as usize][i] =
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    call.fragment_inputs.scalar_array[varying.slot as usize][i] =
                         (
-                            f32::from_bits(call.io_arrays[1].scalar_array[varying.slot as usize][v0 as usize]).min(
-                            f32::from_bits(call.io_arrays[1].scalar_array[varying.slot as usize][v1 as usize])).min(
-                            f32::from_bits(call.io_arrays[1].scalar_array[varying.slot as usize][v2 as usize]))
+                            f32::from_bits(call.vertex_outputs.scalar_array[varying.slot as usize][v0 as usize]).min(
+                            f32::from_bits(call.vertex_outputs.scalar_array[varying.slot as usize][v1 as usize])).min(
+                            f32::from_bits(call.vertex_outputs.scalar_array[varying.slot as usize][v2 as usize]))
                         ).to_bits()
                 });
             },
 
             ShaderVaryingType::F32x4(Interpolation::Max) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    call.io_arrays[2].vector_array[varying.slot as usize][i] = [0, 1, 2, 3].map(|c| {
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    call.fragment_inputs.vector_array[varying.slot as usize][i] = [0, 1, 2, 3].map(|c| {
                         (
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize][c]).max(
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v1 as usize][c])).max(
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v2 as usize][c]))
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize][c]).max(
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v1 as usize][c])).max(
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v2 as usize][c]))
                         ).to_bits()
                     });
                 });
             },
             ShaderVaryingType::F32x3(Interpolation::Max) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
                     let value = [0, 1, 2].map(|c| {
                         (
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize][c]).max(
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v1 as usize][c])).max(
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v2 as usize][c]))
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize][c]).max(
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v1 as usize][c])).max(
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v2 as usize][c]))
                         ).to_bits()
                     });
-                    call.io_arrays[2].vector_array[varying.slot as usize][i][0..=2].copy_from_slice(&value);
+                    call.fragment_inputs.vector_array[varying.slot as usize][i][0..=2].copy_from_slice(&value);
                 });
             },
             ShaderVaryingType::F32x2(Interpolation::Max) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
                     let value = [0, 1].map(|c| {
                         (
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v0 as usize][c]).max(
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v1 as usize][c])).max(
-                            f32::from_bits(call.io_arrays[1].vector_array[varying.slot as usize][v2 as usize][c]))
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v0 as usize][c]).max(
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v1 as usize][c])).max(
+                            f32::from_bits(call.vertex_outputs.vector_array[varying.slot as usize][v2 as usize][c]))
                         ).to_bits()
                     });
-                    call.io_arrays[2].vector_array[varying.slot as usize][i][0..=1].copy_from_slice(&value);
+                    call.fragment_inputs.vector_array[varying.slot as usize][i][0..=1].copy_from_slice(&value);
                 });
             },
             ShaderVaryingType::F32(Interpolation::Max) => {
                 (0..invocation_count).for_each(|i| {
-                    let [v0, v1, v2, _] = call.io_arrays[2].vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
-                    call.io_arrays[2].scalar_array[varying.slot as usize][i] =
+                    let [v0, v1, v2, _] = call.fragment_inputs.vector_array[FRAGMENT_VECTOR_INPUT_BUILTIN_VERTEX_IDS][i];
+                    call.fragment_inputs.scalar_array[varying.slot as usize][i] =
                         (
-                            f32::from_bits(call.io_arrays[1].scalar_array[varying.slot as usize][v0 as usize]).max(
-                            f32::from_bits(call.io_arrays[1].scalar_array[varying.slot as usize][v1 as usize])).max(
-                            f32::from_bits(call.io_arrays[1].scalar_array[varying.slot as usize][v2 as usize]))
+                            f32::from_bits(call.vertex_outputs.scalar_array[varying.slot as usize][v0 as usize]).max(
+                            f32::from_bits(call.vertex_outputs.scalar_array[varying.slot as usize][v1 as usize])).max(
+                            f32::from_bits(call.vertex_outputs.scalar_array[varying.slot as usize][v2 as usize]))
                         ).to_bits()
                 });
             },
@@ -514,14 +1247,16 @@ fn compute_varying_values(invocation_count: usize, call: &mut RasterizerCall<'_>
     }
 }
 
-fn invoke_fragment_shader(invocation_count: usize, call: &mut RasterizerCall<'_>) {
+fn invoke_fragment_shader(invocation_count: usize, call: &mut TileShadingCall<'_>) {
     let mut fragment_run_context = unsafe { ShadingUnitRunContext {
-        scalar_input_array:  &mut *(&mut call.io_arrays[2].scalar_array as *mut _),
-        vector_input_array:  &mut *(&mut call.io_arrays[2].vector_array as *mut _),
-        scalar_output_array: &mut *(&mut call.io_arrays[0].scalar_array as *mut _),
-        vector_output_array: &mut *(&mut call.io_arrays[0].vector_array as *mut _),
+        scalar_input_array:  &mut *(&mut call.fragment_inputs.scalar_array as *mut _),
+        vector_input_array:  &mut *(&mut call.fragment_inputs.vector_array as *mut _),
+        scalar_output_array: &mut *(&mut call.fragment_outputs.scalar_array as *mut _),
+        vector_output_array: &mut *(&mut call.fragment_outputs.vector_array as *mut _),
         scalar_constant_array: &mut *(&mut call.constant_array.scalar_constant_array as *mut _),
         vector_constant_array: &mut *(&mut call.constant_array.vector_constant_array as *mut _),
+        sampler_array: & *(&call.constant_array.sampler_array as *const _),
+        derivatives: true,
     } };
     let fragment_call = FragmentShaderCall {
         state: call.fragment_state,
@@ -529,11 +1264,463 @@ fn invoke_fragment_shader(invocation_count: usize, call: &mut RasterizerCall<'_>
         fragmen_count: invocation_count,
         shading_unit_context: call.shading_unit_context,
         shading_unit_run_context: &mut fragment_run_context,
-        buffer_modules: call.buffer_modules,
+        buffer_modules: call.buffer_modules.reborrow(),
         texture_modules: call.texture_modules,
         shader_modules: call.shader_modules,
+        targets: &mut call.targets,
         resource_map: call.resource_map,
     };
-    run_fragment_shader(fragment_call);
+    let passed = run_fragment_shader(fragment_call);
+    call.statistics.fragments_shaded = call.statistics.fragments_shaded.wrapping_add(invocation_count as u32);
+    call.statistics.fragments_passed = call.statistics.fragments_passed.wrapping_add(passed as u32);
 }
 
+
+#[cfg(test)]
+mod test {
+    use std::marker::PhantomData;
+
+    use super::*;
+    use super::super::types::{ImageDataLayout, PixelDataLayout, Texture};
+
+    const TARGET_SIZE: u32 = 96;
+
+    // a shader that only copies vector inputs to outputs
+    fn copy_shader(shader_type: ShaderType, copies: &[(u8, u8)]) -> ShaderModule {
+        let mut module = ShaderModule { shader_type, ..ShaderModule::default() };
+        for (instruction, &(from, to)) in module.instruction_buffer.iter_mut().zip(copies) {
+            *instruction = ShaderInstruction::CopyVectorRegister { from: RegisterAddress::Input(from, PhantomData), to: RegisterAddress::Output(to, PhantomData) };
+        }
+        module.instruction_count = copies.len();
+        module
+    }
+
+    // fragment output 0 as f32x4 into texture 0, blended if blend is set
+    fn fragment_state(blend: Option<FragmentBlendState>) -> FragmentState {
+        FragmentState {
+            output_assignments: vec![FragmentOutputAssignment { output: 0, texture: 0, t: FragmentOutputType::F32ToF32, c: ShaderCardinality::V4, offset: [0, 0], blend, write_mask: FRAGMENT_WRITE_MASK_ALL }],
+            depth_state: None,
+        }
+    }
+
+    fn vertex_input(input: u8, src_buffer: u8, offset: u32, stride: u32, step_rate: u32) -> VertexInputAssignment {
+        VertexInputAssignment { input, src_buffer, offset, stride, step_rate, t: ShaderInputType::F32FromF32, c: ShaderCardinality::V4 }
+    }
+
+    /*
+    vertices are a clip space position and a color each, in buffer 0. the vertex
+    shader passes both through, and the fragment shader writes the color, which
+    is smooth varying 0x10, to texture 0, a zeroed TARGET_SIZE square D32x4.
+     */
+    struct Scene {
+        buffer_modules: Box<[BufferModule; 256]>,
+        texture_modules: Box<[TextureModule; 64]>,
+        shader_modules: Box<[ShaderModule; 128]>,
+        state: RasterizerState,
+        vertex_state: VertexState,
+        fragment_state: FragmentState,
+        vertex_count: usize,
+        instance_count: usize,
+        index_buffer: Option<IndexBuffer>,
+    }
+
+    impl Scene {
+        fn new(vertices: &[[f32; 8]]) -> Self {
+            let mut scene = Self {
+                buffer_modules: Box::new([(); 256].map(|_| BufferModule::new())),
+                texture_modules: Box::new([(); 64].map(|_| TextureModule::new())),
+                shader_modules: Box::new([(); 128].map(|_| ShaderModule::default())),
+                state: RasterizerState {
+                    varyings: vec![RasterizerVaryingAssignment { slot: 0x10, t: ShaderVaryingType::F32x4(Interpolation::Smooth) }],
+                    line_width: 1.0,
+                    ..RasterizerState::default()
+                },
+                vertex_state: VertexState { inputs: vec![vertex_input(0, 0, 0, 32, 0), vertex_input(0x10, 0, 16, 32, 0)] },
+                fragment_state: fragment_state(None),
+                vertex_count: vertices.len(),
+                instance_count: 1,
+                index_buffer: None,
+            };
+            scene.shader_modules[0] = copy_shader(ShaderType::Vertex, &[(0, 0), (0x10, 0x10)]);
+            scene.shader_modules[1] = copy_shader(ShaderType::Fragment, &[(0x10, 0)]);
+            scene.buffer(0, bytemuck::cast_slice(vertices));
+            scene.texture(0, PixelDataLayout::D32x4);
+            scene
+        }
+
+        fn buffer(&mut self, buffer: usize, bytes: &[u8]) {
+            self.buffer_modules[buffer].memory[0..bytes.len()].copy_from_slice(bytes);
+            self.buffer_modules[buffer].length = bytes.len() as u32;
+        }
+
+        // a zeroed TARGET_SIZE square texture
+        fn texture(&mut self, texture: usize, pixel_layout: PixelDataLayout) {
+            self.texture_modules[texture].config = Texture {
+                width: TARGET_SIZE as u16,
+                height: TARGET_SIZE as u16,
+                pixel_layout,
+                image_layout: ImageDataLayout::Contiguous,
+            };
+            let words = (TARGET_SIZE * TARGET_SIZE) as usize * pixel_layout.pixel_bits() / 32;
+            self.texture_modules[texture].memory = vec![0u32; words].into_boxed_slice();
+        }
+
+        fn draw(&mut self, raster_threads: usize) -> GpuStatistics {
+            let mut constant_array = Box::new(ShadingUnitConstantArray::new());
+            let mut io_arrays = ShadingUnitIOArrays::new();
+            let mut shading_unit_context = ShadingUnitContext::new();
+            let mut workers = RasterWorker::pool(raster_threads);
+            let mut statistics = GpuStatistics::default();
+            run_rasterizer(RasterizerCall {
+                constant_array: &mut constant_array,
+                io_arrays: &mut io_arrays.0,
+                buffer_modules: &mut self.buffer_modules,
+                texture_modules: &mut self.texture_modules,
+                shader_modules: &self.shader_modules,
+                vertex_count: self.vertex_count,
+                instance_count: self.instance_count,
+                index_buffer: self.index_buffer,
+                shading_unit_context: &mut shading_unit_context,
+                workers: &mut workers,
+                state: &self.state,
+                vertex_shader: 0,
+                vertex_state: &self.vertex_state,
+                fragment_shader: 1,
+                fragment_state: &self.fragment_state,
+                target_rect: RasterRect { upper_left: (0, 0), lower_right: (TARGET_SIZE - 1, TARGET_SIZE - 1) },
+                resource_map: &ResourceMap::default(),
+                statistics: &mut statistics,
+            });
+            statistics
+        }
+
+        // texture 0 at (x, y)
+        fn pixel(&self, x: u32, y: u32) -> [f32; 4] {
+            self.texture_modules[0].fetch::<[f32; 4]>(x, y)
+        }
+    }
+
+    // additively blends overlapping triangles spread over every tile
+    fn render(raster_threads: usize) -> (Box<[u32]>, GpuStatistics) {
+        let mut seed = 0x1234_5678u32;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+        let vertices: Vec<[f32; 8]> = (0..40 * 3).map(|_| {
+            [random() * 2.0 - 1.0, random() * 2.0 - 1.0, 0.5, 1.0, random(), random(), random(), random()]
+        }).collect();
+        let mut scene = Scene::new(&vertices);
+        scene.state.varyings[0].t = ShaderVaryingType::F32x4(Interpolation::Linear);
+        scene.fragment_state = fragment_state(Some(FragmentBlendState {
+            color_src: BlendFactor::One,
+            color_dst: BlendFactor::One,
+            color_op: BlendOp::Add,
+            alpha_src: BlendFactor::SrcAlpha,
+            alpha_dst: BlendFactor::OneMinusSrcAlpha,
+            alpha_op: BlendOp::Add,
+        }));
+        let statistics = scene.draw(raster_threads);
+        (std::mem::take(&mut scene.texture_modules[0].memory), statistics)
+    }
+
+    #[test]
+    fn raster_threads_dont_change_output() {
+        let (single, single_statistics) = render(1);
+        assert!(single_statistics.fragments_shaded > 0);
+        assert!(single.iter().any(|&x| x != 0));
+        for raster_threads in [2, 3, 4] {
+            let (threaded, threaded_statistics) = render(raster_threads);
+            assert!(single == threaded, "output differs with {} raster threads", raster_threads);
+            assert_eq!(single_statistics.fragments_shaded, threaded_statistics.fragments_shaded);
+            assert_eq!(single_statistics.fragments_passed, threaded_statistics.fragments_passed);
+        }
+    }
+
+    #[test]
+    fn smooth_varyings_are_perspective_correct() {
+        // the right angle corner's neighbor along x is 4 times as far away
+        let size = TARGET_SIZE as f32;
+        let vertices = [
+            [-1.0, -1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+            [ 4.0, -4.0, 0.0, 4.0, 1.0, 0.0, 0.0, 0.0],
+            [-1.0,  1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+        ];
+        let mut scene = Scene::new(&vertices);
+        scene.state.viewport = Some(Viewport { x: 0.0, y: 0.0, width: size, height: size });
+        scene.draw(1);
+        for (x, y) in [(48, 24), (20, 10), (70, 5), (10, 60)] {
+            let [b1, b2] = [x as f32 / size, y as f32 / size];
+            let b0 = 1.0 - b1 - b2;
+            let expected = (b1 / 4.0) / (b0 + b1 / 4.0 + b2);
+            let value = scene.pixel(x, y)[0];
+            assert!((value - expected).abs() < 1e-3, "({}, {}) is {}, expected {}", x, y, value, expected);
+        }
+
+        // without a viewport positions aren't divided by w and varyings stay screen linear
+        let mut scene = Scene::new(&vertices.map(|v| [v[0] / v[3], v[1] / v[3], v[2], v[3], v[4], v[5], v[6], v[7]]));
+        scene.draw(1);
+        let value = scene.pixel(48, 24)[0];
+        assert!((value - 48.0 / (size - 1.0)).abs() < 1e-3, "(48, 24) is {}", value);
+    }
+
+    #[test]
+    fn cull_mode_and_front_face() {
+        // a triangle winding clockwise on screen in the upper left, and a counter clockwise one in the upper right
+        let vertices = [
+            [-1.0, -1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+            [-0.2, -1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+            [-1.0, -0.2, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+            [ 0.2, -1.0, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0],
+            [ 0.2, -0.2, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0],
+            [ 1.0, -1.0, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0],
+        ];
+        for (front_face, cull_mode, expected) in [
+            (FrontFace::CounterClockwise, CullMode::None,  [1.0, 2.0]),
+            (FrontFace::CounterClockwise, CullMode::Back,  [0.0, 2.0]),
+            (FrontFace::CounterClockwise, CullMode::Front, [1.0, 0.0]),
+            (FrontFace::Clockwise,        CullMode::Back,  [1.0, 0.0]),
+            (FrontFace::Clockwise,        CullMode::Front, [0.0, 2.0]),
+        ] {
+            let mut scene = Scene::new(&vertices);
+            scene.state.front_face = front_face;
+            scene.state.cull_mode = cull_mode;
+            let statistics = scene.draw(1);
+            assert_eq!([scene.pixel(5, 5)[0].round(), scene.pixel(62, 5)[0].round()], expected, "{:?} {:?}", front_face, cull_mode);
+            assert_eq!(statistics.primitives_rasterized, expected.iter().filter(|&&x| x != 0.0).count() as u32);
+        }
+    }
+
+    #[test]
+    fn strip_and_fan_primitives() {
+        assert_eq!(Topology::TriangleStrip.primitive_count(2), 0);
+        assert_eq!(Topology::TriangleStrip.primitive_count(5), 3);
+        assert_eq!(Topology::TriangleFan.primitive_count(5), 3);
+        assert_eq!(Topology::LineStrip.primitive_count(0), 0);
+        assert_eq!(Topology::LineStrip.primitive_count(4), 3);
+        assert_eq!((0..3).map(|p| Topology::TriangleStrip.primitive_vertices(p)).collect::<Vec<_>>(), [[0, 1, 2], [1, 3, 2], [2, 3, 4]]);
+        assert_eq!((0..3).map(|p| Topology::TriangleFan.primitive_vertices(p)).collect::<Vec<_>>(), [[1, 2, 0], [2, 3, 0], [3, 4, 0]]);
+        assert_eq!((0..2).map(|p| Topology::LineStrip.primitive_vertices(p)).collect::<Vec<_>>(), [[0, 1, 1], [1, 2, 2]]);
+    }
+
+    #[test]
+    fn strips_and_fans_keep_winding_and_provoking_vertex() {
+        // quads split along the diagonal from the upper right to the lower left, a flat color per vertex
+        let strip = [
+            [-1.0, -1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+            [ 1.0, -1.0, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0],
+            [-1.0,  1.0, 0.0, 1.0, 3.0, 0.0, 0.0, 0.0],
+            [ 1.0,  1.0, 0.0, 1.0, 4.0, 0.0, 0.0, 0.0],
+        ];
+        let fan = [
+            [ 1.0, -1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+            [ 1.0,  1.0, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0],
+            [-1.0,  1.0, 0.0, 1.0, 3.0, 0.0, 0.0, 0.0],
+            [-1.0, -1.0, 0.0, 1.0, 4.0, 0.0, 0.0, 0.0],
+        ];
+        for (topology, vertices, expected) in [(Topology::TriangleStrip, strip, [1.0, 2.0]), (Topology::TriangleFan, fan, [3.0, 2.0])] {
+            for (cull_mode, drawn) in [(CullMode::None, true), (CullMode::Back, false), (CullMode::Front, true)] {
+                let mut scene = Scene::new(&vertices);
+                scene.state.topology = topology;
+                scene.state.cull_mode = cull_mode;
+                scene.state.varyings[0].t = ShaderVaryingType::F32x4(Interpolation::ProvokingVertexFlat);
+                let statistics = scene.draw(1);
+                let expected = if drawn { expected } else { [0.0; 2] };
+                assert_eq!([scene.pixel(10, 10)[0], scene.pixel(85, 85)[0]], expected, "{:?} {:?}", topology, cull_mode);
+                assert_eq!(statistics.primitives_rasterized, if drawn { 2 } else { 0 });
+            }
+        }
+    }
+
+    #[test]
+    fn instances_step_their_inputs() {
+        // a quad covering the target per instance, colored from buffer 1 and added up
+        let quad = [
+            [-1.0, -1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+            [ 1.0, -1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+            [-1.0,  1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+            [ 1.0,  1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0],
+        ];
+        let colors: [[f32; 4]; 3] = [[1.0, 0.0, 0.0, 0.0], [0.0, 2.0, 0.0, 0.0], [0.0, 0.0, 4.0, 0.0]];
+        for (step_rate, expected) in [(1, [1.0, 2.0, 4.0, 0.0]), (2, [2.0, 2.0, 0.0, 0.0])] {
+            let mut scene = Scene::new(&quad);
+            scene.state.topology = Topology::TriangleStrip;
+            scene.instance_count = 3;
+            scene.buffer(1, bytemuck::cast_slice(&colors));
+            scene.vertex_state.inputs[1] = vertex_input(0x10, 1, 0, 16, step_rate);
+            scene.fragment_state = fragment_state(Some(FragmentBlendState {
+                color_src: BlendFactor::One,
+                color_dst: BlendFactor::One,
+                color_op: BlendOp::Add,
+                alpha_src: BlendFactor::One,
+                alpha_dst: BlendFactor::One,
+                alpha_op: BlendOp::Add,
+            }));
+            let statistics = scene.draw(1);
+            assert_eq!(scene.pixel(10, 80), expected, "step rate {}", step_rate);
+            assert_eq!(statistics.vertices_shaded, 3 * 4);
+            assert_eq!(statistics.primitives_rasterized, 3 * 2);
+        }
+    }
+
+    #[test]
+    fn indexed_draws_reuse_vertices() {
+        let quad = [
+            [-1.0, -1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+            [ 1.0, -1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+            [-1.0,  1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+            [ 1.0,  1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+        ];
+        let indices: [u16; 8] = [0xffff, 0, 1, 2, 1, 3, 2, 0xffff];
+        let mut scene = Scene::new(&quad);
+        scene.buffer(2, bytemuck::cast_slice(&indices));
+        scene.index_buffer = Some(IndexBuffer { buffer: 2, offset: 2, t: IndexType::U16 });
+        scene.vertex_count = 6;
+        let statistics = scene.draw(1);
+        assert_eq!(scene.pixel(10, 10)[0], 1.0);
+        assert_eq!(scene.pixel(85, 85)[0], 1.0);
+        assert_eq!(statistics.vertices_shaded, 4);
+        assert_eq!(statistics.primitives_rasterized, 2);
+
+        // indices past the end of the index buffer stop the draw
+        let mut scene = Scene::new(&quad);
+        scene.buffer(2, bytemuck::cast_slice(&indices));
+        scene.index_buffer = Some(IndexBuffer { buffer: 2, offset: 6, t: IndexType::U16 });
+        scene.vertex_count = 6;
+        let statistics = scene.draw(1);
+        assert_eq!(statistics.vertices_shaded, 0);
+        assert_eq!(scene.pixel(10, 10), [0.0; 4]);
+    }
+
+    #[test]
+    fn stencil_masks_and_faces() {
+        // the upper left half of the target, winding clockwise so it's back facing
+        let half = [
+            [-1.0, -1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+            [ 1.0, -1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+            [-1.0,  1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0],
+        ];
+        let face = |compare_fn, fail_op, pass_op| StencilFaceState { compare_fn, fail_op, depth_fail_op: StencilOp::Keep, pass_op };
+        let depth_state = |reference, read_mask, write_mask, back| FragmentDepthState {
+            depth_texture: 1,
+            compare_fn: DepthCompareFn::Always,
+            depth_write: false,
+            stencil: Some(FragmentStencilState {
+                stencil_texture: 2,
+                reference,
+                read_mask,
+                write_mask,
+                front: face(DepthCompareFn::Never, StencilOp::Zero, StencilOp::Zero),
+                back,
+            }),
+        };
+        let mut scene = Scene::new(&half);
+        scene.texture(1, PixelDataLayout::D32x1);
+        scene.texture(2, PixelDataLayout::D8x1);
+        scene.texture_modules[2].memory.fill(0xf0f0_f0f0);
+
+        // only the write mask's bits of the reference get written
+        scene.fragment_state.depth_state = Some(depth_state(0xa5, 0xff, 0x0f, face(DepthCompareFn::Always, StencilOp::Keep, StencilOp::Replace)));
+        scene.draw(1);
+        assert_eq!(scene.texture_modules[2].fetch::<u8>(10, 10), 0xf5);
+        assert_eq!(scene.texture_modules[2].fetch::<u8>(85, 85), 0xf0);
+
+        // covering the whole target, only fragments whose read mask bits equal the reference's pass
+        let full: [[f32; 8]; 3] = [
+            [-1.0, -1.0, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0],
+            [ 3.0, -1.0, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0],
+            [-1.0,  3.0, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0],
+        ];
+        scene.buffer(0, bytemuck::cast_slice(&full));
+        scene.fragment_state.depth_state = Some(depth_state(0x35, 0x0f, 0xff, face(DepthCompareFn::Equal, StencilOp::IncrementClamp, StencilOp::Keep)));
+        let statistics = scene.draw(1);
+        let stenciled = (0..TARGET_SIZE).flat_map(|y| (0..TARGET_SIZE).map(move |x| (x, y))).filter(|&(x, y)| scene.texture_modules[2].fetch::<u8>(x, y) == 0xf5).count();
+        assert_eq!(statistics.fragments_shaded, TARGET_SIZE * TARGET_SIZE);
+        assert_eq!(statistics.fragments_passed as usize, stenciled);
+        assert_eq!([scene.pixel(10, 10)[0].round(), scene.pixel(85, 85)[0]], [2.0, 0.0]);
+        assert_eq!(scene.texture_modules[2].fetch::<u8>(85, 85), 0xf1);
+    }
+
+    #[test]
+    fn scissor_limits_fragments() {
+        // one triangle covering the whole target, scissored across a tile corner
+        let vertices = [
+            [-1.0, -1.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0],
+            [ 3.0, -1.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0],
+            [-1.0,  3.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0],
+        ];
+        for raster_threads in [1, 4] {
+            let mut scene = Scene::new(&vertices);
+            scene.state.scissor = Some(RasterRect { upper_left: (20, 24), lower_right: (39, 43) });
+            let statistics = scene.draw(raster_threads);
+            assert_eq!(statistics.fragments_shaded, 20 * 20);
+            for (x, y, inside) in [(20, 24, true), (39, 43, true), (32, 32, true), (19, 24, false), (20, 23, false), (40, 43, false), (39, 44, false)] {
+                assert_eq!(scene.pixel(x, y)[0] != 0.0, inside, "({}, {})", x, y);
+            }
+        }
+
+        // a scissor outside the target draws nothing
+        let mut scene = Scene::new(&vertices);
+        scene.state.scissor = Some(RasterRect { upper_left: (200, 0), lower_right: (300, 10) });
+        assert_eq!(scene.draw(1).fragments_shaded, 0);
+    }
+
+    #[test]
+    fn near_and_far_planes_split_triangles() {
+        let inside = [[-1.0, -1.0, 0.5, 1.0], [1.0, -1.0, 0.5, 1.0], [-1.0, 1.0, 0.5, 1.0]];
+        assert_eq!(clip_triangle(inside, true).count, 3);
+        assert_eq!(clip_triangle(inside.map(|p| [p[0], p[1], -0.5, p[3]]), true).count, 0);
+        assert_eq!(clip_triangle(inside.map(|p| [p[0], p[1], 1.5, p[3]]), true).count, 0);
+        // without a viewport nothing is clipped
+        assert_eq!(clip_triangle(inside.map(|p| [p[0], p[1], -0.5, p[3]]), false).count, 3);
+
+        // one vertex behind the near plane and one past the far plane
+        let positions = [[-1.0, -1.0, -0.5, 1.0], [1.0, -1.0, 0.5, 1.0], [-1.0, 1.0, 2.5, 2.0]];
+        let polygon = clip_triangle(positions, true);
+        assert_eq!(polygon.count, 5);
+        for vertex in polygon.vertices[0..polygon.count].iter() {
+            assert!(vertex.position[2] >= -1e-6 && vertex.position[2] <= vertex.position[3] + 1e-6, "{:?}", vertex);
+            assert!((vertex.weights.iter().sum::<f32>() - 1.0).abs() < 1e-6, "{:?}", vertex);
+            // new vertices carry weights that rebuild their position from the original vertices
+            let weighted = [0, 1, 2, 3].map(|c| positions.iter().zip(vertex.weights).map(|(position, weight)| position[c] * weight).sum::<f32>());
+            assert!(weighted.iter().zip(vertex.position).all(|(a, b)| (a - b).abs() < 1e-5), "{:?}", vertex);
+        }
+        assert!(polygon.vertices[0..polygon.count].iter().any(|vertex| vertex.weights == [0.5, 0.5, 0.0]));
+
+        let state = RasterizerState { viewport: Some(Viewport { x: 0.0, y: 0.0, width: 96.0, height: 96.0 }), ..RasterizerState::default() };
+        let target_rect = RasterRect { upper_left: (0, 0), lower_right: (95, 95) };
+        let mut polygons = Vec::new();
+        setup_triangle(&state, &target_rect, [0, 1, 2], positions, &mut polygons);
+        assert_eq!(polygons.len(), 3);
+    }
+
+    #[test]
+    fn clipped_triangles_keep_varying_weights() {
+        // each vertex's color is its own weight, and the first vertex is behind the near plane
+        let vertices = [
+            [-1.0, -1.0, -0.5, 1.0, 1.0, 0.0, 0.0, 0.0],
+            [ 1.0, -1.0,  0.5, 1.0, 0.0, 1.0, 0.0, 0.0],
+            [-1.0,  1.0,  0.5, 1.0, 0.0, 0.0, 1.0, 0.0],
+        ];
+        let mut scene = Scene::new(&vertices);
+        scene.state.viewport = Some(Viewport { x: 0.0, y: 0.0, width: TARGET_SIZE as f32, height: TARGET_SIZE as f32 });
+        let statistics = scene.draw(1);
+        assert_eq!(statistics.primitives_rasterized, 1);
+        assert!(statistics.fragments_shaded > 0);
+        let mut max_weight = 0.0f32;
+        for y in 0..TARGET_SIZE {
+            for x in 0..TARGET_SIZE {
+                let color = scene.pixel(x, y);
+                if color == [0.0; 4] {
+                    continue;
+                }
+                assert!((color.iter().sum::<f32>() - 1.0).abs() < 1e-4, "({}, {}) is {:?}", x, y, color);
+                // z reaches 0 halfway along the first vertex's edges
+                assert!(color[0] <= 0.5 + 1e-4, "({}, {}) is {:?}", x, y, color);
+                max_weight = max_weight.max(color[0]);
+            }
+        }
+        assert!(max_weight > 0.45);
+        assert_eq!(scene.pixel(2, 2), [0.0; 4]);
+    }
+}
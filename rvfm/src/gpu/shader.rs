@@ -2,9 +2,9 @@ use std::marker::PhantomData;
 use std::mem::MaybeUninit;
 use std::ops::Rem;
 
-use super::types::PixelDataLayout;
-use super::buffer::BufferModule;
-use super::texture::TextureModule;
+use super::types::{PixelDataLayout, SampleFilter, TextureSampler};
+use super::buffer::{BufferAccess, BufferModule};
+use super::texture::{palette_color, TextureLevel, TextureModule};
 pub trait RegisterType {}
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -59,6 +59,17 @@ impl Default for ShaderModule {
     }
 }
 
+impl ShaderModule {
+    pub fn writes_buffers(&self) -> bool {
+        self.instruction_buffer[0..self.instruction_count].iter().any(|instruction| matches!(instruction,
+            ShaderInstruction::WriteVectorToBuffer { .. } |
+            ShaderInstruction::ConditionallyWriteVectorToBuffer { .. } |
+            ShaderInstruction::WriteScalarToBuffer { .. } |
+            ShaderInstruction::ConditionallyWriteScalarToBuffer { .. }
+        ))
+    }
+}
+
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ShaderCardinality {
@@ -221,6 +232,13 @@ pub enum TextureLoadType {
     I32FromF32,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SampleLod {
+    Auto,
+    Explicit(RegisterAddress<Scalar>),
+    Bias(RegisterAddress<Scalar>),
+}
+
 #[allow(unused)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum OpDataTypeConversion {
@@ -328,6 +346,8 @@ pub enum VectorBinaryOp {
 #[allow(unused)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum ShaderInstruction {
+    // the stack depth is shared by all lanes: pushes and pops run whenever any lane is
+    // active, and a pop only writes the active lanes
     PushVector(RegisterAddress<Vector>),
     PushScalar(RegisterAddress<Scalar>),
     PopVector(RegisterAddress<Vector>),
@@ -441,6 +461,14 @@ pub enum ShaderInstruction {
         load_type: TextureLoadType,
         texture: u8,
     },
+    SampleTexture {
+        src_uv: RegisterAddress<Vector>,
+        dst: RegisterAddress<Vector>,
+        load_type: TextureLoadType,
+        texture: u8,
+        sampler: u8,
+        lod: SampleLod,
+    },
     ScalarUnaryOp {
         src: RegisterAddress<Scalar>,
         dst: RegisterAddress<Scalar>,
@@ -522,14 +550,94 @@ pub enum ShaderInstruction {
         x: RegisterAddress<Vector>,
         dest: RegisterAddress<Vector>,
     },
+    If {
+        cond: RegisterAddress<Scalar>,
+    },
+    Else,
+    EndIf,
+    Loop,
+    Break,
+    EndLoop,
+    Return,
     Nop,
 }
 
+enum InstructionDestination {
+    Scalar(RegisterAddress<Scalar>),
+    Vector(RegisterAddress<Vector>),
+}
+
+impl ShaderInstruction {
+    fn destination(&self) -> Option<InstructionDestination> {
+        match self {
+            Self::PopScalar(dst) |
+            Self::CopyScalarRegister { to: dst, .. } |
+            Self::ConditionallyCopyScalarRegister { to: dst, .. } |
+            Self::CopyVectorComponentToScalar { to: dst, .. } |
+            Self::ConditionallyCopyVectorComponentToScalar { to: dst, .. } |
+            Self::ReadBufferToScalar { scalar: dst, .. } |
+            Self::LoadTextureScalar { dst, .. } |
+            Self::ScalarUnaryOp { dst, .. } |
+            Self::ScalarBinaryOp { dst, .. } |
+            Self::VectorToScalarUnaryOp { dst, .. } => Some(InstructionDestination::Scalar(*dst)),
+
+            Self::PopVector(dst) |
+            Self::CopyVectorRegister { to: dst, .. } |
+            Self::ConditionallyCopyVectorRegister { to: dst, .. } |
+            Self::CopyScalarToVectorComponent { to: dst, .. } |
+            Self::ConditionallyCopyScalarToVectorComponent { to: dst, .. } |
+            Self::CopyScalarToVectorComponentsMasked { to: dst, .. } |
+            Self::ConditionallyCopyScalarToVectorComponentsMasked { to: dst, .. } |
+            Self::ReadBufferToVector { vector: dst, .. } |
+            Self::LoadTextureVector { dst, .. } |
+            Self::SampleTexture { dst, .. } |
+            Self::VectorComponentwiseScalarUnaryOp { dst, .. } |
+            Self::VectorComponentwiseScalarBinaryOp { dst, .. } |
+            Self::VectorComponentwiseScalarTernaryOp { dst, .. } |
+            Self::VectorComponentwiseScalarUnaryOpMasked { dst, .. } |
+            Self::VectorComponentwiseScalarBinaryOpMasked { dst, .. } |
+            Self::VectorComponentwiseScalarTernaryOpMasked { dst, .. } |
+            Self::VectorToVectorUnaryOp { dst, .. } |
+            Self::VectorBinaryOp { dst, .. } |
+            Self::VectorVectorScalarToVectorTernaryOp { dst, .. } |
+            Self::MatrixMultiply4x4V4 { dest: dst, .. } => Some(InstructionDestination::Vector(*dst)),
+
+            _ => None,
+        }
+    }
+}
+
 pub const CORE_COUNT: usize = 0x1000;
 pub const STACK_SIZE: usize = 0x400;
 pub const LOCAL_COUNT: usize = 0x20;
 pub const INPUT_OUTPUT_COUNT: usize = 0x100;
 pub const CONST_COUNT: usize = 0x100;
+pub const CONTROL_STACK_SIZE: usize = 0x20;
+// loop iterations a single run may take before every loop is forced to exit
+pub const LOOP_ITERATION_LIMIT: usize = 0x10000;
+pub const SAMPLER_COUNT: usize = 0x10;
+pub const DERIVATIVE_NEIGHBOR_NONE: u32 = 0xFFFF_FFFF;
+pub const LANE_MASK_WORDS: usize = (CORE_COUNT + 31) >> 5;
+
+pub type LaneMask = [u32; LANE_MASK_WORDS];
+
+fn lane_mask_first(n: usize) -> LaneMask {
+    let mut mask = [0u32; LANE_MASK_WORDS];
+    (0..n).for_each(|i| mask[i >> 5] |= 1 << (i & 31));
+    mask
+}
+
+fn lane_active(mask: &LaneMask, lane: usize) -> bool {
+    (mask[lane >> 5] & (1 << (lane & 31))) != 0
+}
+
+fn lane_mask_any(mask: &LaneMask) -> bool {
+    mask.iter().any(|x| *x != 0)
+}
+
+fn active_lanes(mask: &LaneMask, n: usize) -> impl Iterator<Item = usize> + '_ {
+    (0..n).filter(|i| lane_active(mask, *i))
+}
 
 type InputArrayRef<'a, T> = &'a mut [[T; CORE_COUNT]; INPUT_OUTPUT_COUNT];
 type ConstArrayRef<'a, T> = &'a mut [T; CONST_COUNT];
@@ -539,9 +647,11 @@ type InputOutputArray<T> = [[T; CORE_COUNT]; INPUT_OUTPUT_COUNT];
 type ConstantArray<T> = [T; CONST_COUNT];
 
 
+#[derive(Clone)]
 pub struct ShadingUnitConstantArray {
     pub scalar_constant_array: ConstantArray<u32>,
     pub vector_constant_array: ConstantArray<[u32; 4]>,
+    pub sampler_array: [TextureSampler; SAMPLER_COUNT],
 }
 
 impl ShadingUnitConstantArray {
@@ -549,6 +659,7 @@ impl ShadingUnitConstantArray {
         Self {
             scalar_constant_array: [ 0u32    ; CONST_COUNT],
             vector_constant_array: [[0u32; 4]; CONST_COUNT],
+            sampler_array: [TextureSampler::default(); SAMPLER_COUNT],
         }
     }
 }
@@ -653,6 +764,8 @@ pub struct ShadingUnitRunContext<'a> {
     pub vector_constant_array: ConstArrayRef<'a, [u32; 4]>,
     pub scalar_output_array: OutputArrayRef<'a, u32>,
     pub vector_output_array: OutputArrayRef<'a, [u32; 4]>,
+    pub sampler_array: &'a [TextureSampler; SAMPLER_COUNT],
+    pub derivatives: bool,
 }
 
 impl ShadingUnitRunContext<'_> {
@@ -664,6 +777,8 @@ impl ShadingUnitRunContext<'_> {
             vector_constant_array: &mut constant_array.vector_constant_array,
             scalar_output_array: &mut output_array.scalar_array,
             vector_output_array: &mut output_array.vector_array,
+            sampler_array: &constant_array.sampler_array,
+            derivatives: false,
         }
     }
 }
@@ -676,6 +791,25 @@ pub struct ShadingUnitContext {
     vector_stack_size:  usize,
     scalar_locals:      [[ u32     ; CORE_COUNT]; LOCAL_COUNT],
     vector_locals:      [[[u32; 4] ; CORE_COUNT]; LOCAL_COUNT],
+    exec_mask:          LaneMask,
+    branch_mask:        LaneMask,
+    break_mask:         LaneMask,
+    return_mask:        LaneMask,
+    control_stack:      [ControlFrame; CONTROL_STACK_SIZE],
+    control_stack_size: usize,
+    loop_iterations:    usize,
+    scalar_scratch:     [ u32     ; CORE_COUNT],
+    vector_scratch:     [[u32; 4] ; CORE_COUNT],
+    derivative_neighbors: [[u32; 2] ; CORE_COUNT],
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ControlFrame {
+    is_loop: bool,
+    loop_start: usize,
+    parent_mask: LaneMask,
+    taken_mask: LaneMask,
+    saved_break_mask: LaneMask,
 }
 
 enum RegisterRead<'a, T> {
@@ -684,6 +818,13 @@ enum RegisterRead<'a, T> {
 }
 
 impl<'a, T> RegisterRead<'a, T> {
+    fn lane(&self, i: usize) -> &'a T {
+        match self {
+            Self::Core(core_ref) => &core_ref[i],
+            Self::Uniform(uniform_ref) => uniform_ref,
+        }
+    }
+
     fn to_ptr(self) -> RegisterReadPtr<T> {
         match self {
             Self::Core(core_ref) => RegisterReadPtr::Core(core_ref as *const _),
@@ -712,12 +853,149 @@ impl ShadingUnitContext {
         unsafe { Box::<MaybeUninit<Self>>::assume_init(box_uninit) }
     }
 
-    pub fn run_instruction<'io, 'rc>(&mut self, n: usize, instruction: &ShaderInstruction, run_context: &'rc mut ShadingUnitRunContext<'io>, buffer_modules: &mut [BufferModule; 256], texture_modules: &mut [TextureModule; 64], resource_map: &ResourceMap) -> Option<()> {
+    pub fn run_shader<'io>(&mut self, n: usize, instructions: &[ShaderInstruction], run_context: &mut ShadingUnitRunContext<'io>, buffer_modules: &mut BufferAccess<'_>, texture_modules: &[TextureModule; 64], resource_map: &ResourceMap) -> Option<()> {
+        let full_mask = lane_mask_first(n);
+        self.exec_mask = full_mask;
+        self.branch_mask = full_mask;
+        self.break_mask = [0; LANE_MASK_WORDS];
+        self.return_mask = [0; LANE_MASK_WORDS];
+        self.control_stack_size = 0;
+        self.scalar_stack_size = 0;
+        self.vector_stack_size = 0;
+        self.loop_iterations = 0;
+        let mut pc = 0;
+        while pc < instructions.len() {
+            let instruction = &instructions[pc];
+            pc += 1;
+            match instruction {
+                ShaderInstruction::If { cond } => {
+                    let mut taken_mask = [0u32; LANE_MASK_WORDS];
+                    match self.read_scalar_register(*cond, run_context) {
+                        RegisterRead::Core(cond_list) =>
+                            active_lanes(&self.exec_mask, n).for_each(|i| if cond_list[i] != 0 { taken_mask[i >> 5] |= 1 << (i & 31) }),
+                        RegisterRead::Uniform(cond) => if *cond != 0 {
+                            taken_mask = self.exec_mask;
+                        },
+                    }
+                    self.push_control_frame(ControlFrame {
+                        is_loop: false,
+                        loop_start: 0,
+                        parent_mask: self.branch_mask,
+                        taken_mask,
+                        saved_break_mask: [0; LANE_MASK_WORDS],
+                    })?;
+                    self.branch_mask = taken_mask;
+                },
+                ShaderInstruction::Else => {
+                    let frame = &self.control_stack[self.control_stack_size.checked_sub(1)?];
+                    (0..LANE_MASK_WORDS).for_each(|w| self.branch_mask[w] = frame.parent_mask[w] & !frame.taken_mask[w]);
+                },
+                ShaderInstruction::EndIf => {
+                    self.control_stack_size = self.control_stack_size.checked_sub(1)?;
+                    self.branch_mask = self.control_stack[self.control_stack_size].parent_mask;
+                },
+                ShaderInstruction::Loop => {
+                    self.push_control_frame(ControlFrame {
+                        is_loop: true,
+                        loop_start: pc,
+                        parent_mask: self.branch_mask,
+                        taken_mask: [0; LANE_MASK_WORDS],
+                        saved_break_mask: self.break_mask,
+                    })?;
+                    self.branch_mask = self.exec_mask;
+                    self.break_mask = [0; LANE_MASK_WORDS];
+                },
+                ShaderInstruction::Break => {
+                    (0..LANE_MASK_WORDS).for_each(|w| self.break_mask[w] |= self.exec_mask[w]);
+                },
+                ShaderInstruction::EndLoop => {
+                    let frame = *self.control_stack.get(self.control_stack_size.checked_sub(1)?)?;
+                    if !frame.is_loop {
+                        return None;
+                    }
+                    let looping = lane_mask_any(&self.exec_mask) && self.loop_iterations < LOOP_ITERATION_LIMIT;
+                    if looping {
+                        self.loop_iterations += 1;
+                        pc = frame.loop_start;
+                    } else {
+                        if lane_mask_any(&self.exec_mask) {
+                            println!("GPU: shader ERROR: loops ran for more than {} iterations, breaking out!", LOOP_ITERATION_LIMIT);
+                        }
+                        self.control_stack_size -= 1;
+                        self.branch_mask = frame.parent_mask;
+                        self.break_mask = frame.saved_break_mask;
+                    }
+                },
+                ShaderInstruction::Return => {
+                    (0..LANE_MASK_WORDS).for_each(|w| self.return_mask[w] |= self.exec_mask[w]);
+                },
+                _ => {
+                    if self.exec_mask == full_mask {
+                        self.run_instruction(n, instruction, run_context, buffer_modules, texture_modules, resource_map)?;
+                    } else if lane_mask_any(&self.exec_mask) {
+                        self.run_instruction_masked(n, instruction, run_context, buffer_modules, texture_modules, resource_map)?;
+                    }
+                },
+            }
+            (0..LANE_MASK_WORDS).for_each(|w| self.exec_mask[w] = self.branch_mask[w] & !self.break_mask[w] & !self.return_mask[w]);
+            if self.control_stack_size == 0 && !lane_mask_any(&self.exec_mask) {
+                break;
+            }
+        }
+        Some(())
+    }
+
+    pub fn derivative_neighbors_mut(&mut self) -> &mut [[u32; 2]; CORE_COUNT] {
+        &mut self.derivative_neighbors
+    }
+
+    fn derivative_lod(&self, i: usize, coord_register: &RegisterRead<'_, [u32; 4]>, width: f32, height: f32) -> f32 {
+        let texel_coord = |lane: usize| {
+            let [u, v, ..] = *coord_register.lane(lane);
+            [f32::from_bits(u) * width, f32::from_bits(v) * height]
+        };
+        let center = texel_coord(i);
+        let rho_squared = self.derivative_neighbors[i].iter()
+            .filter(|neighbor| **neighbor != DERIVATIVE_NEIGHBOR_NONE)
+            .map(|neighbor| {
+                let [u, v] = texel_coord(*neighbor as usize);
+                (u - center[0]).powi(2) + (v - center[1]).powi(2)
+            })
+            .fold(0.0f32, f32::max);
+        0.5 * rho_squared.log2()
+    }
+
+    fn push_control_frame(&mut self, frame: ControlFrame) -> Option<()> {
+        *self.control_stack.get_mut(self.control_stack_size)? = frame;
+        self.control_stack_size += 1;
+        Some(())
+    }
+
+    fn run_instruction_masked<'io>(&mut self, n: usize, instruction: &ShaderInstruction, run_context: &mut ShadingUnitRunContext<'io>, buffer_modules: &mut BufferAccess<'_>, texture_modules: &[TextureModule; 64], resource_map: &ResourceMap) -> Option<()> {
+        match instruction.destination() {
+            Some(InstructionDestination::Scalar(dst)) => {
+                let register = self.write_scalar_register(dst, run_context)?;
+                self.scalar_scratch[0..n].copy_from_slice(&register[0..n]);
+                self.run_instruction(n, instruction, run_context, buffer_modules, texture_modules, resource_map)?;
+                (0..n).for_each(|i| if !lane_active(&self.exec_mask, i) { register[i] = self.scalar_scratch[i] });
+            },
+            Some(InstructionDestination::Vector(dst)) => {
+                let register = self.write_vector_register(dst, run_context)?;
+                self.vector_scratch[0..n].copy_from_slice(&register[0..n]);
+                self.run_instruction(n, instruction, run_context, buffer_modules, texture_modules, resource_map)?;
+                (0..n).for_each(|i| if !lane_active(&self.exec_mask, i) { register[i] = self.vector_scratch[i] });
+            },
+            None => self.run_instruction(n, instruction, run_context, buffer_modules, texture_modules, resource_map)?,
+        }
+        Some(())
+    }
+
+    pub fn run_instruction<'io, 'rc>(&mut self, n: usize, instruction: &ShaderInstruction, run_context: &'rc mut ShadingUnitRunContext<'io>, buffer_modules: &mut BufferAccess<'_>, texture_modules: &[TextureModule; 64], resource_map: &ResourceMap) -> Option<()> {
         match instruction {
             ShaderInstruction::Nop => {},
             ShaderInstruction::PushVector(register) => {
                 let source_register = self.read_vector_register(*register, run_context);
-                let stack_slot = &mut self.vector_stack_array[self.vector_stack_size];
+                let stack_slot = self.vector_stack_array.get_mut(self.vector_stack_size)?;
                 match source_register {
                     RegisterRead::Core(register_list) => stack_slot[0..n].copy_from_slice(&register_list[0..n]),
                     RegisterRead::Uniform(register) => stack_slot[0..n].fill(*register),
@@ -726,7 +1004,7 @@ impl ShadingUnitContext {
             },
             ShaderInstruction::PushScalar(register) => {
                 let source_register = self.read_scalar_register(*register, run_context);
-                let stack_slot = &mut self.scalar_stack_array[self.vector_stack_size];
+                let stack_slot = self.scalar_stack_array.get_mut(self.scalar_stack_size)?;
                 match source_register {
                     RegisterRead::Core(register_list) => stack_slot[0..n].copy_from_slice(&register_list[0..n]),
                     RegisterRead::Uniform(register) => stack_slot[0..n].fill(*register),
@@ -735,13 +1013,13 @@ impl ShadingUnitContext {
             },
             ShaderInstruction::PopVector(register) => {
                 let register_list = self.write_vector_register(*register, run_context)?;
-                self.vector_stack_size -= 1;
+                self.vector_stack_size = self.vector_stack_size.checked_sub(1)?;
                 let stack_slot = &self.vector_stack_array[self.vector_stack_size];
                 register_list[0..n].copy_from_slice(&stack_slot[0..n]);
             },
             ShaderInstruction::PopScalar(register) => {
                 let register_list = self.write_scalar_register(*register, run_context)?;
-                self.scalar_stack_size -= 1;
+                self.scalar_stack_size = self.scalar_stack_size.checked_sub(1)?;
                 let stack_slot = &self.scalar_stack_array[self.scalar_stack_size];
                 register_list[0..n].copy_from_slice(&stack_slot[0..n]);
             },
@@ -909,12 +1187,39 @@ impl ShadingUnitContext {
                 }
             },
             ShaderInstruction::ReadBufferToVector { data_type, vector, offset, addr_src_u32, buffer } => {
-                todo!()
+                let vector_reg = self.write_vector_register(*vector, run_context)?;
+                let addr_src_reg = addr_src_u32.map(|reg_addr| self.read_scalar_register(reg_addr, run_context));
+                let buffer_number = resource_map.buffer[*buffer as usize] as usize;
+                let bytes = buffer_modules.get(buffer_number).bytes();
+                let (element_type, component_count) = match *data_type {
+                    VectorBufferDataType::Scalar(element_type) => (element_type, 1),
+                    VectorBufferDataType::V2(element_type) => (element_type, 2),
+                    VectorBufferDataType::V3(element_type) => (element_type, 3),
+                    VectorBufferDataType::V4(element_type) => (element_type, 4),
+                };
+                let read_fn = buffer_read_fn(element_type);
+                let element_size = buffer_data_type_size(element_type);
+                let read_vector = |address: usize| {
+                    let mut value = [0u32; 4];
+                    for c in 0..component_count {
+                        value[c] = read_fn(bytes, address + c * element_size);
+                    }
+                    value
+                };
+                match addr_src_reg {
+                    None =>
+                        (0..n).for_each(|i| vector_reg[i] = read_vector(*offset as usize)),
+                    Some(RegisterRead::Uniform(addr_src)) =>
+                        (0..n).for_each(|i| vector_reg[i] = read_vector(*addr_src as usize + *offset as usize)),
+                    Some(RegisterRead::Core(addr_src_list)) =>
+                        (0..n).for_each(|i| vector_reg[i] = read_vector(addr_src_list[i] as usize + *offset as usize)),
+                }
             },
             ShaderInstruction::WriteVectorToBuffer{ data_type, src, offset, addr_src_u32, buffer } => {
                 let buffer_number = resource_map.buffer[*buffer as usize] as usize;
-                let buffer = &mut buffer_modules[buffer_number];
+                let buffer = buffer_modules.get_mut(buffer_number)?;
                 let bytes = buffer.bytes_mut();
+                let exec_mask = self.exec_mask;
                 let from_register = self.read_vector_register(*src, run_context);
                 let write_fn = match *data_type {
                     VectorBufferDataType::Scalar(BufferDataType::I8 ) => |bytes: &mut [u8], offset: usize, vector: [u32; 4]|
@@ -1026,22 +1331,22 @@ impl ShadingUnitContext {
                         match self.read_scalar_register(*src_register_addr, run_context) {
                             RegisterRead::Core(addr_register_list) => {
                                 match from_register {
-                                    RegisterRead::Core(from_register_list) => (0..n).for_each(|i| write_fn(bytes, *offset as usize + addr_register_list[i] as usize, from_register_list[i])),
-                                    RegisterRead::Uniform(from_register) => (0..n).for_each(|i| write_fn(bytes, *offset as usize + addr_register_list[i] as usize, *from_register)),
+                                    RegisterRead::Core(from_register_list) => active_lanes(&exec_mask, n).for_each(|i| write_fn(bytes, *offset as usize + addr_register_list[i] as usize, from_register_list[i])),
+                                    RegisterRead::Uniform(from_register) => active_lanes(&exec_mask, n).for_each(|i| write_fn(bytes, *offset as usize + addr_register_list[i] as usize, *from_register)),
                                 }
                             },
                             RegisterRead::Uniform(addr_register) => {
                                 match from_register {
-                                    RegisterRead::Core(from_register_list) => (0..n).for_each(|i| write_fn(bytes, *offset as usize + *addr_register as usize, from_register_list[i])),
-                                    RegisterRead::Uniform(from_register) => (0..n).for_each(|_i| write_fn(bytes, *offset as usize + *addr_register as usize, *from_register)),
+                                    RegisterRead::Core(from_register_list) => active_lanes(&exec_mask, n).for_each(|i| write_fn(bytes, *offset as usize + *addr_register as usize, from_register_list[i])),
+                                    RegisterRead::Uniform(from_register) => active_lanes(&exec_mask, n).for_each(|_i| write_fn(bytes, *offset as usize + *addr_register as usize, *from_register)),
                                 }
                             }
                         }
                     },
                     None => {
                         match from_register {
-                            RegisterRead::Core(from_register_list) => (0..n).for_each(|i| write_fn(bytes, *offset as usize, from_register_list[i])),
-                            RegisterRead::Uniform(from_register) => (0..n).for_each(|_i| write_fn(bytes, *offset as usize, *from_register)),
+                            RegisterRead::Core(from_register_list) => active_lanes(&exec_mask, n).for_each(|i| write_fn(bytes, *offset as usize, from_register_list[i])),
+                            RegisterRead::Uniform(from_register) => active_lanes(&exec_mask, n).for_each(|_i| write_fn(bytes, *offset as usize, *from_register)),
                         }
                     }
                 }
@@ -1049,43 +1354,9 @@ impl ShadingUnitContext {
             ShaderInstruction::ReadBufferToScalar { data_type, scalar, offset, addr_src_u32, buffer } => {
                 let scalar_reg = self.write_scalar_register(*scalar, run_context)?;
                 let addr_src_reg = addr_src_u32.map(|reg_addr| self.read_scalar_register(reg_addr, run_context));
-                let buffer = &buffer_modules[*buffer as usize];
-                let read_fn = match data_type {
-                    BufferDataType::F32 |
-                    BufferDataType::U32 |
-                    BufferDataType::I32     => |bytes: &[u8], offset: usize|
-                        read_bytes_u32(bytes, offset),
-
-                    BufferDataType::INorm32 => |bytes: &[u8], offset: usize|
-                        (read_bytes_u32(bytes, offset) as i32 as f32 / std::i32::MAX as f32).to_bits(),
-
-                    BufferDataType::INorm16 => |bytes: &[u8], offset: usize| 
-                        (read_bytes_u16(bytes, offset) as i16 as f32 / std::i16::MAX as f32).to_bits(),
-
-                    BufferDataType::INorm8  => |bytes: &[u8], offset: usize|
-                        (read_bytes_u8(bytes, offset) as i8 as f32 / std::i8::MAX as f32).to_bits(),
-
-                    BufferDataType::UNorm32 => |bytes: &[u8], offset: usize|
-                        (read_bytes_u32(bytes, offset) as u32 as f32 / std::u32::MAX as f32).to_bits(),
-
-                    BufferDataType::UNorm16 => |bytes: &[u8], offset: usize|
-                        (read_bytes_u16(bytes, offset) as u16 as f32 / std::u16::MAX as f32).to_bits(),
-
-                    BufferDataType::UNorm8  => |bytes: &[u8], offset: usize|
-                        (read_bytes_u8(bytes, offset) as u8 as f32 / std::u8::MAX as f32).to_bits(),
-
-                    BufferDataType::U16     => |bytes: &[u8], offset: usize|
-                        read_bytes_u16(bytes, offset) as u32,
-
-                    BufferDataType::U8      => |bytes: &[u8], offset: usize|
-                        read_bytes_u8(bytes, offset) as u32,
-
-                    BufferDataType::I16     => |bytes: &[u8], offset: usize|
-                        read_bytes_u16(bytes, offset) as i16 as i32 as u32,
-
-                    BufferDataType::I8      => |bytes: &[u8], offset: usize|
-                        read_bytes_u8(bytes, offset) as i8 as i32 as u32,
-                };
+                let buffer_number = resource_map.buffer[*buffer as usize] as usize;
+                let buffer = buffer_modules.get(buffer_number);
+                let read_fn = buffer_read_fn(*data_type);
                 match addr_src_reg {
                     None =>
                         (0..n).for_each(|i| scalar_reg[i] = read_fn(buffer.bytes(), *offset as usize)),
@@ -1097,8 +1368,9 @@ impl ShadingUnitContext {
             },
             ShaderInstruction::WriteScalarToBuffer { data_type, scalar, offset, addr_dst_u32, buffer } => {
                 let buffer_number = resource_map.buffer[*buffer as usize] as usize;
-                let buffer = &mut buffer_modules[buffer_number];
+                let buffer = buffer_modules.get_mut(buffer_number)?;
                 let bytes = buffer.bytes_mut();
+                let exec_mask = self.exec_mask;
                 let from_register = self.read_scalar_register(*scalar, run_context);
                 let write_fn = match *data_type {
                     BufferDataType::I8      => |bytes: &mut [u8], offset: usize, scalar: u32| write_bytes_u8 (&[scalar as i32 as  i8 as  u8], bytes, offset),
@@ -1120,22 +1392,22 @@ impl ShadingUnitContext {
                         match self.read_scalar_register(*src_register_addr, run_context) {
                             RegisterRead::Core(addr_register_list) => {
                                 match from_register {
-                                    RegisterRead::Core(from_register_list) => (0..n).for_each(|i| write_fn(bytes, *offset as usize + addr_register_list[i] as usize, from_register_list[i])),
-                                    RegisterRead::Uniform(from_register) => (0..n).for_each(|i| write_fn(bytes, *offset as usize + addr_register_list[i] as usize, *from_register)),
+                                    RegisterRead::Core(from_register_list) => active_lanes(&exec_mask, n).for_each(|i| write_fn(bytes, *offset as usize + addr_register_list[i] as usize, from_register_list[i])),
+                                    RegisterRead::Uniform(from_register) => active_lanes(&exec_mask, n).for_each(|i| write_fn(bytes, *offset as usize + addr_register_list[i] as usize, *from_register)),
                                 }
                             },
                             RegisterRead::Uniform(addr_register) => {
                                 match from_register {
-                                    RegisterRead::Core(from_register_list) => (0..n).for_each(|i| write_fn(bytes, *offset as usize + *addr_register as usize, from_register_list[i])),
-                                    RegisterRead::Uniform(from_register) => (0..n).for_each(|_i| write_fn(bytes, *offset as usize + *addr_register as usize, *from_register)),
+                                    RegisterRead::Core(from_register_list) => active_lanes(&exec_mask, n).for_each(|i| write_fn(bytes, *offset as usize + *addr_register as usize, from_register_list[i])),
+                                    RegisterRead::Uniform(from_register) => active_lanes(&exec_mask, n).for_each(|_i| write_fn(bytes, *offset as usize + *addr_register as usize, *from_register)),
                                 }
                             }
                         }
                     },
                     None => {
                         match from_register {
-                            RegisterRead::Core(from_register_list) => (0..n).for_each(|i| write_fn(bytes, *offset as usize, from_register_list[i])),
-                            RegisterRead::Uniform(from_register) => (0..n).for_each(|_i| write_fn(bytes, *offset as usize, *from_register)),
+                            RegisterRead::Core(from_register_list) => active_lanes(&exec_mask, n).for_each(|i| write_fn(bytes, *offset as usize, from_register_list[i])),
+                            RegisterRead::Uniform(from_register) => active_lanes(&exec_mask, n).for_each(|_i| write_fn(bytes, *offset as usize, *from_register)),
                         }
                     }
                 }
@@ -1146,242 +1418,49 @@ impl ShadingUnitContext {
                 let pixel_layout = texture.config.pixel_layout;
                 let coord_register = self.read_vector_register(*src_xy_u32, run_context);
                 let vector_register = self.write_vector_register(*dst, run_context)?;
-                let texture_load_op = match (*load_type, pixel_layout) {
-                    (TextureLoadType::F32FromF32,  PixelDataLayout::D32x1)  |
-                    (TextureLoadType::I32FromInt,  PixelDataLayout::D32x1)  |
-                    (TextureLoadType::I32FromUInt, PixelDataLayout::D32x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u32>(u, v);
-                        [x, 0, 0, 0]
-                    },
-                    (TextureLoadType::F32FromF32,  PixelDataLayout::D32x2)  |
-                    (TextureLoadType::I32FromInt,  PixelDataLayout::D32x2)  |
-                    (TextureLoadType::I32FromUInt, PixelDataLayout::D32x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u32; 2]>(u, v);
-                        [x, y, 0, 0]
-                    },
-                    (TextureLoadType::F32FromF32,  PixelDataLayout::D32x4)  |
-                    (TextureLoadType::I32FromInt,  PixelDataLayout::D32x4)  |
-                    (TextureLoadType::I32FromUInt, PixelDataLayout::D32x4) => |u: u32, v: u32, texture: &TextureModule| texture.fetch::<[u32; 4]>(u, v),
-                    (TextureLoadType::F32FromF32,  _                     ) => |_u: u32, _v: u32, _texture: &TextureModule| [0, 0, 0, 0],
-                    (TextureLoadType::I32FromUInt, PixelDataLayout::D16x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u16>(u, v);
-                        [x as u32, 0, 0, 0]
-                    },
-                    (TextureLoadType::I32FromUInt, PixelDataLayout::D16x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u16; 2]>(u, v);
-                        [x as u32, y as u32, 0, 0]
-                    },
-                    (TextureLoadType::I32FromUInt, PixelDataLayout::D16x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u16; 4]>(u, v);
-                        data.map(|x| x as u32)
-                    },
-                    (TextureLoadType::I32FromUInt, PixelDataLayout::D8x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u8>(u, v);
-                        [x as u32, 0, 0, 0]
-                    },
-                    (TextureLoadType::I32FromUInt, PixelDataLayout::D8x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u8; 2]>(u, v);
-                        [x as u32, y as u32, 0, 0]
-                    },
-                    (TextureLoadType::I32FromUInt, PixelDataLayout::D8x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u8; 4]>(u, v);
-                        data.map(|x| x as u32)
-                    },
-                    (TextureLoadType::I32FromInt, PixelDataLayout::D16x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u16>(u, v);
-                        [x as i16 as i32 as u32, 0, 0, 0]
-                    },
-                    (TextureLoadType::I32FromInt, PixelDataLayout::D16x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u16; 2]>(u, v);
-                        [x as i16 as i32 as u32, y as i16 as i32 as u32, 0, 0]
-                    },
-                    (TextureLoadType::I32FromInt, PixelDataLayout::D16x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u16; 4]>(u, v);
-                        data.map(|x| x as i16 as i32 as u32)
-                    },
-                    (TextureLoadType::I32FromInt, PixelDataLayout::D8x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u8>(u, v);
-                        [x as i8 as i32 as u32, 0, 0, 0]
-                    },
-                    (TextureLoadType::I32FromInt, PixelDataLayout::D8x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u8; 2]>(u, v);
-                        [x as i8 as i32 as u32, y as i8 as i32 as u32, 0, 0]
-                    },
-                    (TextureLoadType::I32FromInt, PixelDataLayout::D8x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u8; 4]>(u, v);
-                        data.map(|x| x as i8 as i32 as u32)
-                    },
-                    (TextureLoadType::F32FromINorm, PixelDataLayout::D32x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u32>(u, v);
-                        [(x as i32 as f32 / std::i32::MAX as f32).to_bits(), 0, 0, 0]
-                    },
-                    (TextureLoadType::F32FromINorm, PixelDataLayout::D32x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u32; 2]>(u, v);
-                        [(x as i32 as f32 / std::i32::MAX as f32).to_bits(), (y as i32 as f32 / std::i32::MAX as f32).to_bits(), 0, 0]
-                    },
-                    (TextureLoadType::F32FromINorm, PixelDataLayout::D32x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u32; 4]>(u, v);
-                        data.map(|x| (x as i32 as f32 / std::i32::MAX as f32).to_bits())
-                    },
-                    (TextureLoadType::F32FromINorm, PixelDataLayout::D16x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u16>(u, v);
-                        [(x as i16 as f32 / std::i16::MAX as f32).to_bits(), 0, 0, 0]
-                    },
-                    (TextureLoadType::F32FromINorm, PixelDataLayout::D16x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u16; 2]>(u, v);
-                        [(x as i16 as f32 / std::i16::MAX as f32).to_bits(), (y as i16 as f32 / std::i16::MAX as f32).to_bits(), 0, 0]
-                    },
-                    (TextureLoadType::F32FromINorm, PixelDataLayout::D16x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u16; 4]>(u, v);
-                        data.map(|x| (x as i16 as f32 / std::i16::MAX as f32).to_bits())
-                    },
-                    (TextureLoadType::F32FromINorm, PixelDataLayout::D8x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u8>(u, v);
-                        [(x as i8 as f32 / std::i8::MAX as f32).to_bits(), 0, 0, 0]
-                    },
-                    (TextureLoadType::F32FromINorm, PixelDataLayout::D8x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u8; 2]>(u, v);
-                        [(x as i8 as f32 / std::i8::MAX as f32).to_bits(), (y as i8 as f32 / std::i32::MAX as f32).to_bits(), 0, 0]
-                    },
-                    (TextureLoadType::F32FromINorm, PixelDataLayout::D8x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u8; 4]>(u, v);
-                        data.map(|x| (x as i8 as f32 / std::i8::MAX as f32).to_bits())
-                    },
-                    (TextureLoadType::F32FromUNorm, PixelDataLayout::D32x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u32>(u, v);
-                        [(x as u32 as f32 / std::u32::MAX as f32).to_bits(), 0, 0, 0]
-                    },
-                    (TextureLoadType::F32FromUNorm, PixelDataLayout::D32x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u32; 2]>(u, v);
-                        [(x as u32 as f32 / std::u32::MAX as f32).to_bits(), (y as u32 as f32 / std::u32::MAX as f32).to_bits(), 0, 0]
-                    },
-                    (TextureLoadType::F32FromUNorm, PixelDataLayout::D32x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u32; 4]>(u, v);
-                        data.map(|x| (x as u32 as f32 / std::u32::MAX as f32).to_bits())
-                    },
-                    (TextureLoadType::F32FromUNorm, PixelDataLayout::D16x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u16>(u, v);
-                        [(x as u16 as f32 / std::u16::MAX as f32).to_bits(), 0, 0, 0]
-                    },
-                    (TextureLoadType::F32FromUNorm, PixelDataLayout::D16x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u16; 2]>(u, v);
-                        [(x as u16 as f32 / std::u16::MAX as f32).to_bits(), (y as u16 as f32 / std::u16::MAX as f32).to_bits(), 0, 0]
-                    },
-                    (TextureLoadType::F32FromUNorm, PixelDataLayout::D16x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u16; 4]>(u, v);
-                        data.map(|x| (x as u16 as f32 / std::u16::MAX as f32).to_bits())
-                    },
-                    (TextureLoadType::F32FromUNorm, PixelDataLayout::D8x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u8>(u, v);
-                        [(x as u8 as f32 / std::u8::MAX as f32).to_bits(), 0, 0, 0]
-                    },
-                    (TextureLoadType::F32FromUNorm, PixelDataLayout::D8x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u8; 2]>(u, v);
-                        [(x as u8 as f32 / std::u8::MAX as f32).to_bits(), (y as u8 as f32 / std::u32::MAX as f32).to_bits(), 0, 0]
-                    },
-                    (TextureLoadType::F32FromUNorm, PixelDataLayout::D8x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u8; 4]>(u, v);
-                        data.map(|x| (x as u8 as f32 / std::u8::MAX as f32).to_bits())
-                    },
-                    (TextureLoadType::F32FromInt, PixelDataLayout::D32x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u32>(u, v);
-                        [(x as i32 as f32).to_bits(), 0, 0, 0]
-                    },
-                    (TextureLoadType::F32FromInt, PixelDataLayout::D32x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u32; 2]>(u, v);
-                        [(x as i32 as f32).to_bits(), (y as i32 as f32).to_bits(), 0, 0]
-                    },
-                    (TextureLoadType::F32FromInt, PixelDataLayout::D32x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u32; 4]>(u, v);
-                        data.map(|x| (x as i32 as f32).to_bits())
-                    },
-                    (TextureLoadType::F32FromInt, PixelDataLayout::D16x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u16>(u, v);
-                        [(x as i16 as f32).to_bits(), 0, 0, 0]
-                    },
-                    (TextureLoadType::F32FromInt, PixelDataLayout::D16x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u16; 2]>(u, v);
-                        [(x as i16 as f32).to_bits(), (y as i32 as f32).to_bits(), 0, 0]
-                    },
-                    (TextureLoadType::F32FromInt, PixelDataLayout::D16x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u16; 4]>(u, v);
-                        data.map(|x| (x as i16 as f32).to_bits())
-                    },
-                    (TextureLoadType::F32FromInt, PixelDataLayout::D8x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u8>(u, v);
-                        [(x as i8 as f32).to_bits(), 0, 0, 0]
-                    },
-                    (TextureLoadType::F32FromInt, PixelDataLayout::D8x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u8; 2]>(u, v);
-                        [(x as i8 as f32).to_bits(), (y as i32 as f32).to_bits(), 0, 0]
-                    },
-                    (TextureLoadType::F32FromInt, PixelDataLayout::D8x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u8; 4]>(u, v);
-                        data.map(|x| (x as i8 as f32).to_bits())
-                    },
-
-                    (TextureLoadType::F32FromUInt, PixelDataLayout::D32x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<u32>(u, v);
-                        [(data as f32).to_bits(), 0, 0, 0]
-                    },
-                    (TextureLoadType::F32FromUInt, PixelDataLayout::D32x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u32; 2]>(u, v);
-                        [(x as f32).to_bits(), (y as f32).to_bits(), 0, 0]
-                    },
-                    (TextureLoadType::F32FromUInt, PixelDataLayout::D32x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u32; 4]>(u, v);
-                        data.map(|x| (x as f32).to_bits())
-                    },
-                    (TextureLoadType::F32FromUInt, PixelDataLayout::D16x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<u16>(u, v);
-                        [(data as f32).to_bits(), 0, 0, 0]
-                    },
-                    (TextureLoadType::F32FromUInt, PixelDataLayout::D16x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u16; 2]>(u, v);
-                        [(x as f32).to_bits(), (y as f32).to_bits(), 0, 0]
-                    },
-                    (TextureLoadType::F32FromUInt, PixelDataLayout::D16x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u16; 4]>(u, v);
-                        data.map(|x| (x as f32).to_bits())
-                    },
-                    (TextureLoadType::F32FromUInt, PixelDataLayout::D8x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<u8>(u, v);
-                        [(data as f32).to_bits(), 0, 0, 0]
-                    },
-                    (TextureLoadType::F32FromUInt, PixelDataLayout::D8x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u8; 2]>(u, v);
-                        [(x as f32).to_bits(), (y as f32).to_bits(), 0, 0]
-                    },
-                    (TextureLoadType::F32FromUInt, PixelDataLayout::D8x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u8; 4]>(u, v);
-                        data.map(|x| (x as f32).to_bits())
-                    },
-                    (TextureLoadType::I32FromF32, PixelDataLayout::D32x1) => |u: u32, v: u32, texture: &TextureModule| {
-                        let x = texture.fetch::<u32>(u, v);
-                        [f32::from_bits(x) as i32 as u32, 0, 0, 0]
-                    },
-                    (TextureLoadType::I32FromF32, PixelDataLayout::D32x2) => |u: u32, v: u32, texture: &TextureModule| {
-                        let [x, y] = texture.fetch::<[u32; 2]>(u, v);
-                        [f32::from_bits(x) as i32 as u32, f32::from_bits(y) as i32 as u32, 0, 0]
-                    },
-                    (TextureLoadType::I32FromF32, PixelDataLayout::D32x4) => |u: u32, v: u32, texture: &TextureModule| {
-                        let data = texture.fetch::<[u32; 4]>(u, v);
-                        data.map(|x| f32::from_bits(x) as i32 as u32)
-                    },
-                    (TextureLoadType::I32FromF32, _) => |_u: u32, _v: u32, _texture: &TextureModule| [0, 0, 0, 0],
-                };
+                let texture_load_op = texture_load_fn(*load_type, pixel_layout);
+                let level = texture.level(0).with_palette(buffer_modules.get(texture.palette as usize).bytes());
                 match coord_register {
                     RegisterRead::Core(coord_register_list) => (0..n).for_each(|i| {
                         let [x, y, ..] = coord_register_list[i];
-                        vector_register[i] = texture_load_op(x, y, texture);
+                        vector_register[i] = texture_load_op(x, y, &level);
                     }),
                     RegisterRead::Uniform(coord_register) => {
                         let [x, y, ..] = *coord_register;
-                        let value = texture_load_op(x, y, texture);
+                        let value = texture_load_op(x, y, &level);
                         vector_register[0..n].fill(value);
                     },
                 }
             },
+            ShaderInstruction::SampleTexture { src_uv, dst, load_type, texture, sampler, lod } => {
+                let texture_number = resource_map.texture[*texture as usize] as usize;
+                let texture = &texture_modules[texture_number];
+                let sampler = run_context.sampler_array[*sampler as usize % SAMPLER_COUNT];
+                let texture_load_op = texture_load_fn(*load_type, texture.config.pixel_layout);
+                let filter = match *load_type {
+                    TextureLoadType::I32FromInt  |
+                    TextureLoadType::I32FromUInt |
+                    TextureLoadType::I32FromF32 => SampleFilter::Nearest,
+                    _ => sampler.filter,
+                };
+                let lod_register = match lod {
+                    SampleLod::Auto => None,
+                    SampleLod::Explicit(lod) |
+                    SampleLod::Bias(lod) => Some(self.read_scalar_register(*lod, run_context)),
+                };
+                let auto_lod = run_context.derivatives && !matches!(lod, SampleLod::Explicit(_));
+                let (width, height) = texture.level_size(0);
+                let palette = buffer_modules.get(texture.palette as usize).bytes();
+                let coord_register = self.read_vector_register(*src_uv, run_context);
+                let vector_register = self.write_vector_register(*dst, run_context)?;
+                (0..n).for_each(|i| {
+                    let [u, v, ..] = *coord_register.lane(i);
+                    let lane_lod =
+                        if auto_lod { self.derivative_lod(i, &coord_register, width as f32, height as f32) } else { 0.0 } +
+                        lod_register.as_ref().map(|lod| f32::from_bits(*lod.lane(i))).unwrap_or(0.0);
+                    vector_register[i] = sample_texture(f32::from_bits(u), f32::from_bits(v), lane_lod, filter, &sampler, texture, palette, texture_load_op);
+                });
+            },
             ShaderInstruction::LoadTextureScalar { src_xy_u32, load_type, channel, dst, texture } => {
                 let texture_number = resource_map.texture[*texture as usize] as usize;
                 let texture = &texture_modules[texture_number];
@@ -1403,7 +1482,13 @@ impl ShadingUnitContext {
                     (PixelDataLayout::D16x2, VectorChannel::Z) |
                     (PixelDataLayout::D16x2, VectorChannel::W) |
                     (PixelDataLayout::D32x2, VectorChannel::Z) |
-                    (PixelDataLayout::D32x2, VectorChannel::W) => None?,
+                    (PixelDataLayout::D32x2, VectorChannel::W) |
+                    (PixelDataLayout::I4,    VectorChannel::Y) |
+                    (PixelDataLayout::I4,    VectorChannel::Z) |
+                    (PixelDataLayout::I4,    VectorChannel::W) |
+                    (PixelDataLayout::I8,    VectorChannel::Y) |
+                    (PixelDataLayout::I8,    VectorChannel::Z) |
+                    (PixelDataLayout::I8,    VectorChannel::W) => None?,
                     _ => {}
                 };
                 let channel = *channel as usize;
@@ -1419,7 +1504,29 @@ impl ShadingUnitContext {
                     (TextureLoadType::I32FromF32,  PixelDataLayout::D8x4 ) |
                     (TextureLoadType::I32FromF32,  PixelDataLayout::D16x1) |
                     (TextureLoadType::I32FromF32,  PixelDataLayout::D16x2) |
-                    (TextureLoadType::I32FromF32,  PixelDataLayout::D16x4) => None?,
+                    (TextureLoadType::I32FromF32,  PixelDataLayout::D16x4) |
+                    (TextureLoadType::F32FromF32,  PixelDataLayout::I4   ) |
+                    (TextureLoadType::F32FromF32,  PixelDataLayout::I8   ) |
+                    (TextureLoadType::I32FromF32,  PixelDataLayout::I4   ) |
+                    (TextureLoadType::I32FromF32,  PixelDataLayout::I8   ) |
+                    (TextureLoadType::F32FromINorm, PixelDataLayout::I4  ) |
+                    (TextureLoadType::F32FromINorm, PixelDataLayout::I8  ) |
+                    (TextureLoadType::F32FromUNorm, PixelDataLayout::I4  ) |
+                    (TextureLoadType::F32FromUNorm, PixelDataLayout::I8  ) => None?,
+
+                    // scalar loads read the palette index itself
+                    (TextureLoadType::I32FromInt,  PixelDataLayout::I4) |
+                    (TextureLoadType::I32FromInt,  PixelDataLayout::I8) |
+                    (TextureLoadType::I32FromUInt, PixelDataLayout::I4) |
+                    (TextureLoadType::I32FromUInt, PixelDataLayout::I8) => |u: u32, v: u32, texture: &TextureModule, _channel: usize| {
+                        texture.fetch_index(u, v) as u32
+                    },
+                    (TextureLoadType::F32FromInt,  PixelDataLayout::I4) |
+                    (TextureLoadType::F32FromInt,  PixelDataLayout::I8) |
+                    (TextureLoadType::F32FromUInt, PixelDataLayout::I4) |
+                    (TextureLoadType::F32FromUInt, PixelDataLayout::I8) => |u: u32, v: u32, texture: &TextureModule, _channel: usize| {
+                        (texture.fetch_index(u, v) as f32).to_bits()
+                    },
 
                     (TextureLoadType::I32FromF32,  PixelDataLayout::D32x1) => |u: u32, v: u32, texture: &TextureModule, _channel: usize| {
                         let x = texture.fetch::<u32>(u, v);
@@ -2106,6 +2213,353 @@ impl ShadingUnitContext {
     }
 }
 
+fn texture_load_fn(load_type: TextureLoadType, pixel_layout: PixelDataLayout) -> fn(u32, u32, &TextureLevel) -> [u32; 4] {
+    match (load_type, pixel_layout) {
+        (TextureLoadType::F32FromF32,  PixelDataLayout::D32x1)  |
+        (TextureLoadType::I32FromInt,  PixelDataLayout::D32x1)  |
+        (TextureLoadType::I32FromUInt, PixelDataLayout::D32x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u32>(u, v);
+            [x, 0, 0, 0]
+        },
+        (TextureLoadType::F32FromF32,  PixelDataLayout::D32x2)  |
+        (TextureLoadType::I32FromInt,  PixelDataLayout::D32x2)  |
+        (TextureLoadType::I32FromUInt, PixelDataLayout::D32x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u32; 2]>(u, v);
+            [x, y, 0, 0]
+        },
+        (TextureLoadType::F32FromF32,  PixelDataLayout::D32x4)  |
+        (TextureLoadType::I32FromInt,  PixelDataLayout::D32x4)  |
+        (TextureLoadType::I32FromUInt, PixelDataLayout::D32x4) => |u: u32, v: u32, texture: &TextureLevel| texture.fetch::<[u32; 4]>(u, v),
+        (TextureLoadType::F32FromF32,  _                     ) => |_u: u32, _v: u32, _texture: &TextureLevel| [0, 0, 0, 0],
+        (TextureLoadType::I32FromUInt, PixelDataLayout::D16x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u16>(u, v);
+            [x as u32, 0, 0, 0]
+        },
+        (TextureLoadType::I32FromUInt, PixelDataLayout::D16x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u16; 2]>(u, v);
+            [x as u32, y as u32, 0, 0]
+        },
+        (TextureLoadType::I32FromUInt, PixelDataLayout::D16x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u16; 4]>(u, v);
+            data.map(|x| x as u32)
+        },
+        (TextureLoadType::I32FromUInt, PixelDataLayout::D8x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u8>(u, v);
+            [x as u32, 0, 0, 0]
+        },
+        (TextureLoadType::I32FromUInt, PixelDataLayout::D8x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u8; 2]>(u, v);
+            [x as u32, y as u32, 0, 0]
+        },
+        (TextureLoadType::I32FromUInt, PixelDataLayout::D8x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u8; 4]>(u, v);
+            data.map(|x| x as u32)
+        },
+        (TextureLoadType::I32FromInt, PixelDataLayout::D16x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u16>(u, v);
+            [x as i16 as i32 as u32, 0, 0, 0]
+        },
+        (TextureLoadType::I32FromInt, PixelDataLayout::D16x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u16; 2]>(u, v);
+            [x as i16 as i32 as u32, y as i16 as i32 as u32, 0, 0]
+        },
+        (TextureLoadType::I32FromInt, PixelDataLayout::D16x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u16; 4]>(u, v);
+            data.map(|x| x as i16 as i32 as u32)
+        },
+        (TextureLoadType::I32FromInt, PixelDataLayout::D8x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u8>(u, v);
+            [x as i8 as i32 as u32, 0, 0, 0]
+        },
+        (TextureLoadType::I32FromInt, PixelDataLayout::D8x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u8; 2]>(u, v);
+            [x as i8 as i32 as u32, y as i8 as i32 as u32, 0, 0]
+        },
+        (TextureLoadType::I32FromInt, PixelDataLayout::D8x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u8; 4]>(u, v);
+            data.map(|x| x as i8 as i32 as u32)
+        },
+        (TextureLoadType::F32FromINorm, PixelDataLayout::D32x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u32>(u, v);
+            [(x as i32 as f32 / std::i32::MAX as f32).to_bits(), 0, 0, 0]
+        },
+        (TextureLoadType::F32FromINorm, PixelDataLayout::D32x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u32; 2]>(u, v);
+            [(x as i32 as f32 / std::i32::MAX as f32).to_bits(), (y as i32 as f32 / std::i32::MAX as f32).to_bits(), 0, 0]
+        },
+        (TextureLoadType::F32FromINorm, PixelDataLayout::D32x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u32; 4]>(u, v);
+            data.map(|x| (x as i32 as f32 / std::i32::MAX as f32).to_bits())
+        },
+        (TextureLoadType::F32FromINorm, PixelDataLayout::D16x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u16>(u, v);
+            [(x as i16 as f32 / std::i16::MAX as f32).to_bits(), 0, 0, 0]
+        },
+        (TextureLoadType::F32FromINorm, PixelDataLayout::D16x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u16; 2]>(u, v);
+            [(x as i16 as f32 / std::i16::MAX as f32).to_bits(), (y as i16 as f32 / std::i16::MAX as f32).to_bits(), 0, 0]
+        },
+        (TextureLoadType::F32FromINorm, PixelDataLayout::D16x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u16; 4]>(u, v);
+            data.map(|x| (x as i16 as f32 / std::i16::MAX as f32).to_bits())
+        },
+        (TextureLoadType::F32FromINorm, PixelDataLayout::D8x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u8>(u, v);
+            [(x as i8 as f32 / std::i8::MAX as f32).to_bits(), 0, 0, 0]
+        },
+        (TextureLoadType::F32FromINorm, PixelDataLayout::D8x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u8; 2]>(u, v);
+            [(x as i8 as f32 / std::i8::MAX as f32).to_bits(), (y as i8 as f32 / std::i32::MAX as f32).to_bits(), 0, 0]
+        },
+        (TextureLoadType::F32FromINorm, PixelDataLayout::D8x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u8; 4]>(u, v);
+            data.map(|x| (x as i8 as f32 / std::i8::MAX as f32).to_bits())
+        },
+        (TextureLoadType::F32FromUNorm, PixelDataLayout::D32x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u32>(u, v);
+            [(x as u32 as f32 / std::u32::MAX as f32).to_bits(), 0, 0, 0]
+        },
+        (TextureLoadType::F32FromUNorm, PixelDataLayout::D32x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u32; 2]>(u, v);
+            [(x as u32 as f32 / std::u32::MAX as f32).to_bits(), (y as u32 as f32 / std::u32::MAX as f32).to_bits(), 0, 0]
+        },
+        (TextureLoadType::F32FromUNorm, PixelDataLayout::D32x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u32; 4]>(u, v);
+            data.map(|x| (x as u32 as f32 / std::u32::MAX as f32).to_bits())
+        },
+        (TextureLoadType::F32FromUNorm, PixelDataLayout::D16x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u16>(u, v);
+            [(x as u16 as f32 / std::u16::MAX as f32).to_bits(), 0, 0, 0]
+        },
+        (TextureLoadType::F32FromUNorm, PixelDataLayout::D16x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u16; 2]>(u, v);
+            [(x as u16 as f32 / std::u16::MAX as f32).to_bits(), (y as u16 as f32 / std::u16::MAX as f32).to_bits(), 0, 0]
+        },
+        (TextureLoadType::F32FromUNorm, PixelDataLayout::D16x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u16; 4]>(u, v);
+            data.map(|x| (x as u16 as f32 / std::u16::MAX as f32).to_bits())
+        },
+        (TextureLoadType::F32FromUNorm, PixelDataLayout::D8x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u8>(u, v);
+            [(x as u8 as f32 / std::u8::MAX as f32).to_bits(), 0, 0, 0]
+        },
+        (TextureLoadType::F32FromUNorm, PixelDataLayout::D8x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u8; 2]>(u, v);
+            [(x as u8 as f32 / std::u8::MAX as f32).to_bits(), (y as u8 as f32 / std::u32::MAX as f32).to_bits(), 0, 0]
+        },
+        (TextureLoadType::F32FromUNorm, PixelDataLayout::D8x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u8; 4]>(u, v);
+            data.map(|x| (x as u8 as f32 / std::u8::MAX as f32).to_bits())
+        },
+        (TextureLoadType::F32FromInt, PixelDataLayout::D32x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u32>(u, v);
+            [(x as i32 as f32).to_bits(), 0, 0, 0]
+        },
+        (TextureLoadType::F32FromInt, PixelDataLayout::D32x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u32; 2]>(u, v);
+            [(x as i32 as f32).to_bits(), (y as i32 as f32).to_bits(), 0, 0]
+        },
+        (TextureLoadType::F32FromInt, PixelDataLayout::D32x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u32; 4]>(u, v);
+            data.map(|x| (x as i32 as f32).to_bits())
+        },
+        (TextureLoadType::F32FromInt, PixelDataLayout::D16x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u16>(u, v);
+            [(x as i16 as f32).to_bits(), 0, 0, 0]
+        },
+        (TextureLoadType::F32FromInt, PixelDataLayout::D16x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u16; 2]>(u, v);
+            [(x as i16 as f32).to_bits(), (y as i32 as f32).to_bits(), 0, 0]
+        },
+        (TextureLoadType::F32FromInt, PixelDataLayout::D16x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u16; 4]>(u, v);
+            data.map(|x| (x as i16 as f32).to_bits())
+        },
+        (TextureLoadType::F32FromInt, PixelDataLayout::D8x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u8>(u, v);
+            [(x as i8 as f32).to_bits(), 0, 0, 0]
+        },
+        (TextureLoadType::F32FromInt, PixelDataLayout::D8x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u8; 2]>(u, v);
+            [(x as i8 as f32).to_bits(), (y as i32 as f32).to_bits(), 0, 0]
+        },
+        (TextureLoadType::F32FromInt, PixelDataLayout::D8x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u8; 4]>(u, v);
+            data.map(|x| (x as i8 as f32).to_bits())
+        },
+
+        (TextureLoadType::F32FromUInt, PixelDataLayout::D32x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<u32>(u, v);
+            [(data as f32).to_bits(), 0, 0, 0]
+        },
+        (TextureLoadType::F32FromUInt, PixelDataLayout::D32x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u32; 2]>(u, v);
+            [(x as f32).to_bits(), (y as f32).to_bits(), 0, 0]
+        },
+        (TextureLoadType::F32FromUInt, PixelDataLayout::D32x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u32; 4]>(u, v);
+            data.map(|x| (x as f32).to_bits())
+        },
+        (TextureLoadType::F32FromUInt, PixelDataLayout::D16x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<u16>(u, v);
+            [(data as f32).to_bits(), 0, 0, 0]
+        },
+        (TextureLoadType::F32FromUInt, PixelDataLayout::D16x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u16; 2]>(u, v);
+            [(x as f32).to_bits(), (y as f32).to_bits(), 0, 0]
+        },
+        (TextureLoadType::F32FromUInt, PixelDataLayout::D16x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u16; 4]>(u, v);
+            data.map(|x| (x as f32).to_bits())
+        },
+        (TextureLoadType::F32FromUInt, PixelDataLayout::D8x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<u8>(u, v);
+            [(data as f32).to_bits(), 0, 0, 0]
+        },
+        (TextureLoadType::F32FromUInt, PixelDataLayout::D8x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u8; 2]>(u, v);
+            [(x as f32).to_bits(), (y as f32).to_bits(), 0, 0]
+        },
+        (TextureLoadType::F32FromUInt, PixelDataLayout::D8x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u8; 4]>(u, v);
+            data.map(|x| (x as f32).to_bits())
+        },
+        (TextureLoadType::I32FromF32, PixelDataLayout::D32x1) => |u: u32, v: u32, texture: &TextureLevel| {
+            let x = texture.fetch::<u32>(u, v);
+            [f32::from_bits(x) as i32 as u32, 0, 0, 0]
+        },
+        (TextureLoadType::I32FromF32, PixelDataLayout::D32x2) => |u: u32, v: u32, texture: &TextureLevel| {
+            let [x, y] = texture.fetch::<[u32; 2]>(u, v);
+            [f32::from_bits(x) as i32 as u32, f32::from_bits(y) as i32 as u32, 0, 0]
+        },
+        (TextureLoadType::I32FromF32, PixelDataLayout::D32x4) => |u: u32, v: u32, texture: &TextureLevel| {
+            let data = texture.fetch::<[u32; 4]>(u, v);
+            data.map(|x| f32::from_bits(x) as i32 as u32)
+        },
+        (TextureLoadType::I32FromF32, _) => |_u: u32, _v: u32, _texture: &TextureLevel| [0, 0, 0, 0],
+
+        // indexed textures load as their palette color when read as unorm, or as the raw index when read as integers
+        (TextureLoadType::F32FromUNorm, PixelDataLayout::I4) |
+        (TextureLoadType::F32FromUNorm, PixelDataLayout::I8) => |u: u32, v: u32, texture: &TextureLevel| {
+            let color = palette_color(texture.palette, texture.fetch_index(u, v));
+            color.map(|x| (x as f32 / u8::MAX as f32).to_bits())
+        },
+        (TextureLoadType::I32FromInt,  PixelDataLayout::I4) |
+        (TextureLoadType::I32FromInt,  PixelDataLayout::I8) |
+        (TextureLoadType::I32FromUInt, PixelDataLayout::I4) |
+        (TextureLoadType::I32FromUInt, PixelDataLayout::I8) => |u: u32, v: u32, texture: &TextureLevel| {
+            [texture.fetch_index(u, v) as u32, 0, 0, 0]
+        },
+        (TextureLoadType::F32FromInt,  PixelDataLayout::I4) |
+        (TextureLoadType::F32FromInt,  PixelDataLayout::I8) |
+        (TextureLoadType::F32FromUInt, PixelDataLayout::I4) |
+        (TextureLoadType::F32FromUInt, PixelDataLayout::I8) => |u: u32, v: u32, texture: &TextureLevel| {
+            [(texture.fetch_index(u, v) as f32).to_bits(), 0, 0, 0]
+        },
+        (TextureLoadType::F32FromINorm, PixelDataLayout::I4) |
+        (TextureLoadType::F32FromINorm, PixelDataLayout::I8) => |_u: u32, _v: u32, _texture: &TextureLevel| [0, 0, 0, 0],
+    }
+}
+
+fn sample_texel(x: i32, y: i32, sampler: &TextureSampler, level: &TextureLevel, load_op: fn(u32, u32, &TextureLevel) -> [u32; 4]) -> [u32; 4] {
+    match (sampler.overflow_u.apply(x, level.width), sampler.overflow_v.apply(y, level.height)) {
+        (Some(x), Some(y)) => load_op(x, y, level),
+        _ => [0, 0, 0, 0],
+    }
+}
+
+fn sample_level_nearest(u: f32, v: f32, sampler: &TextureSampler, level: &TextureLevel, load_op: fn(u32, u32, &TextureLevel) -> [u32; 4]) -> [u32; 4] {
+    let x = (u * level.width  as f32).floor() as i32;
+    let y = (v * level.height as f32).floor() as i32;
+    sample_texel(x, y, sampler, level, load_op)
+}
+
+fn sample_level_bilinear(u: f32, v: f32, sampler: &TextureSampler, level: &TextureLevel, load_op: fn(u32, u32, &TextureLevel) -> [u32; 4]) -> [f32; 4] {
+    let x = u * level.width  as f32 - 0.5;
+    let y = v * level.height as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (x - x0, y - y0);
+    // coordinates past the range of i32 saturate, so the neighbour wraps rather than overflow
+    let (x0, y0) = (x0 as i32, y0 as i32);
+    let (x1, y1) = (x0.wrapping_add(1), y0.wrapping_add(1));
+    let t00 = sample_texel(x0, y0, sampler, level, load_op).map(f32::from_bits);
+    let t10 = sample_texel(x1, y0, sampler, level, load_op).map(f32::from_bits);
+    let t01 = sample_texel(x0, y1, sampler, level, load_op).map(f32::from_bits);
+    let t11 = sample_texel(x1, y1, sampler, level, load_op).map(f32::from_bits);
+    [0, 1, 2, 3].map(|c| {
+        let top    = t00[c] + (t10[c] - t00[c]) * tx;
+        let bottom = t01[c] + (t11[c] - t01[c]) * tx;
+        top + (bottom - top) * ty
+    })
+}
+
+fn sample_texture(u: f32, v: f32, lod: f32, filter: SampleFilter, sampler: &TextureSampler, texture: &TextureModule, palette: &[u8], load_op: fn(u32, u32, &TextureLevel) -> [u32; 4]) -> [u32; 4] {
+    let max_lod = (texture.mip_levels.max(1) - 1) as f32;
+    let lod = if lod.is_nan() { 0.0 } else { lod.clamp(0.0, max_lod) };
+    let level = |level: usize| texture.level(level).with_palette(palette);
+    match filter {
+        SampleFilter::Nearest => sample_level_nearest(u, v, sampler, &level(lod.round() as usize), load_op),
+        SampleFilter::Bilinear => {
+            let base_lod = lod.floor();
+            let t = lod - base_lod;
+            let a = sample_level_bilinear(u, v, sampler, &level(base_lod as usize), load_op);
+            if t == 0.0 {
+                a.map(f32::to_bits)
+            } else {
+                let b = sample_level_bilinear(u, v, sampler, &level(base_lod as usize + 1), load_op);
+                [0, 1, 2, 3].map(|c| (a[c] + (b[c] - a[c]) * t).to_bits())
+            }
+        },
+    }
+}
+
+fn buffer_data_type_size(data_type: BufferDataType) -> usize {
+    match data_type {
+        BufferDataType::I8 | BufferDataType::U8 | BufferDataType::INorm8 | BufferDataType::UNorm8 => 1,
+        BufferDataType::I16 | BufferDataType::U16 | BufferDataType::INorm16 | BufferDataType::UNorm16 => 2,
+        BufferDataType::I32 | BufferDataType::U32 | BufferDataType::INorm32 | BufferDataType::UNorm32 | BufferDataType::F32 => 4,
+    }
+}
+
+fn buffer_read_fn(data_type: BufferDataType) -> fn(&[u8], usize) -> u32 {
+    match data_type {
+        BufferDataType::F32 |
+        BufferDataType::U32 |
+        BufferDataType::I32     => |bytes: &[u8], offset: usize|
+            read_bytes_u32(bytes, offset),
+
+        BufferDataType::INorm32 => |bytes: &[u8], offset: usize|
+            (read_bytes_u32(bytes, offset) as i32 as f32 / std::i32::MAX as f32).to_bits(),
+
+        BufferDataType::INorm16 => |bytes: &[u8], offset: usize| 
+            (read_bytes_u16(bytes, offset) as i16 as f32 / std::i16::MAX as f32).to_bits(),
+
+        BufferDataType::INorm8  => |bytes: &[u8], offset: usize|
+            (read_bytes_u8(bytes, offset) as i8 as f32 / std::i8::MAX as f32).to_bits(),
+
+        BufferDataType::UNorm32 => |bytes: &[u8], offset: usize|
+            (read_bytes_u32(bytes, offset) as u32 as f32 / std::u32::MAX as f32).to_bits(),
+
+        BufferDataType::UNorm16 => |bytes: &[u8], offset: usize|
+            (read_bytes_u16(bytes, offset) as u16 as f32 / std::u16::MAX as f32).to_bits(),
+
+        BufferDataType::UNorm8  => |bytes: &[u8], offset: usize|
+            (read_bytes_u8(bytes, offset) as u8 as f32 / std::u8::MAX as f32).to_bits(),
+
+        BufferDataType::U16     => |bytes: &[u8], offset: usize|
+            read_bytes_u16(bytes, offset) as u32,
+
+        BufferDataType::U8      => |bytes: &[u8], offset: usize|
+            read_bytes_u8(bytes, offset) as u32,
+
+        BufferDataType::I16     => |bytes: &[u8], offset: usize|
+            read_bytes_u16(bytes, offset) as i16 as i32 as u32,
+
+        BufferDataType::I8      => |bytes: &[u8], offset: usize|
+            read_bytes_u8(bytes, offset) as i8 as i32 as u32,
+    }
+}
+
 pub(crate) fn read_bytes_u8(bytes: &[u8], offset: usize) -> u8 {
     if offset >= bytes.len() {
         0
@@ -2118,8 +2572,8 @@ pub(crate) fn read_bytes_u16(bytes: &[u8], offset: usize) -> u16 {
     if offset + 2 > bytes.len() {
         0
     } else {
-        ((bytes[offset + 1] as u16) << 0) |
-        ((bytes[offset + 0] as u16) << 8)
+        ((bytes[offset + 0] as u16) << 0) |
+        ((bytes[offset + 1] as u16) << 8)
     }
 }
 
@@ -2135,13 +2589,13 @@ pub(crate) fn read_bytes_u32(bytes: &[u8], offset: usize) -> u32 {
 }
 
 fn write_bytes_u8(from: &[u8], to: &mut [u8], offset: usize) {
-    if offset + from.len() < to.len() {
+    if offset + from.len() <= to.len() {
         to[offset..(offset + from.len())].copy_from_slice(from);
     }
 }
 
 fn write_bytes_u16(from: &[u16], to: &mut [u8], offset: usize) {
-    if offset + from.len() * 2 < to.len() {
+    if offset + from.len() * 2 <= to.len() {
         for i in 0..from.len() {
             to[offset + i * 2 + 0] = (from[i] >> 0) as u8;
             to[offset + i * 2 + 1] = (from[i] >> 8) as u8;
@@ -2150,12 +2604,12 @@ fn write_bytes_u16(from: &[u16], to: &mut [u8], offset: usize) {
 }
 
 fn write_bytes_u32(from: &[u32], to: &mut [u8], offset: usize) {
-    if offset + from.len() * 4 < to.len() {
+    if offset + from.len() * 4 <= to.len() {
         for i in 0..from.len() {
-            to[offset + i * 2 + 0] = (from[i] >>  0) as u8;
-            to[offset + i * 2 + 1] = (from[i] >>  8) as u8;
-            to[offset + i * 2 + 1] = (from[i] >> 16) as u8;
-            to[offset + i * 2 + 1] = (from[i] >> 24) as u8;
+            to[offset + i * 4 + 0] = (from[i] >>  0) as u8;
+            to[offset + i * 4 + 1] = (from[i] >>  8) as u8;
+            to[offset + i * 4 + 2] = (from[i] >> 16) as u8;
+            to[offset + i * 4 + 3] = (from[i] >> 24) as u8;
         }
     }
 }
@@ -2184,3 +2638,168 @@ pub fn f32_bits_to_unorm32_bits(bits: u32) -> u32 {
     (f32::from_bits(bits) * std::u32::MAX as f32) as u32
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::types::{ImageDataLayout, OverflowMode, Texture};
+    use super::super::buffer::BufferModule;
+
+    fn texture_2x2(texels: [f32; 4]) -> TextureModule {
+        let mut texture = TextureModule::new();
+        texture.config = Texture { width: 2, height: 2, pixel_layout: PixelDataLayout::D32x1, image_layout: ImageDataLayout::Contiguous };
+        texture.memory = texels.map(f32::to_bits).to_vec().into_boxed_slice();
+        texture
+    }
+
+    #[test]
+    fn bilinear_sampling_far_out_of_range() {
+        let texture = texture_2x2([1.0, 2.0, 3.0, 4.0]);
+        let load_op = texture_load_fn(TextureLoadType::F32FromF32, PixelDataLayout::D32x1);
+        for overflow in [OverflowMode::Repeate, OverflowMode::Mirror, OverflowMode::Clamp, OverflowMode::Zero] {
+            let sampler = TextureSampler { filter: SampleFilter::Bilinear, overflow_u: overflow, overflow_v: overflow };
+            for (u, v) in [(1e7, 0.25), (-1e7, 0.25), (0.25, 1e10), (f32::MAX, f32::MIN), (f32::INFINITY, f32::NEG_INFINITY), (f32::NAN, 0.25)] {
+                let texel = sample_level_bilinear(u, v, &sampler, &texture.level(0), load_op);
+                assert!(texel[0].is_nan() || (0.0..=4.0).contains(&texel[0]), "{:?} at ({}, {}) sampled {:?}", overflow, u, v, texel);
+            }
+        }
+
+        // texel centers come back exactly, halfway between them is the average
+        let sampler = TextureSampler { filter: SampleFilter::Bilinear, overflow_u: OverflowMode::Clamp, overflow_v: OverflowMode::Clamp };
+        assert_eq!(sample_level_bilinear(0.25, 0.25, &sampler, &texture.level(0), load_op)[0], 1.0);
+        assert_eq!(sample_level_bilinear(0.75, 0.75, &sampler, &texture.level(0), load_op)[0], 4.0);
+        assert_eq!(sample_level_bilinear(0.5, 0.25, &sampler, &texture.level(0), load_op)[0], 1.5);
+    }
+    fn local(index: u8) -> RegisterAddress<Scalar> { RegisterAddress::Local(index, PhantomData) }
+    fn constant(index: u8) -> RegisterAddress<Scalar> { RegisterAddress::Constant(index, PhantomData) }
+    fn input(index: u8) -> RegisterAddress<Scalar> { RegisterAddress::Input(index, PhantomData) }
+    fn output(index: u8) -> RegisterAddress<Scalar> { RegisterAddress::Output(index, PhantomData) }
+
+    fn op(src_a: RegisterAddress<Scalar>, src_b: RegisterAddress<Scalar>, dst: RegisterAddress<Scalar>, op: ScalarBinaryOp) -> ShaderInstruction {
+        ShaderInstruction::ScalarBinaryOp { src_a, src_b, dst, op }
+    }
+
+    fn copy(from: RegisterAddress<Scalar>, to: RegisterAddress<Scalar>) -> ShaderInstruction {
+        ShaderInstruction::CopyScalarRegister { from, to }
+    }
+
+    // runs instructions over lane_count lanes with the lane index in scalar input 0 and
+    // each scalar constant holding its own index, returning scalar outputs 0 and 1
+    fn run_lanes(lane_count: usize, instructions: &[ShaderInstruction]) -> [Vec<u32>; 2] {
+        let mut buffer_modules = Box::new([(); 256].map(|_| BufferModule::new()));
+        let texture_modules = Box::new([(); 64].map(|_| TextureModule::new()));
+        let mut constant_array = Box::new(ShadingUnitConstantArray::new());
+        constant_array.scalar_constant_array.iter_mut().enumerate().for_each(|(i, x)| *x = i as u32);
+        let mut io_arrays = ShadingUnitIOArrays::new();
+        let [input_array, output_array, _] = &mut io_arrays.0;
+        (0..lane_count).for_each(|i| input_array.scalar_array[0][i] = i as u32);
+        let mut run_context = ShadingUnitRunContext::new(&mut constant_array, input_array, output_array);
+        let mut shading_unit_context = ShadingUnitContext::new();
+        assert!(shading_unit_context.run_shader(lane_count, instructions, &mut run_context, &mut BufferAccess::Exclusive(&mut buffer_modules), &texture_modules, &ResourceMap::default()).is_some());
+        [0, 1].map(|o| run_context.scalar_output_array[o][..lane_count].to_vec())
+    }
+
+    const LANES: usize = 70;
+    const I32: OpDataType = OpDataType::I32;
+
+    #[test]
+    fn divergent_if_else() {
+        let [result, after] = run_lanes(LANES, &[
+            op(input(0), constant(2), local(0), ScalarBinaryOp::Modulo(I32)),
+            ShaderInstruction::If { cond: local(0) },
+                op(input(0), constant(3), local(1), ScalarBinaryOp::Modulo(I32)),
+                ShaderInstruction::If { cond: local(1) },
+                    copy(constant(10), output(0)),
+                ShaderInstruction::Else,
+                    copy(constant(30), output(0)),
+                ShaderInstruction::EndIf,
+            ShaderInstruction::Else,
+                copy(constant(20), output(0)),
+            ShaderInstruction::EndIf,
+            op(input(0), constant(1), output(1), ScalarBinaryOp::Add(I32)),
+        ]);
+        for i in 0..LANES {
+            let expected = match (i % 2, i % 3) { (0, _) => 20, (_, 0) => 30, _ => 10 };
+            assert_eq!((result[i], after[i]), (expected, i as u32 + 1), "lane {}", i);
+        }
+    }
+
+    #[test]
+    fn loops_break_per_lane() {
+        // each lane counts down from its index, adding 3 per iteration
+        let [result, after] = run_lanes(LANES, &[
+            copy(input(0), local(0)),
+            copy(constant(0), local(2)),
+            ShaderInstruction::Loop,
+                op(local(0), constant(0), local(1), ScalarBinaryOp::Compare(I32, Comparison::Equal)),
+                ShaderInstruction::If { cond: local(1) },
+                    ShaderInstruction::Break,
+                ShaderInstruction::EndIf,
+                op(local(2), constant(3), local(2), ScalarBinaryOp::Add(I32)),
+                op(local(0), constant(1), local(0), ScalarBinaryOp::Subtract(I32)),
+            ShaderInstruction::EndLoop,
+            copy(local(2), output(0)),
+            op(input(0), constant(1), output(1), ScalarBinaryOp::Add(I32)),
+        ]);
+        for i in 0..LANES {
+            assert_eq!((result[i], after[i]), (i as u32 * 3, i as u32 + 1), "lane {}", i);
+        }
+    }
+
+    #[test]
+    fn returns_retire_lanes() {
+        // odd lanes return from an if, lanes divisible by 4 return from inside a loop the rest break out of
+        let [result, after] = run_lanes(LANES, &[
+            op(input(0), constant(2), local(0), ScalarBinaryOp::Modulo(I32)),
+            ShaderInstruction::If { cond: local(0) },
+                copy(constant(10), output(0)),
+                ShaderInstruction::Return,
+                copy(constant(11), output(0)),
+            ShaderInstruction::EndIf,
+            ShaderInstruction::Loop,
+                op(input(0), constant(4), local(1), ScalarBinaryOp::Modulo(I32)),
+                ShaderInstruction::If { cond: local(1) },
+                    ShaderInstruction::Break,
+                ShaderInstruction::EndIf,
+                copy(constant(20), output(0)),
+                ShaderInstruction::Return,
+            ShaderInstruction::EndLoop,
+            copy(constant(30), output(0)),
+            op(input(0), constant(1), output(1), ScalarBinaryOp::Add(I32)),
+        ]);
+        for i in 0..LANES {
+            let expected = match i % 4 { 0 => (20, 0), 2 => (30, i as u32 + 1), _ => (10, 0) };
+            assert_eq!((result[i], after[i]), expected, "lane {}", i);
+        }
+    }
+
+    #[test]
+    fn runaway_loops_are_broken() {
+        // a loop that never breaks stops at the iteration limit and the code after it still runs
+        let [result, after] = run_lanes(LANES, &[
+            copy(constant(0), local(0)),
+            ShaderInstruction::Loop,
+                op(local(0), constant(1), local(0), ScalarBinaryOp::Add(I32)),
+            ShaderInstruction::EndLoop,
+            copy(local(0), output(0)),
+            op(input(0), constant(1), output(1), ScalarBinaryOp::Add(I32)),
+        ]);
+        for i in 0..LANES {
+            assert_eq!((result[i], after[i]), (LOOP_ITERATION_LIMIT as u32 + 1, i as u32 + 1), "lane {}", i);
+        }
+    }
+
+    #[test]
+    fn pops_only_write_active_lanes() {
+        let [result, _] = run_lanes(LANES, &[
+            copy(input(0), output(0)),
+            ShaderInstruction::PushScalar(constant(5)),
+            op(input(0), constant(2), local(0), ScalarBinaryOp::Modulo(I32)),
+            ShaderInstruction::If { cond: local(0) },
+                ShaderInstruction::PopScalar(output(0)),
+            ShaderInstruction::EndIf,
+        ]);
+        for i in 0..LANES {
+            assert_eq!(result[i], if i % 2 == 1 { 5 } else { i as u32 }, "lane {}", i);
+        }
+    }
+}
@@ -9,6 +9,10 @@ pub enum ShaderParseError {
     UnknownOpcode,
     ShaderTooLong,
     InvalidComparison,
+    InvalidBufferDataType,
+    InvalidTextureLoadType,
+    UnbalancedControlFlow(usize),
+    ControlFlowTooDeep(usize),
 }
 
 // ========================================================================== //
@@ -115,6 +119,21 @@ const OPCODE_SQ_MAG2                                 : u8 = 0x59;
 const OPCODE_SQ_MAG3                                 : u8 = 0x5A;
 const OPCODE_SQ_MAG4                                 : u8 = 0x5B;
 
+const OPCODE_BUFFER_READ_SCALAR                      : u8 = 0x5F;
+const OPCODE_BUFFER_WRITE_SCALAR                     : u8 = 0x60;
+const OPCODE_BUFFER_READ_VECTOR                      : u8 = 0x61;
+const OPCODE_BUFFER_WRITE_VECTOR                     : u8 = 0x62;
+const OPCODE_IF                                      : u8 = 0x63;
+const OPCODE_ELSE                                    : u8 = 0x64;
+const OPCODE_ENDIF                                   : u8 = 0x65;
+const OPCODE_LOOP                                    : u8 = 0x66;
+const OPCODE_BREAK                                   : u8 = 0x67;
+const OPCODE_ENDLOOP                                 : u8 = 0x68;
+const OPCODE_RETURN                                  : u8 = 0x69;
+const OPCODE_SAMPLE_TEXTURE                          : u8 = 0x6A;
+const OPCODE_SAMPLE_TEXTURE_LOD                      : u8 = 0x6B;
+const OPCODE_SAMPLE_TEXTURE_BIAS                     : u8 = 0x6C;
+
 pub fn get_byte(i: &mut usize, code: &[u8]) -> Result<u8, ShaderParseError> {
     if *i >= code.len() {
         Err(ShaderParseError::UnexpectedEndOfCode)
@@ -143,6 +162,60 @@ pub fn get_register<T: RegisterType>(i: &mut usize, code: &[u8]) -> Result<Regis
     })
 }
 
+const REGTYPE_NONE     : u8 = 0xFF;
+
+pub fn get_optional_register<T: RegisterType>(i: &mut usize, code: &[u8]) -> Result<Option<RegisterAddress<T>>, ShaderParseError> {
+    if *i < code.len() && code[*i] == REGTYPE_NONE {
+        get_byte(i, code)?;
+        get_byte(i, code)?;
+        Ok(None)
+    } else {
+        get_register(i, code).map(Some)
+    }
+}
+
+pub fn get_u32(i: &mut usize, code: &[u8]) -> Result<u32, ShaderParseError> {
+    Ok(
+        ((get_byte(i, code)? as u32) <<  0) |
+        ((get_byte(i, code)? as u32) <<  8) |
+        ((get_byte(i, code)? as u32) << 16) |
+        ((get_byte(i, code)? as u32) << 24)
+    )
+}
+
+pub fn get_buffer_data_type(i: &mut usize, code: &[u8]) -> Result<BufferDataType, ShaderParseError> {
+    Ok(match get_byte(i, code)? {
+        0x00 => BufferDataType::I8,
+        0x01 => BufferDataType::I16,
+        0x02 => BufferDataType::I32,
+        0x03 => BufferDataType::U8,
+        0x04 => BufferDataType::U16,
+        0x05 => BufferDataType::U32,
+        0x06 => BufferDataType::INorm8,
+        0x07 => BufferDataType::INorm16,
+        0x08 => BufferDataType::INorm32,
+        0x09 => BufferDataType::UNorm8,
+        0x0A => BufferDataType::UNorm16,
+        0x0B => BufferDataType::UNorm32,
+        0x0C => BufferDataType::F32,
+        _ => Err(ShaderParseError::InvalidBufferDataType)?
+    })
+}
+
+pub fn get_texture_load_type(i: &mut usize, code: &[u8]) -> Result<TextureLoadType, ShaderParseError> {
+    Ok(match get_byte(i, code)? {
+        0x00 => TextureLoadType::F32FromINorm,
+        0x01 => TextureLoadType::F32FromUNorm,
+        0x02 => TextureLoadType::F32FromInt,
+        0x03 => TextureLoadType::F32FromUInt,
+        0x04 => TextureLoadType::F32FromF32,
+        0x05 => TextureLoadType::I32FromInt,
+        0x06 => TextureLoadType::I32FromUInt,
+        0x07 => TextureLoadType::I32FromF32,
+        _ => Err(ShaderParseError::InvalidTextureLoadType)?
+    })
+}
+
 const VECTOR_CHANNEL_X: u8 = 0;
 const VECTOR_CHANNEL_Y: u8 = 1;
 const VECTOR_CHANNEL_Z: u8 = 2;
@@ -160,10 +233,19 @@ pub fn get_vector_channel(i: &mut usize, code: &[u8]) -> Result<VectorChannel, S
     })
 }
 
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum ControlBlock {
+    If,
+    Else,
+    Loop,
+}
+
 pub fn parse_shader_bytecode(shader_type: ShaderType, code: &[u8], module: &mut ShaderModule) -> Result<(), ShaderParseError> {
     let mut instruction = 0;
     let mut i = 0;
+    let mut control_blocks = Vec::new();
     while i < code.len() {
+        let instruction_offset = i;
         if instruction >= module.instruction_buffer.len() {
             Err(ShaderParseError::ShaderTooLong)?
         }
@@ -544,10 +626,104 @@ pub fn parse_shader_bytecode(shader_type: ShaderType, code: &[u8], module: &mut
                 };
             },
 
+            OPCODE_BUFFER_READ_SCALAR => {
+                let data_type = get_buffer_data_type(&mut i, code)?;
+                let scalar = get_register::<Scalar>(&mut i, code)?;
+                let offset = get_u32(&mut i, code)?;
+                let buffer = get_byte(&mut i, code)?;
+                let addr_src_u32 = get_optional_register::<Scalar>(&mut i, code)?;
+                module.instruction_buffer[instruction] = ShaderInstruction::ReadBufferToScalar { data_type, scalar, offset, addr_src_u32, buffer };
+            },
+            OPCODE_BUFFER_WRITE_SCALAR => {
+                let data_type = get_buffer_data_type(&mut i, code)?;
+                let scalar = get_register::<Scalar>(&mut i, code)?;
+                let offset = get_u32(&mut i, code)?;
+                let buffer = get_byte(&mut i, code)?;
+                let addr_dst_u32 = get_optional_register::<Scalar>(&mut i, code)?;
+                module.instruction_buffer[instruction] = ShaderInstruction::WriteScalarToBuffer { data_type, scalar, offset, addr_dst_u32, buffer };
+            },
+            OPCODE_BUFFER_READ_VECTOR => {
+                let data_type = VectorBufferDataType::V4(get_buffer_data_type(&mut i, code)?);
+                let vector = get_register::<Vector>(&mut i, code)?;
+                let offset = get_u32(&mut i, code)?;
+                let buffer = get_byte(&mut i, code)?;
+                let addr_src_u32 = get_optional_register::<Scalar>(&mut i, code)?;
+                module.instruction_buffer[instruction] = ShaderInstruction::ReadBufferToVector { data_type, vector, offset, addr_src_u32, buffer };
+            },
+            OPCODE_BUFFER_WRITE_VECTOR => {
+                let data_type = VectorBufferDataType::V4(get_buffer_data_type(&mut i, code)?);
+                let src = get_register::<Vector>(&mut i, code)?;
+                let offset = get_u32(&mut i, code)?;
+                let buffer = get_byte(&mut i, code)?;
+                let addr_src_u32 = get_optional_register::<Scalar>(&mut i, code)?;
+                module.instruction_buffer[instruction] = ShaderInstruction::WriteVectorToBuffer { data_type, src, offset, addr_src_u32, buffer };
+            },
+
+            OPCODE_IF => {
+                let cond = get_register::<Scalar>(&mut i, code)?;
+                if control_blocks.len() >= CONTROL_STACK_SIZE {
+                    Err(ShaderParseError::ControlFlowTooDeep(instruction_offset))?
+                }
+                control_blocks.push(ControlBlock::If);
+                module.instruction_buffer[instruction] = ShaderInstruction::If { cond };
+            },
+            OPCODE_ELSE => {
+                if control_blocks.pop() != Some(ControlBlock::If) {
+                    Err(ShaderParseError::UnbalancedControlFlow(instruction_offset))?
+                }
+                control_blocks.push(ControlBlock::Else);
+                module.instruction_buffer[instruction] = ShaderInstruction::Else;
+            },
+            OPCODE_ENDIF => {
+                if !matches!(control_blocks.pop(), Some(ControlBlock::If | ControlBlock::Else)) {
+                    Err(ShaderParseError::UnbalancedControlFlow(instruction_offset))?
+                }
+                module.instruction_buffer[instruction] = ShaderInstruction::EndIf;
+            },
+            OPCODE_LOOP => {
+                if control_blocks.len() >= CONTROL_STACK_SIZE {
+                    Err(ShaderParseError::ControlFlowTooDeep(instruction_offset))?
+                }
+                control_blocks.push(ControlBlock::Loop);
+                module.instruction_buffer[instruction] = ShaderInstruction::Loop;
+            },
+            OPCODE_BREAK => {
+                if !control_blocks.contains(&ControlBlock::Loop) {
+                    Err(ShaderParseError::UnbalancedControlFlow(instruction_offset))?
+                }
+                module.instruction_buffer[instruction] = ShaderInstruction::Break;
+            },
+            OPCODE_ENDLOOP => {
+                if control_blocks.pop() != Some(ControlBlock::Loop) {
+                    Err(ShaderParseError::UnbalancedControlFlow(instruction_offset))?
+                }
+                module.instruction_buffer[instruction] = ShaderInstruction::EndLoop;
+            },
+            OPCODE_RETURN => {
+                module.instruction_buffer[instruction] = ShaderInstruction::Return;
+            },
+
+            OPCODE_SAMPLE_TEXTURE | OPCODE_SAMPLE_TEXTURE_LOD | OPCODE_SAMPLE_TEXTURE_BIAS => {
+                let load_type = get_texture_load_type(&mut i, code)?;
+                let dst = get_register::<Vector>(&mut i, code)?;
+                let src_uv = get_register::<Vector>(&mut i, code)?;
+                let texture = get_byte(&mut i, code)?;
+                let sampler = get_byte(&mut i, code)?;
+                let lod = match opcode {
+                    OPCODE_SAMPLE_TEXTURE_LOD  => SampleLod::Explicit(get_register::<Scalar>(&mut i, code)?),
+                    OPCODE_SAMPLE_TEXTURE_BIAS => SampleLod::Bias(get_register::<Scalar>(&mut i, code)?),
+                    _ => SampleLod::Auto,
+                };
+                module.instruction_buffer[instruction] = ShaderInstruction::SampleTexture { src_uv, dst, load_type, texture, sampler, lod };
+            },
+
             _ => Err(ShaderParseError::UnknownOpcode)?
         }
         instruction += 1;
     }
+    if !control_blocks.is_empty() {
+        Err(ShaderParseError::UnbalancedControlFlow(code.len()))?
+    }
     module.instruction_count = instruction;
     module.shader_type = shader_type;
     Ok(())
@@ -0,0 +1,139 @@
+use super::buffer::BufferModule;
+use super::texture::{rgba_unorm8_fetch_fn, TextureModule};
+use super::types::Sprite;
+
+pub const SPRITE_COUNT: usize = 128;
+
+pub struct SpriteTable {
+    pub sprites: [Sprite; SPRITE_COUNT],
+}
+
+impl SpriteTable {
+    pub fn new() -> Self {
+        Self {
+            sprites: [Sprite::new(); SPRITE_COUNT],
+        }
+    }
+
+    /*
+    draws every enabled sprite of the given priority into target. lower sprite
+    indices end up on top of higher ones within the same priority.
+     */
+    pub fn composite(&self, priority: u8, textures: &[TextureModule; 64], buffers: &[BufferModule; 256], target: &mut [[u8; 4]], width: u32, height: u32) {
+        for sprite in self.sprites.iter().rev() {
+            if sprite.enabled && sprite.priority == priority {
+                let texture = &textures[sprite.texture as usize];
+                draw_sprite(sprite, texture, buffers[texture.palette as usize].bytes(), target, width, height);
+            }
+        }
+    }
+}
+
+fn draw_sprite(sprite: &Sprite, texture: &TextureModule, palette: &[u8], target: &mut [[u8; 4]], width: u32, height: u32) {
+    // set_sprite checks the layout, this only skips textures reconfigured since
+    let Some(fetch_fn) = rgba_unorm8_fetch_fn(texture.config.pixel_layout) else {
+        return;
+    };
+    let x_min = sprite.position.x.max(0);
+    let y_min = sprite.position.y.max(0);
+    let x_max = (sprite.position.x + sprite.source.size.x).min(width  as i32);
+    let y_max = (sprite.position.y + sprite.source.size.y).min(height as i32);
+    for y in y_min..y_max {
+        let mut src_y = y - sprite.position.y;
+        if sprite.flip_y {
+            src_y = sprite.source.size.y - 1 - src_y;
+        }
+        src_y += sprite.source.position.y;
+        if src_y < 0 || src_y >= texture.config.height as i32 {
+            continue;
+        }
+        for x in x_min..x_max {
+            let mut src_x = x - sprite.position.x;
+            if sprite.flip_x {
+                src_x = sprite.source.size.x - 1 - src_x;
+            }
+            src_x += sprite.source.position.x;
+            if src_x < 0 || src_x >= texture.config.width as i32 {
+                continue;
+            }
+            let pixel = fetch_fn(texture, palette, src_x as u32, src_y as u32);
+            if sprite.cutout && pixel[3] == 0 {
+                continue;
+            }
+            target[(x + y * width as i32) as usize] = pixel;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::types::{I32x2, ImageDataLayout, PixelDataLayout, Rect, Texture};
+
+    const BACKDROP: [u8; 4] = [9, 9, 9, 9];
+
+    /*
+    texture 0 is 2x2 D8x4, red holds 1 - 4 in reading order and the lower left
+    texel is transparent.
+     */
+    fn textures() -> Box<[TextureModule; 64]> {
+        let mut textures = Box::new([(); 64].map(|_| TextureModule::new()));
+        textures[0].config = Texture { width: 2, height: 2, pixel_layout: PixelDataLayout::D8x4, image_layout: ImageDataLayout::Contiguous };
+        textures[0].memory = [[1, 0, 0, 255], [2, 0, 0, 255], [3, 0, 0, 0], [4, 0, 0, 255]].map(u32::from_le_bytes).into();
+        textures
+    }
+
+    fn sprite(x: i32, y: i32, priority: u8) -> Sprite {
+        Sprite {
+            enabled: true,
+            position: I32x2 { x, y },
+            source: Rect { position: I32x2 { x: 0, y: 0 }, size: I32x2 { x: 2, y: 2 } },
+            priority,
+            ..Sprite::new()
+        }
+    }
+
+    fn red(target: &[[u8; 4]]) -> Vec<u8> {
+        target.iter().map(|pixel| pixel[0]).collect()
+    }
+
+    #[test]
+    fn flip_and_cutout() {
+        let textures = textures();
+        let buffers = Box::new([(); 256].map(|_| BufferModule::new()));
+        let mut table = SpriteTable::new();
+        table.sprites[0] = Sprite { flip_x: true, ..sprite(0, 0, 0) };
+        table.sprites[1] = Sprite { flip_y: true, cutout: true, ..sprite(2, 0, 0) };
+        // clipped against the target edges
+        table.sprites[2] = sprite(5, 1, 0);
+        let mut target = [BACKDROP; 12];
+        table.composite(0, &textures, &buffers, &mut target, 6, 2);
+        assert_eq!(red(&target), [
+            2, 1, 9, 4, 9, 9,
+            4, 3, 1, 2, 9, 1,
+        ]);
+    }
+
+    #[test]
+    fn lower_indices_draw_on_top_within_a_priority() {
+        let textures = textures();
+        let buffers = Box::new([(); 256].map(|_| BufferModule::new()));
+        let mut table = SpriteTable::new();
+        table.sprites[0] = sprite(0, 0, 1);
+        table.sprites[1] = Sprite { flip_x: true, flip_y: true, ..sprite(1, 0, 1) };
+        table.sprites[2] = Sprite { flip_x: true, ..sprite(0, 0, 0) };
+        table.sprites[3] = Sprite { enabled: false, ..sprite(0, 0, 1) };
+        let mut target = [BACKDROP; 6];
+        table.composite(1, &textures, &buffers, &mut target, 3, 2);
+        assert_eq!(red(&target), [
+            1, 2, 3,
+            3, 4, 1,
+        ]);
+        // priorities are composited from 3 down to 0, so priority 0 ends up on top
+        table.composite(0, &textures, &buffers, &mut target, 3, 2);
+        assert_eq!(red(&target), [
+            2, 1, 3,
+            4, 3, 1,
+        ]);
+    }
+}
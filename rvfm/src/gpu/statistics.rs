@@ -0,0 +1,57 @@
+use std::time::Instant;
+
+use crate::hart_clock::HART_CLOCK_MASTER;
+
+/*
+pipeline counters for statistics queries, kept per queue. reset by
+begin_statistics_query and written out by end_statistics_query. counters wrap
+rather than saturate.
+ */
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GpuStatistics {
+    pub vertices_shaded: u32,
+    // primitives of any topology that made it through clipping and culling
+    pub primitives_rasterized: u32,
+    pub fragments_shaded: u32,
+    pub fragments_passed: u32,
+    pub blit_pixels: u32,
+}
+
+impl GpuStatistics {
+    pub fn add(&mut self, other: &Self) {
+        self.vertices_shaded = self.vertices_shaded.wrapping_add(other.vertices_shaded);
+        self.primitives_rasterized = self.primitives_rasterized.wrapping_add(other.primitives_rasterized);
+        self.fragments_shaded = self.fragments_shaded.wrapping_add(other.fragments_shaded);
+        self.fragments_passed = self.fragments_passed.wrapping_add(other.fragments_passed);
+        self.blit_pixels = self.blit_pixels.wrapping_add(other.blit_pixels);
+    }
+
+    pub fn words(&self) -> [u32; 5] {
+        [self.vertices_shaded, self.primitives_rasterized, self.fragments_shaded, self.fragments_passed, self.blit_pixels]
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimestampSource {
+    // nanoseconds of host time since the gpu started
+    Host,
+    // cycles run by hart 0, at least the cycles of every frame before the current one
+    Virtual,
+}
+
+impl TimestampSource {
+    pub fn from_u8(x: u8) -> Option<Self> {
+        Some(match x {
+            0 => Self::Host,
+            1 => Self::Virtual,
+            _ => None?
+        })
+    }
+
+    pub fn read(&self, epoch: Instant) -> u64 {
+        match self {
+            Self::Host => epoch.elapsed().as_nanos() as u64,
+            Self::Virtual => HART_CLOCK_MASTER.virtual_cycles(),
+        }
+    }
+}
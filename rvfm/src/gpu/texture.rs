@@ -2,32 +2,189 @@ use std::fmt::Debug;
 
 use bytemuck::{cast_slice, Pod, cast_slice_mut};
 
+use super::core::f32_to_unorm8;
 use super::types::*;
 
+pub const MAX_MIP_LEVELS: usize = 12;
+pub const TEXTURE_MAX_SIZE: u32 = 2048;
+
 pub struct TextureModule {
     pub memory: Box<[u32]>,
     pub config: Texture,
+    pub mip_levels: usize,
+    // buffer holding the rgba unorm8 palette indexed textures are looked up through
+    pub palette: u8,
+}
+
+/*
+mip levels are packed one after another following level 0. level 0 uses the
+texture's configured image layout, every level after it is stored contiguous.
+ */
+pub struct TextureLevel<'a> {
+    bytes: &'a [u8],
+    pub width: u32,
+    pub height: u32,
+    image_layout: ImageDataLayout,
+    pixel_layout: PixelDataLayout,
+    pub palette: &'a [u8],
+}
+
+impl<'a> TextureLevel<'a> {
+    pub fn with_palette(self, palette: &'a [u8]) -> Self {
+        Self { palette, ..self }
+    }
+
+    pub fn fetch<T: Pod + Copy>(&self, x: u32, y: u32) -> T {
+        let index = self.image_layout.index(x, y, self.width);
+        cast_slice(self.bytes).get(index as usize).copied().unwrap_or_else(T::zeroed)
+    }
+
+    pub fn fetch_index(&self, x: u32, y: u32) -> u8 {
+        let index = self.image_layout.index(x, y, self.width) as usize;
+        fetch_packed_index(self.bytes, self.pixel_layout, index)
+    }
+}
+
+/*
+palettes are 256 rgba unorm8 entries in a buffer, so swapping or cycling colors
+only needs a write to the entries that change. entries past the end of the
+buffer read as transparent black.
+ */
+pub fn palette_color(palette: &[u8], index: u8) -> [u8; 4] {
+    let offset = index as usize * 4;
+    palette.get(offset..offset + 4).map(|c| [c[0], c[1], c[2], c[3]]).unwrap_or([0; 4])
+}
+
+fn fetch_packed_index(bytes: &[u8], pixel_layout: PixelDataLayout, index: usize) -> u8 {
+    match pixel_layout {
+        PixelDataLayout::I4 => bytes.get(index / 2).map(|b| (b >> ((index & 1) * 4)) & 0xF).unwrap_or(0),
+        _ => bytes.get(index).copied().unwrap_or(0),
+    }
 }
 
 impl TextureModule {
     pub fn new() -> Self {
         Self {
-            memory: vec![0u32; 512 * 512 * 4].into_boxed_slice().into(),
+            // storage comes from the vram pool when the texture is configured
+            memory: Box::default(),
             config: Texture {
                 width: 0,
                 height: 0,
                 pixel_layout: PixelDataLayout::D8x1,
                 image_layout: ImageDataLayout::Contiguous,
+            },
+            mip_levels: 1,
+            palette: 0,
+        }
+    }
+
+    pub fn max_mip_levels(&self) -> usize {
+        let size = self.config.width.max(self.config.height).max(1) as u32;
+        ((u32::BITS - size.leading_zeros()) as usize).min(MAX_MIP_LEVELS)
+    }
+
+    pub fn level_size(&self, level: usize) -> (u32, u32) {
+        ((self.config.width as u32 >> level).max(1), (self.config.height as u32 >> level).max(1))
+    }
+
+    // bytes needed to hold every mip level the texture can have
+    pub fn allocation_bytes(&self) -> usize {
+        if self.config.width == 0 || self.config.height == 0 {
+            return 0;
+        }
+        self.level_byte_offset(self.max_mip_levels())
+    }
+
+    fn level_byte_offset(&self, level: usize) -> usize {
+        (0..level).map(|l| {
+            let (w, h) = self.level_size(l);
+            self.config.pixel_layout.image_bytes(w, h)
+        }).sum()
+    }
+
+    fn level_image_layout(&self, level: usize) -> ImageDataLayout {
+        if level == 0 { self.config.image_layout } else { ImageDataLayout::Contiguous }
+    }
+
+    pub fn level(&self, level: usize) -> TextureLevel<'_> {
+        let offset = self.level_byte_offset(level);
+        let (width, height) = self.level_size(level);
+        let length = self.config.pixel_layout.image_bytes(width, height);
+        TextureLevel {
+            bytes: bytemuck::cast_slice(&self.memory[..]).get(offset..offset + length).unwrap_or(&[]),
+            width,
+            height,
+            image_layout: self.level_image_layout(level),
+            pixel_layout: self.config.pixel_layout,
+            palette: &[],
+        }
+    }
+
+    pub fn level_data_slice_mut(&mut self, level: usize) -> &mut [u8] {
+        let offset = self.level_byte_offset(level);
+        let (w, h) = self.level_size(level);
+        let length = self.config.pixel_layout.image_bytes(w, h);
+        bytemuck::cast_slice_mut(&mut self.memory[..]).get_mut(offset..offset + length).unwrap_or(&mut [])
+    }
+
+    pub fn generate_mipmaps(&mut self, component_type: TexelComponentType) {
+        let levels = self.max_mip_levels();
+        let pixel_bytes = self.config.pixel_layout.pixel_bytes();
+        let component_count = self.config.pixel_layout.component_count();
+        let component_bytes = pixel_bytes / component_count;
+        for level in 1..levels {
+            let src_offset = self.level_byte_offset(level - 1);
+            let dst_offset = self.level_byte_offset(level);
+            let (src_w, src_h) = self.level_size(level - 1);
+            let (dst_w, dst_h) = self.level_size(level);
+            let src_layout = self.level_image_layout(level - 1);
+            let memory = bytemuck::cast_slice_mut::<u32, u8>(&mut self.memory[..]);
+            if memory.len() < dst_offset {
+                break;
+            }
+            let (src_bytes, dst_bytes) = memory.split_at_mut(dst_offset);
+            let src_bytes = &src_bytes[src_offset..];
+            for y in 0..dst_h {
+                for x in 0..dst_w {
+                    // block layouts index past the image when the size isn't a multiple of the block, those texels are left out
+                    let mut sum = [0.0f64; 4];
+                    let mut count = 0;
+                    for (sx, sy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let sx = (x * 2 + sx).min(src_w - 1);
+                        let sy = (y * 2 + sy).min(src_h - 1);
+                        let pixel_offset = src_layout.index(sx, sy, src_w) as usize * pixel_bytes;
+                        let Some(texel) = src_bytes.get(pixel_offset..pixel_offset + pixel_bytes) else {
+                            continue;
+                        };
+                        for (c, sum) in sum.iter_mut().enumerate().take(component_count) {
+                            *sum += decode_component(&texel[c * component_bytes..(c + 1) * component_bytes], component_type);
+                        }
+                        count += 1;
+                    }
+                    let pixel_offset = (x + y * dst_w) as usize * pixel_bytes;
+                    if count == 0 {
+                        continue;
+                    }
+                    let Some(texel) = dst_bytes.get_mut(pixel_offset..pixel_offset + pixel_bytes) else {
+                        continue;
+                    };
+                    for (c, sum) in sum.iter().enumerate().take(component_count) {
+                        encode_component(&mut texel[c * component_bytes..(c + 1) * component_bytes], component_type, sum / count as f64);
+                    }
+                }
             }
         }
+        self.mip_levels = levels;
     }
 
     pub fn data_slice_mut(&mut self) -> &mut [u8] {
-        bytemuck::cast_slice_mut(&mut self.memory[0..self.config.pixel_layout.pixel_bytes() * self.config.width as usize * self.config.height as usize])
+        let length = self.config.pixel_layout.image_bytes(self.config.width as u32, self.config.height as u32);
+        &mut bytemuck::cast_slice_mut(&mut self.memory[..])[0..length]
     }
 
     pub fn data_slice(&self) -> &[u8] {
-        bytemuck::cast_slice(&self.memory[0..self.config.pixel_layout.pixel_bytes() * self.config.width as usize * self.config.height as usize])
+        let length = self.config.pixel_layout.image_bytes(self.config.width as u32, self.config.height as u32);
+        &bytemuck::cast_slice(&self.memory[..])[0..length]
     }
 
     pub fn clear(&mut self, data: AbstractPixelData) {
@@ -42,6 +199,8 @@ impl TextureModule {
             (AbstractPixelData::U32(data), PixelDataLayout::D32x1) => self.clear_internal(data[0]),
             (AbstractPixelData::U32(data), PixelDataLayout::D32x2) => self.clear_internal([data[0], data[1]]),
             (AbstractPixelData::U32(data), PixelDataLayout::D32x4) => self.clear_internal(data),
+            (AbstractPixelData::U32(data), PixelDataLayout::I8) => self.clear_internal(data[0] as u8),
+            (AbstractPixelData::U32(data), PixelDataLayout::I4) => self.clear_internal(((data[0] & 0xF) * 0x11) as u8),
 
             (AbstractPixelData::I32(data), PixelDataLayout::D8x1) => self.clear_internal(data[0] as i8),
             (AbstractPixelData::I32(data), PixelDataLayout::D8x2) => self.clear_internal([data[0] as i8, data[1] as i8]),
@@ -82,19 +241,211 @@ impl TextureModule {
     }
 
     fn clear_internal<T: Copy + Pod + Debug>(&mut self, data: T) {
-        let image_size = self.config.width as usize * self.config.height as usize;
+        let image_size = self.config.pixel_layout.image_bytes(self.config.width as u32, self.config.height as u32) / std::mem::size_of::<T>();
         let image_data = &mut cast_slice_mut::<_, T>(&mut self.memory[..])[0..image_size];
         image_data.fill(data);
     }
 
     pub fn fetch<T: Pod + Copy>(&self, x: u32, y: u32) -> T {
         let index = self.config.image_layout.index(x, y, self.config.width as u32);
-        cast_slice(&self.memory[..])[index as usize]
+        cast_slice(&self.memory[..]).get(index as usize).copied().unwrap_or_else(T::zeroed)
     }
 
     pub fn store<T: Pod + Copy>(&mut self, x: u32, y: u32, value: T) {
         let index = self.config.image_layout.index(x, y, self.config.width as u32);
-        cast_slice_mut(&mut self.memory[..])[index as usize] = value;
+        if let Some(texel) = cast_slice_mut(&mut self.memory[..]).get_mut(index as usize) {
+            *texel = value;
+        }
+    }
+
+    pub fn fetch_index(&self, x: u32, y: u32) -> u8 {
+        let index = self.config.image_layout.index(x, y, self.config.width as u32) as usize;
+        fetch_packed_index(cast_slice(&self.memory[..]), self.config.pixel_layout, index)
     }
 
+    pub fn store_index(&mut self, x: u32, y: u32, value: u8) {
+        let index = self.config.image_layout.index(x, y, self.config.width as u32) as usize;
+        let bytes = cast_slice_mut::<_, u8>(&mut self.memory[..]);
+        match self.config.pixel_layout {
+            PixelDataLayout::I4 => if let Some(byte) = bytes.get_mut(index / 2) {
+                let shift = (index & 1) * 4;
+                *byte = (*byte & !(0xF << shift)) | ((value & 0xF) << shift);
+            },
+            _ => if let Some(byte) = bytes.get_mut(index) {
+                *byte = value;
+            },
+        }
+    }
+
+}
+
+/*
+a rectangle of a texture copied out so a raster worker can shade a tile while
+other workers read the texture. texels are addressed in texture coordinates and
+the rectangle is copied back with write_back.
+ */
+pub struct TextureView {
+    pub texture: usize,
+    pub x: u32,
+    pub y: u32,
+    pub module: TextureModule,
+}
+
+impl TextureView {
+    pub fn load(texture_modules: &[TextureModule; 64], texture: usize, x: u32, y: u32, width: u32, height: u32) -> Self {
+        let source = &texture_modules[texture];
+        let width = width.min((source.config.width as u32).saturating_sub(x));
+        let height = height.min((source.config.height as u32).saturating_sub(y));
+        let mut module = TextureModule::new();
+        module.config = Texture {
+            width: width as u16,
+            height: height as u16,
+            pixel_layout: source.config.pixel_layout,
+            image_layout: ImageDataLayout::Contiguous,
+        };
+        module.memory = vec![0u32; source.config.pixel_layout.image_bytes(width, height).div_ceil(4)].into_boxed_slice();
+        for ly in 0..height {
+            for lx in 0..width {
+                copy_texel(source, x + lx, y + ly, &mut module, lx, ly);
+            }
+        }
+        Self { texture, x, y, module }
+    }
+
+    pub fn write_back(&self, texture_modules: &mut [TextureModule; 64]) {
+        let target = &mut texture_modules[self.texture];
+        for ly in 0..self.module.config.height as u32 {
+            for lx in 0..self.module.config.width as u32 {
+                copy_texel(&self.module, lx, ly, target, self.x + lx, self.y + ly);
+            }
+        }
+    }
+
+    // view coordinates of a texel, if the view covers it
+    pub fn local(&self, x: u32, y: u32) -> Option<(u32, u32)> {
+        let (lx, ly) = (x.checked_sub(self.x)?, y.checked_sub(self.y)?);
+        (lx < self.module.config.width as u32 && ly < self.module.config.height as u32).then_some((lx, ly))
+    }
+
+    pub fn fetch<T: Pod + Copy>(&self, x: u32, y: u32) -> T {
+        self.local(x, y).map_or_else(T::zeroed, |(x, y)| self.module.fetch(x, y))
+    }
+
+    pub fn store<T: Pod + Copy>(&mut self, x: u32, y: u32, value: T) {
+        if let Some((x, y)) = self.local(x, y) {
+            self.module.store(x, y, value);
+        }
+    }
+}
+
+fn copy_texel(source: &TextureModule, sx: u32, sy: u32, target: &mut TextureModule, tx: u32, ty: u32) {
+    let pixel_layout = source.config.pixel_layout;
+    if pixel_layout.is_indexed() {
+        target.store_index(tx, ty, source.fetch_index(sx, sy));
+        return;
+    }
+    let pixel_bytes = pixel_layout.pixel_bytes();
+    let source_offset = source.config.image_layout.index(sx, sy, source.config.width as u32) as usize * pixel_bytes;
+    let target_offset = target.config.image_layout.index(tx, ty, target.config.width as u32) as usize * pixel_bytes;
+    let texel = cast_slice::<_, u8>(&source.memory[..]).get(source_offset..source_offset + pixel_bytes);
+    if let (Some(texel), Some(dst)) = (texel, cast_slice_mut::<_, u8>(&mut target.memory[..]).get_mut(target_offset..target_offset + pixel_bytes)) {
+        dst.copy_from_slice(texel);
+    }
+}
+
+pub type RgbaUNorm8FetchFn = fn(&TextureModule, &[u8], u32, u32) -> [u8; 4];
+
+pub fn rgba_unorm8_fetch_fn(pixel_layout: PixelDataLayout) -> Option<RgbaUNorm8FetchFn> {
+    match pixel_layout {
+        PixelDataLayout::D8x4  => Some(|texture, _palette, x, y| texture.fetch::<[u8; 4]>(x, y)),
+        PixelDataLayout::D32x4 => Some(|texture, _palette, x, y| texture.fetch::<[f32; 4]>(x, y).map(f32_to_unorm8)),
+        PixelDataLayout::I4 |
+        PixelDataLayout::I8    => Some(|texture, palette, x, y| palette_color(palette, texture.fetch_index(x, y))),
+        _ => None,
+    }
+}
+
+fn decode_component(bytes: &[u8], component_type: TexelComponentType) -> f64 {
+    match (bytes.len(), component_type) {
+        (1, TexelComponentType::Int) => bytes[0] as i8 as f64,
+        (1, _                      ) => bytes[0] as f64,
+        (2, TexelComponentType::Int) => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+        (2, _                      ) => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+        (_, TexelComponentType::Int) => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+        (_, TexelComponentType::UInt) => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+        (_, TexelComponentType::F32) => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64,
+    }
+}
+
+fn encode_component(bytes: &mut [u8], component_type: TexelComponentType, value: f64) {
+    match (bytes.len(), component_type) {
+        (1, TexelComponentType::Int) => bytes[0] = value.round() as i8 as u8,
+        (1, _                      ) => bytes[0] = value.round() as u8,
+        (2, TexelComponentType::Int) => bytes.copy_from_slice(&(value.round() as i16).to_le_bytes()),
+        (2, _                      ) => bytes.copy_from_slice(&(value.round() as u16).to_le_bytes()),
+        (_, TexelComponentType::Int) => bytes.copy_from_slice(&(value.round() as i32).to_le_bytes()),
+        (_, TexelComponentType::UInt) => bytes.copy_from_slice(&(value.round() as u32).to_le_bytes()),
+        (_, TexelComponentType::F32) => bytes.copy_from_slice(&(value as f32).to_le_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn texture(width: u16, height: u16, pixel_layout: PixelDataLayout, image_layout: ImageDataLayout) -> TextureModule {
+        let mut texture = TextureModule::new();
+        texture.config = Texture { width, height, pixel_layout, image_layout };
+        texture.memory = vec![0u32; texture.allocation_bytes().div_ceil(4)].into_boxed_slice();
+        texture
+    }
+
+    #[test]
+    fn mipmaps_average_texels() {
+        let mut texture = texture(4, 2, PixelDataLayout::D8x1, ImageDataLayout::Contiguous);
+        texture.level_data_slice_mut(0).copy_from_slice(&[0, 2, 10, 20, 4, 6, 30, 40]);
+        texture.generate_mipmaps(TexelComponentType::UInt);
+        assert_eq!(texture.mip_levels, 3);
+        assert_eq!(texture.level_data_slice_mut(1), &[3, 25]);
+        assert_eq!(texture.level_data_slice_mut(2), &[14]);
+    }
+
+    #[test]
+    fn mipmaps_of_partial_blocks() {
+        for (width, height, image_layout) in [(6, 4, ImageDataLayout::Block4x4), (5, 7, ImageDataLayout::Block4x4), (12, 9, ImageDataLayout::Block8x8)] {
+            let mut texture = texture(width, height, PixelDataLayout::D32x4, image_layout);
+            texture.level_data_slice_mut(0).fill(0x3F);
+            texture.generate_mipmaps(TexelComponentType::UInt);
+            assert_eq!(texture.mip_levels, texture.max_mip_levels());
+            // texels whose sources all lie past the image are left as they were
+            assert!(texture.level_data_slice_mut(1).iter().all(|&x| x == 0x3F || x == 0), "{}x{} {:?}", width, height, image_layout);
+            assert_eq!(texture.level(1).fetch::<[u32; 4]>(0, 0), [0x3F3F_3F3F; 4]);
+        }
+    }
+
+    #[test]
+    fn indexed_texels_look_up_the_palette() {
+        let palette: Vec<u8> = (0..6u8).flat_map(|i| [i, 0x10 + i, 0x20 + i, 0xff]).collect();
+        assert_eq!(palette_color(&palette, 5), [5, 0x15, 0x25, 0xff]);
+        // entries past the end of the palette buffer are transparent black
+        assert_eq!(palette_color(&palette, 6), [0; 4]);
+        assert_eq!(palette_color(&palette[..23], 5), [0; 4]);
+
+        for pixel_layout in [PixelDataLayout::I4, PixelDataLayout::I8] {
+            let mut texture = texture(3, 2, pixel_layout, ImageDataLayout::Contiguous);
+            for (i, (x, y)) in [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)].into_iter().enumerate() {
+                texture.store_index(x, y, i as u8);
+            }
+            let fetch = rgba_unorm8_fetch_fn(pixel_layout).unwrap();
+            assert_eq!(fetch(&texture, &palette, 1, 0), [1, 0x11, 0x21, 0xff], "{:?}", pixel_layout);
+            assert_eq!(fetch(&texture, &palette, 2, 1), [5, 0x15, 0x25, 0xff], "{:?}", pixel_layout);
+        }
+
+        // i4 packs the even texel in the low nibble and masks stored indices to 4 bits
+        let mut texture = texture(2, 1, PixelDataLayout::I4, ImageDataLayout::Contiguous);
+        texture.store_index(0, 0, 0x13);
+        texture.store_index(1, 0, 0x05);
+        assert_eq!(cast_slice::<_, u8>(&texture.memory[..])[0], 0x53);
+        assert_eq!([texture.fetch_index(0, 0), texture.fetch_index(1, 0)], [3, 5]);
+    }
 }
@@ -75,6 +75,9 @@ pub enum PixelDataLayout {
     D32x1,
     D32x2,
     D32x4,
+    // palette indices, looked up through the texture's palette buffer. I4 packs two pixels per byte, low nibble first
+    I4,
+    I8,
 }
 
 impl PixelDataLayout {
@@ -89,10 +92,13 @@ impl PixelDataLayout {
             6 => PixelDataLayout::D32x1,
             7 => PixelDataLayout::D32x2,
             8 => PixelDataLayout::D32x4,
+            9 => PixelDataLayout::I4,
+            10 => PixelDataLayout::I8,
             _ => None?
         })
     }
 
+    // I4 pixels share bytes, so this rounds up for them. use image_bytes for the size of an image
     pub fn pixel_bytes(&self) -> usize {
         match self {
             Self::D8x1 =>  1,
@@ -104,8 +110,51 @@ impl PixelDataLayout {
             Self::D32x1 => 4,
             Self::D32x2 => 8,
             Self::D32x4 => 16,
+            Self::I4 => 1,
+            Self::I8 => 1,
         }
     }
+
+    pub fn pixel_bits(&self) -> usize {
+        match self {
+            Self::I4 => 4,
+            _ => self.pixel_bytes() * 8,
+        }
+    }
+
+    pub fn image_bytes(&self, width: u32, height: u32) -> usize {
+        (width as usize * height as usize * self.pixel_bits()).div_ceil(8)
+    }
+
+    pub fn is_indexed(&self) -> bool {
+        matches!(self, Self::I4 | Self::I8)
+    }
+
+    pub fn component_count(&self) -> usize {
+        match self {
+            Self::D8x1 | Self::D16x1 | Self::D32x1 | Self::I4 | Self::I8 => 1,
+            Self::D8x2 | Self::D16x2 | Self::D32x2 => 2,
+            Self::D8x4 | Self::D16x4 | Self::D32x4 => 4,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TexelComponentType {
+    UInt,
+    Int,
+    F32,
+}
+
+impl TexelComponentType {
+    pub fn from_u8(val: u8) -> Option<Self> {
+        Some(match val {
+            0 => Self::UInt,
+            1 => Self::Int,
+            2 => Self::F32,
+            _ => None?
+        })
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -119,6 +168,10 @@ pub enum PixelDataType {
     RgF32,
     RgbF32,
     RgbaF32,
+
+    // palette indices, read as the rgba unorm8 color they look up
+    Indexed4,
+    Indexed8,
 }
 
 impl PixelDataType {
@@ -132,6 +185,8 @@ impl PixelDataType {
             5 => Self::RgF32,
             6 => Self::RgbF32,
             7 => Self::RgbaF32,
+            8 => Self::Indexed4,
+            9 => Self::Indexed8,
             _ => None?,
         })
     }
@@ -141,7 +196,7 @@ impl PixelDataType {
             Self::RUNorm8 | Self::RF32 => 1,
             Self::RgUNorm8 | Self::RgF32 => 2,
             Self::RgbUNorm8 | Self::RgbF32 => 3,
-            Self::RgbaUNorm8 | Self::RgbaF32 => 4,
+            Self::RgbaUNorm8 | Self::RgbaF32 | Self::Indexed4 | Self::Indexed8 => 4,
         }
     }
 }
@@ -228,9 +283,31 @@ pub struct I32x2 {
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Background {
-    pub enabled : bool,
-    pub sampler : Sampler,
-    pub shape   : Rect,
+    pub enabled         : bool,
+    pub atlas_texture   : u8,
+    pub map_buffer      : u8,
+    pub tile_size       : u8,
+    pub map_size        : I32x2,
+    pub scroll          : I32x2,
+    pub wrap            : bool,
+    pub scanline_buffer : Option<u8>,
+    pub priority        : u8,
+}
+
+impl Background {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            atlas_texture: 0,
+            map_buffer: 0,
+            tile_size: 8,
+            map_size: I32x2 { x: 0, y: 0 },
+            scroll: I32x2 { x: 0, y: 0 },
+            wrap: false,
+            scanline_buffer: None,
+            priority: 0,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -283,7 +360,8 @@ impl ConstantSampler {
                 0,
             ]),
 
-            PixelDataType::RgbaUNorm8 => AbstractPixelData::UNorm32([
+            // a constant has no texture to take a palette from, so indexed constants hold the color itself
+            PixelDataType::RgbaUNorm8 | PixelDataType::Indexed4 | PixelDataType::Indexed8 => AbstractPixelData::UNorm32([
                 expand_unorm8_to_32((self.constant_data[0] >> 0) as u8),
                 expand_unorm8_to_32((self.constant_data[0] >> 8) as u8),
                 expand_unorm8_to_32((self.constant_data[0] >> 16) as u8),
@@ -326,19 +404,95 @@ pub enum OverflowMode {
     Zero,
 }
 
+impl OverflowMode {
+    pub fn from_u8(val: u8) -> Option<Self> {
+        Some(match val {
+            0 => Self::Repeate,
+            1 => Self::Mirror,
+            2 => Self::Clamp,
+            3 => Self::Zero,
+            _ => None?
+        })
+    }
+
+    pub fn apply(&self, coord: i32, size: u32) -> Option<u32> {
+        let size = size as i32;
+        if size == 0 {
+            return None;
+        }
+        Some(match self {
+            Self::Repeate => coord.rem_euclid(size),
+            Self::Mirror => {
+                let period_coord = coord.rem_euclid(size * 2);
+                if period_coord >= size { size * 2 - 1 - period_coord } else { period_coord }
+            },
+            Self::Clamp => coord.clamp(0, size - 1),
+            Self::Zero => if coord >= 0 && coord < size { coord } else { None? },
+        } as u32)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SampleFilter {
+    Nearest,
+    Bilinear,
+}
+
+impl SampleFilter {
+    pub fn from_u8(val: u8) -> Option<Self> {
+        Some(match val {
+            0 => Self::Nearest,
+            1 => Self::Bilinear,
+            _ => None?
+        })
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct TextureSampler {
-    pub texture       : u8,
-    pub data_type     : PixelDataType,
-    pub sample_region : Rect,
-    pub overflow_mode : OverflowMode,
+    pub filter     : SampleFilter,
+    pub overflow_u : OverflowMode,
+    pub overflow_v : OverflowMode,
 }
 
-#[derive(Copy, Clone, Debug)]
+impl Default for TextureSampler {
+    fn default() -> Self {
+        Self {
+            filter: SampleFilter::Nearest,
+            overflow_u: OverflowMode::Repeate,
+            overflow_v: OverflowMode::Repeate,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Sprite {
-    pub layer: i8,
-    pub sampler: Sampler,
-    pub transform: [[f32; 3]; 2],
+    pub enabled  : bool,
+    pub texture  : u8,
+    pub position : I32x2,
+    pub source   : Rect,
+    pub flip_x   : bool,
+    pub flip_y   : bool,
+    pub cutout   : bool,
+    pub priority : u8,
+}
+
+impl Sprite {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            texture: 0,
+            position: I32x2 { x: 0, y: 0 },
+            source: Rect {
+                position: I32x2 { x: 0, y: 0 },
+                size: I32x2 { x: 0, y: 0 },
+            },
+            flip_x: false,
+            flip_y: false,
+            cutout: false,
+            priority: 0,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -410,3 +564,36 @@ pub struct Blit {
     pub dst_x: u32,
     pub dst_y: u32,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn overflow_mode_apply() {
+        let repeat: Vec<_> = (-5..9).map(|x| OverflowMode::Repeate.apply(x, 4)).collect();
+        assert_eq!(repeat, [3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0].map(Some));
+        let mirror: Vec<_> = (-5..9).map(|x| OverflowMode::Mirror.apply(x, 4)).collect();
+        assert_eq!(mirror, [3, 3, 2, 1, 0, 0, 1, 2, 3, 3, 2, 1, 0, 0].map(Some));
+        let clamp: Vec<_> = (-2..6).map(|x| OverflowMode::Clamp.apply(x, 4)).collect();
+        assert_eq!(clamp, [0, 0, 0, 1, 2, 3, 3, 3].map(Some));
+        let zero: Vec<_> = (-2..6).map(|x| OverflowMode::Zero.apply(x, 4)).collect();
+        assert_eq!(zero, [None, None, Some(0), Some(1), Some(2), Some(3), None, None]);
+
+        for size in [1, 3, 2048] {
+            for coord in [i32::MIN, i32::MIN + 1, -1_000_000_007, 1_000_000_007, i32::MAX - 1, i32::MAX] {
+                for mode in [OverflowMode::Repeate, OverflowMode::Mirror, OverflowMode::Clamp] {
+                    assert!(mode.apply(coord, size).is_some_and(|x| x < size), "{:?} of {} in {}", mode, coord, size);
+                }
+                assert_eq!(OverflowMode::Zero.apply(coord, size), None);
+            }
+        }
+        assert_eq!(OverflowMode::Repeate.apply(i32::MAX, 2048), Some(2047));
+        assert_eq!(OverflowMode::Mirror.apply(i32::MIN, 2048), Some(0));
+        assert_eq!(OverflowMode::Clamp.apply(i32::MIN, 2048), Some(0));
+        assert_eq!(OverflowMode::Clamp.apply(i32::MAX, 2048), Some(2047));
+        for mode in [OverflowMode::Repeate, OverflowMode::Mirror, OverflowMode::Clamp, OverflowMode::Zero] {
+            assert_eq!(mode.apply(0, 0), None);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use super::{buffer::BufferModule, shader::*, texture::TextureModule};
+use super::{buffer::{BufferAccess, BufferModule}, shader::*, texture::TextureModule};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct VertexInputAssignment {
@@ -6,19 +6,27 @@ pub struct VertexInputAssignment {
     pub src_buffer: u8,
     pub offset: u32,
     pub stride: u32,
+    pub step_rate: u32,
     pub t: ShaderInputType,
     pub c: ShaderCardinality,
 }
 
+impl VertexInputAssignment {
+    // a step rate of 0 advances per vertex, otherwise once every step_rate instances
+    fn element(&self, vertex_id: u32, instance: u32) -> usize {
+        instance.checked_div(self.step_rate).unwrap_or(vertex_id) as usize
+    }
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct VertexState {
     pub inputs: Vec<VertexInputAssignment>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub struct VertexShaderResult {
-    pub remaining_count: usize,
-    pub remaining_offset: usize,
+pub struct VertexInvocation {
+    pub vertex_id: u32,
+    pub provoking: bool,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -28,8 +36,10 @@ pub enum VertexShaderError {
 
 pub const VERTEX_SCALAR_INPUT_BUILTIN_VERTEX_ID        : usize = 0x00;
 pub const VERTEX_SCALAR_INPUT_BUILTIN_PROVOKING_VERTEX : usize = 0x01;
+pub const VERTEX_SCALAR_INPUT_BUILTIN_INSTANCE_ID      : usize = 0x02;
 
 pub const VERTEX_SCALAR_OUTPUT_BUILTIN_VERTEX_DISCARD  : usize = 0x00;
+pub const VERTEX_SCALAR_OUTPUT_BUILTIN_POINT_SIZE      : usize = 0x01;
 
 pub const VERTEX_VECTOR_OUTPUT_BUILTIN_VERTEX_POSITION : usize = 0x00;
 
@@ -40,8 +50,8 @@ pub struct VertexShaderCall<'a> {
     pub shader        : u8,
     pub state         : &'a VertexState,
 
-    pub vertex_count  : usize,
-    pub vertex_offset : usize,
+    pub invocations   : &'a [VertexInvocation],
+    pub instance      : u32,
 
     pub shading_unit_context     : &'a mut ShadingUnitContext,
     pub shading_unit_run_context : ShadingUnitRunContext<'a>,
@@ -53,16 +63,19 @@ pub struct VertexShaderCall<'a> {
     pub resource_map: &'a ResourceMap,
 }
 
-pub fn run_vertex_shader(mut call: VertexShaderCall<'_>) -> Result<VertexShaderResult, VertexShaderError> {
-    let invocation_count = call.vertex_count.min(CORE_COUNT);
+/*
+shades one lane per invocation, so there can't be more than CORE_COUNT of them.
+ */
+pub fn run_vertex_shader(mut call: VertexShaderCall<'_>) -> Result<(), VertexShaderError> {
+    let invocation_count = call.invocations.len().min(CORE_COUNT);
 
-    for v in 0..invocation_count {
-        let builtin_v_id = v + call.vertex_offset;
-        let builtin_provoking = if builtin_v_id % 3 == 0 { 1 } else { 0 };
-        call.shading_unit_run_context.scalar_input_array[VERTEX_SCALAR_INPUT_BUILTIN_VERTEX_ID        as usize][v] = builtin_v_id as u32;
-        call.shading_unit_run_context.scalar_input_array[VERTEX_SCALAR_INPUT_BUILTIN_PROVOKING_VERTEX as usize][v] = builtin_provoking;
+    for (v, invocation) in call.invocations[0..invocation_count].iter().enumerate() {
+        call.shading_unit_run_context.scalar_input_array[VERTEX_SCALAR_INPUT_BUILTIN_VERTEX_ID        as usize][v] = invocation.vertex_id;
+        call.shading_unit_run_context.scalar_input_array[VERTEX_SCALAR_INPUT_BUILTIN_PROVOKING_VERTEX as usize][v] = invocation.provoking as u32;
+        call.shading_unit_run_context.scalar_input_array[VERTEX_SCALAR_INPUT_BUILTIN_INSTANCE_ID      as usize][v] = call.instance;
     }
     call.shading_unit_run_context.scalar_output_array[VERTEX_SCALAR_OUTPUT_BUILTIN_VERTEX_DISCARD ][0..invocation_count].fill(0);
+    call.shading_unit_run_context.scalar_output_array[VERTEX_SCALAR_OUTPUT_BUILTIN_POINT_SIZE     ][0..invocation_count].fill(1.0f32.to_bits());
     call.shading_unit_run_context.vector_output_array[VERTEX_VECTOR_OUTPUT_BUILTIN_VERTEX_POSITION][0..invocation_count].fill([0; 4]);
 
     for input_assignment in call.state.inputs.iter() {
@@ -108,35 +121,28 @@ pub fn run_vertex_shader(mut call: VertexShaderCall<'_>) -> Result<VertexShaderR
             ShaderInputType::IntFromI8 |
             ShaderInputType::UIntFromU8 => 1
         };
-        match input_assignment.c {
-            ShaderCardinality::Scalar => {
-                (0..invocation_count).for_each(|i| {
-                    call.shading_unit_run_context.scalar_input_array[input_assignment.input as usize][i] = read_fn(call.buffer_modules[input_assignment.src_buffer as usize].bytes(), input_assignment.offset as usize + input_assignment.stride as usize * i);
-                });
-            },
-            ShaderCardinality::V2 => {
-                (0..invocation_count).for_each(|i| {
-                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][0] = read_fn(call.buffer_modules[input_assignment.src_buffer as usize].bytes(), input_assignment.offset as usize + input_assignment.stride as usize * i + 0);
-                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][1] = read_fn(call.buffer_modules[input_assignment.src_buffer as usize].bytes(), input_assignment.offset as usize + input_assignment.stride as usize * i + element_size);
-                });
-            },
-            ShaderCardinality::V3 => {
-                (0..invocation_count).for_each(|i| {
-                    let offset_0 = input_assignment.offset as usize + input_assignment.stride as usize * i + element_size * 0;
-                    let offset_1 = input_assignment.offset as usize + input_assignment.stride as usize * i + element_size * 1;
-                    let offset_2 = input_assignment.offset as usize + input_assignment.stride as usize * i + element_size * 2;
-                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][0] = read_fn(call.buffer_modules[input_assignment.src_buffer as usize].bytes(), offset_0);
-                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][1] = read_fn(call.buffer_modules[input_assignment.src_buffer as usize].bytes(), offset_1);
-                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][2] = read_fn(call.buffer_modules[input_assignment.src_buffer as usize].bytes(), offset_2);
-                });
-            },
-            ShaderCardinality::V4 => {
-                (0..invocation_count).for_each(|i| {
-                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][0] = read_fn(call.buffer_modules[input_assignment.src_buffer as usize].bytes(), input_assignment.offset as usize + input_assignment.stride as usize * i + element_size * 0);
-                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][1] = read_fn(call.buffer_modules[input_assignment.src_buffer as usize].bytes(), input_assignment.offset as usize + input_assignment.stride as usize * i + element_size * 1);
-                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][2] = read_fn(call.buffer_modules[input_assignment.src_buffer as usize].bytes(), input_assignment.offset as usize + input_assignment.stride as usize * i + element_size * 2);
-                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][3] = read_fn(call.buffer_modules[input_assignment.src_buffer as usize].bytes(), input_assignment.offset as usize + input_assignment.stride as usize * i + element_size * 3);
-                });
+        let bytes = call.buffer_modules[input_assignment.src_buffer as usize].bytes();
+        for i in 0..invocation_count {
+            let offset = input_assignment.offset as usize + input_assignment.stride as usize * input_assignment.element(call.invocations[i].vertex_id, call.instance);
+            match input_assignment.c {
+                ShaderCardinality::Scalar => {
+                    call.shading_unit_run_context.scalar_input_array[input_assignment.input as usize][i] = read_fn(bytes, offset);
+                },
+                ShaderCardinality::V2 => {
+                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][0] = read_fn(bytes, offset);
+                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][1] = read_fn(bytes, offset + element_size);
+                },
+                ShaderCardinality::V3 => {
+                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][0] = read_fn(bytes, offset);
+                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][1] = read_fn(bytes, offset + element_size);
+                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][2] = read_fn(bytes, offset + element_size * 2);
+                },
+                ShaderCardinality::V4 => {
+                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][0] = read_fn(bytes, offset);
+                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][1] = read_fn(bytes, offset + element_size);
+                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][2] = read_fn(bytes, offset + element_size * 2);
+                    call.shading_unit_run_context.vector_input_array[input_assignment.input as usize][i][3] = read_fn(bytes, offset + element_size * 3);
+                }
             }
         }
     }
@@ -146,16 +152,8 @@ pub fn run_vertex_shader(mut call: VertexShaderCall<'_>) -> Result<VertexShaderR
         return Err(VertexShaderError::BadShader);
     }
 
-    let mut instructions = shader.instruction_buffer[0..shader.instruction_count].iter();
-    let mut continue_instructions = true;
-    while let (Some(instruction), true) = (instructions.next(), continue_instructions) {
-        if call.shading_unit_context.run_instruction(invocation_count, instruction, &mut call.shading_unit_run_context, &mut call.buffer_modules, &mut call.texture_modules, call.resource_map).is_none() {
-            continue_instructions = false;
-        }
-    }
+    let instructions = &shader.instruction_buffer[0..shader.instruction_count];
+    call.shading_unit_context.run_shader(invocation_count, instructions, &mut call.shading_unit_run_context, &mut BufferAccess::Exclusive(call.buffer_modules), call.texture_modules, call.resource_map);
 
-    Ok(VertexShaderResult {
-        remaining_count  : call.vertex_count  - invocation_count,
-        remaining_offset : call.vertex_offset + invocation_count,
-    })
+    Ok(())
 }
@@ -0,0 +1,85 @@
+/*
+simulated video memory. textures take their storage out of a fixed budget set
+with the machine config, sized by configure_texture, instead of each one
+reserving room for the largest texture up front.
+ */
+pub struct VramPool {
+    size: usize,
+    used: usize,
+    pub allocation_failures: u32,
+}
+
+#[derive(Debug)]
+pub enum VramError {
+    OutOfMemory { requested: usize, free: usize },
+}
+
+impl VramPool {
+    // allocations are made in whole 16 byte texels
+    pub const GRANULARITY: usize = 16;
+
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            used: 0,
+            allocation_failures: 0,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn free(&self) -> usize {
+        self.size - self.used
+    }
+
+    pub fn allocate(&mut self, bytes: usize) -> Result<Box<[u32]>, VramError> {
+        let bytes = bytes.next_multiple_of(Self::GRANULARITY);
+        if bytes > self.free() {
+            self.allocation_failures = self.allocation_failures.wrapping_add(1);
+            return Err(VramError::OutOfMemory { requested: bytes, free: self.free() });
+        }
+        self.used += bytes;
+        Ok(vec![0u32; bytes / 4].into_boxed_slice())
+    }
+
+    pub fn release(&mut self, memory: &[u32]) {
+        self.used -= memory.len() * 4;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn allocations_round_up_to_texels() {
+        let mut pool = VramPool::new(256);
+        let memory = pool.allocate(20).unwrap();
+        assert_eq!(memory.len() * 4, 32);
+        assert!(memory.iter().all(|&word| word == 0));
+        assert_eq!(pool.free(), 224);
+        assert_eq!(pool.allocate(0).unwrap().len(), 0);
+        assert_eq!(pool.free(), 224);
+    }
+
+    #[test]
+    fn out_of_memory_and_release() {
+        let mut pool = VramPool::new(64);
+        let first = pool.allocate(48).unwrap();
+        match pool.allocate(17) {
+            Err(VramError::OutOfMemory { requested, free }) => assert_eq!((requested, free), (32, 16)),
+            Ok(_) => panic!("allocation past the end of the pool succeeded"),
+        }
+        assert_eq!(pool.allocation_failures, 1);
+        assert_eq!(pool.free(), 16);
+
+        let second = pool.allocate(16).unwrap();
+        assert_eq!(pool.free(), 0);
+        pool.release(&first);
+        assert_eq!(pool.free(), 48);
+        pool.release(&second);
+        assert_eq!(pool.free(), pool.size());
+    }
+}
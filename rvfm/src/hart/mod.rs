@@ -1,13 +1,16 @@
 pub mod decoder;
 pub mod csrs;
+#[cfg(test)]
 mod test;
 
-use std::sync::Arc;
+use std::{cell::Cell, sync::Arc};
 
 use csrs::*;
 use decoder::*;
 
-use crate::machine::{AtomicLoadResult, AtomicOperationResult, Machine, ReadResult, WriteResult};
+#[cfg(debug_assertions)]
+use crate::semihosting::{SEMIHOSTING_PRE_OPCODE, SEMIHOSTING_POST_OPCODE};
+use crate::machine::{AtomicLoadResult, AtomicOperation, AtomicOperationResult, Machine, ReadResult, WriteResult};
 
 #[derive(Copy, Clone, Debug)]
 pub enum InterruptCause {
@@ -71,6 +74,19 @@ impl InterruptCause {
     }
 }
 
+thread_local! {
+    // the hart stepping on this host thread, for devices that keep per-hart state
+    static CURRENT_HART: Cell<u32> = const { Cell::new(0) };
+}
+
+pub fn current_hart() -> u32 {
+    CURRENT_HART.with(Cell::get)
+}
+
+pub(crate) fn set_current_hart(hart: u32) {
+    CURRENT_HART.with(|current| current.set(hart));
+}
+
 pub struct Hart {
     pub pc: u32,
     pub gprs: [u32; 32],
@@ -114,6 +130,15 @@ enum BusErrorType {
     AtomicWriteInvalid,
 }
 
+fn amo_ordering(acquire: bool, release: bool) -> std::sync::atomic::Ordering {
+    match (acquire, release) {
+        (true, false)  => std::sync::atomic::Ordering::Acquire,
+        (false, true)  => std::sync::atomic::Ordering::Release,
+        (true, true)   => std::sync::atomic::Ordering::SeqCst,
+        (false, false) => std::sync::atomic::Ordering::Relaxed,
+    }
+}
+
 #[allow(unused)]
 impl Hart {
     pub fn new(reset_addr: u32, hart_id: u32, shared_csrs: &SharedCSRs, machine: &Arc<Machine>) -> Self {
@@ -186,6 +211,8 @@ impl Hart {
     }
 
     pub fn single_step<const TRACE: bool>(&mut self) -> StepState {
+        // the debugger steps every hart on one thread
+        set_current_hart(self.csrs.hart_id());
         let result = self.single_step_internal::<TRACE>();
         self.csrs.mcycle += 1;
         result
@@ -677,7 +704,7 @@ impl Hart {
             Rv32Op::Lr { acquire, release, rs1, rd } => {
                 let hart_id = self.csrs.hart_id();
                 let addr = self.gprs[rs1 as usize];
-                let value = match self.machine.load_reserve(addr, hart_id) {
+                let value = match self.machine.load_reserve(addr, hart_id, amo_ordering(acquire, release)) {
                     AtomicLoadResult::Ok(value) => value,
                     AtomicLoadResult::InvalidAddress => return self.bus_error(addr, BusErrorType::AtomicReadInvalid),
                     AtomicLoadResult::AlignmentError => return self.bus_error(addr, BusErrorType::AtomicReadMisaligned),
@@ -689,159 +716,109 @@ impl Hart {
                 let hart_id = self.csrs.hart_id();
                 let addr = self.gprs[rs1 as usize];
                 let value = self.gprs[rs2 as usize];
-                let success = match self.machine.store_conditional(addr, value, hart_id) {
+                let success = match self.machine.store_conditional(addr, value, hart_id, amo_ordering(acquire, release)) {
                     crate::machine::AtomicStoreConditionalResult::Ok => true,
                     crate::machine::AtomicStoreConditionalResult::ReservationExpired => false,
                     crate::machine::AtomicStoreConditionalResult::AlignmentError => return self.bus_error(addr, BusErrorType::AtomicReadMisaligned),
                     crate::machine::AtomicStoreConditionalResult::InvalidAddress => return self.bus_error(addr, BusErrorType::AtomicReadInvalid),
                 };
-                self.set_gpr(rd, if success { 1 } else { 0 });
+                self.set_gpr(rd, if success { 0 } else { 1 });
                 instruction_addr.wrapping_add(4)
             },
             Rv32Op::AmoSwap { acquire, release, rs2, rs1, rd } => {
                 let addr = self.gprs[rs1 as usize];
                 let value_b = self.gprs[rs2 as usize];
-                match self.machine.atomic_operation(addr, value_b, |x, y| y) {
+                match self.machine.atomic_operation(addr, value_b, AtomicOperation::Swap, amo_ordering(acquire, release)) {
                     AtomicOperationResult::Ok(result) => self.set_gpr(rd, result),
                     AtomicOperationResult::InvalidAddress => return self.bus_error(addr, BusErrorType::AtomicWriteInvalid),
                     AtomicOperationResult::AlignmentError => return self.bus_error(addr, BusErrorType::AtomicWriteMisaligned),
                 }
-                match (acquire, release) {
-                    (true, false)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Acquire),
-                    (false, true)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Release),
-                    (true, true)   => std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel),
-                    (false, false) => {},
-                }
                 instruction_addr.wrapping_add(4)
             },
             Rv32Op::AmoAdd { acquire, release, rs2, rs1, rd } => {
                 let addr = self.gprs[rs1 as usize];
                 let value_b = self.gprs[rs2 as usize];
-                match self.machine.atomic_operation(addr, value_b, |x, y| x.wrapping_add(y)) {
+                match self.machine.atomic_operation(addr, value_b, AtomicOperation::Add, amo_ordering(acquire, release)) {
                     AtomicOperationResult::Ok(result) => self.set_gpr(rd, result),
                     AtomicOperationResult::InvalidAddress => return self.bus_error(addr, BusErrorType::AtomicWriteInvalid),
                     AtomicOperationResult::AlignmentError => return self.bus_error(addr, BusErrorType::AtomicWriteMisaligned),
                 }
-                match (acquire, release) {
-                    (true, false)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Acquire),
-                    (false, true)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Release),
-                    (true, true)   => std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel),
-                    (false, false) => {},
-                }
                 instruction_addr.wrapping_add(4)
             },
             Rv32Op::AmoAnd { acquire, release, rs2, rs1, rd } => {
                 let addr = self.gprs[rs1 as usize];
                 let value_b = self.gprs[rs2 as usize];
-                match self.machine.atomic_operation(addr, value_b, |x, y| x & y) {
+                match self.machine.atomic_operation(addr, value_b, AtomicOperation::And, amo_ordering(acquire, release)) {
                     AtomicOperationResult::Ok(result) => self.set_gpr(rd, result),
                     AtomicOperationResult::InvalidAddress => return self.bus_error(addr, BusErrorType::AtomicWriteInvalid),
                     AtomicOperationResult::AlignmentError => return self.bus_error(addr, BusErrorType::AtomicWriteMisaligned),
                 }
-                match (acquire, release) {
-                    (true, false)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Acquire),
-                    (false, true)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Release),
-                    (true, true)   => std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel),
-                    (false, false) => {},
-                }
                 instruction_addr.wrapping_add(4)
             },
             Rv32Op::AmoOr { acquire, release, rs2, rs1, rd } => {
                 let addr = self.gprs[rs1 as usize];
                 let value_b = self.gprs[rs2 as usize];
-                match self.machine.atomic_operation(addr, value_b, |x, y| x | y) {
+                match self.machine.atomic_operation(addr, value_b, AtomicOperation::Or, amo_ordering(acquire, release)) {
                     AtomicOperationResult::Ok(result) => self.set_gpr(rd, result),
                     AtomicOperationResult::InvalidAddress => return self.bus_error(addr, BusErrorType::AtomicWriteInvalid),
                     AtomicOperationResult::AlignmentError => return self.bus_error(addr, BusErrorType::AtomicWriteMisaligned),
                 }
-                match (acquire, release) {
-                    (true, false)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Acquire),
-                    (false, true)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Release),
-                    (true, true)   => std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel),
-                    (false, false) => {},
-                }
                 instruction_addr.wrapping_add(4)
             },
             Rv32Op::AmoXor { acquire, release, rs2, rs1, rd } => {
                 let addr = self.gprs[rs1 as usize];
                 let value_b = self.gprs[rs2 as usize];
-                match self.machine.atomic_operation(addr, value_b, |x, y| x ^ y) {
+                match self.machine.atomic_operation(addr, value_b, AtomicOperation::Xor, amo_ordering(acquire, release)) {
                     AtomicOperationResult::Ok(result) => self.set_gpr(rd, result),
                     AtomicOperationResult::InvalidAddress => return self.bus_error(addr, BusErrorType::AtomicWriteInvalid),
                     AtomicOperationResult::AlignmentError => return self.bus_error(addr, BusErrorType::AtomicWriteMisaligned),
                 }
-                match (acquire, release) {
-                    (true, false)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Acquire),
-                    (false, true)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Release),
-                    (true, true)   => std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel),
-                    (false, false) => {},
-                }
                 instruction_addr.wrapping_add(4)
             },
-            Rv32Op::AmoMax{ acquire, release, rs2, rs1, rd } => {
+            Rv32Op::AmoMax { acquire, release, rs2, rs1, rd } => {
                 let addr = self.gprs[rs1 as usize];
                 let value_b = self.gprs[rs2 as usize];
-                match self.machine.atomic_operation(addr, value_b, |x, y| (x as i32).max(y as i32) as u32) {
+                match self.machine.atomic_operation(addr, value_b, AtomicOperation::Max, amo_ordering(acquire, release)) {
                     AtomicOperationResult::Ok(result) => self.set_gpr(rd, result),
                     AtomicOperationResult::InvalidAddress => return self.bus_error(addr, BusErrorType::AtomicWriteInvalid),
                     AtomicOperationResult::AlignmentError => return self.bus_error(addr, BusErrorType::AtomicWriteMisaligned),
                 }
-                match (acquire, release) {
-                    (true, false)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Acquire),
-                    (false, true)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Release),
-                    (true, true)   => std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel),
-                    (false, false) => {},
-                }
                 instruction_addr.wrapping_add(4)
             },
-            Rv32Op::AmoMin{ acquire, release, rs2, rs1, rd } => {
+            Rv32Op::AmoMin { acquire, release, rs2, rs1, rd } => {
                 let addr = self.gprs[rs1 as usize];
                 let value_b = self.gprs[rs2 as usize];
-                match self.machine.atomic_operation(addr, value_b, |x, y| (x as i32).max(y as i32) as u32) {
+                match self.machine.atomic_operation(addr, value_b, AtomicOperation::Min, amo_ordering(acquire, release)) {
                     AtomicOperationResult::Ok(result) => self.set_gpr(rd, result),
                     AtomicOperationResult::InvalidAddress => return self.bus_error(addr, BusErrorType::AtomicWriteInvalid),
                     AtomicOperationResult::AlignmentError => return self.bus_error(addr, BusErrorType::AtomicWriteMisaligned),
                 }
-                match (acquire, release) {
-                    (true, false)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Acquire),
-                    (false, true)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Release),
-                    (true, true)   => std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel),
-                    (false, false) => {},
-                }
                 instruction_addr.wrapping_add(4)
             },
             Rv32Op::AmoMaxU { acquire, release, rs2, rs1, rd } => {
                 let addr = self.gprs[rs1 as usize];
                 let value_b = self.gprs[rs2 as usize];
-                match self.machine.atomic_operation(addr, value_b, |x, y| x.max(y)) {
+                match self.machine.atomic_operation(addr, value_b, AtomicOperation::MaxU, amo_ordering(acquire, release)) {
                     AtomicOperationResult::Ok(result) => self.set_gpr(rd, result),
                     AtomicOperationResult::InvalidAddress => return self.bus_error(addr, BusErrorType::AtomicWriteInvalid),
                     AtomicOperationResult::AlignmentError => return self.bus_error(addr, BusErrorType::AtomicWriteMisaligned),
                 }
-                match (acquire, release) {
-                    (true, false)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Acquire),
-                    (false, true)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Release),
-                    (true, true)   => std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel),
-                    (false, false) => {},
-                }
                 instruction_addr.wrapping_add(4)
             },
             Rv32Op::AmoMinU { acquire, release, rs2, rs1, rd } => {
                 let addr = self.gprs[rs1 as usize];
                 let value_b = self.gprs[rs2 as usize];
-                match self.machine.atomic_operation(addr, value_b, |x, y| x.min(y)) {
+                match self.machine.atomic_operation(addr, value_b, AtomicOperation::MinU, amo_ordering(acquire, release)) {
                     AtomicOperationResult::Ok(result) => self.set_gpr(rd, result),
                     AtomicOperationResult::InvalidAddress => return self.bus_error(addr, BusErrorType::AtomicWriteInvalid),
                     AtomicOperationResult::AlignmentError => return self.bus_error(addr, BusErrorType::AtomicWriteMisaligned),
                 }
-                match (acquire, release) {
-                    (true, false)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Acquire),
-                    (false, true)  => std::sync::atomic::fence(std::sync::atomic::Ordering::Release),
-                    (true, true)   => std::sync::atomic::fence(std::sync::atomic::Ordering::AcqRel),
-                    (false, false) => {},
-                }
                 instruction_addr.wrapping_add(4)
             },
+            Rv32Op::EBreak => match self.semihosting_call(instruction_addr) {
+                Some(pc) => pc,
+                None => return self.unimplemend_instruction(instruction_addr, opcode_value, op),
+            },
             _ => return self.unimplemend_instruction(instruction_addr, opcode_value, op),
         };
         self.pc = pc;
@@ -891,6 +868,24 @@ pc,
         StepState::InstructionError
     }
 
+    #[cfg(debug_assertions)]
+    fn semihosting_call(&mut self, instruction_addr: u32) -> Option<u32> {
+        let semihosting = self.machine.semihosting.as_ref()?;
+        let pre_opcode = self.machine.read_u32(instruction_addr.wrapping_sub(4)).to_opt()?;
+        let post_opcode = self.machine.read_u32(instruction_addr.wrapping_add(4)).to_opt()?;
+        if pre_opcode != SEMIHOSTING_PRE_OPCODE || post_opcode != SEMIHOSTING_POST_OPCODE {
+            return None;
+        }
+        let result = semihosting.call(&self.machine, self.gprs[10], self.gprs[11]);
+        self.set_gpr(10, result);
+        Some(instruction_addr.wrapping_add(4))
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn semihosting_call(&mut self, _instruction_addr: u32) -> Option<u32> {
+        None
+    }
+
     fn unimplemend_instruction(&mut self, addr: u32, opcode: u32, op: Rv32Op) -> StepState {
         println!("UNIMPLEMENTED INSTRUCTION AT {:#010X}: {:#010X} - {:?}", addr, opcode, op);
         StepState::InstructionError
@@ -1,7 +1,13 @@
 
+use std::sync::Arc;
+
+use crate::config::MachineConfig;
 use crate::hart::decoder::FenceOps;
+use crate::machine::Machine;
 
+use super::csrs::SharedCSRs;
 use super::decoder::Rv32Op;
+use super::{Hart, StepState};
 
 const ZERO: u8 = 0;
 const RA: u8 = 1;
@@ -165,3 +171,316 @@ fn test_disassembly() {
     }
     println!("\ndisassembly test: {} passed, {} failed", passed, failed);
 }
+
+const AMO_LR  : u32 = 0b00010;
+const AMO_SC  : u32 = 0b00011;
+const AMO_SWAP: u32 = 0b00001;
+const AMO_ADD : u32 = 0b00000;
+const AMO_MIN : u32 = 0b10000;
+const AMO_MAX : u32 = 0b10100;
+const AMO_MINU: u32 = 0b11000;
+const AMO_MAXU: u32 = 0b11100;
+
+const WFI: u32 = 0x1050_0073;
+
+fn encode_amo(funct5: u32, acquire: bool, release: bool, rd: u8, rs1: u8, rs2: u8) -> u32 {
+    (funct5 << 27) | ((acquire as u32) << 26) | ((release as u32) << 25) | ((rs2 as u32) << 20) | ((rs1 as u32) << 15) | (0b010 << 12) | ((rd as u32) << 7) | 0b0101111
+}
+
+fn encode_addi(rd: u8, rs1: u8, immediate: i32) -> u32 {
+    (((immediate as u32) & 0xFFF) << 20) | ((rs1 as u32) << 15) | ((rd as u32) << 7) | 0b0010011
+}
+
+fn encode_lw(rd: u8, rs1: u8, immediate: i32) -> u32 {
+    (((immediate as u32) & 0xFFF) << 20) | ((rs1 as u32) << 15) | (0b010 << 12) | ((rd as u32) << 7) | 0b0000011
+}
+
+fn encode_sw(rs2: u8, rs1: u8, immediate: i32) -> u32 {
+    let immediate = immediate as u32;
+    (((immediate >> 5) & 0x7F) << 25) | ((rs2 as u32) << 20) | ((rs1 as u32) << 15) | (0b010 << 12) | ((immediate & 0x1F) << 7) | 0b0100011
+}
+
+fn encode_branch(funct3: u32, rs1: u8, rs2: u8, offset: i32) -> u32 {
+    let offset = offset as u32;
+    (((offset >> 12) & 1) << 31) | (((offset >> 5) & 0x3F) << 25) | ((rs2 as u32) << 20) | ((rs1 as u32) << 15) | (funct3 << 12) | (((offset >> 1) & 0xF) << 8) | (((offset >> 11) & 1) << 7) | 0b1100011
+}
+
+fn encode_beq(rs1: u8, rs2: u8, offset: i32) -> u32 {
+    encode_branch(0b000, rs1, rs2, offset)
+}
+
+fn encode_bne(rs1: u8, rs2: u8, offset: i32) -> u32 {
+    encode_branch(0b001, rs1, rs2, offset)
+}
+
+const LITMUS_CODE_BASE: u32 = 0x0000_1000;
+const LITMUS_CODE_STRIDE: u32 = 0x0000_0100;
+const LITMUS_DATA_BASE: u32 = 0x0000_8000;
+const LITMUS_STEP_LIMIT: usize = 50_000_000;
+
+fn litmus_machine(hart_count: usize) -> Arc<Machine> {
+    Arc::new(Machine::new_headless(&[], &MachineConfig { hart_count, ram_size: 0x0001_0000, ..MachineConfig::default() }).unwrap())
+}
+
+fn litmus_harts(machine: &Arc<Machine>, programs: &[&[u32]], setup: impl Fn(usize, &mut Hart)) -> Vec<Hart> {
+    let shared_csrs = SharedCSRs::new();
+    programs.iter().enumerate().map(|(hart_id, program)| {
+        let code_addr = LITMUS_CODE_BASE + LITMUS_CODE_STRIDE * hart_id as u32;
+        for (i, instruction) in program.iter().enumerate() {
+            assert!(machine.write_u32(code_addr + (i as u32) * 4, *instruction).is_ok());
+        }
+        let mut hart = Hart::new(code_addr, hart_id as u32, &shared_csrs, machine);
+        setup(hart_id, &mut hart);
+        hart
+    }).collect()
+}
+
+fn run_until_wfi(hart: &mut Hart) {
+    for _ in 0..LITMUS_STEP_LIMIT {
+        match hart.single_step::<false>() {
+            StepState::Run => {},
+            StepState::WaitForInterrupt => return,
+            state => panic!("hart {} stopped with {:?} at {:08X}", hart.csrs.hart_id(), state, hart.pc),
+        }
+    }
+    panic!("hart {} did not finish within {} steps", hart.csrs.hart_id(), LITMUS_STEP_LIMIT);
+}
+
+fn run_concurrently(harts: Vec<Hart>) -> Vec<Hart> {
+    let threads: Vec<_> = harts.into_iter().map(|mut hart| std::thread::spawn(move || {
+        run_until_wfi(&mut hart);
+        hart
+    })).collect();
+    threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+}
+
+#[test]
+fn litmus_amo_add_counter() {
+    const ITERATIONS: u32 = 10_000;
+    let machine = litmus_machine(4);
+    let program = [
+        encode_addi(T0, ZERO, 1),
+        encode_amo(AMO_ADD, false, false, ZERO, A0, T0),
+        encode_addi(A1, A1, -1),
+        encode_bne(A1, ZERO, -8),
+        WFI,
+    ];
+    let harts = litmus_harts(&machine, &[&program[..]; 4], |_, hart| {
+        hart.gprs[A0 as usize] = LITMUS_DATA_BASE;
+        hart.gprs[A1 as usize] = ITERATIONS;
+    });
+    run_concurrently(harts);
+    assert_eq!(machine.read_u32(LITMUS_DATA_BASE).unwrap(), ITERATIONS * 4);
+}
+
+#[test]
+fn litmus_lr_sc_counter() {
+    const ITERATIONS: u32 = 10_000;
+    let machine = litmus_machine(4);
+    let program = [
+        encode_amo(AMO_LR, true, false, T0, A0, ZERO),
+        encode_addi(T0, T0, 1),
+        encode_amo(AMO_SC, false, true, T1, A0, T0),
+        encode_bne(T1, ZERO, -12),
+        encode_addi(A1, A1, -1),
+        encode_bne(A1, ZERO, -20),
+        WFI,
+    ];
+    let harts = litmus_harts(&machine, &[&program[..]; 4], |_, hart| {
+        hart.gprs[A0 as usize] = LITMUS_DATA_BASE;
+        hart.gprs[A1 as usize] = ITERATIONS;
+    });
+    run_concurrently(harts);
+    assert_eq!(machine.read_u32(LITMUS_DATA_BASE).unwrap(), ITERATIONS * 4);
+}
+
+#[test]
+fn litmus_store_invalidates_reservation() {
+    let machine = litmus_machine(2);
+    let reserving_program = [
+        encode_amo(AMO_LR, false, false, T0, A0, ZERO),
+        encode_amo(AMO_SC, false, false, T1, A0, T0),
+        WFI,
+    ];
+    let storing_program = [
+        encode_sw(T2, A0, 0),
+        WFI,
+    ];
+    for (store_offset, sc_result) in [(None, 0), (Some(0), 1), (Some(4), 1), (Some(64), 0)] {
+        let mut harts = litmus_harts(&machine, &[&reserving_program, &storing_program], |hart_id, hart| {
+            hart.gprs[A0 as usize] = LITMUS_DATA_BASE + if hart_id == 1 { store_offset.unwrap_or(0) } else { 0 };
+            hart.gprs[T2 as usize] = 5;
+        });
+        let mut storing_hart = harts.pop().unwrap();
+        let mut reserving_hart = harts.pop().unwrap();
+        assert!(matches!(reserving_hart.single_step::<false>(), StepState::Run));
+        if store_offset.is_some() {
+            assert!(matches!(storing_hart.single_step::<false>(), StepState::Run));
+        }
+        assert!(matches!(reserving_hart.single_step::<false>(), StepState::Run));
+        assert_eq!(reserving_hart.gprs[T1 as usize], sc_result, "sc result with store at offset {:?}", store_offset);
+    }
+}
+
+#[test]
+fn litmus_sc_without_reservation_fails() {
+    let machine = litmus_machine(1);
+    let program = [
+        encode_amo(AMO_LR, false, false, T0, A0, ZERO),
+        encode_amo(AMO_SC, false, false, T1, A0, T0),
+        encode_amo(AMO_SC, false, false, T2, A0, T0),
+        WFI,
+    ];
+    let mut harts = litmus_harts(&machine, &[&program], |_, hart| hart.gprs[A0 as usize] = LITMUS_DATA_BASE);
+    run_until_wfi(&mut harts[0]);
+    assert_eq!(harts[0].gprs[T1 as usize], 0);
+    assert_eq!(harts[0].gprs[T2 as usize], 1);
+}
+
+#[test]
+fn litmus_message_passing() {
+    const ROUNDS: usize = 64;
+    let producer = [
+        encode_sw(T2, A0, 64),
+        encode_addi(T0, ZERO, 1),
+        encode_amo(AMO_SWAP, false, true, ZERO, A0, T0),
+        WFI,
+    ];
+    let consumer = [
+        encode_amo(AMO_LR, true, false, T0, A0, ZERO),
+        encode_beq(T0, ZERO, -4),
+        encode_lw(T1, A0, 64),
+        WFI,
+    ];
+    for round in 0..ROUNDS {
+        let machine = litmus_machine(2);
+        let harts = litmus_harts(&machine, &[&producer, &consumer], |_, hart| {
+            hart.gprs[A0 as usize] = LITMUS_DATA_BASE;
+            hart.gprs[T2 as usize] = 0x1234_0000 + round as u32;
+        });
+        let harts = run_concurrently(harts);
+        assert_eq!(harts[1].gprs[T1 as usize], 0x1234_0000 + round as u32);
+    }
+}
+
+#[test]
+fn litmus_amo_min_max() {
+    let machine = litmus_machine(1);
+    let program = [
+        encode_amo(AMO_MIN,  false, false, S2, A0, A2),
+        encode_amo(AMO_MAX,  false, false, S3, A1, A2),
+        encode_amo(AMO_MINU, false, false, S4, A3, A2),
+        encode_amo(AMO_MAXU, false, false, S5, A4, A2),
+        WFI,
+    ];
+    for addr in [0, 4, 8, 12] {
+        assert!(machine.write_u32(LITMUS_DATA_BASE + addr, 3).is_ok());
+    }
+    let mut harts = litmus_harts(&machine, &[&program], |_, hart| {
+        hart.gprs[A0 as usize] = LITMUS_DATA_BASE;
+        hart.gprs[A1 as usize] = LITMUS_DATA_BASE + 4;
+        hart.gprs[A3 as usize] = LITMUS_DATA_BASE + 8;
+        hart.gprs[A4 as usize] = LITMUS_DATA_BASE + 12;
+        hart.gprs[A2 as usize] = (-2i32) as u32;
+    });
+    run_until_wfi(&mut harts[0]);
+    assert_eq!(harts[0].gprs[S2 as usize], 3);
+    assert_eq!(machine.read_u32(LITMUS_DATA_BASE     ).unwrap(), (-2i32) as u32);
+    assert_eq!(machine.read_u32(LITMUS_DATA_BASE +  4).unwrap(), 3);
+    assert_eq!(machine.read_u32(LITMUS_DATA_BASE +  8).unwrap(), 3);
+    assert_eq!(machine.read_u32(LITMUS_DATA_BASE + 12).unwrap(), (-2i32) as u32);
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn semihosting_file_round_trip() {
+    const SEMIHOSTING_PRE: u32 = 0x01F0_1013;
+    const SEMIHOSTING_POST: u32 = 0x4070_5013;
+    const SYS_OPEN: i32 = 0x01;
+    const SYS_CLOSE: i32 = 0x02;
+    const SYS_WRITE: i32 = 0x05;
+    const SYS_READ: i32 = 0x06;
+    const SYS_FLEN: i32 = 0x0C;
+    const EBREAK: u32 = 0x0010_0073;
+
+    let root = std::env::temp_dir().join(format!("rvfm-semihosting-test-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let machine = Arc::new(Machine::new_headless(&[], &MachineConfig { hart_count: 1, ram_size: 0x0001_0000, semihosting_root: Some(root.clone()), ..MachineConfig::default() }).unwrap());
+
+    let name = b"save.bin";
+    let payload = b"hello semihosting";
+    let name_addr = LITMUS_DATA_BASE;
+    let payload_addr = LITMUS_DATA_BASE + 0x100;
+    let read_addr = LITMUS_DATA_BASE + 0x200;
+    let parameters_addr = LITMUS_DATA_BASE + 0x300;
+    assert!(machine.write_block(name_addr, name).is_ok());
+    assert!(machine.write_block(payload_addr, payload).is_ok());
+
+    let semihosting_call = |operation: i32| [
+        encode_addi(A0, ZERO, operation),
+        SEMIHOSTING_PRE,
+        EBREAK,
+        SEMIHOSTING_POST,
+    ];
+    let mut harts = litmus_harts(&machine, &[&semihosting_call(SYS_OPEN)], |_, hart| hart.gprs[A1 as usize] = parameters_addr);
+
+    let mut run_call = |operation: i32, parameters: &[u32]| {
+        for (i, parameter) in parameters.iter().enumerate() {
+            assert!(machine.write_u32(parameters_addr + (i as u32) * 4, *parameter).is_ok());
+        }
+        for (i, instruction) in semihosting_call(operation).iter().enumerate() {
+            assert!(machine.write_u32(LITMUS_CODE_BASE + (i as u32) * 4, *instruction).is_ok());
+        }
+        harts[0].pc = LITMUS_CODE_BASE;
+        for _ in 0..4 {
+            assert!(matches!(harts[0].single_step::<false>(), StepState::Run));
+        }
+        harts[0].gprs[A0 as usize]
+    };
+
+    let handle = run_call(SYS_OPEN, &[name_addr, 6, name.len() as u32]);
+    assert_ne!(handle, 0xFFFF_FFFF);
+    assert_eq!(run_call(SYS_WRITE, &[handle, payload_addr, payload.len() as u32]), 0);
+    assert_eq!(run_call(SYS_CLOSE, &[handle]), 0);
+    assert_eq!(std::fs::read(root.join("save.bin")).unwrap(), payload);
+
+    let handle = run_call(SYS_OPEN, &[name_addr, 0, name.len() as u32]);
+    assert_eq!(run_call(SYS_FLEN, &[handle]), payload.len() as u32);
+    // a read into memory that isn't there leaves the file position alone
+    assert_eq!(run_call(SYS_READ, &[handle, 0x7000_0000, payload.len() as u32]), 0xFFFF_FFFF);
+    assert_eq!(run_call(SYS_READ, &[handle, read_addr, payload.len() as u32 + 4]), 4);
+    let mut read_back = vec![0u8; payload.len()];
+    assert!(machine.read_block(read_addr, &mut read_back[..]).to_opt().is_some());
+    assert_eq!(&read_back[..], payload);
+    assert_eq!(run_call(SYS_CLOSE, &[handle]), 0);
+
+    let escape_name = b"../escape.bin";
+    assert!(machine.write_block(name_addr, escape_name).is_ok());
+    assert_eq!(run_call(SYS_OPEN, &[name_addr, 4, escape_name.len() as u32]), 0xFFFF_FFFF);
+
+    // a dangling link inside the sandbox must not let open create its target outside it
+    let outside = root.with_extension("outside");
+    std::os::unix::fs::symlink(&outside, root.join("link.bin")).unwrap();
+    let link_name = b"link.bin";
+    assert!(machine.write_block(name_addr, link_name).is_ok());
+    assert_eq!(run_call(SYS_OPEN, &[name_addr, 4, link_name.len() as u32]), 0xFFFF_FFFF);
+    assert!(!outside.exists());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn start_last_hart() {
+    use crate::hart_clock::{ClockEvent, HartClock, HART_CLOCK_MASTER};
+    use crate::machine::DEVICE_TEST_LOCK;
+    let machine = litmus_machine(MachineConfig::MAX_HART_COUNT);
+    let last = MachineConfig::MAX_HART_COUNT as u32 - 1;
+    {
+        // other tests building machines would lower the hart count
+        let _lock = DEVICE_TEST_LOCK.lock();
+        HART_CLOCK_MASTER.set_hart_count(MachineConfig::MAX_HART_COUNT);
+        assert_eq!(machine.read_u32(0x8002_0000 + last * 4).to_opt(), Some(0));
+        assert!(machine.write_u32(0x8002_0000 + last * 4, 0x1234).is_ok());
+    }
+    assert!(matches!(HartClock::new(last as usize).wait_for_event(), ClockEvent::Reset(0x1234)));
+}
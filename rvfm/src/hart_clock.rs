@@ -1,10 +1,10 @@
-use std::sync::{Arc, atomic::{AtomicBool, self, AtomicUsize, AtomicU32}};
+use std::sync::{Arc, atomic::{AtomicBool, self, AtomicUsize, AtomicU32, AtomicU64}};
 use parking_lot::{Condvar, Mutex};
 use static_init::dynamic;
 
-use crate::machine::{WriteResult, ReadResult};
+use crate::{machine::{WriteResult, ReadResult}, config::MachineConfig};
 
-const HART_CYCLES_PER_FRAME: usize = 500000;
+pub const HART_CYCLES_PER_FRAME: usize = 500000;
 
 #[derive(Clone)]
 pub struct HartClockMaster {
@@ -29,9 +29,12 @@ struct HartClockMasterState {
     pub frame: AtomicUsize,
     pub event_cv_lock: Mutex<()>,
     pub event_cv: Condvar,
-    pub interrupts: [AtomicBool; 4],
-    pub start_flags: [AtomicBool; 4],
-    pub start_address: [AtomicU32; 4],
+    pub hart_count: AtomicUsize,
+    pub interrupts: [AtomicBool; MachineConfig::MAX_HART_COUNT],
+    pub start_flags: [AtomicBool; MachineConfig::MAX_HART_COUNT],
+    pub start_address: [AtomicU32; MachineConfig::MAX_HART_COUNT],
+    // frame * HART_CYCLES_PER_FRAME + cycles hart 0 has run in that frame, as of hart 0's last register_cycles
+    pub hart0_cycles: AtomicU64,
 }
 
 #[dynamic]
@@ -44,9 +47,11 @@ impl HartClockMaster {
                 frame: AtomicUsize::new(0),
                 event_cv: Condvar::new(),
                 event_cv_lock: Mutex::new(()),
-                interrupts: [(); 4].map(|_| AtomicBool::new(false)),
-                start_flags: [(); 4].map(|_| AtomicBool::new(false)),
-                start_address: [(); 4].map(|_| AtomicU32::new(0)),
+                hart_count: AtomicUsize::new(MachineConfig::MAX_HART_COUNT),
+                interrupts: [(); MachineConfig::MAX_HART_COUNT].map(|_| AtomicBool::new(false)),
+                start_flags: [(); MachineConfig::MAX_HART_COUNT].map(|_| AtomicBool::new(false)),
+                start_address: [(); MachineConfig::MAX_HART_COUNT].map(|_| AtomicU32::new(0)),
+                hart0_cycles: AtomicU64::new(0),
             })
         }
     }
@@ -60,13 +65,33 @@ impl HartClockMaster {
         self.state.frame.load(atomic::Ordering::Acquire) as u64
     }
 
+    // virtual time for gpu timestamps, advancing as hart 0 runs and jumping forward to each new frame
+    pub fn virtual_cycles(&self) -> u64 {
+        let frame_start = self.frame() * HART_CYCLES_PER_FRAME as u64;
+        self.state.hart0_cycles.load(atomic::Ordering::Acquire).max(frame_start)
+    }
+
+    pub fn set_hart_count(&self, hart_count: usize) {
+        self.state.hart_count.store(hart_count.min(MachineConfig::MAX_HART_COUNT), atomic::Ordering::Release);
+    }
+
+    pub fn hart_count(&self) -> usize {
+        self.state.hart_count.load(atomic::Ordering::Acquire)
+    }
+
     pub fn start_hart(&self, hart: usize, start_address: u32) {
+        if hart >= self.hart_count() {
+            return;
+        }
         self.state.start_address[hart].store(start_address, atomic::Ordering::Release);
         self.state.start_flags[hart].store(true, atomic::Ordering::Release);
         self.state.event_cv.notify_all();
     }
 
     pub fn interrupt_hart(&self, hart: usize) {
+        if hart >= self.hart_count() {
+            return;
+        }
         self.state.interrupts[hart].store(true, atomic::Ordering::Release);
         self.state.event_cv.notify_all();
     }
@@ -93,6 +118,10 @@ impl HartClock {
     
     pub fn register_cycles(&mut self, cycles: usize) {
         self.elapsed_cycles += cycles;
+        if self.hart == 0 {
+            let cycles = self.current_frame as u64 * HART_CYCLES_PER_FRAME as u64 + self.elapsed_cycles as u64;
+            self.master.state.hart0_cycles.fetch_max(cycles, atomic::Ordering::AcqRel);
+        }
     }
 
     pub fn wfi(&mut self) {
@@ -156,7 +185,7 @@ impl HartClock {
 
 pub fn clock_write_u32(offset: u32, value: u32) -> WriteResult {
     match offset {
-        0 | 4 | 8 | 12 => {
+        0x00 ..= 0x1F if (offset & 3) == 0 && ((offset >> 2) as usize) < HART_CLOCK_MASTER.hart_count() => {
             HART_CLOCK_MASTER.start_hart((offset >> 2) as usize, value);
             WriteResult::Ok
         },
@@ -175,7 +204,7 @@ pub fn clock_write_u8(offset: u32, value: u8) -> WriteResult {
 
 pub fn clock_read_u32(offset: u32) -> ReadResult<u32> {
     match offset {
-        0 | 4 | 8 | 12 => ReadResult::Ok(0),
+        0x00 ..= 0x1F if (offset & 3) == 0 && ((offset >> 2) as usize) < HART_CLOCK_MASTER.hart_count() => ReadResult::Ok(0),
         _ => ReadResult::InvalidAddress
     }
 }
@@ -187,3 +216,31 @@ pub fn clock_read_u16(offset: u32) -> ReadResult<u16> {
 pub fn clock_read_u8(offset: u32) -> ReadResult<u8> {
     clock_read_u32(offset).map(|x: u32| x as u8)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn virtual_cycles_advance_within_a_frame() {
+        let master = HartClockMaster::new();
+        let mut clock = HartClock { master: master.clone(), current_frame: 0, elapsed_cycles: 0, state: RunState::Run, hart: 0 };
+        assert_eq!(master.virtual_cycles(), 0);
+        clock.register_cycles(1000);
+        assert_eq!(master.virtual_cycles(), 1000);
+        clock.register_cycles(24);
+        assert_eq!(master.virtual_cycles(), 1024);
+
+        // a new frame starts the count at the frame's first cycle even before hart 0 gets to run in it
+        master.next_frame();
+        assert_eq!(master.virtual_cycles(), HART_CYCLES_PER_FRAME as u64);
+        assert!(matches!(clock.wait_for_event(), ClockEvent::Cycles(HART_CYCLES_PER_FRAME)));
+        clock.register_cycles(10);
+        assert_eq!(master.virtual_cycles(), HART_CYCLES_PER_FRAME as u64 + 10);
+
+        // other harts don't move it
+        let mut other = HartClock { master: master.clone(), current_frame: 1, elapsed_cycles: 0, state: RunState::Run, hart: 1 };
+        other.register_cycles(5000);
+        assert_eq!(master.virtual_cycles(), HART_CYCLES_PER_FRAME as u64 + 10);
+    }
+}
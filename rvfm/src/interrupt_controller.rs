@@ -3,7 +3,7 @@ use std::{sync::atomic::{AtomicU32, AtomicBool, self}, future::Pending};
 use parking_lot::RwLock;
 use static_init::dynamic;
 
-use crate::{machine::{WriteResult, ReadResult}, hart_clock::HART_CLOCK_MASTER, hart::csrs::InterruptBits};
+use crate::{machine::{WriteResult, ReadResult}, hart_clock::HART_CLOCK_MASTER, hart::csrs::InterruptBits, config::MachineConfig, mailbox::MAILBOX};
 
 struct Interrupt {
     enabled: bool,
@@ -53,8 +53,8 @@ pub struct InterruptController {
     present_interrupt: RwLock<Interrupt>,
     vsync_interrupt: RwLock<Interrupt>,
     spu_interrupt: RwLock<Interrupt>,
-    ihis: [RwLock<InterHartInterrupt>; 4],
-    mips: [AtomicU32; 4],
+    ihis: [RwLock<InterHartInterrupt>; MachineConfig::MAX_HART_COUNT],
+    mips: [AtomicU32; MachineConfig::MAX_HART_COUNT],
 }
 
 #[dynamic]
@@ -67,11 +67,15 @@ impl InterruptController {
             present_interrupt: RwLock::new(Interrupt::new()),
             vsync_interrupt: RwLock::new(Interrupt::new()),
             spu_interrupt: RwLock::new(Interrupt::new()),
-            ihis: [(); 4].map(|_| RwLock::new(InterHartInterrupt::new())),
-            mips: [(); 4].map(|_| AtomicU32::new(0))
+            ihis: [(); MachineConfig::MAX_HART_COUNT].map(|_| RwLock::new(InterHartInterrupt::new())),
+            mips: [(); MachineConfig::MAX_HART_COUNT].map(|_| AtomicU32::new(0))
         }
     }
 
+    fn valid_hart(hart: u32) -> bool {
+        (hart as usize) < HART_CLOCK_MASTER.hart_count()
+    }
+
     fn get_interrupt(&self, interrupt: u32) -> Option<&RwLock<Interrupt>> {
         match interrupt {
             0 => Some(&self.gpu_interrupt),
@@ -99,6 +103,9 @@ impl InterruptController {
 
     pub fn set_interrupt_hart(&self, interrupt: u32, hart: u32) {
         println!("set_interrupt_hart(int: {interrupt}, hart: {hart})");
+        if !Self::valid_hart(hart) {
+            return;
+        }
         if let Some(interrupt) = self.get_interrupt(interrupt) {
             self.update_and_propogate_interrupt(interrupt, |interrupt| interrupt.hart = hart)
         }
@@ -156,6 +163,9 @@ impl InterruptController {
     }
 
     fn check_ihi(&self, hart: u32) -> bool {
+        if !Self::valid_hart(hart) {
+            return false;
+        }
         let ihi = self.ihis[hart as usize].read();
         ihi.enabled && ihi.flag
     }
@@ -200,13 +210,13 @@ impl InterruptController {
 
     pub fn set_ihi_enabled(&self, hart: u32, enabled: bool) {
         println!("set_ihi_enabled {}: {}", hart, enabled);
-        if (0..4).contains(&hart) {
+        if Self::valid_hart(hart) {
             self.update_and_propogate_ihi(&self.ihis[hart as usize], hart, |ihi| ihi.enabled = enabled);
         }
     }
 
     pub fn get_ihi_enabled(&self, hart: u32) -> bool {
-        if (0..4).contains(&hart) {
+        if Self::valid_hart(hart) {
             self.ihis[hart as usize].read().enabled
         } else {
             false
@@ -214,7 +224,7 @@ impl InterruptController {
     }
 
     pub fn get_ihi_flag(&self, hart: u32) -> bool {
-        if (0..4).contains(&hart) {
+        if Self::valid_hart(hart) {
             self.ihis[hart as usize].read().flag
         } else {
             false
@@ -222,20 +232,22 @@ impl InterruptController {
     }
 
     pub fn clear_ihi(&self, hart: u32) {
-        if (0..4).contains(&hart) {
-            self.update_and_propogate_ihi(&self.ihis[hart as usize], hart, |ihi| ihi.flag = false);
+        if Self::valid_hart(hart) {
+            // the flag stays raised while the hart's mailbox still holds messages
+            let mailbox_pending = MAILBOX.has_messages(hart);
+            self.update_and_propogate_ihi(&self.ihis[hart as usize], hart, |ihi| ihi.flag = mailbox_pending);
         }
     }
 
     pub fn trigger_ihi(&self, hart: u32) {
         println!("trigger_ihi {}", hart);
-        if (0..4).contains(&hart) {
+        if Self::valid_hart(hart) {
             self.update_and_propogate_ihi(&self.ihis[hart as usize], hart, |ihi| ihi.flag = true);
         }
     }
 
     pub fn mip(&self, hart: u32) -> u32 {
-        if (0..4).contains(&hart) {
+        if Self::valid_hart(hart) {
             self.mips[hart as usize].load(atomic::Ordering::Acquire)
         } else {
             0
@@ -243,35 +255,41 @@ impl InterruptController {
     }
 }
 
+// per-hart registers for harts 0-3 live in blocks 0xFC-0xFF, and for harts 4-7 in blocks 0xF8-0xFB
+fn per_hart_register(interrupt: u32, register: u32) -> (u32, u32) {
+    let hart = (register >> 2) + if (interrupt & 4) == 0 { 4 } else { 0 };
+    (interrupt | 4, hart)
+}
+
 pub fn interrupt_controller_write_u32(offset: u32, value: u32) -> WriteResult {
     let register = offset & 0xF;
     let interrupt = offset >> 4;
     match (interrupt, register) {
-        (0xFD, register) => {
-            let hart = register >> 2;
-            INTERRUPT_CONTROLLER.set_ihi_enabled(hart, value != 0);
-            WriteResult::Ok
-        },
-        (0xFE, register) => {
-            let hart = register >> 2;
-            if (value & 1) != 0 {
-                INTERRUPT_CONTROLLER.clear_ihi(hart);
-            }
-            WriteResult::Ok
-        },
-        (0xFF, register) => {
-            let hart = register >> 2;
-            if (value & 1) != 0 {
-                INTERRUPT_CONTROLLER.trigger_ihi(hart);
-            }
-            WriteResult::Ok
+        (0xF8 ..= 0xFF, register) => match per_hart_register(interrupt, register) {
+            (0xFD, hart) => {
+                INTERRUPT_CONTROLLER.set_ihi_enabled(hart, value != 0);
+                WriteResult::Ok
+            },
+            (0xFE, hart) => {
+                if (value & 1) != 0 {
+                    INTERRUPT_CONTROLLER.clear_ihi(hart);
+                }
+                WriteResult::Ok
+            },
+            (0xFF, hart) => {
+                if (value & 1) != 0 {
+                    INTERRUPT_CONTROLLER.trigger_ihi(hart);
+                }
+                WriteResult::Ok
+            },
+            _ => WriteResult::ReadOnly,
         },
         (interrupt, 0) => {
             INTERRUPT_CONTROLLER.set_interrupt_enabled(interrupt, value != 0);
             WriteResult::Ok
         },
         (interrupt, 4) => {
-            INTERRUPT_CONTROLLER.set_interrupt_hart(interrupt, value & 3);
+            INTERRUPT_CONTROLLER.set_interrupt_hart(interrupt, value);
             WriteResult::Ok
         },
         (interrupt, 8) => {
@@ -296,17 +314,10 @@ pub fn interrupt_controller_read_u32(offset: u32) -> ReadResult<u32> {
     let register = offset & 0x0F;
     let interrupt = offset >> 4;
     match (interrupt, register) {
-        (0xFC, register) => {
-            let hart = register >> 2;
-            ReadResult::Ok(INTERRUPT_CONTROLLER.pending_interrupt_number(hart).unwrap_or(0xFFFF_FFFF))
-        }
-        (0xFD, register) => {
-            let hart = register >> 2;
-            ReadResult::Ok(if INTERRUPT_CONTROLLER.get_ihi_enabled(hart) { 1 } else { 0 })
-        },
-        (0xFE | 0xFF, register) => {
-            let hart = register >> 2;
-            ReadResult::Ok(if INTERRUPT_CONTROLLER.get_ihi_flag(hart) { 1 } else { 0 })
+        (0xF8 ..= 0xFF, register) => match per_hart_register(interrupt, register) {
+            (0xFC, hart) => ReadResult::Ok(INTERRUPT_CONTROLLER.pending_interrupt_number(hart).unwrap_or(0xFFFF_FFFF)),
+            (0xFD, hart) => ReadResult::Ok(if INTERRUPT_CONTROLLER.get_ihi_enabled(hart) { 1 } else { 0 }),
+            (_, hart) => ReadResult::Ok(if INTERRUPT_CONTROLLER.get_ihi_flag(hart) { 1 } else { 0 }),
         },
         (interrupt, 0) => ReadResult::Ok(if INTERRUPT_CONTROLLER.get_interrupt_enabled(interrupt) { 1 } else { 0 }),
         (interrupt, 4) => ReadResult::Ok(INTERRUPT_CONTROLLER.get_interrupt_hart(interrupt)),
@@ -1,8 +1,12 @@
-use std::{ops::RangeInclusive, sync::{atomic::{self, AtomicU32}, Arc}};
+#[cfg(debug_assertions)]
+use crate::semihosting::Semihosting;
+use std::{io, ops::RangeInclusive, sync::{atomic::{self, AtomicI32, AtomicU32}, Arc}};
 
-use parking_lot::Mutex;
+use crate::{config::MachineConfig, rom::ROM_MAX_SIZE, debug::*, entropy::*, gpu::*, mailbox::*, machine_info::*, rtc::*, hart_clock::{HART_CLOCK_MASTER, clock_read_u16, clock_read_u32, clock_read_u8, clock_write_u16, clock_write_u32, clock_write_u8}, input::*, interrupt_controller::{interrupt_controller_read_u16, interrupt_controller_read_u32, interrupt_controller_read_u8, interrupt_controller_write_u16, interrupt_controller_write_u32, interrupt_controller_write_u8}, spu::{spu_init, spu_read_u16, spu_read_u32, spu_read_u8, spu_write_u16, spu_write_u32, spu_write_u8, SpuStreamHandle}, ui::main_window::{self, MainWindow}};
 
-use crate::{debug::*, gpu::*, hart_clock::{clock_read_u16, clock_read_u32, clock_read_u8, clock_write_u16, clock_write_u32, clock_write_u8}, input::*, interrupt_controller::{interrupt_controller_read_u16, interrupt_controller_read_u32, interrupt_controller_read_u8, interrupt_controller_write_u16, interrupt_controller_write_u32, interrupt_controller_write_u8}, spu::{spu_init, spu_read_u16, spu_read_u32, spu_read_u8, spu_write_u16, spu_write_u32, spu_write_u8, SpuStreamHandle}, ui::main_window::{self, MainWindow}};
+// building a machine resets the global devices, tests that use them directly hold this
+#[cfg(test)]
+pub(crate) static DEVICE_TEST_LOCK: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
 
 pub enum WriteResult {
     Ok,
@@ -73,10 +77,32 @@ pub enum AtomicOperationResult {
     InvalidAddress,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AtomicOperation {
+    Swap,
+    Add,
+    And,
+    Or,
+    Xor,
+    Max,
+    Min,
+    MaxU,
+    MinU,
+}
+
+const RESERVATION_NONE: u32 = 0xFFFF_FFFF;
+const RESERVATION_GRANULE_SHIFT: u32 = 6;
+
 pub struct Machine {
     pub ram: *mut u8,
     pub rom: *mut u8,
-    pub atomic_reservations: Box<[Mutex<u32>]>,
+    pub rom_size: u32,
+    pub config: MachineConfig,
+    #[cfg(debug_assertions)]
+    pub semihosting: Option<Semihosting>,
+    reservations: [AtomicU32; MachineConfig::MAX_HART_COUNT],
+    reservation_values: [AtomicU32; MachineConfig::MAX_HART_COUNT],
+    active_reservations: AtomicU32,
 }
 
 pub struct MachineMainThread {
@@ -86,10 +112,15 @@ pub struct MachineMainThread {
 /*
 Memory map
 ==========
-0x0000_0000 .. 0x07FF_FFFF = RAM
+0x0000_0000 .. 0x07FF_FFFF = RAM (only the configured ram size is mapped)
 ...
 0x8000_0000 .. 0x8000_0010 = Debug Serial Port
-0x8001_0000 .. 0x8001_0001 = GPU
+0x8001_0000 .. 0x8001_000B = GPU
+...
+0x8006_0000 .. 0x8006_0013 = Machine Info
+0x8007_0000 .. 0x8007_0027 = Real Time Clock
+0x8008_0000 .. 0x8008_0007 = Entropy
+0x8009_0000 .. 0x8009_017F = Mailbox and Semaphores
 ...
 0xF800_0000 .. 0xFFFF_FFFF = ROM
  */
@@ -103,43 +134,68 @@ unsafe impl Sync for Machine {}
 impl Machine {
     pub const ADDRESS_RANGE_RAM: RangeInclusive<u32> = 0x0000_0000 ..= 0x07FF_FFFF;
     pub const ADDRESS_RANGE_DBG: RangeInclusive<u32> = 0x8000_0000 ..= 0x8000_0013;
-    pub const ADDRESS_RANGE_GPU: RangeInclusive<u32> = 0x8001_0000 ..= 0x8001_0003;
-    pub const ADDRESS_RANGE_CLK: RangeInclusive<u32> = 0x8002_0000 ..= 0x8002_000F;
+    pub const ADDRESS_RANGE_GPU: RangeInclusive<u32> = 0x8001_0000 ..= 0x8001_000B;
+    pub const ADDRESS_RANGE_CLK: RangeInclusive<u32> = 0x8002_0000 ..= 0x8002_001F;
     pub const ADDRESS_RANGE_INT: RangeInclusive<u32> = 0x8003_0000 ..= 0x8003_0FFF;
     pub const ADDRESS_RANGE_SPU: RangeInclusive<u32> = 0x8004_0000 ..= 0x8004_0010;
     pub const ADDRESS_RANGE_INP: RangeInclusive<u32> = 0x8005_0000 ..= 0x8005_0030;
+    pub const ADDRESS_RANGE_INF: RangeInclusive<u32> = 0x8006_0000 ..= 0x8006_0013;
+    pub const ADDRESS_RANGE_RTC: RangeInclusive<u32> = 0x8007_0000 ..= 0x8007_0027;
+    pub const ADDRESS_RANGE_RNG: RangeInclusive<u32> = 0x8008_0000 ..= 0x8008_0007;
+    pub const ADDRESS_RANGE_MBX: RangeInclusive<u32> = 0x8009_0000 ..= 0x8009_017F;
     pub const ADDRESS_RANGE_ROM: RangeInclusive<u32> = 0xF800_0000 ..= 0xFFFF_FFFF;
 
-    pub fn new(rom_data: &[u8], main_window: MainWindow) -> (Arc<Self>, MachineMainThread) {
-        let ram = Box::leak(vec![0u8; 0x800_0000].into_boxed_slice()).as_mut_ptr();
-        let rom = Box::leak(vec![0u8; 0x800_0000].into_boxed_slice()).as_mut_ptr();
-        let mut atomic_reservations = Vec::new();
-        (0..0x20_0000).for_each(|_| atomic_reservations.push(Mutex::new(0)));
-        let atomic_reservations = atomic_reservations.into_boxed_slice();
-        unsafe { std::slice::from_raw_parts_mut(rom, rom_data.len().min(0x800_000)).copy_from_slice(rom_data) };
-        let machine = Arc::new(Self {
-            ram,
-            rom,
-            atomic_reservations
-        });
+    pub fn new(rom_data: &[u8], config: &MachineConfig, main_window: MainWindow) -> io::Result<(Arc<Self>, MachineMainThread)> {
+        let machine = Arc::new(Self::new_headless(rom_data, config)?);
         gpu_init(&machine, main_window);
         let spu_stream = spu_init(&machine);
         let machine_main_thread = MachineMainThread {
             spu_stream
         };
-        (machine, machine_main_thread)
+        Ok((machine, machine_main_thread))
+    }
+
+    pub(crate) fn new_headless(rom_data: &[u8], config: &MachineConfig) -> io::Result<Self> {
+        #[cfg(debug_assertions)]
+        let semihosting = config.semihosting_root.as_deref().map(Semihosting::new).transpose()?;
+        // ram is allocated as words so that atomic operations on it are always host-aligned
+        let ram = Box::leak(vec![0u32; (config.ram_size >> 2) as usize].into_boxed_slice()).as_mut_ptr() as *mut u8;
+        let rom = Box::leak(vec![0u8; ROM_MAX_SIZE].into_boxed_slice()).as_mut_ptr();
+        let rom_size = rom_data.len().min(ROM_MAX_SIZE);
+        unsafe { std::slice::from_raw_parts_mut(rom, rom_size).copy_from_slice(&rom_data[..rom_size]) };
+        #[cfg(test)]
+        let _device_lock = DEVICE_TEST_LOCK.lock();
+        HART_CLOCK_MASTER.set_hart_count(config.hart_count);
+        rtc_init(config.deterministic_seed);
+        entropy_init(config.deterministic_seed);
+        mailbox_init();
+        Ok(Self {
+            ram,
+            rom,
+            rom_size: rom_size as u32,
+            config: config.clone(),
+            #[cfg(debug_assertions)]
+            semihosting,
+            reservations: [(); MachineConfig::MAX_HART_COUNT].map(|_| AtomicU32::new(RESERVATION_NONE)),
+            reservation_values: [(); MachineConfig::MAX_HART_COUNT].map(|_| AtomicU32::new(0)),
+            active_reservations: AtomicU32::new(0),
+        })
     }
 
     pub fn read_u8(self: &Arc<Self>, addr: u32) -> ReadResult<u8> {
         unsafe {
             match addr {
-                0x0000_0000 ..= 0x07FF_FFFF => ReadResult::Ok(self.ram_read(addr)),
+                0x0000_0000 ..= 0x07FF_FFFF if addr < self.config.ram_size => ReadResult::Ok(self.ram_read(addr)),
                 0x8000_0000 ..= 0x8000_0013 => debug_read_u8(self, (addr & 0x1F)),
-                0x8001_0000                 => gpu_read_u8(0),
-                0x8002_0000 ..= 0x8002_000F => clock_read_u8(addr & 0x1F),
+                0x8001_0000 ..= 0x8001_000B => gpu_read_u8(addr & 0x0F),
+                0x8002_0000 ..= 0x8002_001F => clock_read_u8(addr & 0x1F),
                 0x8003_0000 ..= 0x8003_0FFF => interrupt_controller_read_u8(addr & 0xFFF),
                 0x8004_0000 ..= 0x8004_001F => spu_read_u8(addr & 0x1F),
                 0x8005_0000 ..= 0x8005_002F => input_read_u8(addr & 0x2F),
+                0x8006_0000 ..= 0x8006_0013 => machine_info_read_u8(self, addr & 0x1F),
+                0x8007_0000 ..= 0x8007_0027 => rtc_read_u8(addr & 0x3F),
+                0x8008_0000 ..= 0x8008_0007 => entropy_read_u8(addr & 0x07),
+                0x8009_0000 ..= 0x8009_017F => mailbox_read_u8(addr & 0x1FF),
                 0xF800_0000 ..= 0xFFFF_FFFF => ReadResult::Ok(self.rom_read(addr & 0x07FF_FFFF)),
                 _ => ReadResult::InvalidAddress
             }
@@ -149,13 +205,17 @@ impl Machine {
     pub fn read_u16(self: &Arc<Self>, addr: u32) -> ReadResult<u16> {
         unsafe {
             match addr {
-                0x0000_0000 ..= 0x07FF_FFFE => ReadResult::Ok(self.ram_read(addr)),
+                0x0000_0000 ..= 0x07FF_FFFE if addr + 2 <= self.config.ram_size => ReadResult::Ok(self.ram_read(addr)),
                 0x8000_0000 ..= 0x8000_0012 => debug_read_u16(self, (addr & 0x1F)),
-                0x8001_0000                 => gpu_read_u16(0),
-                0x8002_0000 ..= 0x8002_000E => clock_read_u16(addr & 0x1F),
+                0x8001_0000 ..= 0x8001_000A => gpu_read_u16(addr & 0x0F),
+                0x8002_0000 ..= 0x8002_001E => clock_read_u16(addr & 0x1F),
                 0x8003_0000 ..= 0x8003_0FFE => interrupt_controller_read_u16(addr & 0xFFF),
                 0x8004_0000 ..= 0x8004_001E => spu_read_u16(addr & 0x1F),
                 0x8005_0000 ..= 0x8005_002E => input_read_u16(addr & 0x2E),
+                0x8006_0000 ..= 0x8006_0012 => machine_info_read_u16(self, addr & 0x1F),
+                0x8007_0000 ..= 0x8007_0026 => rtc_read_u16(addr & 0x3F),
+                0x8008_0000 ..= 0x8008_0006 => entropy_read_u16(addr & 0x07),
+                0x8009_0000 ..= 0x8009_017E => mailbox_read_u16(addr & 0x1FF),
                 0xF800_0000 ..= 0xFFFF_FFFE => ReadResult::Ok(self.rom_read(addr & 0x07FF_FFFF)),
                 _ => ReadResult::InvalidAddress
             }
@@ -165,13 +225,17 @@ impl Machine {
     pub fn read_u32(self: &Arc<Self>, addr: u32) -> ReadResult<u32> {
         unsafe {
             match addr {
-                0x0000_0000 ..= 0x07FF_FFFC => ReadResult::Ok(self.ram_read(addr)),
+                0x0000_0000 ..= 0x07FF_FFFC if addr + 4 <= self.config.ram_size => ReadResult::Ok(self.ram_read(addr)),
                 0x8000_0000 ..= 0x8000_001F => debug_read_u32(self, (addr & 0x1F)),
-                0x8001_0000                 => gpu_read_u32(0),
-                0x8002_0000 ..= 0x8002_000C => clock_read_u32(addr & 0x0F),
+                0x8001_0000 ..= 0x8001_0008 => gpu_read_u32(addr & 0x0F),
+                0x8002_0000 ..= 0x8002_001C => clock_read_u32(addr & 0x1F),
                 0x8003_0000 ..= 0x8003_0FFC => interrupt_controller_read_u32(addr & 0xFFF),
                 0x8004_0000 ..= 0x8004_001C => spu_read_u32(addr & 0x1F),
                 0x8005_0000 ..= 0x8005_002C => input_read_u32(addr & 0x2F),
+                0x8006_0000 ..= 0x8006_0010 => machine_info_read_u32(self, addr & 0x1F),
+                0x8007_0000 ..= 0x8007_0024 => rtc_read_u32(addr & 0x3F),
+                0x8008_0000 ..= 0x8008_0004 => entropy_read_u32(addr & 0x07),
+                0x8009_0000 ..= 0x8009_017C => mailbox_read_u32(addr & 0x1FF),
                 0xF800_0000 ..= 0xFFFF_FFFC => ReadResult::Ok(self.rom_read(addr & 0x07FF_FFFF)),
                 _ => ReadResult::InvalidAddress
             }
@@ -181,13 +245,17 @@ impl Machine {
     pub fn read_u32_unaligned(self: &Arc<Self>, addr: u32) -> ReadResult<u32> {
         unsafe {
             match addr {
-                0x0000_0000 ..= 0x07FF_FFFC => ReadResult::Ok(self.ram_read_unaligned::<u32>(addr)),
+                0x0000_0000 ..= 0x07FF_FFFC if addr + 4 <= self.config.ram_size => ReadResult::Ok(self.ram_read_unaligned::<u32>(addr)),
                 0x8000_0000 ..= 0x8000_001F => debug_read_u32(self, (addr & 0x1F)),
-                0x8001_0000                 => gpu_read_u32(0),
-                0x8002_0000 ..= 0x8002_000C => clock_read_u32(addr & 0x0F),
+                0x8001_0000 ..= 0x8001_0008 => gpu_read_u32(addr & 0x0F),
+                0x8002_0000 ..= 0x8002_001C => clock_read_u32(addr & 0x1F),
                 0x8003_0000 ..= 0x8003_0FFC => interrupt_controller_read_u32(addr & 0xFFF),
                 0x8004_0000 ..= 0x8004_001C => spu_read_u32(addr & 0x1F),
                 0x8005_0000 ..= 0x8005_002C => input_read_u32(addr & 0x2F),
+                0x8006_0000 ..= 0x8006_0010 => machine_info_read_u32(self, addr & 0x1F),
+                0x8007_0000 ..= 0x8007_0024 => rtc_read_u32(addr & 0x3F),
+                0x8008_0000 ..= 0x8008_0004 => entropy_read_u32(addr & 0x07),
+                0x8009_0000 ..= 0x8009_017C => mailbox_read_u32(addr & 0x1FF),
                 0xF800_0000 ..= 0xFFFF_FFFC => ReadResult::Ok(self.rom_read_unaligned::<u32>(addr & 0x07FF_FFFF)),
                 _ => ReadResult::InvalidAddress
             }
@@ -196,8 +264,8 @@ impl Machine {
 
     pub fn read_block(self: &Arc<Self>, addr: u32, data: &mut [u8]) -> ReadResult<()> {
         match addr {
-            0x0000_0000 ..= 0x07FF_FFFF => {
-                if addr.wrapping_add(data.len() as u32) >= 0x0800_0000 {
+            0x0000_0000 ..= 0x07FF_FFFF if addr < self.config.ram_size => {
+                if addr.wrapping_add(data.len() as u32) >= self.config.ram_size {
                     ReadResult::InvalidAddress
                 } else {
                     unsafe { data.copy_from_slice(std::slice::from_raw_parts(self.ram.add(addr as usize) as *const u8, data.len())); }
@@ -216,16 +284,38 @@ impl Machine {
         }
     }
 
+    pub fn write_block(self: &Arc<Self>, addr: u32, data: &[u8]) -> WriteResult {
+        match addr {
+            0x0000_0000 ..= 0x07FF_FFFF if addr < self.config.ram_size => {
+                if addr as usize + data.len() > self.config.ram_size as usize {
+                    WriteResult::InvalidAddress
+                } else {
+                    unsafe { std::slice::from_raw_parts_mut(self.ram.add(addr as usize), data.len()).copy_from_slice(data); }
+                    if !data.is_empty() {
+                        self.invalidate_reservations(addr, data.len() as u32);
+                    }
+                    WriteResult::Ok
+                }
+            },
+            0xF800_0000 ..= 0xFFFF_FFFF => WriteResult::ReadOnly,
+            _ => WriteResult::InvalidAddress
+        }
+    }
+
     pub fn write_u8(self: &Arc<Self>, addr: u32,  value: u8 ) -> WriteResult {
         unsafe {
             match addr {
-                0x0000_0000 ..= 0x07FF_FFFF => self.ram_write(addr, value),
+                0x0000_0000 ..= 0x07FF_FFFF if addr < self.config.ram_size => self.ram_write(addr, value),
                 0x8000_0000 ..= 0x8000_0013 => return debug_write_u8(self, addr & 0x1F, value),
-                0x8001_0000                 => return gpu_write_u8(0, value),
-                0x8002_0000 ..= 0x8002_000F => return clock_write_u8(addr & 0x1F, value),
+                0x8001_0000 ..= 0x8001_000B => return gpu_write_u8(addr & 0x0F, value),
+                0x8002_0000 ..= 0x8002_001F => return clock_write_u8(addr & 0x1F, value),
                 0x8003_0000 ..= 0x8003_0FFF => return interrupt_controller_write_u8(addr & 0xFFF, value),
                 0x8004_0000 ..= 0x8004_001F => return spu_write_u8(addr & 0x1F, value),
                 0x8005_0000 ..= 0x8005_002F => return input_write_u8(addr & 0x3F, value),
+                0x8006_0000 ..= 0x8006_0013 => return machine_info_write_u8(self, addr & 0x1F, value),
+                0x8007_0000 ..= 0x8007_0027 => return rtc_write_u8(addr & 0x3F, value),
+                0x8008_0000 ..= 0x8008_0007 => return entropy_write_u8(addr & 0x07, value),
+                0x8009_0000 ..= 0x8009_017F => return mailbox_write_u8(addr & 0x1FF, value),
                 0xF800_0000 ..= 0xFFFF_FFFF => return WriteResult::ReadOnly,
                 _ => return WriteResult::InvalidAddress,
             }
@@ -236,13 +326,17 @@ impl Machine {
     pub fn write_u16(self: &Arc<Self>, addr: u32, value: u16) -> WriteResult {
         unsafe { 
             match addr {
-                0x0000_0000 ..= 0x07FF_FFFE => self.ram_write(addr, value),
+                0x0000_0000 ..= 0x07FF_FFFE if addr + 2 <= self.config.ram_size => self.ram_write(addr, value),
                 0x8000_0000 ..= 0x8000_0012 => return debug_write_u16(self, addr & 0x1F, value),
-                0x8001_0000                 => return gpu_write_u16(0, value),
-                0x8002_0000 ..= 0x8002_000E => return clock_write_u16(addr & 0x1F, value),
+                0x8001_0000 ..= 0x8001_000A => return gpu_write_u16(addr & 0x0F, value),
+                0x8002_0000 ..= 0x8002_001E => return clock_write_u16(addr & 0x1F, value),
                 0x8003_0000 ..= 0x8003_0FFE => return interrupt_controller_write_u16(addr & 0xFFF, value),
                 0x8004_0000 ..= 0x8004_001E => return spu_write_u16(addr & 0x1F, value),
                 0x8005_0000 ..= 0x8005_002E => return input_write_u16(addr & 0x3F, value),
+                0x8006_0000 ..= 0x8006_0012 => return machine_info_write_u16(self, addr & 0x1F, value),
+                0x8007_0000 ..= 0x8007_0026 => return rtc_write_u16(addr & 0x3F, value),
+                0x8008_0000 ..= 0x8008_0006 => return entropy_write_u16(addr & 0x07, value),
+                0x8009_0000 ..= 0x8009_017E => return mailbox_write_u16(addr & 0x1FF, value),
                 0xF800_0000 ..= 0xFFFF_FFFE => return WriteResult::ReadOnly,
                 _ => return WriteResult::InvalidAddress
             }
@@ -253,13 +347,17 @@ impl Machine {
     pub fn write_u32(self: &Arc<Self>, addr: u32, value: u32) -> WriteResult {
         unsafe {
             match addr {
-                0x0000_0000 ..= 0x07FF_FFFC => self.ram_write(addr, value),
+                0x0000_0000 ..= 0x07FF_FFFC if addr + 4 <= self.config.ram_size => self.ram_write(addr, value),
                 0x8000_0000 ..= 0x8000_0013 => return debug_write_u32(self, addr & 0x1F, value),
-                0x8001_0000                 => return gpu_write_u32(0, value),
-                0x8002_0000 ..= 0x8002_000C => return clock_write_u32(addr & 0x0F, value),
+                0x8001_0000 ..= 0x8001_0008 => return gpu_write_u32(addr & 0x0F, value),
+                0x8002_0000 ..= 0x8002_001C => return clock_write_u32(addr & 0x1F, value),
                 0x8003_0000 ..= 0x8003_0FFC => return interrupt_controller_write_u32(addr & 0xFFF, value),
                 0x8004_0000 ..= 0x8004_001C => return spu_write_u32(addr & 0x1F, value),
                 0x8005_0000 ..= 0x8005_002C => return input_write_u32(addr & 0x3F, value),
+                0x8006_0000 ..= 0x8006_0010 => return machine_info_write_u32(self, addr & 0x1F, value),
+                0x8007_0000 ..= 0x8007_0024 => return rtc_write_u32(addr & 0x3F, value),
+                0x8008_0000 ..= 0x8008_0004 => return entropy_write_u32(addr & 0x07, value),
+                0x8009_0000 ..= 0x8009_017C => return mailbox_write_u32(addr & 0x1FF, value),
                 0xF800_0000 ..= 0xFFFF_FFFC => return WriteResult::ReadOnly,
                 _ => return WriteResult::InvalidAddress
             }
@@ -270,13 +368,17 @@ impl Machine {
     pub fn write_u32_unaligned(self: &Arc<Self>, addr: u32, value: u32) -> WriteResult {
         unsafe {
             match addr {
-                0x0000_0000 ..= 0x07FF_FFFC => self.ram_write_unaligned(addr, value),
+                0x0000_0000 ..= 0x07FF_FFFC if addr + 4 <= self.config.ram_size => self.ram_write_unaligned(addr, value),
                 0x8000_0000 ..= 0x8000_0013 => return debug_write_u32(self, addr & 0x1F, value),
-                0x8001_0000                 => return gpu_write_u32(0, value),
-                0x8002_0000 ..= 0x8002_000C => return clock_write_u32(addr & 0x1F, value),
+                0x8001_0000 ..= 0x8001_0008 => return gpu_write_u32(addr & 0x0F, value),
+                0x8002_0000 ..= 0x8002_001C => return clock_write_u32(addr & 0x1F, value),
                 0x8003_0000 ..= 0x8003_0FFC => return interrupt_controller_write_u32(addr & 0xFFF, value),
                 0x8004_0000 ..= 0x8004_001C => return spu_write_u32(addr & 0x1F, value),
                 0x8005_0000 ..= 0x8005_002C => return input_write_u32(addr & 0x3F, value),
+                0x8006_0000 ..= 0x8006_0010 => return machine_info_write_u32(self, addr & 0x1F, value),
+                0x8007_0000 ..= 0x8007_0024 => return rtc_write_u32(addr & 0x3F, value),
+                0x8008_0000 ..= 0x8008_0004 => return entropy_write_u32(addr & 0x07, value),
+                0x8009_0000 ..= 0x8009_017C => return mailbox_write_u32(addr & 0x1FF, value),
                 0xF800_0000 ..= 0xFFFF_FFFC => return WriteResult::ReadOnly,
                 _ => return WriteResult::InvalidAddress
             }
@@ -284,16 +386,22 @@ impl Machine {
         WriteResult::Ok
     }
 
-    pub fn load_reserve(self: &Arc<Self>, addr: u32, hart_id: u32) -> AtomicLoadResult {
+    pub fn load_reserve(self: &Arc<Self>, addr: u32, hart_id: u32, ordering: atomic::Ordering) -> AtomicLoadResult {
         match addr {
-            0x0000_0000 ..= 0x07FF_FFFC => {
+            0x0000_0000 ..= 0x07FF_FFFC if addr + 4 <= self.config.ram_size => {
                 if addr & 3 != 0 {
                     AtomicLoadResult::AlignmentError
                 } else {
-                    let atomic_line = addr >> 6;
-                    let mut reservation = self.atomic_reservations[atomic_line as usize].lock();
-                    *reservation = hart_id;
-                    let value = self.read_u32(addr).unwrap();
+                    let load_ordering = match ordering {
+                        atomic::Ordering::Release => atomic::Ordering::Relaxed,
+                        atomic::Ordering::AcqRel  => atomic::Ordering::Acquire,
+                        ordering => ordering,
+                    };
+                    if self.reservations[hart_id as usize].swap(addr, atomic::Ordering::SeqCst) == RESERVATION_NONE {
+                        self.active_reservations.fetch_add(1, atomic::Ordering::SeqCst);
+                    }
+                    let value = self.ram_atomic(addr).load(load_ordering);
+                    self.reservation_values[hart_id as usize].store(value, atomic::Ordering::Relaxed);
                     AtomicLoadResult::Ok(value)
                 }
             },
@@ -301,22 +409,26 @@ impl Machine {
         }
     }
 
-    pub fn store_conditional(self: &Arc<Self>, addr: u32, value: u32, hart_id: u32) -> AtomicStoreConditionalResult {
+    pub fn store_conditional(self: &Arc<Self>, addr: u32, value: u32, hart_id: u32, ordering: atomic::Ordering) -> AtomicStoreConditionalResult {
         match addr {
-            0x0000_0000 ..= 0x07FF_FFFC => {
+            0x0000_0000 ..= 0x07FF_FFFC if addr + 4 <= self.config.ram_size => {
                 if addr & 3 != 0 {
                     AtomicStoreConditionalResult::AlignmentError
                 } else {
-                    let atomic_line = addr >> 6;
-                    let reservation = self.atomic_reservations[atomic_line as usize].lock();
-                    if *reservation == hart_id {
-                        if self.write_u32(addr, value).is_ok() {
+                    // an sc always consumes the reservation, whether or not it succeeds
+                    if self.reservations[hart_id as usize].compare_exchange(addr, RESERVATION_NONE, atomic::Ordering::SeqCst, atomic::Ordering::SeqCst).is_err() {
+                        self.clear_reservation(hart_id);
+                        return AtomicStoreConditionalResult::ReservationExpired;
+                    }
+                    self.active_reservations.fetch_sub(1, atomic::Ordering::SeqCst);
+                    // the compare-exchange against the reserved value catches stores which race with the reservation check
+                    let reserved_value = self.reservation_values[hart_id as usize].load(atomic::Ordering::Relaxed);
+                    match self.ram_atomic(addr).compare_exchange(reserved_value, value, ordering, atomic::Ordering::Relaxed) {
+                        Ok(_) => {
+                            self.invalidate_reservations(addr, 4);
                             AtomicStoreConditionalResult::Ok
-                        } else {
-                            AtomicStoreConditionalResult::InvalidAddress
-                        }
-                    } else {
-                        AtomicStoreConditionalResult::ReservationExpired
+                        },
+                        Err(_) => AtomicStoreConditionalResult::ReservationExpired,
                     }
                 }
             },
@@ -324,37 +436,74 @@ impl Machine {
         }
     }
 
-    pub fn atomic_operation(self: &Arc<Self>, addr: u32, value_b: u32, op: impl Fn(u32, u32) -> u32) -> AtomicOperationResult {
+    pub fn atomic_operation(self: &Arc<Self>, addr: u32, value_b: u32, op: AtomicOperation, ordering: atomic::Ordering) -> AtomicOperationResult {
         match addr {
-            0x0000_0000 ..= 0x07FF_FFFC => {
+            0x0000_0000 ..= 0x07FF_FFFC if addr + 4 <= self.config.ram_size => {
                 if addr & 3 != 0 {
                     AtomicOperationResult::AlignmentError
                 } else {
-                    let atomic_line = addr >> 6;
-                    let reservation = self.atomic_reservations[atomic_line as usize].lock();
-                    match self.read_u32(addr) {
-                        ReadResult::Ok(value) => {
-                            let modified = op(value, value_b);
-                            match self.write_u32(addr, modified) {
-                                WriteResult::Ok => AtomicOperationResult::Ok(value),
-                                WriteResult::ReadOnly |
-                                WriteResult::InvalidAddress => AtomicOperationResult::InvalidAddress,
-                            }
-                        },
-                        ReadResult::InvalidAddress => AtomicOperationResult::InvalidAddress,
-                    }
+                    let word = self.ram_atomic(addr);
+                    let previous = match op {
+                        AtomicOperation::Swap => word.swap(value_b, ordering),
+                        AtomicOperation::Add  => word.fetch_add(value_b, ordering),
+                        AtomicOperation::And  => word.fetch_and(value_b, ordering),
+                        AtomicOperation::Or   => word.fetch_or(value_b, ordering),
+                        AtomicOperation::Xor  => word.fetch_xor(value_b, ordering),
+                        AtomicOperation::Max  => self.ram_atomic_signed(addr).fetch_max(value_b as i32, ordering) as u32,
+                        AtomicOperation::Min  => self.ram_atomic_signed(addr).fetch_min(value_b as i32, ordering) as u32,
+                        AtomicOperation::MaxU => word.fetch_max(value_b, ordering),
+                        AtomicOperation::MinU => word.fetch_min(value_b, ordering),
+                    };
+                    self.invalidate_reservations(addr, 4);
+                    AtomicOperationResult::Ok(previous)
                 }
             },
             _ => AtomicOperationResult::InvalidAddress,
         }
     }
 
+    fn clear_reservation(&self, hart_id: u32) {
+        let reservation = &self.reservations[hart_id as usize];
+        let reserved_addr = reservation.load(atomic::Ordering::SeqCst);
+        if reserved_addr != RESERVATION_NONE && reservation.compare_exchange(reserved_addr, RESERVATION_NONE, atomic::Ordering::SeqCst, atomic::Ordering::SeqCst).is_ok() {
+            self.active_reservations.fetch_sub(1, atomic::Ordering::SeqCst);
+        }
+    }
+
+    fn invalidate_reservations(&self, offset: u32, size: u32) {
+        if self.active_reservations.load(atomic::Ordering::SeqCst) == 0 {
+            return;
+        }
+        let first_granule = offset >> RESERVATION_GRANULE_SHIFT;
+        let last_granule = (offset + size - 1) >> RESERVATION_GRANULE_SHIFT;
+        for reservation in self.reservations.iter() {
+            let reserved_addr = reservation.load(atomic::Ordering::SeqCst);
+            if reserved_addr == RESERVATION_NONE {
+                continue;
+            }
+            let reserved_granule = reserved_addr >> RESERVATION_GRANULE_SHIFT;
+            if (first_granule..=last_granule).contains(&reserved_granule) && reservation.compare_exchange(reserved_addr, RESERVATION_NONE, atomic::Ordering::SeqCst, atomic::Ordering::SeqCst).is_ok() {
+                self.active_reservations.fetch_sub(1, atomic::Ordering::SeqCst);
+            }
+        }
+    }
+
+    fn ram_atomic(&self, offset: u32) -> &AtomicU32 {
+        unsafe { AtomicU32::from_ptr(self.ram.add(offset as usize) as *mut u32) }
+    }
+
+    fn ram_atomic_signed(&self, offset: u32) -> &AtomicI32 {
+        unsafe { AtomicI32::from_ptr(self.ram.add(offset as usize) as *mut i32) }
+    }
+
     fn ram_write<T>(self: &Arc<Self>, offset: u32, value: T) {
         unsafe { *(self.ram.add(offset as usize) as *mut T) = value; }
+        self.invalidate_reservations(offset, std::mem::size_of::<T>() as u32);
     }
 
     fn ram_write_unaligned<T>(self: &Arc<Self>, offset: u32, value: T) {
         unsafe { (self.ram.add(offset as usize) as *mut T).write_unaligned(value) }
+        self.invalidate_reservations(offset, std::mem::size_of::<T>() as u32);
     }
 
     fn ram_read<T: Copy>(self: &Arc<Self>, offset: u32) -> T {
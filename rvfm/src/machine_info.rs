@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use crate::{machine::{WriteResult, Machine, ReadResult}, hart_clock::HART_CYCLES_PER_FRAME};
+
+/*
+Machine info registers (read-only)
+==================================
+0x00 = hart count
+0x04 = ram size in bytes
+0x08 = rom image size in bytes
+0x0C = hart cycles per frame
+0x10 = gpu vram size in bytes
+ */
+
+pub fn machine_info_read_u32(machine: &Arc<Machine>, offset: u32) -> ReadResult<u32> {
+    match offset {
+        0x00 => ReadResult::Ok(machine.config.hart_count as u32),
+        0x04 => ReadResult::Ok(machine.config.ram_size),
+        0x08 => ReadResult::Ok(machine.rom_size),
+        0x0C => ReadResult::Ok(HART_CYCLES_PER_FRAME as u32),
+        0x10 => ReadResult::Ok(machine.config.vram_size),
+        _ => ReadResult::InvalidAddress
+    }
+}
+
+pub fn machine_info_read_u16(machine: &Arc<Machine>, offset: u32) -> ReadResult<u16> {
+    machine_info_read_u32(machine, offset).map(|x| x as u16)
+}
+
+pub fn machine_info_read_u8(machine: &Arc<Machine>, offset: u32) -> ReadResult<u8> {
+    machine_info_read_u32(machine, offset).map(|x| x as u8)
+}
+
+pub fn machine_info_write_u32(_machine: &Arc<Machine>, offset: u32, _value: u32) -> WriteResult {
+    match offset {
+        0x00 | 0x04 | 0x08 | 0x0C | 0x10 => WriteResult::ReadOnly,
+        _ => WriteResult::InvalidAddress
+    }
+}
+
+pub fn machine_info_write_u16(machine: &Arc<Machine>, offset: u32, value: u16) -> WriteResult {
+    machine_info_write_u32(machine, offset, value as u32)
+}
+
+pub fn machine_info_write_u8(machine: &Arc<Machine>, offset: u32, value: u8) -> WriteResult {
+    machine_info_write_u32(machine, offset, value as u32)
+}
@@ -0,0 +1,259 @@
+use std::{collections::VecDeque, sync::atomic::{AtomicBool, AtomicU32, Ordering}};
+
+use parking_lot::Mutex;
+use static_init::dynamic;
+
+use crate::{machine::{WriteResult, ReadResult}, hart::current_hart, hart_clock::HART_CLOCK_MASTER, interrupt_controller::INTERRUPT_CONTROLLER, config::MachineConfig};
+
+/*
+Mailbox registers
+=================
+Each hart n has a block of registers at 0x10 * n:
+0x00 = send (write-only), pushes a message onto hart n's queue and raises hart n's inter-hart interrupt
+0x04 = receive (read-only), pops the oldest message from hart n's queue, 0 if the queue is empty
+0x08 = message count (read-only)
+0x0C = status (bit 0 = queue full, bit 1 = a message was dropped because the queue was full, write 1 to bit 1 to clear)
+
+The inter-hart interrupt of a hart stays raised while its queue is non-empty.
+
+0x80 = send result (read-only), 1 if the last send made by the reading hart was queued, 0 if it was dropped
+
+Semaphore registers
+===================
+0x100 + 4 * n = semaphore n (0 - 31)
+    read: test-and-set, returns the previous value (0 = acquired) and sets the semaphore
+    write: 0 releases the semaphore, any other value sets it
+ */
+
+pub const MAILBOX_QUEUE_DEPTH: usize = 16;
+pub const SEMAPHORE_COUNT: usize = 32;
+
+const STATUS_FULL: u32 = 1;
+const STATUS_OVERFLOW: u32 = 2;
+
+struct MailboxQueue {
+    messages: VecDeque<u32>,
+    overflow: bool,
+}
+
+pub struct Mailbox {
+    queues: [Mutex<MailboxQueue>; MachineConfig::MAX_HART_COUNT],
+    semaphores: [AtomicU32; SEMAPHORE_COUNT],
+    send_results: [AtomicBool; MachineConfig::MAX_HART_COUNT],
+}
+
+#[dynamic]
+pub static MAILBOX: Mailbox = Mailbox::new();
+
+impl Mailbox {
+    pub fn new() -> Self {
+        Self {
+            queues: [(); MachineConfig::MAX_HART_COUNT].map(|_| Mutex::new(MailboxQueue { messages: VecDeque::with_capacity(MAILBOX_QUEUE_DEPTH), overflow: false })),
+            semaphores: [(); SEMAPHORE_COUNT].map(|_| AtomicU32::new(0)),
+            send_results: [(); MachineConfig::MAX_HART_COUNT].map(|_| AtomicBool::new(false)),
+        }
+    }
+
+    fn valid_hart(hart: u32) -> bool {
+        (hart as usize) < HART_CLOCK_MASTER.hart_count()
+    }
+
+    pub fn reset(&self) {
+        for queue in self.queues.iter() {
+            let mut queue = queue.lock();
+            queue.messages.clear();
+            queue.overflow = false;
+        }
+        for semaphore in self.semaphores.iter() {
+            semaphore.store(0, Ordering::Release);
+        }
+        for send_result in self.send_results.iter() {
+            send_result.store(false, Ordering::Release);
+        }
+    }
+
+    // returns whether the message was queued
+    pub fn send(&self, hart: u32, message: u32) -> bool {
+        if !Self::valid_hart(hart) {
+            return false;
+        }
+        {
+            let mut queue = self.queues[hart as usize].lock();
+            if queue.messages.len() >= MAILBOX_QUEUE_DEPTH {
+                queue.overflow = true;
+                return false;
+            }
+            queue.messages.push_back(message);
+        }
+        INTERRUPT_CONTROLLER.trigger_ihi(hart);
+        true
+    }
+
+    fn send_from(&self, sender: u32, hart: u32, message: u32) {
+        let queued = self.send(hart, message);
+        if let Some(send_result) = self.send_results.get(sender as usize) {
+            send_result.store(queued, Ordering::Release);
+        }
+    }
+
+    pub fn send_result(&self, sender: u32) -> bool {
+        self.send_results.get(sender as usize).is_some_and(|send_result| send_result.load(Ordering::Acquire))
+    }
+
+    pub fn receive(&self, hart: u32) -> u32 {
+        if !Self::valid_hart(hart) {
+            return 0;
+        }
+        self.queues[hart as usize].lock().messages.pop_front().unwrap_or(0)
+    }
+
+    pub fn message_count(&self, hart: u32) -> u32 {
+        if !Self::valid_hart(hart) {
+            return 0;
+        }
+        self.queues[hart as usize].lock().messages.len() as u32
+    }
+
+    pub fn has_messages(&self, hart: u32) -> bool {
+        self.message_count(hart) != 0
+    }
+
+    pub fn status(&self, hart: u32) -> u32 {
+        if !Self::valid_hart(hart) {
+            return 0;
+        }
+        let queue = self.queues[hart as usize].lock();
+        (if queue.messages.len() >= MAILBOX_QUEUE_DEPTH { STATUS_FULL } else { 0 }) |
+        (if queue.overflow { STATUS_OVERFLOW } else { 0 })
+    }
+
+    pub fn clear_overflow(&self, hart: u32) {
+        if Self::valid_hart(hart) {
+            self.queues[hart as usize].lock().overflow = false;
+        }
+    }
+
+    pub fn test_and_set_semaphore(&self, semaphore: usize) -> u32 {
+        self.semaphores[semaphore].swap(1, Ordering::AcqRel)
+    }
+
+    pub fn set_semaphore(&self, semaphore: usize, value: bool) {
+        self.semaphores[semaphore].store(if value { 1 } else { 0 }, Ordering::Release);
+    }
+}
+
+pub fn mailbox_init() {
+    MAILBOX.reset();
+}
+
+pub fn mailbox_read_u32(offset: u32) -> ReadResult<u32> {
+    match offset {
+        0x000 ..= 0x07F => {
+            let hart = offset >> 4;
+            match offset & 0xF {
+                0x4 => ReadResult::Ok(MAILBOX.receive(hart)),
+                0x8 => ReadResult::Ok(MAILBOX.message_count(hart)),
+                0xC => ReadResult::Ok(MAILBOX.status(hart)),
+                _ => ReadResult::InvalidAddress
+            }
+        },
+        0x080 => ReadResult::Ok(MAILBOX.send_result(current_hart()) as u32),
+        0x100 ..= 0x17F => ReadResult::Ok(MAILBOX.test_and_set_semaphore(((offset - 0x100) >> 2) as usize)),
+        _ => ReadResult::InvalidAddress
+    }
+}
+
+pub fn mailbox_read_u16(offset: u32) -> ReadResult<u16> {
+    mailbox_read_u32(offset).map(|x| x as u16)
+}
+
+pub fn mailbox_read_u8(offset: u32) -> ReadResult<u8> {
+    mailbox_read_u32(offset).map(|x| x as u8)
+}
+
+pub fn mailbox_write_u32(offset: u32, value: u32) -> WriteResult {
+    match offset {
+        0x000 ..= 0x07F => {
+            let hart = offset >> 4;
+            match offset & 0xF {
+                0x0 => {
+                    MAILBOX.send_from(current_hart(), hart, value);
+                    WriteResult::Ok
+                },
+                0x4 | 0x8 => WriteResult::ReadOnly,
+                0xC => {
+                    if (value & STATUS_OVERFLOW) != 0 {
+                        MAILBOX.clear_overflow(hart);
+                    }
+                    WriteResult::Ok
+                },
+                _ => WriteResult::InvalidAddress
+            }
+        },
+        0x080 => WriteResult::ReadOnly,
+        0x100 ..= 0x17F => {
+            MAILBOX.set_semaphore(((offset - 0x100) >> 2) as usize, value != 0);
+            WriteResult::Ok
+        },
+        _ => WriteResult::InvalidAddress
+    }
+}
+
+pub fn mailbox_write_u16(offset: u32, value: u16) -> WriteResult {
+    mailbox_write_u32(offset, value as u32)
+}
+
+pub fn mailbox_write_u8(offset: u32, value: u8) -> WriteResult {
+    mailbox_write_u32(offset, value as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::machine::DEVICE_TEST_LOCK;
+
+    #[test]
+    fn queue_registers_and_ihi() {
+        let _lock = DEVICE_TEST_LOCK.lock();
+        MAILBOX.reset();
+        INTERRUPT_CONTROLLER.clear_ihi(0);
+        assert!(!INTERRUPT_CONTROLLER.get_ihi_flag(0));
+
+        for message in 0..(MAILBOX_QUEUE_DEPTH as u32) {
+            assert!(mailbox_write_u32(0x00, message + 100).is_ok());
+            assert_eq!(mailbox_read_u32(0x80).to_opt(), Some(1));
+        }
+        assert!(mailbox_write_u32(0x00, 200).is_ok());
+        assert_eq!(mailbox_read_u32(0x80).to_opt(), Some(0));
+        assert!(INTERRUPT_CONTROLLER.get_ihi_flag(0));
+        assert_eq!(mailbox_read_u32(0x08).to_opt(), Some(MAILBOX_QUEUE_DEPTH as u32));
+        assert_eq!(mailbox_read_u32(0x0C).to_opt(), Some(STATUS_FULL | STATUS_OVERFLOW));
+        assert!(mailbox_write_u32(0x0C, STATUS_OVERFLOW).is_ok());
+        assert_eq!(mailbox_read_u32(0x0C).to_opt(), Some(STATUS_FULL));
+        assert!(matches!(mailbox_write_u32(0x04, 0), WriteResult::ReadOnly));
+
+        // clearing the interrupt leaves it raised until the queue is drained
+        for message in 0..(MAILBOX_QUEUE_DEPTH as u32) {
+            INTERRUPT_CONTROLLER.clear_ihi(0);
+            assert!(INTERRUPT_CONTROLLER.get_ihi_flag(0));
+            assert_eq!(mailbox_read_u32(0x04).to_opt(), Some(message + 100));
+        }
+        INTERRUPT_CONTROLLER.clear_ihi(0);
+        assert!(!INTERRUPT_CONTROLLER.get_ihi_flag(0));
+        assert_eq!(mailbox_read_u32(0x08).to_opt(), Some(0));
+        assert_eq!(mailbox_read_u32(0x04).to_opt(), Some(0));
+    }
+
+    #[test]
+    fn semaphore_registers() {
+        let _lock = DEVICE_TEST_LOCK.lock();
+        MAILBOX.reset();
+        assert_eq!(mailbox_read_u32(0x10C).to_opt(), Some(0));
+        assert_eq!(mailbox_read_u32(0x10C).to_opt(), Some(1));
+        assert_eq!(mailbox_read_u32(0x110).to_opt(), Some(0));
+        assert!(mailbox_write_u32(0x10C, 0).is_ok());
+        assert_eq!(mailbox_read_u32(0x10C).to_opt(), Some(0));
+        assert!(mailbox_write_u32(0x17C, 5).is_ok());
+        assert_eq!(mailbox_read_u32(0x17C).to_opt(), Some(1));
+    }
+}
@@ -1,6 +1,6 @@
 #![feature(new_uninit)]
 
-use config::Config;
+use config::{Config, MachineConfig};
 use hart::{StepState, Hart};
 use machine::Machine;
 
@@ -19,21 +19,82 @@ mod config;
 mod command_list;
 mod pointer_queue;
 mod input;
+mod machine_info;
+mod rtc;
+mod entropy;
+mod mailbox;
+mod rom;
+#[cfg(debug_assertions)]
+mod semihosting;
 
-use run::{run, ROM_START_ADDRESS};
+use rom::{RomImage, ROM_BASE_ADDRESS};
+use run::run;
 use run_debugger::run_debugger;
 use ui::main_window;
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    let config = Config::default();
+    let mut config = Config::default();
+    let mut machine_config = MachineConfig::default();
 
     let mut debug_elf = None;
+    let mut hart_count_given = false;
+    let mut ram_size_given = false;
 
     for i in 1..args.len() - 1 {
         if args[i] == "-d" {
             debug_elf = Some(args[i + 1].clone());
         }
+        if args[i] == "-harts" {
+            hart_count_given = true;
+            machine_config.hart_count = match args[i + 1].parse() {
+                Ok(hart_count) => hart_count,
+                Err(_) => {
+                    println!("Invalid hart count: {}", args[i + 1]);
+                    return;
+                }
+            };
+        }
+        if args[i] == "-seed" {
+            machine_config.deterministic_seed = match args[i + 1].parse::<u64>() {
+                Ok(seed) => Some(seed),
+                Err(_) => {
+                    println!("Invalid seed: {}", args[i + 1]);
+                    return;
+                }
+            };
+        }
+        if args[i] == "-semihost" {
+            machine_config.semihosting_root = Some(args[i + 1].clone().into());
+        }
+        if args[i] == "-vram" {
+            machine_config.vram_size = match args[i + 1].parse::<u32>() {
+                Ok(vram_size_mib) => vram_size_mib.saturating_mul(0x10_0000),
+                Err(_) => {
+                    println!("Invalid vram size: {}", args[i + 1]);
+                    return;
+                }
+            };
+        }
+        if args[i] == "-raster-threads" {
+            machine_config.raster_threads = match args[i + 1].parse() {
+                Ok(raster_threads) => raster_threads,
+                Err(_) => {
+                    println!("Invalid raster thread count: {}", args[i + 1]);
+                    return;
+                }
+            };
+        }
+        if args[i] == "-ram" {
+            ram_size_given = true;
+            machine_config.ram_size = match args[i + 1].parse::<u32>() {
+                Ok(ram_size_mib) => ram_size_mib.saturating_mul(0x10_0000),
+                Err(_) => {
+                    println!("Invalid ram size: {}", args[i + 1]);
+                    return;
+                }
+            };
+        }
     }
     
     if args.len() == 1 {
@@ -42,17 +103,79 @@ fn main() {
         println!("        * -d <rom elf>: Runs the debugger using the given elf file for ");
         println!("            debugging information. It is still necessary to pass the  ");
         println!("            rom binary.");
+        println!("        * -harts <count>: Sets the number of harts in the machine (1-{}).", MachineConfig::MAX_HART_COUNT);
+        println!("            Defaults to {}.", MachineConfig::default().hart_count);
+        println!("        * -ram <MiB>: Sets the size of the machine's ram in MiB (1-{}).", MachineConfig::MAX_RAM_SIZE >> 20);
+        println!("            Defaults to {}.", MachineConfig::default().ram_size >> 20);
+        println!("        * -vram <MiB>: Sets the size of the gpu's texture memory in MiB (1-{}).", MachineConfig::MAX_VRAM_SIZE >> 20);
+        println!("            Defaults to {}.", MachineConfig::DEFAULT_VRAM_SIZE >> 20);
+        println!("        * -raster-threads <count>: Sets how many host threads the gpu shades");
+        println!("            triangles on (1-{}). Defaults to the host's thread count, up to {}.", MachineConfig::MAX_RASTER_THREADS, MachineConfig::DEFAULT_MAX_RASTER_THREADS);
+        println!("        * -seed <seed>: Runs the real time clock and entropy devices in");
+        println!("            deterministic mode, deriving their values from the given seed.");
+        println!("        * -semihost <directory>: Enables semihosting file access, sandboxed to");
+        println!("            the given host directory. Only available in debug builds.");
         println!("    rom:");
-        println!("        The rom file to run as a flat binary. The base address and start");
-        println!("        address of the rom is located at {:08X}", ROM_START_ADDRESS);
+        println!("        The rom file to run. The base address of the rom is located at");
+        println!("        {:08X}. Roms packed with rom_packer carry a header giving their", ROM_BASE_ADDRESS);
+        println!("        title, entry point and required machine configuration. Flat");
+        println!("        binaries without a header start at the base address.");
         return;
     }
 
     let rom_path = args[args.len() - 1].clone();
-    let rom: Vec<u8> = std::fs::read(rom_path.clone()).expect(&format!("Failed to read rom file: {}", rom_path));
+    let rom_file = match std::fs::read(&rom_path) {
+        Ok(rom_file) => rom_file,
+        Err(error) => {
+            println!("Failed to read rom file {}: {}", rom_path, error);
+            return;
+        }
+    };
+    let rom_image = match RomImage::parse(rom_file) {
+        Ok(rom_image) => rom_image,
+        Err(error) => {
+            println!("Invalid rom {}: {}", rom_path, error);
+            return;
+        }
+    };
+
+    machine_config.entry_point = rom_image.entry_point();
+    if let Some(header) = rom_image.header.as_ref() {
+        println!("Loaded rom \"{}\" (entry point {:08X})", header.title, header.entry_point);
+        if !header.title.is_empty() {
+            config.window_title = format!("RVFM - {}", header.title);
+        }
+        if (header.hart_count as usize) > machine_config.hart_count {
+            if hart_count_given {
+                println!("Warning: rom requires {} harts, but the machine is configured with {}", header.hart_count, machine_config.hart_count);
+            } else {
+                machine_config.hart_count = header.hart_count as usize;
+            }
+        }
+        if header.min_ram_size > machine_config.ram_size {
+            if ram_size_given {
+                println!("Warning: rom requires {} bytes of ram, but the machine is configured with {}", header.min_ram_size, machine_config.ram_size);
+            } else {
+                machine_config.ram_size = header.min_ram_size;
+            }
+        }
+    }
+
+    if let Err(error) = machine_config.validate() {
+        println!("Invalid machine configuration: {}", error);
+        return;
+    }
+    let rom = rom_image.data;
     
     ui::main_window::MainWindow::run(&config, move |main_window| {
-        let (machine, machine_main_thread) = Machine::new(&rom[..], main_window.clone());
+        let (machine, machine_main_thread) = match Machine::new(&rom[..], &machine_config, main_window.clone()) {
+            Ok(machine) => machine,
+            Err(error) => {
+                println!("Failed to create machine: {}", error);
+                main_window.exit();
+                return;
+            }
+        };
         drop(rom);
         if let Some(debug_elf) = debug_elf {
             run_debugger(machine.clone(), Some(&debug_elf));
@@ -0,0 +1,20 @@
+// the header format is shared with rom_packer, this is where the machine maps the image
+pub use rom_format::{RomImage, ROM_BASE_ADDRESS, ROM_MAX_SIZE};
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{config::MachineConfig, machine::Machine};
+
+    #[test]
+    fn rom_larger_than_8_mib_is_mapped() {
+        let words = 0x0090_0000u32 / 4;
+        let rom: Vec<u8> = (0..words).flat_map(u32::to_le_bytes).collect();
+        let machine = Arc::new(Machine::new_headless(&rom, &MachineConfig { hart_count: 1, ram_size: 0x0001_0000, ..MachineConfig::default() }).unwrap());
+        assert_eq!(machine.rom_size as usize, rom.len());
+        assert_eq!(machine.read_u32(ROM_BASE_ADDRESS + 0x0080_0000).to_opt(), Some(0x0080_0000 / 4));
+        assert_eq!(machine.read_u32(ROM_BASE_ADDRESS + (words - 1) * 4).to_opt(), Some(words - 1));
+    }
+}
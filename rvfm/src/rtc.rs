@@ -0,0 +1,187 @@
+use std::{cell::RefCell, time::{SystemTime, UNIX_EPOCH}};
+
+use parking_lot::RwLock;
+use static_init::dynamic;
+
+use crate::{machine::{WriteResult, ReadResult}, hart_clock::HART_CLOCK_MASTER};
+
+/*
+Real time clock registers (read-only)
+=====================================
+0x00 = unix time, low word (reading latches all registers for the reading hart)
+0x04 = unix time, high word
+0x08 = year
+0x0C = month (1 - 12)
+0x10 = day of month (1 - 31)
+0x14 = hour (0 - 23)
+0x18 = minute (0 - 59)
+0x1C = second (0 - 59)
+0x20 = day of week (0 = sunday)
+0x24 = deterministic mode (1 if the clock is derived from the machine seed)
+ */
+
+// 2000-01-01 00:00:00 UTC
+const DETERMINISTIC_EPOCH: u64 = 946_684_800;
+const DETERMINISTIC_EPOCH_RANGE: u64 = 100 * 365 * 24 * 60 * 60;
+const FRAMES_PER_SECOND: u64 = 60;
+
+enum RtcSource {
+    Host,
+    Deterministic(u64),
+}
+
+#[derive(Copy, Clone, Default)]
+struct RtcLatch {
+    unix_time: u64,
+    year: u32,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    weekday: u32,
+}
+
+#[dynamic]
+static RTC_SOURCE: RwLock<RtcSource> = RwLock::new(RtcSource::Host);
+
+thread_local! {
+    static RTC_LATCH: RefCell<RtcLatch> = RefCell::new(RtcLatch::default());
+}
+
+pub fn rtc_init(deterministic_seed: Option<u64>) {
+    *RTC_SOURCE.write() = match deterministic_seed {
+        Some(seed) => RtcSource::Deterministic(DETERMINISTIC_EPOCH + seed % DETERMINISTIC_EPOCH_RANGE),
+        None => RtcSource::Host,
+    };
+}
+
+fn rtc_unix_time() -> u64 {
+    match *RTC_SOURCE.read() {
+        RtcSource::Host => SystemTime::now().duration_since(UNIX_EPOCH).map(|t| t.as_secs()).unwrap_or(0),
+        RtcSource::Deterministic(base_time) => base_time + HART_CLOCK_MASTER.frame() / FRAMES_PER_SECOND,
+    }
+}
+
+// days since 1970-01-01 to (year, month, day), see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (u32, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year as u32, month as u32, day as u32)
+}
+
+fn rtc_latch() -> RtcLatch {
+    let unix_time = rtc_unix_time();
+    let days = (unix_time / 86_400) as i64;
+    let seconds_of_day = (unix_time % 86_400) as u32;
+    let (year, month, day) = civil_from_days(days);
+    RtcLatch {
+        unix_time,
+        year,
+        month,
+        day,
+        hour: seconds_of_day / 3600,
+        minute: (seconds_of_day / 60) % 60,
+        second: seconds_of_day % 60,
+        // 1970-01-01 was a thursday
+        weekday: ((days + 4).rem_euclid(7)) as u32,
+    }
+}
+
+pub fn rtc_read_u32(offset: u32) -> ReadResult<u32> {
+    if offset == 0x00 {
+        let latch = rtc_latch();
+        RTC_LATCH.with(|l| *l.borrow_mut() = latch);
+    }
+    let latch = RTC_LATCH.with(|l| *l.borrow());
+    match offset {
+        0x00 => ReadResult::Ok(latch.unix_time as u32),
+        0x04 => ReadResult::Ok((latch.unix_time >> 32) as u32),
+        0x08 => ReadResult::Ok(latch.year),
+        0x0C => ReadResult::Ok(latch.month),
+        0x10 => ReadResult::Ok(latch.day),
+        0x14 => ReadResult::Ok(latch.hour),
+        0x18 => ReadResult::Ok(latch.minute),
+        0x1C => ReadResult::Ok(latch.second),
+        0x20 => ReadResult::Ok(latch.weekday),
+        0x24 => ReadResult::Ok(match *RTC_SOURCE.read() { RtcSource::Host => 0, RtcSource::Deterministic(_) => 1 }),
+        _ => ReadResult::InvalidAddress
+    }
+}
+
+pub fn rtc_read_u16(offset: u32) -> ReadResult<u16> {
+    rtc_read_u32(offset).map(|x| x as u16)
+}
+
+pub fn rtc_read_u8(offset: u32) -> ReadResult<u8> {
+    rtc_read_u32(offset).map(|x| x as u8)
+}
+
+pub fn rtc_write_u32(offset: u32, _value: u32) -> WriteResult {
+    match offset {
+        0x00 ..= 0x24 if (offset & 3) == 0 => WriteResult::ReadOnly,
+        _ => WriteResult::InvalidAddress
+    }
+}
+
+pub fn rtc_write_u16(offset: u32, value: u16) -> WriteResult {
+    rtc_write_u32(offset, value as u32)
+}
+
+pub fn rtc_write_u8(offset: u32, value: u8) -> WriteResult {
+    rtc_write_u32(offset, value as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::machine::DEVICE_TEST_LOCK;
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(10_957), (2000, 1, 1));
+        assert_eq!(civil_from_days(24_855), (2038, 1, 19));
+        // 2000 and 2024 are leap years, 1900 and 2100 aren't
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(47_540), (2100, 2, 28));
+        assert_eq!(civil_from_days(47_541), (2100, 3, 1));
+        assert_eq!(civil_from_days(-25_508), (1900, 3, 1));
+        assert_eq!(civil_from_days(-25_509), (1900, 2, 28));
+    }
+
+    fn read(offset: u32) -> u32 {
+        rtc_read_u32(offset).to_opt().unwrap()
+    }
+
+    #[test]
+    fn deterministic_clock_follows_frames() {
+        let _lock = DEVICE_TEST_LOCK.lock();
+        // 2001-01-01 13:14:15, a monday
+        let start = DETERMINISTIC_EPOCH + 366 * 86_400 + 13 * 3600 + 14 * 60 + 15;
+        rtc_init(Some(start - DETERMINISTIC_EPOCH - HART_CLOCK_MASTER.frame() / FRAMES_PER_SECOND));
+        assert_eq!(read(0x00) as u64 | (read(0x04) as u64) << 32, start);
+        assert_eq!([0x08, 0x0C, 0x10, 0x14, 0x18, 0x1C, 0x20, 0x24].map(read), [2001, 1, 1, 13, 14, 15, 1, 1]);
+
+        // the other registers hold the time latched by the last read of unix time
+        (0..FRAMES_PER_SECOND).for_each(|_| HART_CLOCK_MASTER.next_frame());
+        assert_eq!(read(0x1C), 15);
+        assert_eq!(read(0x00) as u64, start + 1);
+        assert_eq!(read(0x1C), 16);
+
+        rtc_init(None);
+        let host_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        assert!(read(0x00) as u64 >= host_time);
+        assert_eq!(read(0x24), 0);
+    }
+}
@@ -2,23 +2,22 @@ use std::sync::Arc;
 
 use crate::{hart::{StepState, Hart, csrs::SharedCSRs}, machine::Machine, hart_clock::{HartClockMaster, HartClock, ClockEvent, HART_CLOCK_MASTER}, ui::main_window::{self, MainWindow}, interrupt_controller::{INTERRUPT_CONTROLLER, PendingInterrupt}};
 
-pub const ROM_START_ADDRESS: u32 = 0xF800_0000;
-
 pub fn run(machine: &Arc<Machine>) {
     let shared_csrs = SharedCSRs::new();
-    let hart0 = Hart::new(0xF800_0000, 0, &shared_csrs, &machine);
-    let hart1 = Hart::new(0xF800_0000, 1, &shared_csrs, &machine);
-    let hart2 = Hart::new(0xF800_0000, 2, &shared_csrs, &machine);
-    let hart3 = Hart::new(0xF800_0000, 3, &shared_csrs, &machine);
+    let entry_point = machine.config.entry_point;
+    let hart0 = Hart::new(entry_point, 0, &shared_csrs, machine);
+    for hart_id in 1..machine.config.hart_count {
+        let hart = Hart::new(entry_point, hart_id as u32, &shared_csrs, machine);
+        std::thread::spawn(move || run_hart_clocked(hart_id, hart));
+    }
 
-    HART_CLOCK_MASTER.start_hart(0, ROM_START_ADDRESS);
+    HART_CLOCK_MASTER.start_hart(0, entry_point);
 
-    std::thread::spawn(move || run_hart_clocked(1, hart1));
-    std::thread::spawn(move || run_hart_clocked(2, hart2));
-    std::thread::spawn(move || run_hart_clocked(3, hart3));
     run_hart_clocked(0, hart0);
 }
 
+const PUBLISH_CYCLES: usize = 1024;
+
 fn run_hart_clocked(hart_id: usize, mut hart: Hart) {
     let mut clock = HartClock::new(hart_id);
     loop {
@@ -33,6 +32,11 @@ fn run_hart_clocked(hart_id: usize, mut hart: Hart) {
                     match hart.single_step::<false>() {
                         StepState::Run => {
                             elapsed_cycles += 1;
+                            // publish progress so virtual timestamps move within the frame
+                            if elapsed_cycles == PUBLISH_CYCLES {
+                                clock.register_cycles(elapsed_cycles);
+                                elapsed_cycles = 0;
+                            }
                         },
                         StepState::WaitForInterrupt => {
                             clock.wfi();
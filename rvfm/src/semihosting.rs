@@ -0,0 +1,256 @@
+use std::{fs::{File, OpenOptions}, io::{self, Read, Seek, SeekFrom, Write}, os::unix::fs::OpenOptionsExt, path::{Component, Path, PathBuf}, sync::Arc, time::UNIX_EPOCH};
+
+use parking_lot::Mutex;
+
+use crate::machine::{Machine, ReadResult, WriteResult};
+
+/*
+Semihosting
+===========
+A semihosting call is the sequence
+
+    slli x0, x0, 0x1F
+    ebreak
+    srai x0, x0, 7
+
+with the operation number in a0 and the address of a parameter block (an array of words) in a1. The
+result is returned in a0. Failing operations return -1, and the host error can be fetched with ERRNO.
+All paths are relative to the sandbox directory given on the command line, and may not end in a symlink.
+
+0x01 OPEN  [name_ptr, mode, name_len]     -> handle. mode follows fopen: 0/1 = r, 2/3 = r+, 4/5 = w, 6/7 = w+, 8/9 = a, 10/11 = a+
+0x02 CLOSE [handle]                        -> 0
+0x05 WRITE [handle, data_ptr, length]      -> number of bytes not written
+0x06 READ  [handle, data_ptr, length]      -> number of bytes not read
+0x0A SEEK  [handle, position]              -> 0
+0x0C FLEN  [handle]                        -> file length
+0x13 ERRNO                                 -> host error code of the last failing operation
+0x100 STAT [name_ptr, name_len, stat_ptr]  -> 0, and writes [size, is_directory, mtime_lo, mtime_hi] to stat_ptr
+ */
+
+pub const SEMIHOSTING_PRE_OPCODE : u32 = 0x01F0_1013;
+pub const SEMIHOSTING_POST_OPCODE: u32 = 0x4070_5013;
+
+const SYS_OPEN : u32 = 0x01;
+const SYS_CLOSE: u32 = 0x02;
+const SYS_WRITE: u32 = 0x05;
+const SYS_READ : u32 = 0x06;
+const SYS_SEEK : u32 = 0x0A;
+const SYS_FLEN : u32 = 0x0C;
+const SYS_ERRNO: u32 = 0x13;
+const SYS_STAT : u32 = 0x100;
+
+const SEMIHOSTING_ERROR: u32 = 0xFFFF_FFFF;
+const MAX_PATH_LENGTH: u32 = 0x1000;
+const MAX_TRANSFER_LENGTH: u32 = 0x10_0000;
+
+// errno values reported for failures that do not come from the host
+const ERRNO_INVALID: u32 = 22;
+const ERRNO_BAD_HANDLE: u32 = 9;
+const ERRNO_ACCESS: u32 = 13;
+const ERRNO_FAULT: u32 = 14;
+
+pub struct Semihosting {
+    root: PathBuf,
+    files: Mutex<Vec<Option<File>>>,
+    errno: Mutex<u32>,
+}
+
+impl Semihosting {
+    pub fn new(root: &Path) -> io::Result<Self> {
+        Ok(Self {
+            root: root.canonicalize()?,
+            files: Mutex::new(Vec::new()),
+            errno: Mutex::new(0),
+        })
+    }
+
+    pub fn call(&self, machine: &Arc<Machine>, operation: u32, parameters: u32) -> u32 {
+        let result = match operation {
+            SYS_OPEN  => self.open(machine, parameters),
+            SYS_CLOSE => self.close(machine, parameters),
+            SYS_WRITE => self.write(machine, parameters),
+            SYS_READ  => self.read(machine, parameters),
+            SYS_SEEK  => self.seek(machine, parameters),
+            SYS_FLEN  => self.flen(machine, parameters),
+            SYS_ERRNO => Ok(*self.errno.lock()),
+            SYS_STAT  => self.stat(machine, parameters),
+            _ => Err(ERRNO_INVALID),
+        };
+        match result {
+            Ok(value) => value,
+            Err(errno) => {
+                println!("semihosting: operation {:#X} failed with errno {}", operation, errno);
+                *self.errno.lock() = errno;
+                SEMIHOSTING_ERROR
+            }
+        }
+    }
+
+    fn parameter(machine: &Arc<Machine>, parameters: u32, index: u32) -> Result<u32, u32> {
+        match machine.read_u32(parameters.wrapping_add(index * 4)) {
+            ReadResult::Ok(value) => Ok(value),
+            ReadResult::InvalidAddress => Err(ERRNO_FAULT),
+        }
+    }
+
+    fn host_path(&self, machine: &Arc<Machine>, name_ptr: u32, name_len: u32) -> Result<PathBuf, u32> {
+        if name_len == 0 || name_len > MAX_PATH_LENGTH {
+            return Err(ERRNO_INVALID);
+        }
+        let mut name = vec![0u8; name_len as usize];
+        if let ReadResult::InvalidAddress = machine.read_block(name_ptr, &mut name[..]) {
+            return Err(ERRNO_FAULT);
+        }
+        let name = String::from_utf8(name).map_err(|_| ERRNO_INVALID)?;
+        let relative_path = Path::new(&name);
+        if !relative_path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+            return Err(ERRNO_ACCESS);
+        }
+        let path = self.root.join(relative_path);
+        // resolve symlinks in the existing part of the path so they can't escape the sandbox
+        let resolved_parent = path.parent().ok_or(ERRNO_INVALID)?.canonicalize().map_err(io_errno)?;
+        if !resolved_parent.starts_with(&self.root) {
+            return Err(ERRNO_ACCESS);
+        }
+        // the last component is never followed, even when it is a dangling link open would create through
+        let path = resolved_parent.join(path.file_name().ok_or(ERRNO_INVALID)?);
+        if path.symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            return Err(ERRNO_ACCESS);
+        }
+        Ok(path)
+    }
+
+    fn with_file<T>(&self, handle: u32, file_fn: impl FnOnce(&mut File) -> Result<T, u32>) -> Result<T, u32> {
+        let mut files = self.files.lock();
+        match files.get_mut(handle as usize) {
+            Some(Some(file)) => file_fn(file),
+            _ => Err(ERRNO_BAD_HANDLE),
+        }
+    }
+
+    fn open(&self, machine: &Arc<Machine>, parameters: u32) -> Result<u32, u32> {
+        let name_ptr = Self::parameter(machine, parameters, 0)?;
+        let mode     = Self::parameter(machine, parameters, 1)?;
+        let name_len = Self::parameter(machine, parameters, 2)?;
+        let path = self.host_path(machine, name_ptr, name_len)?;
+        let mut options = OpenOptions::new();
+        match mode >> 1 {
+            0 => options.read(true),
+            1 => options.read(true).write(true),
+            2 => options.write(true).create(true).truncate(true),
+            3 => options.read(true).write(true).create(true).truncate(true),
+            4 => options.append(true).create(true),
+            5 => options.read(true).append(true).create(true),
+            _ => return Err(ERRNO_INVALID),
+        };
+        options.custom_flags(libc::O_NOFOLLOW);
+        let file = options.open(&path).map_err(io_errno)?;
+        println!("semihosting: opened {:?}", path);
+        let mut files = self.files.lock();
+        let handle = match files.iter().position(|file| file.is_none()) {
+            Some(handle) => {
+                files[handle] = Some(file);
+                handle
+            },
+            None => {
+                files.push(Some(file));
+                files.len() - 1
+            }
+        };
+        Ok(handle as u32)
+    }
+
+    fn close(&self, machine: &Arc<Machine>, parameters: u32) -> Result<u32, u32> {
+        let handle = Self::parameter(machine, parameters, 0)?;
+        let mut files = self.files.lock();
+        match files.get_mut(handle as usize) {
+            Some(file @ Some(_)) => {
+                *file = None;
+                Ok(0)
+            },
+            _ => Err(ERRNO_BAD_HANDLE),
+        }
+    }
+
+    fn write(&self, machine: &Arc<Machine>, parameters: u32) -> Result<u32, u32> {
+        let handle   = Self::parameter(machine, parameters, 0)?;
+        let data_ptr = Self::parameter(machine, parameters, 1)?;
+        let length   = Self::parameter(machine, parameters, 2)?;
+        if length > MAX_TRANSFER_LENGTH {
+            return Err(ERRNO_INVALID);
+        }
+        let mut data = vec![0u8; length as usize];
+        if let ReadResult::InvalidAddress = machine.read_block(data_ptr, &mut data[..]) {
+            return Err(ERRNO_FAULT);
+        }
+        self.with_file(handle, |file| {
+            let written = file.write(&data[..]).map_err(io_errno)?;
+            Ok(length - written as u32)
+        })
+    }
+
+    fn read(&self, machine: &Arc<Machine>, parameters: u32) -> Result<u32, u32> {
+        let handle   = Self::parameter(machine, parameters, 0)?;
+        let data_ptr = Self::parameter(machine, parameters, 1)?;
+        let length   = Self::parameter(machine, parameters, 2)?;
+        if length > MAX_TRANSFER_LENGTH {
+            return Err(ERRNO_INVALID);
+        }
+        let mut data = vec![0u8; length as usize];
+        self.with_file(handle, |file| {
+            let read = file.read(&mut data[..]).map_err(io_errno)?;
+            match machine.write_block(data_ptr, &data[..read]) {
+                WriteResult::Ok => Ok(length - read as u32),
+                _ => {
+                    // put the bytes back so a bad buffer doesn't lose file data
+                    file.seek(SeekFrom::Current(-(read as i64))).map_err(io_errno)?;
+                    Err(ERRNO_FAULT)
+                },
+            }
+        })
+    }
+
+    fn seek(&self, machine: &Arc<Machine>, parameters: u32) -> Result<u32, u32> {
+        let handle   = Self::parameter(machine, parameters, 0)?;
+        let position = Self::parameter(machine, parameters, 1)?;
+        self.with_file(handle, |file| {
+            file.seek(SeekFrom::Start(position as u64)).map_err(io_errno)?;
+            Ok(0)
+        })
+    }
+
+    fn flen(&self, machine: &Arc<Machine>, parameters: u32) -> Result<u32, u32> {
+        let handle = Self::parameter(machine, parameters, 0)?;
+        self.with_file(handle, |file| {
+            let length = file.metadata().map_err(io_errno)?.len();
+            Ok(length.min(0xFFFF_FFFE) as u32)
+        })
+    }
+
+    fn stat(&self, machine: &Arc<Machine>, parameters: u32) -> Result<u32, u32> {
+        let name_ptr = Self::parameter(machine, parameters, 0)?;
+        let name_len = Self::parameter(machine, parameters, 1)?;
+        let stat_ptr = Self::parameter(machine, parameters, 2)?;
+        let path = self.host_path(machine, name_ptr, name_len)?;
+        let metadata = std::fs::metadata(&path).map_err(io_errno)?;
+        let mtime = metadata.modified().ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        let stat = [
+            metadata.len().min(0xFFFF_FFFF) as u32,
+            if metadata.is_dir() { 1 } else { 0 },
+            mtime as u32,
+            (mtime >> 32) as u32,
+        ];
+        let stat_bytes: Vec<u8> = stat.iter().flat_map(|x| x.to_le_bytes()).collect();
+        match machine.write_block(stat_ptr, &stat_bytes[..]) {
+            WriteResult::Ok => Ok(0),
+            _ => Err(ERRNO_FAULT),
+        }
+    }
+}
+
+fn io_errno(error: io::Error) -> u32 {
+    error.raw_os_error().map(|errno| errno as u32).unwrap_or(ERRNO_INVALID)
+}
@@ -31,7 +31,8 @@ unsafe impl Send for WindowMessage {}
 
 #[derive(Clone)]
 pub struct MainWindow {
-    event_proxy: EventLoopProxy<WindowMessage>
+    // None for a headless window, which drops everything sent to it
+    event_proxy: Option<EventLoopProxy<WindowMessage>>
 }
 
 impl MainWindow {
@@ -43,12 +44,12 @@ impl MainWindow {
         let window = WindowBuilder::new()
             .with_inner_size(PhysicalSize::new((512.0 * config.ui_scale) as u32, (384.0 * config.ui_scale) as u32))
             .with_resizable(false)
-            .with_title("RVFM")
+            .with_title(&config.window_title)
             .build(&event_loop)
             .unwrap();
 
         let main_window = MainWindow {
-            event_proxy
+            event_proxy: Some(event_proxy)
         };
 
         let mut video_resolution = VideoResolution::V256x192;
@@ -117,15 +118,27 @@ impl MainWindow {
         })
     }
 
+    // lets tests drive the gpu core, which presents through a window, without an event loop
+    #[cfg(test)]
+    pub fn headless() -> Self {
+        Self { event_proxy: None }
+    }
+
+    fn send(&self, message: WindowMessage) {
+        if let Some(event_proxy) = &self.event_proxy {
+            let _ = event_proxy.send_event(message);
+        }
+    }
+
     pub fn exit(&self) {
-        let _ = self.event_proxy.send_event(WindowMessage::Exit);
+        self.send(WindowMessage::Exit);
     }
 
     pub fn present_texture(&self, texture: *const u8, completion_addr: u32, interrupt: bool, machine: Arc<Machine>) {
-        let _ = self.event_proxy.send_event(WindowMessage::PresentTexture(texture, completion_addr, interrupt, machine));
+        self.send(WindowMessage::PresentTexture(texture, completion_addr, interrupt, machine));
     }
 
     pub fn set_video_resolution(&self, resolution: VideoResolution) {
-        let _ = self.event_proxy.send_event(WindowMessage::SetVideoResolution(resolution));
+        self.send(WindowMessage::SetVideoResolution(resolution));
     }
 }
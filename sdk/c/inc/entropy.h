@@ -0,0 +1,14 @@
+#pragma once
+
+#include "types.h"
+
+static volatile const u32 * const entropy_value         = (volatile const u32 * const) 0x80080000;
+static volatile const u32 * const entropy_deterministic = (volatile const u32 * const) 0x80080004;
+
+inline static u32 random_u32() {
+    return *entropy_value;
+}
+
+inline static bool entropy_is_deterministic() {
+    return *entropy_deterministic != 0;
+}
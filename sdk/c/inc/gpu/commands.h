@@ -14,6 +14,8 @@ typedef enum {
     PixelDataLayoutD32x1 = 6,
     PixelDataLayoutD32x2 = 7,
     PixelDataLayoutD32x4 = 8,
+    PixelDataLayoutI4 = 9,
+    PixelDataLayoutI8 = 10,
 } PixelDataLayout;
 
 typedef enum {
@@ -31,6 +33,8 @@ typedef enum {
     PixelDataTypeRgF32 = 5,
     PixelDataTypeRgbF32 = 6,
     PixelDataTypeRgbaF32 = 7,
+    PixelDataTypeIndexed4 = 8,
+    PixelDataTypeIndexed8 = 9,
 } PixelDataType;
 
 #define COMMAND_ENCODED_U16(x) \
@@ -46,6 +50,8 @@ typedef enum {
 #define PUSH_COMMAND \
     push_command(recorder, data, sizeof(data))
 
+// shaders, pipeline states and draws, indexed and instanced ones included, are only recorded by the rust sdk so far
+
 inline static bool gpu_command_clear_texture(CommandListRecorder * recorder, u8 texture, u8 constant_sampler) {
     u8 data[] = {
         0x00,
@@ -251,8 +257,412 @@ inline static bool gpu_command_cutout_blit(CommandListRecorder * recorder, u8 sr
     return PUSH_COMMAND;
 }
 
-static inline void gpu_submit_commandlist(CommandList command_list, volatile u32 * completion_flag) {
+typedef enum {
+    SampleFilterNearest = 0,
+    SampleFilterBilinear = 1,
+} SampleFilter;
+
+typedef enum {
+    OverflowModeRepeat = 0,
+    OverflowModeMirror = 1,
+    OverflowModeClamp = 2,
+    OverflowModeZero = 3,
+} OverflowMode;
+
+/*
+configure_sampler <sampler> <filter> <overflow u> <overflow v>    ..
+[         13 00 ] [    SS ] [    FF ] [       UU ] [       VV ] [ 00 00 ]
+*/
+
+inline static bool gpu_command_configure_sampler(CommandListRecorder * recorder, u8 sampler, SampleFilter filter, OverflowMode overflow_u, OverflowMode overflow_v) {
+    u8 data[] = {
+        0x13,
+        0x00,
+        sampler,
+        (u8) filter,
+        (u8) overflow_u,
+        (u8) overflow_v,
+        0x00,
+        0x00,
+    };
+    return PUSH_COMMAND;
+}
+
+typedef struct {
+    bool enabled;
+    u8 texture;
+    i16 x;
+    i16 y;
+    u16 src_x;
+    u16 src_y;
+    u16 width;
+    u16 height;
+    bool flip_x;
+    bool flip_y;
+    bool cutout;
+    // 0 - 3, 0 drawn on top
+    u8 priority;
+} SpriteConfig;
+
+/*
+set_sprite <sprite> <texture> < x     > < y     > < src x > < src y > < width > < height> <flags> <priority>   ..
+[  16 00 ] [   II ] [     TT ] [ XX XX ] [ YY YY ] [ UU UU ] [ VV VV ] [ WW WW ] [ HH HH ] [  FF ] [      PP ] [ 00 00 ]
+an enabled sprite's texture has to be configured with a D8x4, D32x4, I4 or I8 layout first.
+*/
+
+inline static bool gpu_command_set_sprite(CommandListRecorder * recorder, u8 sprite, const SpriteConfig * config) {
+    u8 flags =
+        (config->enabled ? 1 : 0) |
+        (config->flip_x  ? 2 : 0) |
+        (config->flip_y  ? 4 : 0) |
+        (config->cutout  ? 8 : 0);
+    u8 data[] = {
+        0x16,
+        0x00,
+        sprite,
+        config->texture,
+        COMMAND_ENCODED_U16(config->x),
+        COMMAND_ENCODED_U16(config->y),
+        COMMAND_ENCODED_U16(config->src_x),
+        COMMAND_ENCODED_U16(config->src_y),
+        COMMAND_ENCODED_U16(config->width),
+        COMMAND_ENCODED_U16(config->height),
+        flags,
+        config->priority,
+        0x00,
+        0x00,
+    };
+    return PUSH_COMMAND;
+}
+
+/*
+move_sprite <sprite>   ..   < x     > < y     >
+[   17 00 ] [   II ] [ 00 ] [ XX XX ] [ YY YY ]
+*/
+
+inline static bool gpu_command_move_sprite(CommandListRecorder * recorder, u8 sprite, i16 x, i16 y) {
+    u8 data[] = {
+        0x17,
+        0x00,
+        sprite,
+        0x00,
+        COMMAND_ENCODED_U16((u16) x),
+        COMMAND_ENCODED_U16((u16) y),
+    };
+    return PUSH_COMMAND;
+}
+
+typedef struct {
+    bool enabled;
+    u8 atlas_texture;
+    u8 map_buffer;
+    u8 tile_size;
+    u16 map_width;
+    u16 map_height;
+    bool wrap;
+    // scanline_buffer is only used when scanline_scroll is set
+    bool scanline_scroll;
+    u8 scanline_buffer;
+    // 0 - 3, 0 drawn on top
+    u8 priority;
+} BackgroundConfig;
+
+/*
+configure_background <background> <flags> <atlas> <map buffer> <scanline buffer> <tile size> < map width > < map height> <priority>     ..
+[          18 00 ] [       BB ] [  FF ] [  TT ] [        MM ] [             SS ] [      ZZ ] [     WW WW ] [      HH HH ] [      PP ] [ 00 00 00 ]
+an enabled background's atlas has to be configured with a D8x4, D32x4, I4 or I8 layout first.
+*/
+
+inline static bool gpu_command_configure_background(CommandListRecorder * recorder, u8 background, const BackgroundConfig * config) {
+    u8 flags =
+        (config->enabled         ? 1 : 0) |
+        (config->wrap            ? 2 : 0) |
+        (config->scanline_scroll ? 4 : 0);
+    u8 data[] = {
+        0x18,
+        0x00,
+        background,
+        flags,
+        config->atlas_texture,
+        config->map_buffer,
+        config->scanline_scroll ? config->scanline_buffer : 0,
+        config->tile_size,
+        COMMAND_ENCODED_U16(config->map_width),
+        COMMAND_ENCODED_U16(config->map_height),
+        config->priority,
+        0x00,
+        0x00,
+        0x00,
+    };
+    return PUSH_COMMAND;
+}
+
+/*
+scroll_background <background>   ..   < x     > < y     >
+[       19 00 ] [       BB ] [ 00 ] [ XX XX ] [ YY YY ]
+*/
+
+inline static bool gpu_command_scroll_background(CommandListRecorder * recorder, u8 background, i16 x, i16 y) {
+    u8 data[] = {
+        0x19,
+        0x00,
+        background,
+        0x00,
+        COMMAND_ENCODED_U16((u16) x),
+        COMMAND_ENCODED_U16((u16) y),
+    };
+    return PUSH_COMMAND;
+}
+
+typedef struct {
+    u32 size;
+    u32 free;
+    u32 allocation_failures;
+} VramInfo;
+
+/*
+query_vram     ..      <   address   >
+[  1C 00 ] [ 00 00 ] [ AA AA AA AA ]
+*/
+
+inline static bool gpu_command_query_vram(CommandListRecorder * recorder, volatile VramInfo * info) {
+    u8 data[] = {
+        0x1C,
+        0x00,
+        0x00,
+        0x00,
+        COMMAND_ENCODED_U32(info),
+    };
+    return PUSH_COMMAND;
+}
+
+/*
+set_texture_palette <texture> <palette>
+[           1D 00 ] [    TT ] [     PP ]
+*/
+
+inline static bool gpu_command_set_texture_palette(CommandListRecorder * recorder, u8 texture, u8 palette_buffer) {
+    u8 data[] = {
+        0x1D,
+        0x00,
+        texture,
+        palette_buffer
+    };
+    return PUSH_COMMAND;
+}
+
+/*
+download_texture <texture> <dst_image_layout> <   x   > <   y   > < width > <height > < dst address > <completion addr> <interrupt>    ..
+[        1E 00 ] [    TT ] [              LL ] [ xx xx ] [ yy yy ] [ WW WW ] [ HH HH ] [ AA AA AA AA ] [   CC CC CC CC ] [       II ] [ 00 00 00 ]
+*/
+
+inline static bool gpu_command_download_texture(CommandListRecorder * recorder, u8 texture, ImageDataLayout dst_layout, u16 x, u16 y, u16 width, u16 height, volatile u8 * dst, volatile u32 * completion_flag, bool completion_interrupt) {
+    u8 data[] = {
+        0x1E,
+        0x00,
+        texture,
+        (u8) dst_layout,
+        COMMAND_ENCODED_U16(x),
+        COMMAND_ENCODED_U16(y),
+        COMMAND_ENCODED_U16(width),
+        COMMAND_ENCODED_U16(height),
+        COMMAND_ENCODED_U32(dst),
+        COMMAND_ENCODED_U32(completion_flag),
+        completion_interrupt ? 1 : 0,
+        0x00,
+        0x00,
+        0x00,
+    };
+    return PUSH_COMMAND;
+}
+
+typedef enum {
+    TimestampSourceHost = 0,
+    TimestampSourceVirtual = 1,
+} TimestampSource;
+
+/*
+write_timestamp <source>  ..   <   address   >
+[       1F 00 ] [    SS ] [ 00 ] [ AA AA AA AA ]
+*/
+
+inline static bool gpu_command_write_timestamp(CommandListRecorder * recorder, TimestampSource source, volatile u64 * timestamp) {
+    u8 data[] = {
+        0x1F,
+        0x00,
+        (u8) source,
+        0x00,
+        COMMAND_ENCODED_U32(timestamp),
+    };
+    return PUSH_COMMAND;
+}
+
+typedef struct {
+    u32 vertices_shaded;
+    u32 primitives_rasterized;
+    u32 fragments_shaded;
+    u32 fragments_passed;
+    u32 blit_pixels;
+} GpuStatistics;
+
+/*
+begin_statistics_query   ..
+[              20 00 ] [ 00 00 ]
+*/
+
+inline static bool gpu_command_begin_statistics_query(CommandListRecorder * recorder) {
+    u8 data[] = {
+        0x20,
+        0x00,
+        0x00,
+        0x00,
+    };
+    return PUSH_COMMAND;
+}
+
+/*
+end_statistics_query     ..      <   address   >
+[            21 00 ] [ 00 00 ] [ AA AA AA AA ]
+*/
+
+inline static bool gpu_command_end_statistics_query(CommandListRecorder * recorder, volatile GpuStatistics * statistics) {
+    u8 data[] = {
+        0x21,
+        0x00,
+        0x00,
+        0x00,
+        COMMAND_ENCODED_U32(statistics),
+    };
+    return PUSH_COMMAND;
+}
+
+/*
+call_list    ..      <   address   >
+[  22 00 ] [ 00 00 ] [ AA AA AA AA ]
+*/
+
+inline static bool gpu_command_call_list(CommandListRecorder * recorder, CommandList command_list) {
+    command_list->completion_flag = 0;
+    u8 data[] = {
+        0x22,
+        0x00,
+        0x00,
+        0x00,
+        COMMAND_ENCODED_U32(command_list),
+    };
+    return PUSH_COMMAND;
+}
+
+/*
+jump_list    ..      <   address   >
+[  23 00 ] [ 00 00 ] [ AA AA AA AA ]
+*/
+
+inline static bool gpu_command_jump_list(CommandListRecorder * recorder, CommandList command_list) {
+    command_list->completion_flag = 0;
+    u8 data[] = {
+        0x23,
+        0x00,
+        0x00,
+        0x00,
+        COMMAND_ENCODED_U32(command_list),
+    };
+    return PUSH_COMMAND;
+}
+
+typedef enum {
+    CompareFnNever = 0,
+    CompareFnAlways = 1,
+    CompareFnLess = 2,
+    CompareFnLessOrEqual = 3,
+    CompareFnGreater = 4,
+    CompareFnGreaterOrEqual = 5,
+    CompareFnEqual = 6,
+    CompareFnNotEqual = 7,
+} CompareFn;
+
+/*
+skip_if <compare> ..   <   address   > <  reference  > <    mask     > < skip bytes  >
+[ 24 00 ] [    CC ] [ 00 ] [ AA AA AA AA ] [ VV VV VV VV ] [ MM MM MM MM ] [ SS SS SS SS ]
+*/
+
+inline static bool gpu_command_skip_if(CommandListRecorder * recorder, CompareFn compare_fn, volatile u32 * value, u32 reference, u32 mask, u32 skip_bytes) {
+    u8 data[] = {
+        0x24,
+        0x00,
+        (u8) compare_fn,
+        0x00,
+        COMMAND_ENCODED_U32(value),
+        COMMAND_ENCODED_U32(reference),
+        COMMAND_ENCODED_U32(mask),
+        COMMAND_ENCODED_U32(skip_bytes),
+    };
+    return PUSH_COMMAND;
+}
+
+/*
+wait_until <compare> ..   <   address   > <  reference  > <    mask     >
+[    25 00 ] [    CC ] [ 00 ] [ AA AA AA AA ] [ VV VV VV VV ] [ MM MM MM MM ]
+*/
+
+inline static bool gpu_command_wait_until(CommandListRecorder * recorder, CompareFn compare_fn, volatile u32 * value, u32 reference, u32 mask) {
+    u8 data[] = {
+        0x25,
+        0x00,
+        (u8) compare_fn,
+        0x00,
+        COMMAND_ENCODED_U32(value),
+        COMMAND_ENCODED_U32(reference),
+        COMMAND_ENCODED_U32(mask),
+    };
+    return PUSH_COMMAND;
+}
+
+/*
+signal_fence <fence>  ..   <    value    >
+[    26 00 ] [  FF ] [ 00 ] [ VV VV VV VV ]
+*/
+
+inline static bool gpu_command_signal_fence(CommandListRecorder * recorder, u8 fence, u32 value) {
+    u8 data[] = {
+        0x26,
+        0x00,
+        fence,
+        0x00,
+        COMMAND_ENCODED_U32(value),
+    };
+    return PUSH_COMMAND;
+}
+
+/*
+wait_fence <fence>  ..   <    value    >
+[  27 00 ] [  FF ] [ 00 ] [ VV VV VV VV ]
+*/
+
+inline static bool gpu_command_wait_fence(CommandListRecorder * recorder, u8 fence, u32 value) {
+    u8 data[] = {
+        0x27,
+        0x00,
+        fence,
+        0x00,
+        COMMAND_ENCODED_U32(value),
+    };
+    return PUSH_COMMAND;
+}
+
+// the gpu runs high priority lists first, then graphics, then transfer
+typedef enum {
+    GpuQueueGraphics = 0,
+    GpuQueueTransfer = 1,
+    GpuQueueHighPriority = 2,
+} GpuQueue;
+
+static inline void gpu_submit_commandlist_to_queue(GpuQueue queue, CommandList command_list, volatile u32 * completion_flag) {
     *completion_flag = 0;
     command_list->completion_flag = completion_flag;
-    *GPU_COMMANDLIST_SUBMISSION_PORT = (usize) command_list;
+    GPU_COMMANDLIST_SUBMISSION_PORT[queue] = (usize) command_list;
+}
+
+static inline void gpu_submit_commandlist(CommandList command_list, volatile u32 * completion_flag) {
+    gpu_submit_commandlist_to_queue(GpuQueueGraphics, command_list, completion_flag);
 }
@@ -7,6 +7,10 @@ typedef enum {
     Hart1 = 1,
     Hart2 = 2,
     Hart3 = 3,
+    Hart4 = 4,
+    Hart5 = 5,
+    Hart6 = 6,
+    Hart7 = 7,
 } Hart;
 
 inline static Hart this_hart() {
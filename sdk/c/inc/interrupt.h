@@ -17,6 +17,11 @@ static volatile u32 * const ihi_enables = (volatile u32 * const) 0x80030FD0;
 static volatile u32 * const ihi_clears = (volatile u32 * const) 0x80030FE0;
 static volatile u32 * const ihi_triggers = (volatile u32 * const) 0x80030FF0;
 
+// harts 4-7 use the register blocks 0x40 bytes below those of harts 0-3
+inline static i32 hart_register_index(Hart hart) {
+    return hart < 4 ? (i32) hart : (i32) hart - 20;
+}
+
 inline static void set_interrupt_target(Interrupt interrupt, Hart hart) {
     u32 address = 0x80030000 + (interrupt << 4) + 4;
     *((volatile u32 *) address) = hart;
@@ -43,27 +48,27 @@ inline static void disable_interrupt(Interrupt interrupt) {
 }
 
 inline static void enable_ihi(Hart hart) {
-    ihi_enables[hart] = 1;
+    ihi_enables[hart_register_index(hart)] = 1;
 }
 
 inline static void disable_ihi(Hart hart) {
-    ihi_enables[hart] = 0;
+    ihi_enables[hart_register_index(hart)] = 0;
 }
 
 inline static void send_ihi(Hart hart) {
-    ihi_triggers[hart] = 1;
+    ihi_triggers[hart_register_index(hart)] = 1;
 }
 
 inline static void clear_ihi(Hart hart) {
-    ihi_clears[hart] = 1;
+    ihi_clears[hart_register_index(hart)] = 1;
 }
 
 inline static bool poll_ihi(Hart hart) {
-    return ihi_clears[hart] != 0;
+    return ihi_clears[hart_register_index(hart)] != 0;
 }
 
 inline static bool get_pending_interrupt(Hart hart, Interrupt * interrupt) {
-    u32 pending = pending_interrupts[hart];
+    u32 pending = pending_interrupts[hart_register_index(hart)];
     if (pending == 0xFFFFFFFF) {
         return false;
     }
@@ -0,0 +1,29 @@
+#pragma once
+
+#include "types.h"
+
+static volatile const u32 * const machine_info_hart_count       = (volatile const u32 * const) 0x80060000;
+static volatile const u32 * const machine_info_ram_size         = (volatile const u32 * const) 0x80060004;
+static volatile const u32 * const machine_info_rom_size         = (volatile const u32 * const) 0x80060008;
+static volatile const u32 * const machine_info_cycles_per_frame = (volatile const u32 * const) 0x8006000C;
+static volatile const u32 * const machine_info_vram_size        = (volatile const u32 * const) 0x80060010;
+
+inline static u32 machine_hart_count() {
+    return *machine_info_hart_count;
+}
+
+inline static u32 machine_ram_size() {
+    return *machine_info_ram_size;
+}
+
+inline static u32 machine_rom_size() {
+    return *machine_info_rom_size;
+}
+
+inline static u32 machine_hart_cycles_per_frame() {
+    return *machine_info_cycles_per_frame;
+}
+
+inline static u32 machine_vram_size() {
+    return *machine_info_vram_size;
+}
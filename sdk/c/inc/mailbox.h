@@ -0,0 +1,57 @@
+#pragma once
+
+#include "types.h"
+#include "hart.h"
+
+#define MAILBOX_QUEUE_DEPTH 16
+#define SEMAPHORE_COUNT 32
+
+#define MAILBOX_STATUS_FULL 1
+#define MAILBOX_STATUS_OVERFLOW 2
+
+inline static volatile u32 * mailbox_register(u32 hart, u32 offset) {
+    return (volatile u32 *) (0x80090000 + (hart << 4) + offset);
+}
+
+inline static volatile u32 * semaphore_register(u32 semaphore) {
+    return (volatile u32 *) (0x80090100 + (semaphore << 2));
+}
+
+// returns false if the target hart's queue was full and the message was dropped
+inline static bool mailbox_send(u32 hart, u32 message) {
+    *mailbox_register(hart, 0x0) = message;
+    return *(volatile u32 *) 0x80090080 != 0;
+}
+
+inline static u32 mailbox_pending(u32 hart) {
+    return *mailbox_register(hart, 0x8);
+}
+
+// returns false if the queue is empty
+inline static bool mailbox_receive(u32 hart, u32 * message) {
+    if (mailbox_pending(hart) == 0) {
+        return false;
+    }
+    *message = *mailbox_register(hart, 0x4);
+    return true;
+}
+
+inline static bool mailbox_take_overflow(u32 hart) {
+    if ((*mailbox_register(hart, 0xC) & MAILBOX_STATUS_OVERFLOW) == 0) {
+        return false;
+    }
+    *mailbox_register(hart, 0xC) = MAILBOX_STATUS_OVERFLOW;
+    return true;
+}
+
+inline static bool semaphore_try_acquire(u32 semaphore) {
+    return *semaphore_register(semaphore) == 0;
+}
+
+inline static void semaphore_acquire(u32 semaphore) {
+    while (! semaphore_try_acquire(semaphore)) {}
+}
+
+inline static void semaphore_release(u32 semaphore) {
+    *semaphore_register(semaphore) = 0;
+}
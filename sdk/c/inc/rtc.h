@@ -0,0 +1,34 @@
+#pragma once
+
+#include "types.h"
+
+static volatile const u32 * const rtc_registers = (volatile const u32 * const) 0x80070000;
+
+typedef struct {
+    u32 unix_time_lo;
+    u32 unix_time_hi;
+    u32 year;
+    u32 month;
+    u32 day;
+    u32 hour;
+    u32 minute;
+    u32 second;
+    u32 weekday;
+} DateTime;
+
+// reading the low word of the unix time latches the rest of the registers
+inline static void rtc_now(DateTime * date_time) {
+    date_time->unix_time_lo = rtc_registers[0];
+    date_time->unix_time_hi = rtc_registers[1];
+    date_time->year         = rtc_registers[2];
+    date_time->month        = rtc_registers[3];
+    date_time->day          = rtc_registers[4];
+    date_time->hour         = rtc_registers[5];
+    date_time->minute       = rtc_registers[6];
+    date_time->second       = rtc_registers[7];
+    date_time->weekday      = rtc_registers[8];
+}
+
+inline static bool rtc_is_deterministic() {
+    return rtc_registers[9] != 0;
+}
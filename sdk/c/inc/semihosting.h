@@ -0,0 +1,91 @@
+#pragma once
+
+#include "types.h"
+
+// Semihosting is only available when the emulator is a debug build started with -semihost <directory>
+
+#define SEMIHOSTING_SYS_OPEN  0x01
+#define SEMIHOSTING_SYS_CLOSE 0x02
+#define SEMIHOSTING_SYS_WRITE 0x05
+#define SEMIHOSTING_SYS_READ  0x06
+#define SEMIHOSTING_SYS_SEEK  0x0A
+#define SEMIHOSTING_SYS_FLEN  0x0C
+#define SEMIHOSTING_SYS_ERRNO 0x13
+#define SEMIHOSTING_SYS_STAT  0x100
+
+#define SEMIHOSTING_MODE_READ        0
+#define SEMIHOSTING_MODE_READ_WRITE  2
+#define SEMIHOSTING_MODE_WRITE       4
+#define SEMIHOSTING_MODE_WRITE_READ  6
+#define SEMIHOSTING_MODE_APPEND      8
+#define SEMIHOSTING_MODE_APPEND_READ 10
+
+#define SEMIHOSTING_ERROR 0xFFFFFFFF
+
+typedef struct {
+    u32 size;
+    u32 is_directory;
+    u32 modified_time_lo;
+    u32 modified_time_hi;
+} semihosting_stat_t;
+
+inline static u32 semihosting_call(u32 operation, const u32 * parameters) {
+    register u32 a0 asm("a0") = operation;
+    register const u32 * a1 asm("a1") = parameters;
+    asm volatile(
+        ".balign 16\n"
+        ".option push\n"
+        ".option norvc\n"
+        "slli x0, x0, 0x1F\n"
+        "ebreak\n"
+        "srai x0, x0, 7\n"
+        ".option pop\n"
+        : "+r"(a0)
+        : "r"(a1)
+        : "memory"
+    );
+    return a0;
+}
+
+// all functions return SEMIHOSTING_ERROR on failure, see semihosting_errno()
+
+inline static u32 semihosting_open(const char * name, u32 name_length, u32 mode) {
+    const u32 parameters[3] = { (u32) name, mode, name_length };
+    return semihosting_call(SEMIHOSTING_SYS_OPEN, parameters);
+}
+
+inline static u32 semihosting_close(u32 handle) {
+    const u32 parameters[1] = { handle };
+    return semihosting_call(SEMIHOSTING_SYS_CLOSE, parameters);
+}
+
+// returns the number of bytes not written
+inline static u32 semihosting_write(u32 handle, const void * data, u32 length) {
+    const u32 parameters[3] = { handle, (u32) data, length };
+    return semihosting_call(SEMIHOSTING_SYS_WRITE, parameters);
+}
+
+// returns the number of bytes not read
+inline static u32 semihosting_read(u32 handle, void * data, u32 length) {
+    const u32 parameters[3] = { handle, (u32) data, length };
+    return semihosting_call(SEMIHOSTING_SYS_READ, parameters);
+}
+
+inline static u32 semihosting_seek(u32 handle, u32 position) {
+    const u32 parameters[2] = { handle, position };
+    return semihosting_call(SEMIHOSTING_SYS_SEEK, parameters);
+}
+
+inline static u32 semihosting_flen(u32 handle) {
+    const u32 parameters[1] = { handle };
+    return semihosting_call(SEMIHOSTING_SYS_FLEN, parameters);
+}
+
+inline static u32 semihosting_stat(const char * name, u32 name_length, semihosting_stat_t * stat) {
+    const u32 parameters[3] = { (u32) name, name_length, (u32) stat };
+    return semihosting_call(SEMIHOSTING_SYS_STAT, parameters);
+}
+
+inline static u32 semihosting_errno() {
+    return semihosting_call(SEMIHOSTING_SYS_ERRNO, 0);
+}
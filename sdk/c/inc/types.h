@@ -10,6 +10,8 @@ typedef unsigned short u16;
 typedef short i16;
 typedef unsigned char u8;
 typedef char i8;
+typedef unsigned long long u64;
+typedef long long i64;
 
 typedef u32 usize;
 typedef i32 isize;
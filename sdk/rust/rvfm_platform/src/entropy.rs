@@ -0,0 +1,14 @@
+const ENTROPY_VALUE        : *const u32 = 0x8008_0000_u32 as _;
+const ENTROPY_DETERMINISTIC: *const u32 = 0x8008_0004_u32 as _;
+
+pub fn random_u32() -> u32 {
+    unsafe { ENTROPY_VALUE.read_volatile() }
+}
+
+pub fn random_u64() -> u64 {
+    ((random_u32() as u64) << 32) | random_u32() as u64
+}
+
+pub fn is_deterministic() -> bool {
+    unsafe { ENTROPY_DETERMINISTIC.read_volatile() != 0 }
+}
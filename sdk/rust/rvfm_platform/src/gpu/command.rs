@@ -1,7 +1,7 @@
 use core::sync::atomic::{self, AtomicU32};
 
 use crate::command_list::*;
-use super::pipeline_state::GraphicsPipelineState;
+use super::pipeline_state::{ComputePipelineState, DepthCompareFn, GraphicsPipelineState};
 use crate::debug::println;
 use crate::rvfm_platform;
 
@@ -22,6 +22,8 @@ pub enum PixelDataLayout {
     D32x1 = 6,
     D32x2 = 7,
     D32x4 = 8,
+    I4 = 9,
+    I8 = 10,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -49,6 +51,46 @@ pub enum PixelDataType {
     RgF32 = 5,
     RgbF32 = 6,
     RgbaF32 = 7,
+    Indexed4 = 8,
+    Indexed8 = 9,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SampleFilter {
+    Nearest = 0,
+    Bilinear = 1,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OverflowMode {
+    Repeat = 0,
+    Mirror = 1,
+    Clamp = 2,
+    Zero = 3,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TexelComponentType {
+    UInt = 0,
+    Int = 1,
+    F32 = 2,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IndexType {
+    U16 = 0,
+    U32 = 1,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimestampSource {
+    Host = 0,
+    Virtual = 1,
 }
 
 #[repr(u8)]
@@ -61,6 +103,55 @@ pub enum ShaderKind {
 
 pub struct GpuCommands;
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SpriteConfig {
+    pub enabled: bool,
+    pub texture: u8,
+    pub x: i16,
+    pub y: i16,
+    pub src_x: u16,
+    pub src_y: u16,
+    pub width: u16,
+    pub height: u16,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub cutout: bool,
+    pub priority: u8,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BackgroundConfig {
+    pub enabled: bool,
+    pub atlas_texture: u8,
+    pub map_buffer: u8,
+    pub tile_size: u8,
+    pub map_width: u16,
+    pub map_height: u16,
+    pub wrap: bool,
+    pub scanline_buffer: Option<u8>,
+    pub priority: u8,
+}
+
+// written by query_vram
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct VramInfo {
+    pub size: u32,
+    pub free: u32,
+    pub allocation_failures: u32,
+}
+
+// written by end_statistics_query
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GpuStatistics {
+    pub vertices_shaded: u32,
+    pub primitives_rasterized: u32,
+    pub fragments_shaded: u32,
+    pub fragments_passed: u32,
+    pub blit_pixels: u32,
+}
+
 pub struct ClippingRect {
     pub x_low: u16,
     pub x_high: u16,
@@ -85,6 +176,29 @@ pub trait GpuCommandBuilderExt<'c, 'd>: CommandListBuilder<'c, 'd, GpuCommands>
     fn upload_graphics_pipeline_state(&mut self, index: u8, /*flags: u8, */state: &'static GraphicsPipelineState) -> Result<(), GpuCommandBuilderError>;
     fn draw_graphics_pipeline(&mut self, index: u8, vertex_shader: u8, fragment_shader: u8, vertex_count: u32, clipping_rect: ClippingRect) -> Result<(), GpuCommandBuilderError>;
     fn write_buffer(&mut self, buffer: u8, data: *const u8, length: u32, offset: u32) -> Result<(), GpuCommandBuilderError>;
+    fn upload_compute_pipeline_state(&mut self, index: u8, state: &'static ComputePipelineState) -> Result<(), GpuCommandBuilderError>;
+    fn dispatch_compute(&mut self, state_index: u8, shader: u8, workgroup_size: u16, workgroups_x: u16, workgroups_y: u16) -> Result<(), GpuCommandBuilderError>;
+    fn configure_sampler(&mut self, sampler: u8, filter: SampleFilter, overflow_u: OverflowMode, overflow_v: OverflowMode) -> Result<(), GpuCommandBuilderError>;
+    fn generate_mipmaps(&mut self, texture: u8, component_type: TexelComponentType) -> Result<(), GpuCommandBuilderError>;
+    fn upload_texture_level(&mut self, texture: u8, level: u8, level_data: *const u8) -> Result<(), GpuCommandBuilderError>;
+    fn set_sprite(&mut self, sprite: u8, config: &SpriteConfig) -> Result<(), GpuCommandBuilderError>;
+    fn move_sprite(&mut self, sprite: u8, x: i16, y: i16) -> Result<(), GpuCommandBuilderError>;
+    fn configure_background(&mut self, background: u8, config: &BackgroundConfig) -> Result<(), GpuCommandBuilderError>;
+    fn scroll_background(&mut self, background: u8, x: i16, y: i16) -> Result<(), GpuCommandBuilderError>;
+    fn draw_graphics_pipeline_instanced(&mut self, index: u8, vertex_shader: u8, fragment_shader: u8, vertex_count: u32, instance_count: u32, clipping_rect: ClippingRect) -> Result<(), GpuCommandBuilderError>;
+    fn draw_indexed_graphics_pipeline(&mut self, index: u8, vertex_shader: u8, fragment_shader: u8, index_buffer: u8, index_type: IndexType, index_offset: u32, index_count: u32, instance_count: u32, clipping_rect: ClippingRect) -> Result<(), GpuCommandBuilderError>;
+    fn query_vram(&mut self, info: *mut VramInfo) -> Result<(), GpuCommandBuilderError>;
+    fn set_texture_palette(&mut self, texture: u8, palette_buffer: u8) -> Result<(), GpuCommandBuilderError>;
+    fn download_texture(&mut self, texture: u8, dst_layout: ImageDataLayout, x: u16, y: u16, width: u16, height: u16, dst: *mut u8, completion: &mut Self::Completion, interrupt: bool) -> Result<(), GpuCommandBuilderError>;
+    fn write_timestamp(&mut self, source: TimestampSource, timestamp: *mut u64) -> Result<(), GpuCommandBuilderError>;
+    fn begin_statistics_query(&mut self) -> Result<(), GpuCommandBuilderError>;
+    fn end_statistics_query(&mut self, statistics: *mut GpuStatistics) -> Result<(), GpuCommandBuilderError>;
+    fn call_list<'e, List: CommandListData<'e, GpuCommands>>(&mut self, list: &mut List) -> Result<(), GpuCommandBuilderError>;
+    fn jump_list<'e, List: CommandListData<'e, GpuCommands>>(&mut self, list: &mut List) -> Result<(), GpuCommandBuilderError>;
+    fn skip_if(&mut self, compare_fn: DepthCompareFn, value: *const u32, reference: u32, mask: u32, skip_bytes: u32) -> Result<(), GpuCommandBuilderError>;
+    fn wait_until(&mut self, compare_fn: DepthCompareFn, value: *const u32, reference: u32, mask: u32) -> Result<(), GpuCommandBuilderError>;
+    fn signal_fence(&mut self, fence: u8, value: u32) -> Result<(), GpuCommandBuilderError>;
+    fn wait_fence(&mut self, fence: u8, value: u32) -> Result<(), GpuCommandBuilderError>;
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -490,16 +604,605 @@ impl<'c, 'd, Builder: CommandListBuilder<'c, 'd, GpuCommands>> GpuCommandBuilder
 			Ok(())
 		}
     }
+    fn upload_compute_pipeline_state(&mut self, index: u8, state: &'static ComputePipelineState) -> Result<(), GpuCommandBuilderError> {
+        let state_address_bytes = command_u32_bytes(state as *const _ as usize as u32);
+        let data = &[
+            0x11,
+            0x00,
+            index,
+            0x00,
+            state_address_bytes[0],
+            state_address_bytes[1],
+            state_address_bytes[2],
+            state_address_bytes[3],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn dispatch_compute(&mut self, state_index: u8, shader: u8, workgroup_size: u16, workgroups_x: u16, workgroups_y: u16) -> Result<(), GpuCommandBuilderError> {
+        let workgroup_size_bytes = command_u16_bytes(workgroup_size);
+        let workgroups_x_bytes = command_u16_bytes(workgroups_x);
+        let workgroups_y_bytes = command_u16_bytes(workgroups_y);
+        let data = &[
+            0x12,
+            0x00,
+            state_index,
+            shader,
+
+            workgroup_size_bytes[0],
+            workgroup_size_bytes[1],
+            0x00,
+            0x00,
+
+            workgroups_x_bytes[0],
+            workgroups_x_bytes[1],
+            workgroups_y_bytes[0],
+            workgroups_y_bytes[1],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn configure_sampler(&mut self, sampler: u8, filter: SampleFilter, overflow_u: OverflowMode, overflow_v: OverflowMode) -> Result<(), GpuCommandBuilderError> {
+        let data = &[
+            0x13,
+            0x00,
+            sampler,
+            filter as u8,
+            overflow_u as u8,
+            overflow_v as u8,
+            0x00,
+            0x00,
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn generate_mipmaps(&mut self, texture: u8, component_type: TexelComponentType) -> Result<(), GpuCommandBuilderError> {
+        let data = &[
+            0x14,
+            0x00,
+            texture,
+            component_type as u8,
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn upload_texture_level(&mut self, texture: u8, level: u8, level_data: *const u8) -> Result<(), GpuCommandBuilderError> {
+        let data_address_bytes = command_u32_bytes(level_data as usize as u32);
+        let data = &[
+            0x15,
+            0x00,
+            texture,
+            level,
+            data_address_bytes[0],
+            data_address_bytes[1],
+            data_address_bytes[2],
+            data_address_bytes[3],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn set_sprite(&mut self, sprite: u8, config: &SpriteConfig) -> Result<(), GpuCommandBuilderError> {
+        let x_bytes      = command_u16_bytes(config.x as u16);
+        let y_bytes      = command_u16_bytes(config.y as u16);
+        let src_x_bytes  = command_u16_bytes(config.src_x);
+        let src_y_bytes  = command_u16_bytes(config.src_y);
+        let width_bytes  = command_u16_bytes(config.width);
+        let height_bytes = command_u16_bytes(config.height);
+        let flags =
+            if config.enabled { 1 } else { 0 } |
+            if config.flip_x  { 2 } else { 0 } |
+            if config.flip_y  { 4 } else { 0 } |
+            if config.cutout  { 8 } else { 0 };
+        let data = &[
+            0x16,
+            0x00,
+            sprite,
+            config.texture,
+            x_bytes[0],
+            x_bytes[1],
+            y_bytes[0],
+            y_bytes[1],
+            src_x_bytes[0],
+            src_x_bytes[1],
+            src_y_bytes[0],
+            src_y_bytes[1],
+            width_bytes[0],
+            width_bytes[1],
+            height_bytes[0],
+            height_bytes[1],
+            flags,
+            config.priority,
+            0x00,
+            0x00,
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn move_sprite(&mut self, sprite: u8, x: i16, y: i16) -> Result<(), GpuCommandBuilderError> {
+        let x_bytes = command_u16_bytes(x as u16);
+        let y_bytes = command_u16_bytes(y as u16);
+        let data = &[
+            0x17,
+            0x00,
+            sprite,
+            0x00,
+            x_bytes[0],
+            x_bytes[1],
+            y_bytes[0],
+            y_bytes[1],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn configure_background(&mut self, background: u8, config: &BackgroundConfig) -> Result<(), GpuCommandBuilderError> {
+        let map_width_bytes  = command_u16_bytes(config.map_width);
+        let map_height_bytes = command_u16_bytes(config.map_height);
+        let flags =
+            if config.enabled                   { 1 } else { 0 } |
+            if config.wrap                      { 2 } else { 0 } |
+            if config.scanline_buffer.is_some() { 4 } else { 0 };
+        let data = &[
+            0x18,
+            0x00,
+            background,
+            flags,
+            config.atlas_texture,
+            config.map_buffer,
+            config.scanline_buffer.unwrap_or(0),
+            config.tile_size,
+            map_width_bytes[0],
+            map_width_bytes[1],
+            map_height_bytes[0],
+            map_height_bytes[1],
+            config.priority,
+            0x00,
+            0x00,
+            0x00,
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn scroll_background(&mut self, background: u8, x: i16, y: i16) -> Result<(), GpuCommandBuilderError> {
+        let x_bytes = command_u16_bytes(x as u16);
+        let y_bytes = command_u16_bytes(y as u16);
+        let data = &[
+            0x19,
+            0x00,
+            background,
+            0x00,
+            x_bytes[0],
+            x_bytes[1],
+            y_bytes[0],
+            y_bytes[1],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn draw_graphics_pipeline_instanced(&mut self, state_index: u8, vertex_shader: u8, fragment_shader: u8, vertex_count: u32, instance_count: u32, clipping_rect: ClippingRect) -> Result<(), GpuCommandBuilderError> {
+        let vertex_count_bytes = command_u32_bytes(vertex_count);
+        let instance_count_bytes = command_u32_bytes(instance_count);
+        let clipping_rect_x_low_bytes  = command_u16_bytes(clipping_rect.x_low );
+        let clipping_rect_x_high_bytes = command_u16_bytes(clipping_rect.x_high);
+        let clipping_rect_y_low_bytes  = command_u16_bytes(clipping_rect.y_low );
+        let clipping_rect_y_high_bytes = command_u16_bytes(clipping_rect.y_high);
+        let data = &[
+            0x1A,
+            0x00,
+            state_index,
+            fragment_shader,
+
+            vertex_shader,
+            0x00,
+            0x00,
+            0x00,
+
+            vertex_count_bytes[0],
+            vertex_count_bytes[1],
+            vertex_count_bytes[2],
+            vertex_count_bytes[3],
+
+            clipping_rect_x_low_bytes[0],
+            clipping_rect_x_low_bytes[1],
+            clipping_rect_x_high_bytes[0],
+            clipping_rect_x_high_bytes[1],
+
+            clipping_rect_y_low_bytes[0],
+            clipping_rect_y_low_bytes[1],
+            clipping_rect_y_high_bytes[0],
+            clipping_rect_y_high_bytes[1],
+
+            instance_count_bytes[0],
+            instance_count_bytes[1],
+            instance_count_bytes[2],
+            instance_count_bytes[3],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn draw_indexed_graphics_pipeline(&mut self, state_index: u8, vertex_shader: u8, fragment_shader: u8, index_buffer: u8, index_type: IndexType, index_offset: u32, index_count: u32, instance_count: u32, clipping_rect: ClippingRect) -> Result<(), GpuCommandBuilderError> {
+        let index_count_bytes = command_u32_bytes(index_count);
+        let index_offset_bytes = command_u32_bytes(index_offset);
+        let instance_count_bytes = command_u32_bytes(instance_count);
+        let clipping_rect_x_low_bytes  = command_u16_bytes(clipping_rect.x_low );
+        let clipping_rect_x_high_bytes = command_u16_bytes(clipping_rect.x_high);
+        let clipping_rect_y_low_bytes  = command_u16_bytes(clipping_rect.y_low );
+        let clipping_rect_y_high_bytes = command_u16_bytes(clipping_rect.y_high);
+        let data = &[
+            0x1B,
+            0x00,
+            state_index,
+            fragment_shader,
+
+            vertex_shader,
+            index_buffer,
+            index_type as u8,
+            0x00,
+
+            index_count_bytes[0],
+            index_count_bytes[1],
+            index_count_bytes[2],
+            index_count_bytes[3],
+
+            index_offset_bytes[0],
+            index_offset_bytes[1],
+            index_offset_bytes[2],
+            index_offset_bytes[3],
+
+            clipping_rect_x_low_bytes[0],
+            clipping_rect_x_low_bytes[1],
+            clipping_rect_x_high_bytes[0],
+            clipping_rect_x_high_bytes[1],
+
+            clipping_rect_y_low_bytes[0],
+            clipping_rect_y_low_bytes[1],
+            clipping_rect_y_high_bytes[0],
+            clipping_rect_y_high_bytes[1],
+
+            instance_count_bytes[0],
+            instance_count_bytes[1],
+            instance_count_bytes[2],
+            instance_count_bytes[3],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn query_vram(&mut self, info: *mut VramInfo) -> Result<(), GpuCommandBuilderError> {
+        let info_address_bytes = command_u32_bytes(info as usize as u32);
+        let data = &[
+            0x1C,
+            0x00,
+            0x00,
+            0x00,
+            info_address_bytes[0],
+            info_address_bytes[1],
+            info_address_bytes[2],
+            info_address_bytes[3],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn set_texture_palette(&mut self, texture: u8, palette_buffer: u8) -> Result<(), GpuCommandBuilderError> {
+        let data = &[
+            0x1D,
+            0x00,
+            texture,
+            palette_buffer,
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn download_texture(&mut self, texture: u8, dst_layout: ImageDataLayout, x: u16, y: u16, width: u16, height: u16, dst: *mut u8, completion: &mut Builder::Completion, interrupt: bool) -> Result<(), GpuCommandBuilderError> {
+        let x_bytes = command_u16_bytes(x);
+        let y_bytes = command_u16_bytes(y);
+        let width_bytes = command_u16_bytes(width);
+        let height_bytes = command_u16_bytes(height);
+        let dst_address_bytes = command_u32_bytes(dst as usize as u32);
+        let completion_flag_bytes = command_u32_bytes(unsafe { completion.raw_ptr() } as usize as u32);
+        let data = &[
+            0x1E,
+            0x00,
+            texture,
+            dst_layout as u8,
+            x_bytes[0],
+            x_bytes[1],
+            y_bytes[0],
+            y_bytes[1],
+            width_bytes[0],
+            width_bytes[1],
+            height_bytes[0],
+            height_bytes[1],
+            dst_address_bytes[0],
+            dst_address_bytes[1],
+            dst_address_bytes[2],
+            dst_address_bytes[3],
+            completion_flag_bytes[0],
+            completion_flag_bytes[1],
+            completion_flag_bytes[2],
+            completion_flag_bytes[3],
+            if interrupt { 0x01 } else { 0x00 },
+            0x00,
+            0x00,
+            0x00,
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn write_timestamp(&mut self, source: TimestampSource, timestamp: *mut u64) -> Result<(), GpuCommandBuilderError> {
+        let timestamp_address_bytes = command_u32_bytes(timestamp as usize as u32);
+        let data = &[
+            0x1F,
+            0x00,
+            source as u8,
+            0x00,
+            timestamp_address_bytes[0],
+            timestamp_address_bytes[1],
+            timestamp_address_bytes[2],
+            timestamp_address_bytes[3],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn begin_statistics_query(&mut self) -> Result<(), GpuCommandBuilderError> {
+        let data = &[
+            0x20,
+            0x00,
+            0x00,
+            0x00,
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn end_statistics_query(&mut self, statistics: *mut GpuStatistics) -> Result<(), GpuCommandBuilderError> {
+        let statistics_address_bytes = command_u32_bytes(statistics as usize as u32);
+        let data = &[
+            0x21,
+            0x00,
+            0x00,
+            0x00,
+            statistics_address_bytes[0],
+            statistics_address_bytes[1],
+            statistics_address_bytes[2],
+            statistics_address_bytes[3],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn call_list<'e, List: CommandListData<'e, GpuCommands>>(&mut self, list: &mut List) -> Result<(), GpuCommandBuilderError> {
+        let list_address_bytes = command_u32_bytes(sub_list_header(list) as usize as u32);
+        let data = &[
+            0x22,
+            0x00,
+            0x00,
+            0x00,
+            list_address_bytes[0],
+            list_address_bytes[1],
+            list_address_bytes[2],
+            list_address_bytes[3],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn jump_list<'e, List: CommandListData<'e, GpuCommands>>(&mut self, list: &mut List) -> Result<(), GpuCommandBuilderError> {
+        let list_address_bytes = command_u32_bytes(sub_list_header(list) as usize as u32);
+        let data = &[
+            0x23,
+            0x00,
+            0x00,
+            0x00,
+            list_address_bytes[0],
+            list_address_bytes[1],
+            list_address_bytes[2],
+            list_address_bytes[3],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn skip_if(&mut self, compare_fn: DepthCompareFn, value: *const u32, reference: u32, mask: u32, skip_bytes: u32) -> Result<(), GpuCommandBuilderError> {
+        let value_address_bytes = command_u32_bytes(value as usize as u32);
+        let reference_bytes = command_u32_bytes(reference);
+        let mask_bytes = command_u32_bytes(mask);
+        let skip_bytes = command_u32_bytes(skip_bytes);
+        let data = &[
+            0x24,
+            0x00,
+            compare_fn as u8,
+            0x00,
+            value_address_bytes[0],
+            value_address_bytes[1],
+            value_address_bytes[2],
+            value_address_bytes[3],
+            reference_bytes[0],
+            reference_bytes[1],
+            reference_bytes[2],
+            reference_bytes[3],
+            mask_bytes[0],
+            mask_bytes[1],
+            mask_bytes[2],
+            mask_bytes[3],
+            skip_bytes[0],
+            skip_bytes[1],
+            skip_bytes[2],
+            skip_bytes[3],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn wait_until(&mut self, compare_fn: DepthCompareFn, value: *const u32, reference: u32, mask: u32) -> Result<(), GpuCommandBuilderError> {
+        let value_address_bytes = command_u32_bytes(value as usize as u32);
+        let reference_bytes = command_u32_bytes(reference);
+        let mask_bytes = command_u32_bytes(mask);
+        let data = &[
+            0x25,
+            0x00,
+            compare_fn as u8,
+            0x00,
+            value_address_bytes[0],
+            value_address_bytes[1],
+            value_address_bytes[2],
+            value_address_bytes[3],
+            reference_bytes[0],
+            reference_bytes[1],
+            reference_bytes[2],
+            reference_bytes[3],
+            mask_bytes[0],
+            mask_bytes[1],
+            mask_bytes[2],
+            mask_bytes[3],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn signal_fence(&mut self, fence: u8, value: u32) -> Result<(), GpuCommandBuilderError> {
+        let value_bytes = command_u32_bytes(value);
+        let data = &[
+            0x26,
+            0x00,
+            fence,
+            0x00,
+            value_bytes[0],
+            value_bytes[1],
+            value_bytes[2],
+            value_bytes[3],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+
+    fn wait_fence(&mut self, fence: u8, value: u32) -> Result<(), GpuCommandBuilderError> {
+        let value_bytes = command_u32_bytes(value);
+        let data = &[
+            0x27,
+            0x00,
+            fence,
+            0x00,
+            value_bytes[0],
+            value_bytes[1],
+            value_bytes[2],
+            value_bytes[3],
+        ];
+        if !self.push_command(data) {
+			Err(GpuCommandBuilderError::OutOfSpace)
+		} else {
+			Ok(())
+		}
+    }
+}
+
+// sub-lists are called and jumped to by header, so finished lists reused every frame
+// keep a zero completion flag instead of being signalled on each fetch
+fn sub_list_header<'e, List: CommandListData<'e, GpuCommands>>(list: &mut List) -> *const u8 {
+    let bytes = list.command_list_bytes();
+    bytes[4..8].copy_from_slice(&command_u32_bytes(0));
+    bytes.as_ptr()
 }
 
 const GPU_COMMANDLIST_SUBMISSION_PORT: usize = 0x80010000;
 
+// the gpu runs high priority lists first, then graphics, then transfer
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GpuQueue {
+    Graphics = 0,
+    Transfer = 1,
+    HighPriority = 2,
+}
+
 pub fn gpu_submit<'d, 'b: 'd, 'c: 'b, Completion: CommandListCompletion<'c>, CommandList: CommandListData<'d, GpuCommands>>(command_list: &mut CommandList, completion: &mut Completion) {
+    gpu_submit_to_queue(GpuQueue::Graphics, command_list, completion);
+}
+
+pub fn gpu_submit_to_queue<'d, 'b: 'd, 'c: 'b, Completion: CommandListCompletion<'c>, CommandList: CommandListData<'d, GpuCommands>>(queue: GpuQueue, command_list: &mut CommandList, completion: &mut Completion) {
     unsafe {
         AtomicU32::from_ptr(completion.raw_ptr()).store(0, atomic::Ordering::Release);
         let len = command_list.command_list_bytes().len();
         command_list.command_list_bytes()[0..4].copy_from_slice(&command_u32_bytes((len - 8) as u32));
         command_list.command_list_bytes()[4..8].copy_from_slice(&command_u32_bytes(completion.raw_ptr() as usize as u32));
-        core::ptr::write(GPU_COMMANDLIST_SUBMISSION_PORT as * mut u32, command_list.command_list_bytes().as_ptr() as usize as u32);
+        core::ptr::write((GPU_COMMANDLIST_SUBMISSION_PORT + queue as usize * 4) as * mut u32, command_list.command_list_bytes().as_ptr() as usize as u32);
     }
 }
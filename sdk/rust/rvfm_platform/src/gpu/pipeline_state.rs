@@ -89,6 +89,11 @@ pub struct VertexInputAssignment {
     pub input_cardinality: ShaderCardinality,
     pub offset: u32,
     pub stride: u32,
+    /*
+    0 advances the input once per vertex. anything else makes it a per-instance
+    input, advancing once every step_rate instances
+     */
+    pub step_rate: u32,
 }
 
 #[repr(C, align(4))]
@@ -98,6 +103,7 @@ pub struct FragmentState {
     pub output_assignments: *const FragmentOutputAssignment,
     pub output_assignment_count: u8,
     pub _dummy: [u8; 3],
+    pub blend_states: *const FragmentBlendState,
 }
 
 impl FragmentState {
@@ -106,9 +112,18 @@ impl FragmentState {
             depth_state: if let Some(depth) = depth { depth as *const _ } else { core::ptr::null() },
             output_assignments: output_assignments.as_ptr(),
             output_assignment_count: output_assignments.len() as u8,
-            _dummy: [0; 3]
+            _dummy: [0; 3],
+            blend_states: core::ptr::null(),
         }
     }
+
+    /*
+    blend_states must hold one entry per output assignment, in the same order
+     */
+    pub const fn with_blend_states(mut self, blend_states: &'static [FragmentBlendState]) -> Self {
+        self.blend_states = blend_states.as_ptr();
+        self
+    }
 }
 
 #[repr(u8)]
@@ -133,6 +148,96 @@ pub struct FragmentOutputAssignment {
     pub offset: [u32; 2],
 }
 
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlendFactor {
+    Zero,
+    One,
+    SrcColor,
+    OneMinusSrcColor,
+    DstColor,
+    OneMinusDstColor,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    DstAlpha,
+    OneMinusDstAlpha,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlendOp {
+    Add,
+    Subtract,
+    ReverseSubtract,
+    Min,
+    Max,
+}
+
+pub const WRITE_MASK_R: u8 = 0x1;
+pub const WRITE_MASK_G: u8 = 0x2;
+pub const WRITE_MASK_B: u8 = 0x4;
+pub const WRITE_MASK_A: u8 = 0x8;
+pub const WRITE_MASK_ALL: u8 = 0xF;
+
+#[repr(C, align(4))]
+#[derive(Copy, Clone, Debug)]
+pub struct FragmentBlendState {
+    pub enable: u8,
+    pub color_src: BlendFactor,
+    pub color_dst: BlendFactor,
+    pub color_op: BlendOp,
+    pub alpha_src: BlendFactor,
+    pub alpha_dst: BlendFactor,
+    pub alpha_op: BlendOp,
+    pub write_mask: u8,
+}
+
+impl FragmentBlendState {
+    pub const fn replace() -> Self {
+        Self {
+            enable: 0,
+            color_src: BlendFactor::One,
+            color_dst: BlendFactor::Zero,
+            color_op: BlendOp::Add,
+            alpha_src: BlendFactor::One,
+            alpha_dst: BlendFactor::Zero,
+            alpha_op: BlendOp::Add,
+            write_mask: WRITE_MASK_ALL,
+        }
+    }
+
+    pub const fn alpha_blend() -> Self {
+        Self {
+            enable: 1,
+            color_src: BlendFactor::SrcAlpha,
+            color_dst: BlendFactor::OneMinusSrcAlpha,
+            color_op: BlendOp::Add,
+            alpha_src: BlendFactor::One,
+            alpha_dst: BlendFactor::OneMinusSrcAlpha,
+            alpha_op: BlendOp::Add,
+            write_mask: WRITE_MASK_ALL,
+        }
+    }
+
+    pub const fn additive() -> Self {
+        Self {
+            enable: 1,
+            color_src: BlendFactor::One,
+            color_dst: BlendFactor::One,
+            color_op: BlendOp::Add,
+            alpha_src: BlendFactor::One,
+            alpha_dst: BlendFactor::One,
+            alpha_op: BlendOp::Add,
+            write_mask: WRITE_MASK_ALL,
+        }
+    }
+
+    pub const fn with_write_mask(mut self, write_mask: u8) -> Self {
+        self.write_mask = write_mask;
+        self
+    }
+}
+
 #[repr(u8)]
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum DepthCompareFn {
@@ -142,6 +247,41 @@ pub enum DepthCompareFn {
     LessOrEqual,
     Greater,
     GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    IncrementClamp,
+    DecrementClamp,
+    Invert,
+    IncrementWrap,
+    DecrementWrap,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct StencilFaceState {
+    pub compare_fn: DepthCompareFn,
+    pub fail_op: StencilOp,
+    pub depth_fail_op: StencilOp,
+    pub pass_op: StencilOp,
+}
+
+impl StencilFaceState {
+    pub const fn new(compare_fn: DepthCompareFn, fail_op: StencilOp, depth_fail_op: StencilOp, pass_op: StencilOp) -> Self {
+        Self {
+            compare_fn,
+            fail_op,
+            depth_fail_op,
+            pass_op,
+        }
+    }
 }
 
 #[repr(C, align(4))]
@@ -150,6 +290,50 @@ pub struct FragmentDepthState {
     pub texture: u8,
     pub compare_fn: DepthCompareFn,
     pub depth_write: u8,
+    pub stencil_enable: u8,
+    pub stencil_texture: u8,
+    pub stencil_reference: u8,
+    pub stencil_read_mask: u8,
+    pub stencil_write_mask: u8,
+    pub stencil_front: StencilFaceState,
+    pub stencil_back: StencilFaceState,
+}
+
+impl FragmentDepthState {
+    /*
+    a compare_fn of Always without depth_write leaves the depth texture
+    untouched, for stencil only passes
+     */
+    pub const fn new(texture: u8, compare_fn: DepthCompareFn, depth_write: bool) -> Self {
+        let keep = StencilFaceState::new(DepthCompareFn::Always, StencilOp::Keep, StencilOp::Keep, StencilOp::Keep);
+        Self {
+            texture,
+            compare_fn,
+            depth_write: depth_write as u8,
+            stencil_enable: 0,
+            stencil_texture: 0,
+            stencil_reference: 0,
+            stencil_read_mask: 0xFF,
+            stencil_write_mask: 0xFF,
+            stencil_front: keep,
+            stencil_back: keep,
+        }
+    }
+
+    /*
+    the stencil texture is D8x1. reference & read_mask is compared against the
+    stored value & read_mask, and the ops only change the bits in write_mask
+     */
+    pub const fn with_stencil(mut self, texture: u8, reference: u8, read_mask: u8, write_mask: u8, front: StencilFaceState, back: StencilFaceState) -> Self {
+        self.stencil_enable = 1;
+        self.stencil_texture = texture;
+        self.stencil_reference = reference;
+        self.stencil_read_mask = read_mask;
+        self.stencil_write_mask = write_mask;
+        self.stencil_front = front;
+        self.stencil_back = back;
+        self
+    }
 }
 
 #[repr(C, align(4))]
@@ -163,8 +347,18 @@ pub struct RasterizerState {
     pub constant_count: u8,
     pub buffer_mapping_count: u8,
     pub texture_mapping_count: u8,
+    pub front_face: FrontFace,
+    pub cull_mode: CullMode,
+    pub flags: u8,
+    pub topology: Topology,
+    pub scissor: ScissorRect,
+    pub viewport: Viewport,
+    pub line_width: f32,
 }
 
+pub const RASTERIZER_FLAG_SCISSOR: u8 = 0x01;
+pub const RASTERIZER_FLAG_VIEWPORT: u8 = 0x02;
+
 impl RasterizerState {
     pub const fn new(varyings: &'static [VaryingAssignment], constants: &'static [ConstantAssignment], buffer_mappings: &'static [u8], texture_mappings: &'static[u8]) -> Self {
         Self {
@@ -176,6 +370,126 @@ impl RasterizerState {
             buffer_mapping_count: buffer_mappings.len() as u8,
             texture_mappings: texture_mappings.as_ptr(),
             texture_mapping_count: texture_mappings.len() as u8,
+            front_face: FrontFace::CounterClockwise,
+            cull_mode: CullMode::None,
+            flags: 0,
+            topology: Topology::TriangleList,
+            scissor: ScissorRect { x_low: 0, x_high: 0, y_low: 0, y_high: 0 },
+            viewport: Viewport { x: 0.0, y: 0.0, width: 0.0, height: 0.0 },
+            line_width: 1.0,
+        }
+    }
+
+    pub const fn with_culling(mut self, front_face: FrontFace, cull_mode: CullMode) -> Self {
+        self.front_face = front_face;
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    /*
+    the scissor rect is intersected with the clipping rect given to each draw
+     */
+    pub const fn with_scissor(mut self, scissor: ScissorRect) -> Self {
+        self.scissor = scissor;
+        self.flags |= RASTERIZER_FLAG_SCISSOR;
+        self
+    }
+
+    /*
+    with a viewport set, vertex positions are clip space and get divided by w.
+    triangles are clipped to 0 <= z <= w first. without one, they are taken as
+    normalized device coordinates spanning the clipping rect of the draw
+     */
+    pub const fn with_viewport(mut self, viewport: Viewport) -> Self {
+        self.viewport = viewport;
+        self.flags |= RASTERIZER_FLAG_VIEWPORT;
+        self
+    }
+
+    /*
+    points are drawn as squares sized by the point_size vertex shader output,
+    which defaults to 1 pixel
+     */
+    pub const fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub const fn with_line_width(mut self, line_width: f32) -> Self {
+        self.line_width = line_width;
+        self
+    }
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrontFace {
+    CounterClockwise,
+    Clockwise,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+}
+
+/*
+flat varyings take their value from the first vertex of each primitive: vertex
+i of strip triangle i, vertex i + 1 of fan triangle i and vertex i of line strip
+segment i
+ */
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Topology {
+    TriangleList,
+    TriangleStrip,
+    TriangleFan,
+    PointList,
+    LineList,
+    LineStrip,
+}
+
+#[repr(C, align(4))]
+#[derive(Copy, Clone, Debug)]
+pub struct ScissorRect {
+    pub x_low: u16,
+    pub x_high: u16,
+    pub y_low: u16,
+    pub y_high: u16,
+}
+
+#[repr(C, align(4))]
+#[derive(Copy, Clone, Debug)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[repr(C, align(4))]
+#[derive(Copy, Clone, Debug)]
+pub struct ComputePipelineState {
+    pub constants: *const ConstantAssignment,
+    pub buffer_mappings: *const u8,
+    pub texture_mappings: *const u8,
+    pub constant_count: u8,
+    pub buffer_mapping_count: u8,
+    pub texture_mapping_count: u8,
+}
+
+impl ComputePipelineState {
+    pub const fn new(constants: &'static [ConstantAssignment], buffer_mappings: &'static [u8], texture_mappings: &'static[u8]) -> Self {
+        Self {
+            constants: constants.as_ptr(),
+            constant_count: constants.len() as u8,
+            buffer_mappings: buffer_mappings.as_ptr(),
+            buffer_mapping_count: buffer_mappings.len() as u8,
+            texture_mappings: texture_mappings.as_ptr(),
+            texture_mapping_count: texture_mappings.len() as u8,
         }
     }
 }
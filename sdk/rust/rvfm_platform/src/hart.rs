@@ -6,6 +6,10 @@ pub enum Hart {
     Hart1,
     Hart2,
     Hart3,
+    Hart4,
+    Hart5,
+    Hart6,
+    Hart7,
 }
 
 const HART_START_TRIGGER_BASE: u32 = 0x8002_0000;
@@ -18,16 +22,42 @@ impl Hart {
             1 => Self::Hart1,
             2 => Self::Hart2,
             3 => Self::Hart3,
+            4 => Self::Hart4,
+            5 => Self::Hart5,
+            6 => Self::Hart6,
+            7 => Self::Hart7,
             _ => unreachable!()
         }
     }
 
+    pub fn from_u32(id: u32) -> Option<Self> {
+        Some(match id {
+            0 => Self::Hart0,
+            1 => Self::Hart1,
+            2 => Self::Hart2,
+            3 => Self::Hart3,
+            4 => Self::Hart4,
+            5 => Self::Hart5,
+            6 => Self::Hart6,
+            7 => Self::Hart7,
+            _ => return None
+        })
+    }
+
+    pub fn count() -> u32 {
+        crate::machine_info::hart_count()
+    }
+
     pub fn to_u32(self) -> u32 {
         match self {
             Self::Hart0 => 0,
             Self::Hart1 => 1,
             Self::Hart2 => 2,
             Self::Hart3 => 3,
+            Self::Hart4 => 4,
+            Self::Hart5 => 5,
+            Self::Hart6 => 6,
+            Self::Hart7 => 7,
         }
     }
 
@@ -11,6 +11,16 @@ const IHI_ENABLE_BASE: u32 = 0x80030FD0;
 const IHI_CLEAR_BASE: u32 = 0x80030FE0;
 const IHI_TRIGGER_BASE: u32 = 0x80030FF0;
 
+// harts 4-7 use the register blocks 0x40 bytes below those of harts 0-3
+fn hart_register_address(base: u32, hart: Hart) -> u32 {
+    let hart = hart.to_u32();
+    if hart < 4 {
+        base + (hart << 2)
+    } else {
+        base - 0x40 + ((hart - 4) << 2)
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Interrupt {
     GpuInterrupt = 0,
@@ -52,7 +62,7 @@ impl Interrupt {
 
     pub fn get_pending() -> Option<PendingInterrupt> {
         let hart = Hart::current();
-        let address = PENDING_INTERRUPT_BASE + hart.to_u32() << 4;
+        let address = hart_register_address(PENDING_INTERRUPT_BASE, hart);
         let value = unsafe { core::ptr::read_volatile::<u32>(address as usize as *const u32) };
         Some(match value {
             0 => PendingInterrupt::Peripheral(Self::GpuInterrupt),
@@ -74,27 +84,27 @@ impl InterHartInterrupt {
     }
 
     fn enable(self) {
-        let address = IHI_ENABLE_BASE + (self.0.to_u32() << 2);
+        let address = hart_register_address(IHI_ENABLE_BASE, self.0);
         unsafe { core::ptr::write_volatile(address as usize as *mut u32, 1); }
     }
 
     fn disable(self) {
-        let address = IHI_ENABLE_BASE + (self.0.to_u32() << 2);
+        let address = hart_register_address(IHI_ENABLE_BASE, self.0);
         unsafe { core::ptr::write_volatile(address as usize as *mut u32, 0); }
     }
 
     fn send(self) {
-        let address = IHI_TRIGGER_BASE + (self.0.to_u32() << 2);
+        let address = hart_register_address(IHI_TRIGGER_BASE, self.0);
         unsafe { core::ptr::write_volatile(address as usize as *mut u32, 1); }
     }
 
     fn clear(self) {
-        let address = IHI_CLEAR_BASE + (self.0.to_u32() << 2);
+        let address = hart_register_address(IHI_CLEAR_BASE, self.0);
         unsafe { core::ptr::write_volatile(address as usize as *mut u32, 1); }
     }
 
     fn poll(self) -> bool {
-        let address = IHI_CLEAR_BASE + (self.0.to_u32() << 2);
+        let address = hart_register_address(IHI_CLEAR_BASE, self.0);
         unsafe { core::ptr::read_volatile::<u32>(address as usize as *const u32) != 0 }
     }
 }
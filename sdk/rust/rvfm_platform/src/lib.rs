@@ -6,6 +6,10 @@ pub mod interrupt;
 pub mod hart;
 pub mod command_list;
 pub mod input;
+pub mod machine_info;
+pub mod rtc;
+pub mod entropy;
+pub mod semihosting;
 pub mod spu;
 #[cfg(feature = "multihart")]
 pub mod multihart;
@@ -0,0 +1,25 @@
+const MACHINE_INFO_HART_COUNT       : *const u32 = 0x8006_0000_u32 as _;
+const MACHINE_INFO_RAM_SIZE         : *const u32 = 0x8006_0004_u32 as _;
+const MACHINE_INFO_ROM_SIZE         : *const u32 = 0x8006_0008_u32 as _;
+const MACHINE_INFO_CYCLES_PER_FRAME : *const u32 = 0x8006_000C_u32 as _;
+const MACHINE_INFO_VRAM_SIZE        : *const u32 = 0x8006_0010_u32 as _;
+
+pub fn hart_count() -> u32 {
+    unsafe { MACHINE_INFO_HART_COUNT.read_volatile() }
+}
+
+pub fn ram_size() -> u32 {
+    unsafe { MACHINE_INFO_RAM_SIZE.read_volatile() }
+}
+
+pub fn rom_size() -> u32 {
+    unsafe { MACHINE_INFO_ROM_SIZE.read_volatile() }
+}
+
+pub fn hart_cycles_per_frame() -> u32 {
+    unsafe { MACHINE_INFO_CYCLES_PER_FRAME.read_volatile() }
+}
+
+pub fn vram_size() -> u32 {
+    unsafe { MACHINE_INFO_VRAM_SIZE.read_volatile() }
+}
@@ -0,0 +1,119 @@
+use core::sync::atomic::{fence, Ordering};
+
+use crate::hart::Hart;
+
+const MAILBOX_BASE: u32 = 0x8009_0000;
+const SEMAPHORE_BASE: u32 = 0x8009_0100;
+const SEND_RESULT_REGISTER: u32 = 0x8009_0080;
+
+const MAILBOX_SEND_OFFSET: u32 = 0x0;
+const MAILBOX_RECEIVE_OFFSET: u32 = 0x4;
+const MAILBOX_COUNT_OFFSET: u32 = 0x8;
+const MAILBOX_STATUS_OFFSET: u32 = 0xC;
+
+const MAILBOX_STATUS_FULL: u32 = 1;
+const MAILBOX_STATUS_OVERFLOW: u32 = 2;
+
+pub const MAILBOX_QUEUE_DEPTH: u32 = 16;
+pub const SEMAPHORE_COUNT: u32 = 32;
+
+fn mailbox_register(hart: Hart, offset: u32) -> *mut u32 {
+    (MAILBOX_BASE + (hart.to_u32() << 4) + offset) as usize as *mut u32
+}
+
+// Queues a message for the given hart and raises its inter-hart interrupt. Returns false if the queue was full and the message was dropped.
+pub fn send(hart: Hart, message: u32) -> bool {
+    fence(Ordering::Release);
+    unsafe {
+        mailbox_register(hart, MAILBOX_SEND_OFFSET).write_volatile(message);
+        (SEND_RESULT_REGISTER as usize as *const u32).read_volatile() != 0
+    }
+}
+
+// Takes the oldest message from the current hart's queue.
+pub fn receive() -> Option<u32> {
+    let hart = Hart::current();
+    if pending(hart) == 0 {
+        None
+    } else {
+        let message = unsafe { mailbox_register(hart, MAILBOX_RECEIVE_OFFSET).read_volatile() };
+        fence(Ordering::Acquire);
+        Some(message)
+    }
+}
+
+pub fn pending(hart: Hart) -> u32 {
+    unsafe { mailbox_register(hart, MAILBOX_COUNT_OFFSET).read_volatile() }
+}
+
+pub fn is_full(hart: Hart) -> bool {
+    unsafe { (mailbox_register(hart, MAILBOX_STATUS_OFFSET).read_volatile() & MAILBOX_STATUS_FULL) != 0 }
+}
+
+// Returns whether a message sent to the current hart was dropped since the last call.
+pub fn take_overflow() -> bool {
+    let status_register = mailbox_register(Hart::current(), MAILBOX_STATUS_OFFSET);
+    unsafe {
+        let overflow = (status_register.read_volatile() & MAILBOX_STATUS_OVERFLOW) != 0;
+        if overflow {
+            status_register.write_volatile(MAILBOX_STATUS_OVERFLOW);
+        }
+        overflow
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Semaphore(u32);
+
+pub struct SemaphoreGuard {
+    semaphore: Semaphore,
+}
+
+impl Semaphore {
+    pub const fn new(index: u32) -> Option<Self> {
+        if index < SEMAPHORE_COUNT {
+            Some(Self(index))
+        } else {
+            None
+        }
+    }
+
+    fn register(self) -> *mut u32 {
+        (SEMAPHORE_BASE + (self.0 << 2)) as usize as *mut u32
+    }
+
+    pub fn try_acquire(self) -> Option<SemaphoreGuard> {
+        if unsafe { self.register().read_volatile() } == 0 {
+            fence(Ordering::Acquire);
+            Some(SemaphoreGuard { semaphore: self })
+        } else {
+            None
+        }
+    }
+
+    pub fn acquire(self) -> SemaphoreGuard {
+        loop {
+            if let Some(guard) = self.try_acquire() {
+                return guard;
+            }
+        }
+    }
+
+    // Releases the semaphore without a guard, for semaphores acquired on another hart.
+    pub unsafe fn release_raw(self) {
+        fence(Ordering::Release);
+        self.register().write_volatile(0);
+    }
+}
+
+impl SemaphoreGuard {
+    pub fn semaphore(&self) -> Semaphore {
+        self.semaphore
+    }
+}
+
+impl Drop for SemaphoreGuard {
+    fn drop(&mut self) {
+        unsafe { self.semaphore.release_raw(); }
+    }
+}
@@ -10,6 +10,7 @@ pub mod critical_section;
 pub mod spinlock;
 
 pub mod hart_local;
+pub mod mailbox;
 
 global_asm!(include_str!("trampoline.s"));
 
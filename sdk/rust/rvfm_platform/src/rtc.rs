@@ -0,0 +1,43 @@
+const RTC_UNIX_TIME_LO : *const u32 = 0x8007_0000_u32 as _;
+const RTC_UNIX_TIME_HI : *const u32 = 0x8007_0004_u32 as _;
+const RTC_YEAR         : *const u32 = 0x8007_0008_u32 as _;
+const RTC_MONTH        : *const u32 = 0x8007_000C_u32 as _;
+const RTC_DAY          : *const u32 = 0x8007_0010_u32 as _;
+const RTC_HOUR         : *const u32 = 0x8007_0014_u32 as _;
+const RTC_MINUTE       : *const u32 = 0x8007_0018_u32 as _;
+const RTC_SECOND       : *const u32 = 0x8007_001C_u32 as _;
+const RTC_WEEKDAY      : *const u32 = 0x8007_0020_u32 as _;
+const RTC_DETERMINISTIC: *const u32 = 0x8007_0024_u32 as _;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub unix_time: u64,
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub weekday: u32,
+}
+
+pub fn now() -> DateTime {
+    unsafe {
+        // reading the low word of the unix time latches the rest of the registers
+        let unix_time_lo = RTC_UNIX_TIME_LO.read_volatile();
+        DateTime {
+            unix_time: ((RTC_UNIX_TIME_HI.read_volatile() as u64) << 32) | unix_time_lo as u64,
+            year: RTC_YEAR.read_volatile(),
+            month: RTC_MONTH.read_volatile(),
+            day: RTC_DAY.read_volatile(),
+            hour: RTC_HOUR.read_volatile(),
+            minute: RTC_MINUTE.read_volatile(),
+            second: RTC_SECOND.read_volatile(),
+            weekday: RTC_WEEKDAY.read_volatile(),
+        }
+    }
+}
+
+pub fn is_deterministic() -> bool {
+    unsafe { RTC_DETERMINISTIC.read_volatile() != 0 }
+}
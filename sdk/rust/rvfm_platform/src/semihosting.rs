@@ -0,0 +1,117 @@
+// Semihosting is only available when the emulator is a debug build started with -semihost <directory>
+
+use core::arch::asm;
+
+const SYS_OPEN : u32 = 0x01;
+const SYS_CLOSE: u32 = 0x02;
+const SYS_WRITE: u32 = 0x05;
+const SYS_READ : u32 = 0x06;
+const SYS_SEEK : u32 = 0x0A;
+const SYS_FLEN : u32 = 0x0C;
+const SYS_ERRNO: u32 = 0x13;
+const SYS_STAT : u32 = 0x100;
+
+const SEMIHOSTING_ERROR: u32 = 0xFFFF_FFFF;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OpenMode {
+    Read,
+    ReadWrite,
+    Write,
+    WriteRead,
+    Append,
+    AppendRead,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct File(u32);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Stat {
+    pub size: u32,
+    pub is_directory: bool,
+    pub modified_time: u64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Error(pub u32);
+
+unsafe fn semihosting_call(operation: u32, parameters: *const u32) -> u32 {
+    let mut result = operation;
+    asm!(
+        ".balign 16",
+        ".option push",
+        ".option norvc",
+        "slli x0, x0, 0x1F",
+        "ebreak",
+        "srai x0, x0, 7",
+        ".option pop",
+        inout("a0") result,
+        in("a1") parameters,
+        options(nostack)
+    );
+    result
+}
+
+fn check(result: u32) -> Result<u32, Error> {
+    if result == SEMIHOSTING_ERROR {
+        Err(Error(unsafe { semihosting_call(SYS_ERRNO, core::ptr::null()) }))
+    } else {
+        Ok(result)
+    }
+}
+
+pub fn open(name: &str, mode: OpenMode) -> Result<File, Error> {
+    let mode = match mode {
+        OpenMode::Read       => 0,
+        OpenMode::ReadWrite  => 2,
+        OpenMode::Write      => 4,
+        OpenMode::WriteRead  => 6,
+        OpenMode::Append     => 8,
+        OpenMode::AppendRead => 10,
+    };
+    let parameters = [name.as_ptr() as u32, mode, name.len() as u32];
+    check(unsafe { semihosting_call(SYS_OPEN, parameters.as_ptr()) }).map(File)
+}
+
+pub fn stat(name: &str) -> Result<Stat, Error> {
+    let mut stat = [0u32; 4];
+    let parameters = [name.as_ptr() as u32, name.len() as u32, stat.as_mut_ptr() as u32];
+    check(unsafe { semihosting_call(SYS_STAT, parameters.as_ptr()) })?;
+    Ok(Stat {
+        size: stat[0],
+        is_directory: stat[1] != 0,
+        modified_time: ((stat[3] as u64) << 32) | stat[2] as u64,
+    })
+}
+
+impl File {
+    pub fn close(self) -> Result<(), Error> {
+        let parameters = [self.0];
+        check(unsafe { semihosting_call(SYS_CLOSE, parameters.as_ptr()) }).map(|_| ())
+    }
+
+    // returns the number of bytes written
+    pub fn write(&self, data: &[u8]) -> Result<usize, Error> {
+        let parameters = [self.0, data.as_ptr() as u32, data.len() as u32];
+        let not_written = check(unsafe { semihosting_call(SYS_WRITE, parameters.as_ptr()) })?;
+        Ok(data.len() - not_written as usize)
+    }
+
+    // returns the number of bytes read, 0 at the end of the file
+    pub fn read(&self, data: &mut [u8]) -> Result<usize, Error> {
+        let parameters = [self.0, data.as_mut_ptr() as u32, data.len() as u32];
+        let not_read = check(unsafe { semihosting_call(SYS_READ, parameters.as_ptr()) })?;
+        Ok(data.len() - not_read as usize)
+    }
+
+    pub fn seek(&self, position: u32) -> Result<(), Error> {
+        let parameters = [self.0, position];
+        check(unsafe { semihosting_call(SYS_SEEK, parameters.as_ptr()) }).map(|_| ())
+    }
+
+    pub fn len(&self) -> Result<u32, Error> {
+        let parameters = [self.0];
+        check(unsafe { semihosting_call(SYS_FLEN, parameters.as_ptr()) })
+    }
+}
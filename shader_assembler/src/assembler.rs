@@ -79,6 +79,7 @@ fn expect_write_register<'t, I: Iterator<Item = &'t Token>>(iter: &mut Peekable<
                 TokenType::Register(RegisterName::OutputV(_)) => true,
                 TokenType::Register(RegisterName::BuiltinS(scalar_builtin)) => {
                     match (assembly_mode, scalar_builtin) {
+                        (AssemblyMode::Vertex, ScalarBuiltin::PointSize) |
                         (AssemblyMode::Fragment, ScalarBuiltin::Depth) => true,
                         _ => false,
                     }
@@ -124,7 +125,11 @@ fn expect_read_register<'t, I: Iterator<Item = &'t Token>>(iter: &mut Peekable<I
                     match (assembly_mode, scalar_builtin) {
                         (AssemblyMode::Vertex, ScalarBuiltin::VertexId)        |
                         (AssemblyMode::Vertex, ScalarBuiltin::ProvokingVertex) |
-                        (AssemblyMode::Fragment, ScalarBuiltin::Depth)         => true,
+                        (AssemblyMode::Vertex, ScalarBuiltin::InstanceId)      |
+                        (AssemblyMode::Fragment, ScalarBuiltin::Depth)         |
+                        (AssemblyMode::Fragment, ScalarBuiltin::FrontFacing)   |
+                        (AssemblyMode::Compute, ScalarBuiltin::InvocationId)   |
+                        (AssemblyMode::Compute, ScalarBuiltin::LocalInvocationId) => true,
                         _ => false,
                     }
                 },
@@ -133,7 +138,8 @@ fn expect_read_register<'t, I: Iterator<Item = &'t Token>>(iter: &mut Peekable<I
                         (AssemblyMode::Fragment, VectorBuiltin::VertexPosition) |
                         (AssemblyMode::Fragment, VectorBuiltin::Barycentric   ) |
                         (AssemblyMode::Fragment, VectorBuiltin::Linear         ) |
-                        (AssemblyMode::Fragment, VectorBuiltin::VertexIds     ) => true,
+                        (AssemblyMode::Fragment, VectorBuiltin::VertexIds     ) |
+                        (AssemblyMode::Compute,  VectorBuiltin::WorkgroupId   ) => true,
                         _ => false
                     }
                 },
@@ -200,6 +206,24 @@ pub fn expect_buffer<'t, I: Iterator<Item = &'t Token>>(iter: &mut Peekable<I>,
     }
 }
 
+pub fn expect_texture<'t, I: Iterator<Item = &'t Token>>(iter: &mut Peekable<I>) -> Result<(u8, &'t Token), SourceError> {
+    let token = expect_token_with("texture name", |t| matches!(t, TokenType::Texture(_)), iter)?;
+    if let Token { t: TokenType::Texture(n), .. } = token {
+        Ok((*n, token))
+    } else {
+        unreachable!()
+    }
+}
+
+pub fn expect_sampler<'t, I: Iterator<Item = &'t Token>>(iter: &mut Peekable<I>) -> Result<(u8, &'t Token), SourceError> {
+    let token = expect_token_with("sampler name", |t| matches!(t, TokenType::Sampler(_)), iter)?;
+    if let Token { t: TokenType::Sampler(n), .. } = token {
+        Ok((*n, token))
+    } else {
+        unreachable!()
+    }
+}
+
 pub fn run_assembler(tokens: &[Token], entry_mode: AssemblyMode) -> Result<Box<[u8]>, Vec<SourceError>> {
     let mut bytes = Vec::new();
     let mut token_iter = tokens.iter().filter(|x| x.t != TokenType::Comment && x.t != TokenType::Whitespace).peekable();
@@ -318,6 +342,20 @@ pub fn run_assembler(tokens: &[Token], entry_mode: AssemblyMode) -> Result<Box<[
                     column: token.column,
                 })
             },
+            TokenType::Texture(_) => {
+                errors.push(SourceError {
+                    message: "Unexpected texture name".to_string(),
+                    line: token.line,
+                    column: token.column,
+                })
+            },
+            TokenType::Sampler(_) => {
+                errors.push(SourceError {
+                    message: "Unexpected sampler name".to_string(),
+                    line: token.line,
+                    column: token.column,
+                })
+            },
         }
     }
     if !entry_found {
@@ -439,7 +477,7 @@ fn handle_instruction<'t, I: Iterator<Item = &'t Token>>(bytes: &mut Vec<u8>, in
                 x as i32 as u32
             } else {
                 Err(SourceError {
-                    message: format!("Invalid offset value - floating point values aren't allowed as offsets"),
+                    message: "Invalid offset value - floating point values aren't allowed as offsets".to_string(),
                     line: offset_token.line,
                     column: offset_token.column
                 })?
@@ -452,7 +490,44 @@ fn handle_instruction<'t, I: Iterator<Item = &'t Token>>(bytes: &mut Vec<u8>, in
             };
             write_buffer_read_op(bytes, &instruction_token, instruction_t, dt, dst, dst_token, src_addr_reg, offset, buffer, assembly_mode)?;
         },
-        InstructionType::Write(_)        => todo!(),
+        InstructionType::Write(dt)      => {
+            let (buffer, _) = expect_buffer(iter, Some(buffer_aliases), assembly_mode)?;
+            let offset_token = expect_token(TokenType::Number, iter)?;
+            let offset = if let NumberParse::Integer(x) = parse_number(offset_token) {
+                x as i32 as u32
+            } else {
+                Err(SourceError {
+                    message: "Invalid offset value - floating point values aren't allowed as offsets".to_string(),
+                    line: offset_token.line,
+                    column: offset_token.column
+                })?
+            };
+            expect_token(TokenType::Comma, iter)?;
+            let (src, _) = expect_read_register(iter, Some(register_aliases), assembly_mode)?;
+            let dst_addr_reg = if try_expect_token(TokenType::Comma, iter).is_some() {
+                let (dst_addr_reg, _) = expect_read_register(iter, Some(register_aliases), assembly_mode)?;
+                Some(dst_addr_reg)
+            } else {
+                None
+            };
+            write_buffer_write_op(bytes, dt, src, dst_addr_reg, offset, buffer, assembly_mode)?;
+        },
+        InstructionType::Sample(op_type, lod_mode) => {
+            let (dst, dst_token) = expect_write_register(iter, Some(register_aliases), assembly_mode)?;
+            expect_token(TokenType::Comma, iter)?;
+            let (texture, _) = expect_texture(iter)?;
+            expect_token(TokenType::Comma, iter)?;
+            let (sampler, _) = expect_sampler(iter)?;
+            expect_token(TokenType::Comma, iter)?;
+            let (uv, uv_token) = expect_read_register(iter, Some(register_aliases), assembly_mode)?;
+            let lod = if lod_mode != SampleLodMode::Auto {
+                expect_token(TokenType::Comma, iter)?;
+                Some(expect_read_register(iter, Some(register_aliases), assembly_mode)?)
+            } else {
+                None
+            };
+            write_sample(bytes, op_type, lod_mode, SampleOperands { dst: (dst, dst_token), texture, sampler, uv: (uv, uv_token), lod }, assembly_mode)?;
+        },
         InstructionType::CRead           => todo!(),
         InstructionType::CWrite          => todo!(),
         InstructionType::Store           => todo!(),
@@ -562,6 +637,19 @@ fn handle_instruction<'t, I: Iterator<Item = &'t Token>>(bytes: &mut Vec<u8>, in
             let (dst, _) = expect_read_register(iter, Some(register_aliases), assembly_mode)?;
         }
 
+        InstructionType::If => {
+            let (cond, cond_token) = expect_read_register(iter, Some(register_aliases), assembly_mode)?;
+            write_if(bytes, cond, cond_token, assembly_mode)?;
+        },
+        InstructionType::Else    |
+        InstructionType::EndIf   |
+        InstructionType::Loop    |
+        InstructionType::Break   |
+        InstructionType::EndLoop |
+        InstructionType::Return  => {
+            write_control_flow_op(bytes, instruction_t);
+        },
+
         InstructionType::Load(op_type, data_type) => {
             todo!()
         },
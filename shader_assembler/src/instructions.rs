@@ -34,18 +34,25 @@ impl WriteInstructionBytes for RegisterName {
 
             (AssemblyMode::Vertex,   RegisterName::BuiltinS(ScalarBuiltin::VertexId       )) => (0x00, REGTYPE_INPUT ),
             (AssemblyMode::Vertex,   RegisterName::BuiltinS(ScalarBuiltin::ProvokingVertex)) => (0x01, REGTYPE_INPUT ),
+            (AssemblyMode::Vertex,   RegisterName::BuiltinS(ScalarBuiltin::InstanceId     )) => (0x02, REGTYPE_INPUT ),
 
             (AssemblyMode::Vertex,   RegisterName::BuiltinS(ScalarBuiltin::Discard        )) => (0x00, REGTYPE_OUTPUT),
+            (AssemblyMode::Vertex,   RegisterName::BuiltinS(ScalarBuiltin::PointSize      )) => (0x01, REGTYPE_OUTPUT),
             (AssemblyMode::Vertex,   RegisterName::BuiltinV(VectorBuiltin::VertexPosition )) => (0x00, REGTYPE_OUTPUT),
 
             (AssemblyMode::Fragment, RegisterName::BuiltinV(VectorBuiltin::VertexPosition )) => (0x00, REGTYPE_INPUT ),
             (AssemblyMode::Fragment, RegisterName::BuiltinV(VectorBuiltin::Barycentric    )) => (0x01, REGTYPE_INPUT ),
             (AssemblyMode::Fragment, RegisterName::BuiltinV(VectorBuiltin::Linear         )) => (0x02, REGTYPE_INPUT ),
             (AssemblyMode::Fragment, RegisterName::BuiltinV(VectorBuiltin::VertexIds      )) => (0x03, REGTYPE_INPUT ),
+            (AssemblyMode::Fragment, RegisterName::BuiltinS(ScalarBuiltin::FrontFacing    )) => (0x00, REGTYPE_INPUT ),
 
             (AssemblyMode::Fragment, RegisterName::BuiltinS(ScalarBuiltin::Discard        )) => (0x00, REGTYPE_OUTPUT),
             (AssemblyMode::Fragment, RegisterName::BuiltinS(ScalarBuiltin::Depth          )) => (0x01, REGTYPE_OUTPUT),
 
+            (AssemblyMode::Compute,  RegisterName::BuiltinS(ScalarBuiltin::InvocationId     )) => (0x00, REGTYPE_INPUT ),
+            (AssemblyMode::Compute,  RegisterName::BuiltinS(ScalarBuiltin::LocalInvocationId)) => (0x01, REGTYPE_INPUT ),
+            (AssemblyMode::Compute,  RegisterName::BuiltinV(VectorBuiltin::WorkgroupId      )) => (0x00, REGTYPE_INPUT ),
+
             _ => panic!("Attempt to write illegal register address"),
         };
         bytes.push(regtype);
@@ -171,6 +178,19 @@ const OPCODE_FMA_SCALAR_F32                          : u8 = 0x5D;
 const OPCODE_FMA_SCALAR_I32                          : u8 = 0x5E;
 
 const OPCODE_BUFFER_READ_SCALAR                      : u8 = 0x5F;
+const OPCODE_BUFFER_WRITE_SCALAR                     : u8 = 0x60;
+const OPCODE_BUFFER_READ_VECTOR                      : u8 = 0x61;
+const OPCODE_BUFFER_WRITE_VECTOR                     : u8 = 0x62;
+const OPCODE_IF                                      : u8 = 0x63;
+const OPCODE_ELSE                                    : u8 = 0x64;
+const OPCODE_ENDIF                                   : u8 = 0x65;
+const OPCODE_LOOP                                    : u8 = 0x66;
+const OPCODE_BREAK                                   : u8 = 0x67;
+const OPCODE_ENDLOOP                                 : u8 = 0x68;
+const OPCODE_RETURN                                  : u8 = 0x69;
+const OPCODE_SAMPLE_TEXTURE                          : u8 = 0x6A;
+const OPCODE_SAMPLE_TEXTURE_LOD                      : u8 = 0x6B;
+const OPCODE_SAMPLE_TEXTURE_BIAS                     : u8 = 0x6C;
 
 pub fn write_push(bytes: &mut Vec<u8>, src: RegisterName, _src_token: &Token, assembly_mode: AssemblyMode) -> Result<(), SourceError> {
     bytes.push(if src.is_vector() { OPCODE_VECTOR_PUSH } else { OPCODE_SCALAR_PUSH });
@@ -675,16 +695,8 @@ pub fn write_ternary_op(bytes: &mut Vec<u8>, op_token: &Token, op: InstructionTy
     Ok(())
 }
 
-pub fn write_buffer_read_op(bytes: &mut Vec<u8>, _op_token: &Token, _op: InstructionType, data_type: BufferDataType, dst: RegisterName, dst_token: &Token, src_addr_u32: Option<RegisterName>, offset: u32, buffer: u8, assembly_mode: AssemblyMode) -> Result<(), SourceError> {
-    let vector = dst.is_vector();
-    if vector {
-        Err(SourceError {
-            message: format!("Expected scalar register in scalar buffer read"),
-            line: dst_token.line,
-            column: dst_token.column,
-        })?
-    }
-    bytes.push(OPCODE_BUFFER_READ_SCALAR);
+pub fn write_buffer_read_op(bytes: &mut Vec<u8>, _op_token: &Token, _op: InstructionType, data_type: BufferDataType, dst: RegisterName, _dst_token: &Token, src_addr_u32: Option<RegisterName>, offset: u32, buffer: u8, assembly_mode: AssemblyMode) -> Result<(), SourceError> {
+    bytes.push(if dst.is_vector() { OPCODE_BUFFER_READ_VECTOR } else { OPCODE_BUFFER_READ_SCALAR });
     bytes.push(data_type.to_u8());
     dst.write(bytes, assembly_mode);
     write_offset_u32(bytes, offset);
@@ -696,3 +708,93 @@ pub fn write_buffer_read_op(bytes: &mut Vec<u8>, _op_token: &Token, _op: Instruc
     }
     Ok(())
 }
+
+pub fn write_buffer_write_op(bytes: &mut Vec<u8>, data_type: BufferDataType, src: RegisterName, dst_addr_u32: Option<RegisterName>, offset: u32, buffer: u8, assembly_mode: AssemblyMode) -> Result<(), SourceError> {
+    bytes.push(if src.is_vector() { OPCODE_BUFFER_WRITE_VECTOR } else { OPCODE_BUFFER_WRITE_SCALAR });
+    bytes.push(data_type.to_u8());
+    src.write(bytes, assembly_mode);
+    write_offset_u32(bytes, offset);
+    bytes.push(buffer);
+    if let Some(addr_dst_reg) = dst_addr_u32 {
+        addr_dst_reg.write(bytes, assembly_mode);
+    } else {
+        RegisterName::write_none(bytes);
+    }
+    Ok(())
+}
+
+pub fn write_if(bytes: &mut Vec<u8>, cond: RegisterName, cond_token: &Token, assembly_mode: AssemblyMode) -> Result<(), SourceError> {
+    if cond.is_vector() {
+        Err(SourceError {
+            message: "Invalid condition type - if conditions must be scalar registers".to_string(),
+            line: cond_token.line,
+            column: cond_token.column,
+        })?
+    }
+    bytes.push(OPCODE_IF);
+    cond.write(bytes, assembly_mode);
+    Ok(())
+}
+
+pub fn write_control_flow_op(bytes: &mut Vec<u8>, op: InstructionType) {
+    bytes.push(match op {
+        InstructionType::Else    => OPCODE_ELSE,
+        InstructionType::EndIf   => OPCODE_ENDIF,
+        InstructionType::Loop    => OPCODE_LOOP,
+        InstructionType::Break   => OPCODE_BREAK,
+        InstructionType::EndLoop => OPCODE_ENDLOOP,
+        InstructionType::Return  => OPCODE_RETURN,
+        _ => unreachable!(),
+    });
+}
+
+// registers come with the token they were parsed from, for error locations
+pub struct SampleOperands<'a> {
+    pub dst: (RegisterName, &'a Token),
+    pub texture: u8,
+    pub sampler: u8,
+    pub uv: (RegisterName, &'a Token),
+    pub lod: Option<(RegisterName, &'a Token)>,
+}
+
+pub fn write_sample(bytes: &mut Vec<u8>, op_type: TextureOpType, lod_mode: SampleLodMode, operands: SampleOperands, assembly_mode: AssemblyMode) -> Result<(), SourceError> {
+    let SampleOperands { dst: (dst, dst_token), texture, sampler, uv: (uv, uv_token), lod } = operands;
+    for (register, token) in [(dst, dst_token), (uv, uv_token)] {
+        if !register.is_vector() {
+            Err(SourceError {
+                message: "Invalid operand type - sample operands must be vector registers".to_string(),
+                line: token.line,
+                column: token.column,
+            })?
+        }
+    }
+    if let Some((lod, lod_token)) = lod {
+        if lod.is_vector() {
+            Err(SourceError {
+                message: "Invalid operand type - sample lod operands must be scalar registers".to_string(),
+                line: lod_token.line,
+                column: lod_token.column,
+            })?
+        }
+    }
+    bytes.push(match lod_mode {
+        SampleLodMode::Auto => OPCODE_SAMPLE_TEXTURE,
+        SampleLodMode::Lod  => OPCODE_SAMPLE_TEXTURE_LOD,
+        SampleLodMode::Bias => OPCODE_SAMPLE_TEXTURE_BIAS,
+    });
+    bytes.push(match op_type {
+        TextureOpType::INorm => 0x00,
+        TextureOpType::UNorm => 0x01,
+        TextureOpType::Int   => 0x02,
+        TextureOpType::UInt  => 0x03,
+        TextureOpType::F32   => 0x04,
+    });
+    dst.write(bytes, assembly_mode);
+    uv.write(bytes, assembly_mode);
+    bytes.push(texture);
+    bytes.push(sampler);
+    if let Some((lod, _)) = lod {
+        lod.write(bytes, assembly_mode);
+    }
+    Ok(())
+}
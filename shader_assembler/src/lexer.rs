@@ -39,6 +39,13 @@ pub enum TextureOpType {
     F32,
 }
 
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SampleLodMode {
+    Auto,
+    Lod,
+    Bias,
+}
+
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum BufferDataType {
     I8,
@@ -84,6 +91,7 @@ pub enum InstructionType {
     CMov,
     Read(BufferDataType),
     Write(BufferDataType),
+    Sample(TextureOpType, SampleLodMode),
     CRead,
     CWrite,
     Load(TextureOpType, OpDataType),
@@ -129,6 +137,13 @@ pub enum InstructionType {
     SqMag4,
     Cross,
     MatrixMultiply4x4V4,
+    If,
+    Else,
+    EndIf,
+    Loop,
+    Break,
+    EndLoop,
+    Return,
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -136,7 +151,8 @@ pub enum VectorBuiltin {
     VertexPosition,
     Barycentric,
     Linear,
-    VertexIds
+    VertexIds,
+    WorkgroupId,
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -144,7 +160,12 @@ pub enum ScalarBuiltin {
     Depth,
     VertexId,
     ProvokingVertex,
+    InstanceId,
+    PointSize,
+    FrontFacing,
     Discard,
+    InvocationId,
+    LocalInvocationId,
 }
 
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -179,6 +200,8 @@ pub enum TokenType {
     Whitespace,
     Name,
     Buffer(u8),
+    Texture(u8),
+    Sampler(u8),
     Register(RegisterName),
     Command(CommandType),
     Instruction(InstructionType),
@@ -331,7 +354,40 @@ pub fn run_lexer(input: &str) -> Result<Vec<Token>, Vec<SourceError>>  {
                     },
                     None => None
                 };
-                let (t, accepts_component, clear_component) = if register_string.to_lowercase().starts_with("vloc_") {
+                let (t, accepts_component, clear_component) = if register_string.to_lowercase().starts_with("buf_") {
+                    if let Ok(index) = register_string[4..].parse::<u8>() {
+                        (TokenType::Buffer(index), false, false)
+                    } else {
+                        errors.push(SourceError {
+                            message: "Expected index after buf_ ".to_string(),
+                            line: token_line,
+                            column: token_column,
+                        });
+                        continue;
+                    }
+                } else if register_string.to_lowercase().starts_with("tex_") {
+                    if let Ok(index) = register_string[4..].parse::<u8>() {
+                        (TokenType::Texture(index), false, false)
+                    } else {
+                        errors.push(SourceError {
+                            message: "Expected index after tex_ ".to_string(),
+                            line: token_line,
+                            column: token_column,
+                        });
+                        continue;
+                    }
+                } else if register_string.to_lowercase().starts_with("smp_") {
+                    if let Ok(index) = register_string[4..].parse::<u8>() {
+                        (TokenType::Sampler(index), false, false)
+                    } else {
+                        errors.push(SourceError {
+                            message: "Expected index after smp_ ".to_string(),
+                            line: token_line,
+                            column: token_column,
+                        });
+                        continue;
+                    }
+                } else if register_string.to_lowercase().starts_with("vloc_") {
                     if let Ok(index) = register_string[5..].parse::<u32>() {
                         (TokenType::Register(RegisterName::LocalV(index as u8)), true, false)
                     } else {
@@ -428,7 +484,13 @@ pub fn run_lexer(input: &str) -> Result<Vec<Token>, Vec<SourceError>>  {
                         "depth"            => (TokenType::Register(RegisterName::BuiltinS(ScalarBuiltin::Depth)), false, false),
                         "vertex_id"        => (TokenType::Register(RegisterName::BuiltinS(ScalarBuiltin::VertexId)), false, false),
                         "provoking_vertex" => (TokenType::Register(RegisterName::BuiltinS(ScalarBuiltin::ProvokingVertex)), false, false),
+                        "instance_id"      => (TokenType::Register(RegisterName::BuiltinS(ScalarBuiltin::InstanceId)), false, false),
+                        "point_size"       => (TokenType::Register(RegisterName::BuiltinS(ScalarBuiltin::PointSize)), false, false),
+                        "front_facing"     => (TokenType::Register(RegisterName::BuiltinS(ScalarBuiltin::FrontFacing)), false, false),
                         "discard"          => (TokenType::Register(RegisterName::BuiltinS(ScalarBuiltin::Discard)), false, false),
+                        "invocation_id"    => (TokenType::Register(RegisterName::BuiltinS(ScalarBuiltin::InvocationId)), false, false),
+                        "local_invocation_id" => (TokenType::Register(RegisterName::BuiltinS(ScalarBuiltin::LocalInvocationId)), false, false),
+                        "workgroup_id"     => (TokenType::Register(RegisterName::BuiltinV(VectorBuiltin::WorkgroupId)), false, false),
 
                         "vertex!"          => (TokenType::Command(CommandType::SetMode(AssemblyMode::Vertex)), false, false),
                         "fragment!"        => (TokenType::Command(CommandType::SetMode(AssemblyMode::Fragment)), false, false),
@@ -455,6 +517,41 @@ pub fn run_lexer(input: &str) -> Result<Vec<Token>, Vec<SourceError>>  {
                         "read.unorm32"     => (TokenType::Instruction(InstructionType::Read(BufferDataType::UNorm32)), false, true),
                         "read.unorm16"     => (TokenType::Instruction(InstructionType::Read(BufferDataType::UNorm16)), false, true),
                         "read.unorm8"      => (TokenType::Instruction(InstructionType::Read(BufferDataType::UNorm8)), false, true),
+                        "sample.f32"       => (TokenType::Instruction(InstructionType::Sample(TextureOpType::F32, SampleLodMode::Auto)), false, true),
+                        "sample.int"       => (TokenType::Instruction(InstructionType::Sample(TextureOpType::Int, SampleLodMode::Auto)), false, true),
+                        "sample.uint"      => (TokenType::Instruction(InstructionType::Sample(TextureOpType::UInt, SampleLodMode::Auto)), false, true),
+                        "sample.inorm"     => (TokenType::Instruction(InstructionType::Sample(TextureOpType::INorm, SampleLodMode::Auto)), false, true),
+                        "sample.unorm"     => (TokenType::Instruction(InstructionType::Sample(TextureOpType::UNorm, SampleLodMode::Auto)), false, true),
+                        "sample.lod.f32"   => (TokenType::Instruction(InstructionType::Sample(TextureOpType::F32, SampleLodMode::Lod)), false, true),
+                        "sample.lod.int"   => (TokenType::Instruction(InstructionType::Sample(TextureOpType::Int, SampleLodMode::Lod)), false, true),
+                        "sample.lod.uint"  => (TokenType::Instruction(InstructionType::Sample(TextureOpType::UInt, SampleLodMode::Lod)), false, true),
+                        "sample.lod.inorm" => (TokenType::Instruction(InstructionType::Sample(TextureOpType::INorm, SampleLodMode::Lod)), false, true),
+                        "sample.lod.unorm" => (TokenType::Instruction(InstructionType::Sample(TextureOpType::UNorm, SampleLodMode::Lod)), false, true),
+                        "sample.bias.f32"  => (TokenType::Instruction(InstructionType::Sample(TextureOpType::F32, SampleLodMode::Bias)), false, true),
+                        "sample.bias.int"  => (TokenType::Instruction(InstructionType::Sample(TextureOpType::Int, SampleLodMode::Bias)), false, true),
+                        "sample.bias.uint" => (TokenType::Instruction(InstructionType::Sample(TextureOpType::UInt, SampleLodMode::Bias)), false, true),
+                        "sample.bias.inorm" => (TokenType::Instruction(InstructionType::Sample(TextureOpType::INorm, SampleLodMode::Bias)), false, true),
+                        "sample.bias.unorm" => (TokenType::Instruction(InstructionType::Sample(TextureOpType::UNorm, SampleLodMode::Bias)), false, true),
+                        "write.f32"        => (TokenType::Instruction(InstructionType::Write(BufferDataType::F32)), false, true),
+                        "write.u32"        => (TokenType::Instruction(InstructionType::Write(BufferDataType::U32)), false, true),
+                        "write.u16"        => (TokenType::Instruction(InstructionType::Write(BufferDataType::U16)), false, true),
+                        "write.u8"         => (TokenType::Instruction(InstructionType::Write(BufferDataType::U8)), false, true),
+                        "write.i32"        => (TokenType::Instruction(InstructionType::Write(BufferDataType::I32)), false, true),
+                        "write.i16"        => (TokenType::Instruction(InstructionType::Write(BufferDataType::I16)), false, true),
+                        "write.i8"         => (TokenType::Instruction(InstructionType::Write(BufferDataType::I8)), false, true),
+                        "write.inorm32"    => (TokenType::Instruction(InstructionType::Write(BufferDataType::INorm32)), false, true),
+                        "write.inorm16"    => (TokenType::Instruction(InstructionType::Write(BufferDataType::INorm16)), false, true),
+                        "write.inorm8"     => (TokenType::Instruction(InstructionType::Write(BufferDataType::INorm8)), false, true),
+                        "write.unorm32"    => (TokenType::Instruction(InstructionType::Write(BufferDataType::UNorm32)), false, true),
+                        "write.unorm16"    => (TokenType::Instruction(InstructionType::Write(BufferDataType::UNorm16)), false, true),
+                        "write.unorm8"     => (TokenType::Instruction(InstructionType::Write(BufferDataType::UNorm8)), false, true),
+                        "if"               => (TokenType::Instruction(InstructionType::If), false, false),
+                        "else"             => (TokenType::Instruction(InstructionType::Else), false, false),
+                        "endif"            => (TokenType::Instruction(InstructionType::EndIf), false, false),
+                        "loop"             => (TokenType::Instruction(InstructionType::Loop), false, false),
+                        "break"            => (TokenType::Instruction(InstructionType::Break), false, false),
+                        "endloop"          => (TokenType::Instruction(InstructionType::EndLoop), false, false),
+                        "ret"              => (TokenType::Instruction(InstructionType::Return), false, false),
                         //"read"             => (TokenType::Instruction(InstructionType::Read), false, false),
                         //"write"            => (TokenType::Instruction(InstructionType::Write), false, false),
                         //"cread"            => (TokenType::Instruction(InstructionType::CRead), false, false),
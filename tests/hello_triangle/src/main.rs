@@ -51,6 +51,7 @@ const VERTEX_STATE: VertexState = VertexState::new(
             input_cardinality: ShaderCardinality::V3,
             offset: 0,
             stride: 24,
+            step_rate: 0,
         },
         VertexInputAssignment {
             input: 0x11,
@@ -59,6 +60,7 @@ const VERTEX_STATE: VertexState = VertexState::new(
             input_cardinality: ShaderCardinality::V3,
             offset: 12,
             stride: 24,
+            step_rate: 0,
         }
     ]
 );